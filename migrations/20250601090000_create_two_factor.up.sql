@@ -0,0 +1,14 @@
+CREATE TABLE user_totp (
+    user_id UUID PRIMARY KEY REFERENCES users(user_id),
+    secret TEXT NOT NULL, -- base32, as shown to the authenticator app
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    enabled_at TIMESTAMPTZ, -- NULL until the first code has been confirmed
+    last_used_step BIGINT -- prevents reusing a code within its time step
+);
+
+CREATE TABLE totp_recovery_codes (
+    recovery_code_id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID REFERENCES users(user_id) NOT NULL,
+    code_hash TEXT NOT NULL,
+    used_at TIMESTAMPTZ
+);
@@ -0,0 +1,11 @@
+CREATE TABLE failed_login_attempts (
+    attempt_id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    email TEXT, -- NULL for failures at the second factor
+    user_id UUID REFERENCES users(user_id), -- NULL for unknown emails
+    ip_address TEXT,
+    reason TEXT NOT NULL,
+    attempted_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX failed_login_attempts_user_id_idx ON failed_login_attempts (user_id, attempted_at);
+CREATE INDEX failed_login_attempts_ip_address_idx ON failed_login_attempts (ip_address, attempted_at);
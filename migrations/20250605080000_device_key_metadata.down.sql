@@ -0,0 +1,6 @@
+DROP INDEX IF EXISTS device_keys_user_id_idx;
+
+ALTER TABLE device_keys
+    DROP COLUMN IF EXISTS last_used_at,
+    DROP COLUMN IF EXISTS created_at,
+    DROP COLUMN IF EXISTS device_name;
@@ -0,0 +1,6 @@
+ALTER TABLE device_keys
+    ADD COLUMN device_name TEXT,
+    ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    ADD COLUMN last_used_at TIMESTAMPTZ;
+
+CREATE INDEX device_keys_user_id_idx ON device_keys (user_id);
@@ -0,0 +1,5 @@
+DROP TABLE IF EXISTS device_key_generations;
+
+ALTER TABLE device_keys
+    DROP COLUMN IF EXISTS generation,
+    DROP COLUMN IF EXISTS key_created_at;
@@ -0,0 +1,15 @@
+ALTER TABLE device_keys
+    ADD COLUMN generation INT NOT NULL DEFAULT 1,
+    ADD COLUMN key_created_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+UPDATE device_keys SET key_created_at = created_at;
+
+-- keys a device has rotated away from, the current one stays in device_keys
+CREATE TABLE device_key_generations (
+    device_id UUID REFERENCES device_keys(device_id) ON DELETE CASCADE NOT NULL,
+    generation INT NOT NULL,
+    public_key_pem TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    retired_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (device_id, generation)
+);
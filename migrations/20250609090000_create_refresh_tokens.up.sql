@@ -0,0 +1,13 @@
+CREATE TABLE refresh_tokens (
+    -- sha256 of the token, the token itself is only ever known to the client
+    token_hash TEXT PRIMARY KEY NOT NULL,
+    device_id UUID REFERENCES device_keys(device_id) ON DELETE CASCADE NOT NULL,
+    user_id UUID REFERENCES users(user_id) NOT NULL,
+    -- whether the login that started this chain passed a second factor
+    two_factor BOOLEAN NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ
+);
+
+CREATE INDEX refresh_tokens_device_id_idx ON refresh_tokens (device_id);
@@ -0,0 +1,8 @@
+-- roles an admin hands out, patients, doctors and admins are implied elsewhere
+CREATE TABLE user_roles (
+    user_id UUID REFERENCES users(user_id) NOT NULL,
+    role TEXT NOT NULL CHECK (role IN ('pharmacist', 'auditor')),
+    granted_by UUID REFERENCES admins(user_id) NOT NULL,
+    granted_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, role)
+);
@@ -0,0 +1,24 @@
+-- every read of a user's records by somebody else, visible to that user
+CREATE TABLE access_log (
+    log_id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    -- whose records were read
+    user_id UUID REFERENCES users(user_id) NOT NULL,
+    actor_id UUID REFERENCES users(user_id) NOT NULL,
+    -- the role and scope of the grant that allowed the read
+    role TEXT NOT NULL,
+    scope TEXT NOT NULL,
+    resource TEXT NOT NULL,
+    accessed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX access_log_user_id_idx ON access_log (user_id, accessed_at);
+
+CREATE FUNCTION access_log_append_only() RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'access_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER access_log_append_only
+    BEFORE UPDATE OR DELETE ON access_log
+    FOR EACH ROW EXECUTE FUNCTION access_log_append_only();
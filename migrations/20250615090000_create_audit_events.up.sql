@@ -0,0 +1,12 @@
+-- hash chain of every write, see `src/audit.rs`
+CREATE TABLE audit_events (
+    seq BIGINT PRIMARY KEY,
+    actor_id UUID REFERENCES users(user_id) NOT NULL,
+    -- the user whose data changed, if any
+    subject_id UUID REFERENCES users(user_id),
+    action TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    prev_hash TEXT NOT NULL,
+    hash TEXT UNIQUE NOT NULL
+);
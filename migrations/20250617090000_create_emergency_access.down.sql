@@ -0,0 +1,3 @@
+ALTER TABLE audit_events DROP COLUMN IF EXISTS flagged;
+DROP TABLE IF EXISTS notifications;
+DROP TABLE IF EXISTS emergency_access_grants;
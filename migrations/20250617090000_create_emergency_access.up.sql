@@ -0,0 +1,32 @@
+-- break-the-glass grants, read-only access to a patient's allergies and
+-- conditions for a doctor without a consultation, see
+-- `src/route/emergency.rs`
+CREATE TABLE emergency_access_grants (
+    grant_id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    doctor_id UUID REFERENCES doctor_profiles(doctor_id) NOT NULL,
+    user_id UUID REFERENCES users(user_id) NOT NULL,
+    justification TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    -- set once an admin has looked at the grant
+    reviewed_by UUID REFERENCES admins(user_id),
+    reviewed_at TIMESTAMPTZ,
+    review_note TEXT
+);
+
+CREATE INDEX emergency_access_grants_user_id_idx
+    ON emergency_access_grants (user_id, expires_at);
+
+CREATE TABLE notifications (
+    notification_id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID REFERENCES users(user_id) NOT NULL,
+    kind TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    read_at TIMESTAMPTZ
+);
+
+CREATE INDEX notifications_user_id_idx ON notifications (user_id, created_at);
+
+-- events an admin has to look at, covered by the hash when set
+ALTER TABLE audit_events ADD COLUMN flagged BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,8 @@
+-- the card each patient currently publishes, see `src/emergency_card.rs`
+CREATE TABLE emergency_cards (
+    user_id UUID PRIMARY KEY REFERENCES users(user_id),
+    -- `jti` of the token, tokens with any other value no longer render
+    card_id UUID NOT NULL,
+    token TEXT NOT NULL,
+    published_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
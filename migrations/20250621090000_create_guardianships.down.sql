@@ -0,0 +1,6 @@
+DROP TABLE IF EXISTS dependent_handovers;
+DROP TABLE IF EXISTS guardianships;
+-- fails while dependents without credentials are left
+ALTER TABLE users
+    ALTER COLUMN email SET NOT NULL,
+    ALTER COLUMN password_hash SET NOT NULL;
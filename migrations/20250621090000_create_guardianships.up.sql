@@ -0,0 +1,25 @@
+-- dependents are created by a guardian and have no credentials until they are
+-- handed over, see `src/route/dependent.rs`
+ALTER TABLE users
+    ALTER COLUMN email DROP NOT NULL,
+    ALTER COLUMN password_hash DROP NOT NULL;
+
+CREATE TABLE guardianships (
+    dependent_id UUID REFERENCES users(user_id) NOT NULL,
+    guardian_id UUID REFERENCES users(user_id) NOT NULL,
+    added_by UUID REFERENCES users(user_id) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (dependent_id, guardian_id),
+    CHECK (dependent_id <> guardian_id)
+);
+
+CREATE INDEX guardianships_guardian_id_idx ON guardianships (guardian_id);
+
+-- one-time codes a dependent sets their own credentials with
+CREATE TABLE dependent_handovers (
+    code_hash TEXT PRIMARY KEY,
+    dependent_id UUID REFERENCES users(user_id) NOT NULL,
+    created_by UUID REFERENCES users(user_id) NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ
+);
@@ -0,0 +1,17 @@
+-- read access a patient hands to a trusted contact, limited to a few
+-- resources and revocable, see `src/route/delegation.rs`
+CREATE TABLE delegations (
+    delegation_id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID REFERENCES users(user_id) NOT NULL,
+    delegate_id UUID REFERENCES users(user_id) NOT NULL,
+    -- `Resource` names, e.g. `prescription`
+    resources TEXT[] NOT NULL CHECK (cardinality(resources) > 0),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ,
+    CHECK (user_id <> delegate_id)
+);
+
+CREATE INDEX delegations_delegate_id_idx
+    ON delegations (delegate_id, user_id, expires_at);
+CREATE INDEX delegations_user_id_idx ON delegations (user_id);
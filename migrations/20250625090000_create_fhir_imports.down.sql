@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS external_encounters;
+DROP TABLE IF EXISTS imported_records;
+DROP TABLE IF EXISTS fhir_imports;
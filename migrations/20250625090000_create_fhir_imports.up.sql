@@ -0,0 +1,39 @@
+-- records brought in from other clinics, see `src/route/import.rs`
+CREATE TABLE fhir_imports (
+    import_id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID REFERENCES users(user_id) NOT NULL,
+    imported_by UUID REFERENCES users(user_id) NOT NULL,
+    -- `meta.source`, `identifier` or `id` of the Bundle, whichever it has
+    source TEXT,
+    imported_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX fhir_imports_user_id_idx ON fhir_imports (user_id, imported_at);
+
+-- which FHIR resource a row was created from, a measurement is made of two
+CREATE TABLE imported_records (
+    import_id UUID REFERENCES fhir_imports(import_id) NOT NULL,
+    source_id TEXT NOT NULL,
+    resource_type TEXT NOT NULL,
+    -- `allergy`, `medical_condition`, `measurement` or `external_encounter`
+    target TEXT NOT NULL,
+    row_id UUID NOT NULL,
+    PRIMARY KEY (import_id, source_id)
+);
+
+CREATE INDEX imported_records_row_id_idx ON imported_records (row_id);
+
+-- consultations held elsewhere, only ever written by an import
+CREATE TABLE external_encounters (
+    encounter_id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID REFERENCES users(user_id) NOT NULL,
+    import_id UUID REFERENCES fhir_imports(import_id) NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL,
+    ended_at TIMESTAMPTZ,
+    facility TEXT,
+    practitioner TEXT,
+    reason TEXT
+);
+
+CREATE INDEX external_encounters_user_id_idx
+    ON external_encounters (user_id, started_at);
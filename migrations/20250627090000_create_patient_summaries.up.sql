@@ -0,0 +1,14 @@
+-- every printable summary handed out, see `src/summary.rs`
+CREATE TABLE patient_summaries (
+    summary_id UUID PRIMARY KEY,
+    user_id UUID REFERENCES users(user_id) NOT NULL,
+    issued_by UUID REFERENCES users(user_id) NOT NULL,
+    -- hex SHA-256 of the PDF
+    sha256 TEXT NOT NULL,
+    -- the signed attestation of `sha256`
+    token TEXT NOT NULL,
+    issued_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX patient_summaries_user_id_idx
+    ON patient_summaries (user_id, issued_at);
@@ -0,0 +1,33 @@
+-- set once the account has been purged, the row stays for what refers to it
+ALTER TABLE users ADD COLUMN deleted_at TIMESTAMPTZ;
+
+-- accounts their owner asked to delete, purged once `purge_after` has passed,
+-- see `src/route/account.rs`
+CREATE TABLE account_deletions (
+    user_id UUID PRIMARY KEY REFERENCES users(user_id),
+    -- the guardian when asked for a dependent
+    requested_by UUID REFERENCES users(user_id) NOT NULL,
+    requested_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    purge_after TIMESTAMPTZ NOT NULL,
+    purged_at TIMESTAMPTZ
+);
+
+CREATE INDEX account_deletions_purge_after_idx
+    ON account_deletions (purge_after) WHERE purged_at IS NULL;
+
+-- copies of everything kept about a user, built in the background, see
+-- `src/data_export.rs`
+CREATE TABLE data_exports (
+    export_id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID REFERENCES users(user_id) NOT NULL,
+    requested_by UUID REFERENCES users(user_id) NOT NULL,
+    requested_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    status TEXT NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'ready', 'failed')),
+    completed_at TIMESTAMPTZ,
+    -- the ZIP while it can be downloaded
+    archive BYTEA,
+    expires_at TIMESTAMPTZ
+);
+
+CREATE INDEX data_exports_user_id_idx ON data_exports (user_id, requested_at);
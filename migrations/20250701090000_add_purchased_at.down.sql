@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS purchases_user_id_idx;
+ALTER TABLE purchases DROP COLUMN IF EXISTS purchased_at;
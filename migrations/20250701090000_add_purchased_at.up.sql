@@ -0,0 +1,4 @@
+-- lists are paged by time, purchases made before this are dated to it
+ALTER TABLE purchases ADD COLUMN purchased_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+CREATE INDEX purchases_user_id_idx ON purchases (user_id, purchased_at);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS consultations_amends_idx;
+ALTER TABLE consultations DROP COLUMN IF EXISTS amends;
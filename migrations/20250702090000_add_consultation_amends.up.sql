@@ -0,0 +1,6 @@
+-- consultations are never edited, a doctor who got one wrong records a new
+-- one that amends it, and each consultation can be amended only once
+ALTER TABLE consultations
+    ADD COLUMN amends UUID REFERENCES consultations(consultation_id);
+
+CREATE UNIQUE INDEX consultations_amends_idx ON consultations (amends);
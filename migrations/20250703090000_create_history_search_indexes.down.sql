@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS medical_conditions_condition_search_idx;
+DROP INDEX IF EXISTS prescriptions_search_idx;
+DROP INDEX IF EXISTS diagnoses_diagnosis_search_idx;
+DROP INDEX IF EXISTS consultations_symptoms_search_idx;
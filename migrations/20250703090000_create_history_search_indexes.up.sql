@@ -0,0 +1,15 @@
+-- full-text search over a patient's history, see `src/route/search.rs`.
+-- records mix Indonesian and English and drug names must match as written,
+-- so words are only lowercased, not stemmed
+CREATE INDEX consultations_symptoms_search_idx
+    ON consultations USING GIN (to_tsvector('simple', symptoms));
+
+CREATE INDEX diagnoses_diagnosis_search_idx
+    ON diagnoses USING GIN (to_tsvector('simple', diagnosis));
+
+CREATE INDEX prescriptions_search_idx
+    ON prescriptions
+    USING GIN (to_tsvector('simple', drug_name || ' ' || instruction));
+
+CREATE INDEX medical_conditions_condition_search_idx
+    ON medical_conditions USING GIN (to_tsvector('simple', condition));
@@ -0,0 +1,9 @@
+ALTER TABLE diagnoses
+    DROP COLUMN IF EXISTS icd10_code,
+    ALTER COLUMN severity TYPE TEXT
+        USING COALESCE(severity_text, severity::TEXT, ''),
+    ALTER COLUMN severity SET NOT NULL,
+    DROP COLUMN IF EXISTS severity_text;
+
+DROP TYPE IF EXISTS diagnosis_severity;
+DROP TABLE IF EXISTS icd10_codes;
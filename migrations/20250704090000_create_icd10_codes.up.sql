@@ -0,0 +1,106 @@
+-- ICD-10 codes diagnoses are coded with. Only common primary care codes are
+-- seeded, the full classification is loaded with `cargo run --bin load_icd10`
+CREATE TABLE icd10_codes (
+    code TEXT PRIMARY KEY CHECK (code ~ '^[A-Z][0-9]{2}(\.[0-9A-Z]{1,4})?$'),
+    title TEXT NOT NULL
+);
+
+-- autocomplete matches codes by prefix
+CREATE INDEX icd10_codes_code_prefix_idx ON icd10_codes (code text_pattern_ops);
+
+INSERT INTO icd10_codes (code, title)
+VALUES
+    ('A01.0', 'Typhoid fever'),
+    ('A09', 'Other gastroenteritis and colitis of infectious and unspecified origin'),
+    ('A15', 'Respiratory tuberculosis, bacteriologically and histologically confirmed'),
+    ('A16', 'Respiratory tuberculosis, not confirmed bacteriologically or histologically'),
+    ('A90', 'Dengue fever [classical dengue]'),
+    ('A91', 'Dengue haemorrhagic fever'),
+    ('B01', 'Varicella [chickenpox]'),
+    ('B05', 'Measles'),
+    ('B34.9', 'Viral infection, unspecified'),
+    ('B54', 'Unspecified malaria'),
+    ('D50', 'Iron deficiency anaemia'),
+    ('D64.9', 'Anaemia, unspecified'),
+    ('E03.9', 'Hypothyroidism, unspecified'),
+    ('E05', 'Thyrotoxicosis [hyperthyroidism]'),
+    ('E10', 'Type 1 diabetes mellitus'),
+    ('E11', 'Type 2 diabetes mellitus'),
+    ('E66', 'Obesity'),
+    ('E78.0', 'Pure hypercholesterolaemia'),
+    ('E78.5', 'Hyperlipidaemia, unspecified'),
+    ('E86', 'Volume depletion'),
+    ('F32', 'Depressive episode'),
+    ('F41.1', 'Generalized anxiety disorder'),
+    ('F41.9', 'Anxiety disorder, unspecified'),
+    ('G43', 'Migraine'),
+    ('G44.2', 'Tension-type headache'),
+    ('G47.0', 'Disorders of initiating and maintaining sleep [insomnias]'),
+    ('H10', 'Conjunctivitis'),
+    ('H66', 'Suppurative and unspecified otitis media'),
+    ('I10', 'Essential (primary) hypertension'),
+    ('I20', 'Angina pectoris'),
+    ('I21', 'Acute myocardial infarction'),
+    ('I25', 'Chronic ischaemic heart disease'),
+    ('I48', 'Atrial fibrillation and flutter'),
+    ('I50', 'Heart failure'),
+    ('I63', 'Cerebral infarction'),
+    ('I64', 'Stroke, not specified as haemorrhage or infarction'),
+    ('J00', 'Acute nasopharyngitis [common cold]'),
+    ('J01', 'Acute sinusitis'),
+    ('J02', 'Acute pharyngitis'),
+    ('J03', 'Acute tonsillitis'),
+    ('J06.9', 'Acute upper respiratory infection, unspecified'),
+    ('J11', 'Influenza, virus not identified'),
+    ('J18', 'Pneumonia, organism unspecified'),
+    ('J20', 'Acute bronchitis'),
+    ('J30', 'Vasomotor and allergic rhinitis'),
+    ('J44', 'Other chronic obstructive pulmonary disease'),
+    ('J45', 'Asthma'),
+    ('K21', 'Gastro-oesophageal reflux disease'),
+    ('K25', 'Gastric ulcer'),
+    ('K29', 'Gastritis and duodenitis'),
+    ('K35', 'Acute appendicitis'),
+    ('K52.9', 'Noninfective gastroenteritis and colitis, unspecified'),
+    ('K59.0', 'Constipation'),
+    ('K80', 'Cholelithiasis'),
+    ('L20', 'Atopic dermatitis'),
+    ('L30.9', 'Dermatitis, unspecified'),
+    ('L50', 'Urticaria'),
+    ('M10', 'Gout'),
+    ('M17', 'Gonarthrosis [arthrosis of knee]'),
+    ('M54.5', 'Low back pain'),
+    ('M79.1', 'Myalgia'),
+    ('N18', 'Chronic kidney disease'),
+    ('N39.0', 'Urinary tract infection, site not specified'),
+    ('R05', 'Cough'),
+    ('R10.4', 'Other and unspecified abdominal pain'),
+    ('R11', 'Nausea and vomiting'),
+    ('R50.9', 'Fever, unspecified'),
+    ('R51', 'Headache'),
+    ('T78.4', 'Allergy, unspecified'),
+    ('U07.1', 'COVID-19, virus identified'),
+    ('U07.2', 'COVID-19, virus not identified'),
+    ('Z00.0', 'General medical examination');
+
+CREATE TYPE diagnosis_severity AS ENUM ('MILD', 'MODERATE', 'SEVERE');
+
+-- severities used to be free text, what can't be read as one of the above is
+-- left empty rather than guessed. The text as it was written is kept aside so
+-- nothing recorded is lost
+ALTER TABLE diagnoses ADD COLUMN severity_text TEXT;
+UPDATE diagnoses SET severity_text = severity;
+
+ALTER TABLE diagnoses
+    ALTER COLUMN severity DROP NOT NULL,
+    ALTER COLUMN severity TYPE diagnosis_severity USING (
+        CASE lower(trim(severity))
+            WHEN 'mild' THEN 'MILD'
+            WHEN 'ringan' THEN 'MILD'
+            WHEN 'moderate' THEN 'MODERATE'
+            WHEN 'sedang' THEN 'MODERATE'
+            WHEN 'severe' THEN 'SEVERE'
+            WHEN 'berat' THEN 'SEVERE'
+        END
+    )::diagnosis_severity,
+    ADD COLUMN icd10_code TEXT REFERENCES icd10_codes(code);
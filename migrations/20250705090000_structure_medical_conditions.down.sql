@@ -0,0 +1,16 @@
+DROP TABLE IF EXISTS condition_status_history;
+
+ALTER TABLE medical_conditions
+    DROP CONSTRAINT IF EXISTS medical_conditions_confirmed_check,
+    DROP CONSTRAINT IF EXISTS medical_conditions_source_check,
+    DROP COLUMN IF EXISTS created_at,
+    DROP COLUMN IF EXISTS confirmed_at,
+    DROP COLUMN IF EXISTS confirmed_by,
+    DROP COLUMN IF EXISTS consultation_id,
+    DROP COLUMN IF EXISTS source,
+    DROP COLUMN IF EXISTS icd10_code,
+    DROP COLUMN IF EXISTS status,
+    DROP COLUMN IF EXISTS onset;
+
+DROP TYPE IF EXISTS condition_source;
+DROP TYPE IF EXISTS condition_status;
@@ -0,0 +1,39 @@
+CREATE TYPE condition_status AS ENUM ('ACTIVE', 'RESOLVED', 'IN_REMISSION');
+CREATE TYPE condition_source AS ENUM ('SELF_REPORTED', 'DIAGNOSED');
+
+-- conditions listed so far were typed in by the patients themselves
+ALTER TABLE medical_conditions
+    ADD COLUMN onset DATE,
+    ADD COLUMN status condition_status NOT NULL DEFAULT 'ACTIVE',
+    ADD COLUMN icd10_code TEXT REFERENCES icd10_codes(code),
+    ADD COLUMN source condition_source NOT NULL DEFAULT 'SELF_REPORTED',
+    -- where a diagnosed condition was diagnosed
+    ADD COLUMN consultation_id UUID REFERENCES consultations(consultation_id),
+    -- the doctor who confirmed a self-reported condition
+    ADD COLUMN confirmed_by UUID REFERENCES doctor_profiles(doctor_id),
+    ADD COLUMN confirmed_at TIMESTAMPTZ,
+    ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    ADD CONSTRAINT medical_conditions_source_check CHECK (
+        (source = 'DIAGNOSED') = (consultation_id IS NOT NULL)
+    ),
+    ADD CONSTRAINT medical_conditions_confirmed_check CHECK (
+        (confirmed_by IS NULL) = (confirmed_at IS NULL)
+    );
+
+CREATE TABLE condition_status_history (
+    transition_id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    condition_id UUID NOT NULL
+        REFERENCES medical_conditions(condition_id) ON DELETE CASCADE,
+    -- empty for the status a condition was recorded with
+    from_status condition_status,
+    to_status condition_status NOT NULL,
+    changed_by UUID NOT NULL REFERENCES users(user_id),
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX condition_status_history_condition_idx
+    ON condition_status_history (condition_id, changed_at);
+
+-- what is listed already was listed as active by its patient
+INSERT INTO condition_status_history (condition_id, to_status, changed_by)
+SELECT condition_id, status, user_id FROM medical_conditions;
@@ -0,0 +1,14 @@
+ALTER TABLE allergies
+    DROP CONSTRAINT IF EXISTS allergies_verification_check,
+    DROP CONSTRAINT IF EXISTS allergies_ingredient_check,
+    DROP COLUMN IF EXISTS verified_at,
+    DROP COLUMN IF EXISTS verified_by,
+    DROP COLUMN IF EXISTS verification,
+    DROP COLUMN IF EXISTS onset,
+    DROP COLUMN IF EXISTS reactions,
+    DROP COLUMN IF EXISTS medicine_ingredient_id,
+    DROP COLUMN IF EXISTS category;
+
+DROP TYPE IF EXISTS allergy_verification;
+DROP TYPE IF EXISTS allergy_reaction;
+DROP TYPE IF EXISTS allergy_category;
@@ -0,0 +1,39 @@
+CREATE TYPE allergy_category AS ENUM ('DRUG', 'FOOD', 'ENVIRONMENTAL');
+CREATE TYPE allergy_reaction AS ENUM (
+    'RASH',
+    'HIVES',
+    'ITCHING',
+    'ANGIOEDEMA',
+    'WHEEZING',
+    'SHORTNESS_OF_BREATH',
+    'NAUSEA',
+    'VOMITING',
+    'DIARRHEA',
+    'ANAPHYLAXIS',
+    'OTHER'
+);
+CREATE TYPE allergy_verification AS ENUM (
+    'SELF_REPORTED',
+    'CLINICIAN_CONFIRMED'
+);
+
+-- allergies listed so far were typed in by the patients themselves, without
+-- a category
+ALTER TABLE allergies
+    ADD COLUMN category allergy_category,
+    -- the ingredient of the medicine catalog a drug allergy is to
+    ADD COLUMN medicine_ingredient_id UUID
+        REFERENCES medicine_ingredients(medicine_ingredient_id),
+    ADD COLUMN reactions allergy_reaction[] NOT NULL DEFAULT '{}',
+    ADD COLUMN onset DATE,
+    ADD COLUMN verification allergy_verification NOT NULL
+        DEFAULT 'SELF_REPORTED',
+    ADD COLUMN verified_by UUID REFERENCES doctor_profiles(doctor_id),
+    ADD COLUMN verified_at TIMESTAMPTZ,
+    ADD CONSTRAINT allergies_ingredient_check CHECK (
+        medicine_ingredient_id IS NULL OR category = 'DRUG'
+    ),
+    ADD CONSTRAINT allergies_verification_check CHECK (
+        (verification = 'CLINICIAN_CONFIRMED') = (verified_by IS NOT NULL)
+        AND (verified_by IS NULL) = (verified_at IS NULL)
+    );
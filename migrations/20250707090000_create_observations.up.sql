@@ -0,0 +1,41 @@
+-- What can be observed, by LOINC code. Values outside of the range can't be
+-- right and are refused
+CREATE TABLE observation_types (
+    code TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    -- UCUM
+    unit TEXT NOT NULL,
+    category TEXT NOT NULL CHECK (category IN ('vital-signs', 'laboratory')),
+    min_value DOUBLE PRECISION NOT NULL,
+    max_value DOUBLE PRECISION NOT NULL,
+    CHECK (min_value < max_value)
+);
+
+INSERT INTO observation_types (code, name, unit, category, min_value, max_value)
+VALUES
+    ('8480-6', 'Systolic blood pressure', 'mm[Hg]', 'vital-signs', 40, 300),
+    ('8462-4', 'Diastolic blood pressure', 'mm[Hg]', 'vital-signs', 20, 200),
+    ('8867-4', 'Heart rate', '/min', 'vital-signs', 20, 300),
+    ('9279-1', 'Respiratory rate', '/min', 'vital-signs', 2, 80),
+    ('8310-5', 'Body temperature', 'Cel', 'vital-signs', 25, 45),
+    ('59408-5', 'Oxygen saturation by pulse oximetry', '%', 'vital-signs', 50, 100),
+    ('2339-0', 'Glucose in blood', 'mg/dL', 'laboratory', 10, 1500),
+    ('4548-4', 'Hemoglobin A1c', '%', 'laboratory', 2, 20);
+
+-- body height and weight stay in user_measurements
+CREATE TABLE observations (
+    observation_id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(user_id),
+    code TEXT NOT NULL REFERENCES observation_types(code),
+    value DOUBLE PRECISION NOT NULL,
+    observed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- the patient, their guardian, or the doctor of the consultation
+    recorded_by UUID NOT NULL REFERENCES users(user_id),
+    -- where a doctor took it
+    consultation_id UUID REFERENCES consultations(consultation_id)
+);
+
+CREATE INDEX observations_user_observed_idx
+    ON observations (user_id, observed_at, observation_id);
+CREATE INDEX observations_user_code_observed_idx
+    ON observations (user_id, code, observed_at);
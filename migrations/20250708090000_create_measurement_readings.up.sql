@@ -0,0 +1,17 @@
+-- One row per value a trend is drawn from, whichever table it is kept in
+CREATE VIEW measurement_readings AS
+SELECT user_id, 'weight' AS metric, 'kg' AS unit,
+    weight_in_kg::NUMERIC::DOUBLE PRECISION AS value, measured_at
+FROM user_measurements
+UNION ALL
+SELECT user_id, 'bmi', 'kg/m2',
+    weight_in_kg::NUMERIC::DOUBLE PRECISION
+        / (height_in_cm::NUMERIC::DOUBLE PRECISION / 100) ^ 2,
+    measured_at
+FROM user_measurements
+WHERE height_in_cm > 0
+UNION ALL
+SELECT user_id, CASE code WHEN '8480-6' THEN 'systolic' ELSE 'diastolic' END,
+    'mm[Hg]', value, observed_at
+FROM observations
+WHERE code IN ('8480-6', '8462-4');
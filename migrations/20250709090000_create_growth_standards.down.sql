@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS growth_standards;
+DROP TYPE IF EXISTS growth_indicator;
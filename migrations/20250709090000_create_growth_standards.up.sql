@@ -0,0 +1,19 @@
+-- LMS parameters of the WHO child growth standards (birth to 5 years) and
+-- growth reference (5 to 19 years) by completed month of age. None are
+-- seeded, the published tables are loaded with
+-- `cargo run --bin load_growth_standards`
+CREATE TYPE growth_indicator AS ENUM (
+    'HEIGHT_FOR_AGE',
+    'WEIGHT_FOR_AGE',
+    'BMI_FOR_AGE'
+);
+
+CREATE TABLE growth_standards (
+    indicator growth_indicator NOT NULL,
+    sex CHAR NOT NULL CHECK (sex IN ('M', 'F')),
+    age_in_months INTEGER NOT NULL CHECK (age_in_months BETWEEN 0 AND 228),
+    l DOUBLE PRECISION NOT NULL,
+    m DOUBLE PRECISION NOT NULL CHECK (m > 0),
+    s DOUBLE PRECISION NOT NULL CHECK (s > 0),
+    PRIMARY KEY (indicator, sex, age_in_months)
+);
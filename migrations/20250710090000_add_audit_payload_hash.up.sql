@@ -0,0 +1,4 @@
+-- entries commit to the hash of their payload rather than the payload, so
+-- the payload can be scrubbed when the account it is about is purged, see
+-- `src/audit.rs`. Entries from before keep committing to the payload itself
+ALTER TABLE audit_events ADD COLUMN payload_hash TEXT;
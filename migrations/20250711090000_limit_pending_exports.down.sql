@@ -0,0 +1 @@
+DROP INDEX IF EXISTS data_exports_one_pending_idx;
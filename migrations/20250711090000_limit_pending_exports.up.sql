@@ -0,0 +1,3 @@
+-- building an export reads every table of the user, one at a time is enough
+CREATE UNIQUE INDEX data_exports_one_pending_idx
+    ON data_exports (user_id) WHERE status = 'pending';
@@ -0,0 +1,17 @@
+-- the latest entry of the audit chain, see `src/audit.rs`. Appending locks
+-- this one row instead of the whole table, and verifying the chain checks its
+-- end against it so entries deleted from the end don't go unnoticed
+CREATE TABLE audit_head (
+    only_row BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (only_row),
+    seq BIGINT NOT NULL,
+    hash TEXT NOT NULL
+);
+
+INSERT INTO audit_head (seq, hash)
+SELECT
+    COALESCE(MAX(seq), 0),
+    COALESCE(
+        (SELECT hash FROM audit_events ORDER BY seq DESC LIMIT 1),
+        REPEAT('0', 64)
+    )
+FROM audit_events;
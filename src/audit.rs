@@ -0,0 +1,413 @@
+//! Tamper-evident audit trail of every write.
+//!
+//! Each entry in `audit_events` commits to the hash of the entry before it,
+//! so rewriting, removing or reordering any past entry breaks the chain from
+//! that point on. The hash of an entry is the SHA-256 of the previous hash
+//! followed by the canonical JSON of the entry itself (see [`AuditEntry`]).
+//!
+//! The entry covers the SHA-256 of its payload rather than the payload, so
+//! purging an account can replace the payloads about it with a `null`
+//! tombstone (see [`scrub_payloads`]) and leave the chain intact. Any other
+//! change to a payload still shows, entries recorded before payload hashes
+//! cover the payload itself and can't be scrubbed.
+//!
+//! Writes are recorded in the same transaction as the change they describe.
+//! Events an admin has to review, such as emergency access, are recorded with
+//! [`record_flagged`].
+//!
+//! The sequence number and hash of the latest entry are also kept in the
+//! one-row `audit_head` table. Appending locks that row, so entries are
+//! chained one after the other while the table stays open to readers, and
+//! [`verify_chain`] checks that the chain ends where the head says, which
+//! catches entries deleted from the end. Somebody able to rewrite both tables
+//! can still cut the end off unnoticed, keeping a copy of the head elsewhere
+//! is left to operations.
+//!
+//! [`verify_chain`] walks the whole table and reports the first entry that
+//! does not match, it backs both `GET /audit-events/verify` and the
+//! `verify_audit` binary.
+
+use chrono::{DateTime, SubsecRound, Utc};
+use data_encoding::HEXLOWER;
+use futures_util::TryStreamExt;
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, Pool, Postgres, Transaction, query, query_as};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::error::{APIResult, AppError};
+
+/// `prev_hash` of the very first entry
+pub const GENESIS_HASH: &str =
+    "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// What the hash of an entry covers
+#[derive(Serialize)]
+pub struct AuditEntry<'a> {
+    pub seq: i64,
+    pub actor_id: Uuid,
+    /// The user whose data changed, if any
+    pub subject_id: Option<Uuid>,
+    /// Dotted name of the change, e.g. `allergy.create`
+    pub action: &'a str,
+    /// Only hashed for entries from before `payload_hash`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<&'a Value>,
+    /// See [`payload_hash`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload_hash: Option<&'a str>,
+    pub created_at: DateTime<Utc>,
+    pub prev_hash: &'a str,
+    /// Left out when unset so entries from before flagging keep their hash
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub flagged: bool,
+}
+
+impl AuditEntry<'_> {
+    pub fn hash(&self) -> APIResult<String> {
+        let canonical =
+            serde_json_canonicalizer::to_string(self).map_err(|e| {
+                error!("Error while canonicalizing audit entry: {:?}", e);
+                AppError::InternalError
+            })?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(self.prev_hash.as_bytes());
+        hasher.update(canonical.as_bytes());
+
+        Ok(HEXLOWER.encode(&hasher.finalize()))
+    }
+}
+
+/// SHA-256 of the canonical JSON of `payload`
+pub fn payload_hash(payload: &Value) -> APIResult<String> {
+    let canonical =
+        serde_json_canonicalizer::to_string(payload).map_err(|e| {
+            error!("Error while canonicalizing audit payload: {:?}", e);
+            AppError::InternalError
+        })?;
+
+    Ok(HEXLOWER.encode(&Sha256::digest(canonical.as_bytes())))
+}
+
+#[derive(Serialize)]
+pub struct AuditEvent {
+    pub seq: i64,
+    pub actor_id: Uuid,
+    pub subject_id: Option<Uuid>,
+    pub action: String,
+    /// `null` once scrubbed
+    pub payload: Value,
+    pub payload_hash: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub prev_hash: String,
+    pub hash: String,
+    pub flagged: bool,
+}
+
+impl AuditEvent {
+    /// Whether the payload is the one that was hashed, or its tombstone
+    fn payload_intact(&self) -> APIResult<bool> {
+        match &self.payload_hash {
+            Some(_) if self.payload.is_null() => Ok(true),
+            Some(hash) => Ok(payload_hash(&self.payload)? == *hash),
+            None => Ok(true),
+        }
+    }
+
+    fn entry(&self) -> AuditEntry<'_> {
+        AuditEntry {
+            seq: self.seq,
+            actor_id: self.actor_id,
+            subject_id: self.subject_id,
+            action: &self.action,
+            payload: match self.payload_hash {
+                Some(_) => None,
+                None => Some(&self.payload),
+            },
+            payload_hash: self.payload_hash.as_deref(),
+            created_at: self.created_at,
+            prev_hash: &self.prev_hash,
+            flagged: self.flagged,
+        }
+    }
+}
+
+/// Appends an entry to the chain.
+///
+/// Meant to be called inside the transaction of the write it describes, the
+/// head of the chain is locked until that transaction ends so entries can't
+/// race for the same predecessor.
+pub async fn record(
+    conn: &mut PgConnection,
+    actor_id: Uuid,
+    subject_id: Option<Uuid>,
+    action: &str,
+    payload: Value,
+) -> APIResult<()> {
+    append(conn, actor_id, subject_id, action, payload, false).await
+}
+
+/// Like [`record`], but marks the entry for review by an admin
+pub async fn record_flagged(
+    conn: &mut PgConnection,
+    actor_id: Uuid,
+    subject_id: Option<Uuid>,
+    action: &str,
+    payload: Value,
+) -> APIResult<()> {
+    append(conn, actor_id, subject_id, action, payload, true).await
+}
+
+async fn append(
+    conn: &mut PgConnection,
+    actor_id: Uuid,
+    subject_id: Option<Uuid>,
+    action: &str,
+    payload: Value,
+    flagged: bool,
+) -> APIResult<()> {
+    let head = query!("SELECT seq, hash FROM audit_head FOR UPDATE")
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| {
+            error!("Error while locking the audit head: {:?}", e);
+            AppError::InternalError
+        })?;
+    let seq = head.seq + 1;
+    let prev_hash = head.hash;
+
+    // postgres only keeps microseconds, hash what will be read back
+    let created_at = Utc::now().trunc_subsecs(6);
+    let payload_hash = payload_hash(&payload)?;
+    let hash = AuditEntry {
+        seq,
+        actor_id,
+        subject_id,
+        action,
+        payload: None,
+        payload_hash: Some(&payload_hash),
+        created_at,
+        prev_hash: &prev_hash,
+        flagged,
+    }
+    .hash()?;
+
+    query!(
+        "INSERT INTO audit_events (seq, actor_id, subject_id, action, \
+         payload, payload_hash, created_at, prev_hash, hash, flagged) VALUES \
+         ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+        seq,
+        actor_id,
+        subject_id,
+        action,
+        payload,
+        payload_hash,
+        created_at,
+        prev_hash,
+        hash,
+        flagged
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| {
+        error!("Error while recording audit event {}: {:?}", action, e);
+        AppError::InternalError
+    })?;
+
+    query!("UPDATE audit_head SET seq = $1, hash = $2", seq, hash)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| {
+            error!("Error while moving the audit head to {}: {:?}", seq, e);
+            AppError::InternalError
+        })?;
+
+    Ok(())
+}
+
+/// Starts the transaction a write and its audit event share
+pub async fn begin(
+    db_pool: &Pool<Postgres>,
+) -> APIResult<Transaction<'static, Postgres>> {
+    db_pool.begin().await.map_err(|e| {
+        error!("Error occured while starting a transaction: {:?}", e);
+        AppError::InternalError
+    })
+}
+
+pub async fn commit(tx: Transaction<'_, Postgres>) -> APIResult<()> {
+    tx.commit().await.map_err(|e| {
+        error!("Error occured while committing transaction: {:?}", e);
+        AppError::InternalError
+    })
+}
+
+/// Replaces the payloads of every entry about `subject_id` with a `null`
+/// tombstone, except those from before payload hashes
+pub async fn scrub_payloads(
+    conn: &mut PgConnection,
+    subject_id: Uuid,
+) -> APIResult<()> {
+    query!(
+        "UPDATE audit_events SET payload = 'null' WHERE subject_id = $1 AND \
+         payload_hash IS NOT NULL",
+        subject_id
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| {
+        error!(
+            "Error while scrubbing audit events of {}: {:?}",
+            subject_id, e
+        );
+        AppError::InternalError
+    })?;
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChainReport {
+    pub valid: bool,
+    /// Number of entries checked
+    pub events: i64,
+    /// First entry that does not follow from the ones before it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_invalid_seq: Option<i64>,
+}
+
+/// Recomputes every hash of the chain, up to the head it had when called.
+///
+/// Entries are streamed rather than loaded at once, the chain only grows.
+pub async fn verify_chain(db_pool: &Pool<Postgres>) -> APIResult<ChainReport> {
+    let head = query!("SELECT seq, hash FROM audit_head")
+        .fetch_one(db_pool)
+        .await
+        .map_err(|e| {
+            error!("Error while fetching the audit head: {:?}", e);
+            AppError::InternalError
+        })?;
+
+    // entries appended meanwhile are left for the next check
+    let mut events = query_as!(
+        AuditEvent,
+        "SELECT seq, actor_id, subject_id, action, payload, payload_hash, \
+         created_at, prev_hash, hash, flagged FROM audit_events WHERE seq <= \
+         $1 ORDER BY seq",
+        head.seq
+    )
+    .fetch(db_pool);
+
+    let mut count = 0;
+    let mut last_seq = 0;
+    let mut expected_prev_hash = GENESIS_HASH.to_string();
+    let mut first_invalid_seq = None;
+
+    while let Some(event) = events.try_next().await.map_err(|e| {
+        error!("Error while fetching audit events: {:?}", e);
+        AppError::InternalError
+    })? {
+        count += 1;
+        if first_invalid_seq.is_some() {
+            continue;
+        }
+
+        let intact = event.seq == last_seq + 1
+            && event.prev_hash == expected_prev_hash
+            && event.entry().hash()? == event.hash
+            && event.payload_intact()?;
+
+        if !intact {
+            first_invalid_seq = Some(event.seq);
+        }
+
+        last_seq = event.seq;
+        expected_prev_hash = event.hash;
+    }
+
+    // whatever is missing at the end was deleted
+    if first_invalid_seq.is_none()
+        && (last_seq != head.seq || expected_prev_hash != head.hash)
+    {
+        first_invalid_seq = Some(last_seq + 1);
+    }
+
+    Ok(ChainReport {
+        valid: first_invalid_seq.is_none(),
+        events: count,
+        first_invalid_seq,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_hash_commits_to_every_field() {
+        let payload = json!({ "allergen": "pollen" });
+        let entry = AuditEntry {
+            seq: 1,
+            actor_id: Uuid::nil(),
+            subject_id: None,
+            action: "allergy.create",
+            payload: Some(&payload),
+            payload_hash: None,
+            created_at: DateTime::UNIX_EPOCH,
+            prev_hash: GENESIS_HASH,
+            flagged: false,
+        };
+        let hash = entry.hash().ok().unwrap();
+
+        let other_payload = json!({ "allergen": "beans" });
+        let tampered = AuditEntry {
+            payload: Some(&other_payload),
+            ..entry
+        };
+        assert_ne!(tampered.hash().ok().unwrap(), hash);
+
+        let reordered = AuditEntry {
+            seq: 2,
+            payload: Some(&payload),
+            ..tampered
+        };
+        assert_ne!(reordered.hash().ok().unwrap(), hash);
+
+        let flagged = AuditEntry {
+            seq: 1,
+            flagged: true,
+            ..reordered
+        };
+        assert_ne!(flagged.hash().ok().unwrap(), hash);
+    }
+
+    #[test]
+    fn test_scrubbed_payload_keeps_the_hash() {
+        let payload = json!({ "name": "bob" });
+        let mut event = AuditEvent {
+            seq: 1,
+            actor_id: Uuid::nil(),
+            subject_id: Some(Uuid::nil()),
+            action: "user_detail.set".to_string(),
+            payload_hash: Some(payload_hash(&payload).ok().unwrap()),
+            payload,
+            created_at: DateTime::UNIX_EPOCH,
+            prev_hash: GENESIS_HASH.to_string(),
+            hash: String::new(),
+            flagged: false,
+        };
+        let hash = event.entry().hash().ok().unwrap();
+        assert!(event.payload_intact().ok().unwrap());
+
+        event.payload = json!({ "name": "eve" });
+        assert!(!event.payload_intact().ok().unwrap());
+
+        event.payload = Value::Null;
+        assert!(event.payload_intact().ok().unwrap());
+        assert_eq!(event.entry().hash().ok().unwrap(), hash);
+    }
+}
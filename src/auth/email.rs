@@ -3,27 +3,31 @@ use argon2::{
     password_hash::{SaltString, rand_core::OsRng},
 };
 use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
 use axum::{Json, extract::State};
-use base64::{Engine, engine::general_purpose};
-use ed25519_compact::{KeyPair, Seed};
 use serde::Deserialize;
 use serde_json::Value;
 use serde_json::json;
-use tracing::{debug, error, info};
-use uuid::Uuid;
+use sqlx::{query, query_scalar};
+use tracing::{debug, error, info, warn};
 
-use crate::AppState;
-use crate::auth::{
-    AuthError, AuthResponse, create_session_id, query_user, store_public_key,
+use crate::auth::throttle::{self, ClientIp, LoginFailure};
+use crate::auth::two_factor::{
+    TwoFactorChallengeResponse, create_two_factor_challenge, two_factor_enabled,
 };
-use crate::error::AppError;
+use crate::auth::{AuthError, AuthResponse, query_user, start_session};
+use crate::error::{AppError, DatabaseError};
+use crate::route::dependent::hash_handover_code;
 use crate::schema::User;
+use crate::{AppState, audit};
 
 // Login request payload
 #[derive(Debug, Deserialize)]
 pub struct LoginRequest {
     pub email: String,
     pub password: String,
+    /// Human readable name shown in `GET /me/devices`, e.g. "Pixel 8"
+    pub device_name: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -32,17 +36,72 @@ pub struct RegisterRequest {
     pub password: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct RegisterDependentRequest {
+    /// Handover code from one of the dependent's guardians
+    pub code: String,
+    pub email: String,
+    pub password: String,
+}
+
+/// Outcome of the password step of a login
+pub enum LoginResponse {
+    /// The account has no second factor, the session is ready to use
+    Authenticated(AuthResponse),
+    /// The client has to finish the login through `POST /login/two-factor`
+    TwoFactorRequired(TwoFactorChallengeResponse),
+}
+
+impl IntoResponse for LoginResponse {
+    fn into_response(self) -> Response {
+        match self {
+            LoginResponse::Authenticated(response) => {
+                (StatusCode::OK, Json(response)).into_response()
+            }
+            LoginResponse::TwoFactorRequired(challenge) => {
+                (StatusCode::ACCEPTED, Json(challenge)).into_response()
+            }
+        }
+    }
+}
+
 pub async fn login(
     State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
     Json(payload): Json<LoginRequest>,
-) -> Result<Json<AuthResponse>, AppError> {
-    // Find the user
+) -> Result<LoginResponse, AppError> {
     let email = payload.email;
     let password = payload.password;
-    let user: User = query_user(&email, &state.db_pool).await?;
+
+    // counted before the password is checked, so no number of concurrent
+    // tries gets past the limit, and taken back unless the attempt fails
+    let ip_attempt = throttle::reserve_ip(&state.login_throttle, ip)?;
+
+    // Find the user
+    let user: User = match query_user(&email, &state.db_pool).await {
+        Ok(user) => user,
+        Err(AppError::Auth(AuthError::UserNotFound)) => {
+            ip_attempt.fail();
+            throttle::record_failure(
+                &state.db_pool,
+                Some(&email),
+                None,
+                ip,
+                LoginFailure::UnknownUser,
+            )
+            .await;
+            return Err(AuthError::UserNotFound.into());
+        }
+        Err(e) => return Err(e),
+    };
+
+    let account_attempt =
+        throttle::reserve_account(&state.login_throttle, user.user_id)?;
 
     // verify user
-    let password_hash_str: String = user.password_hash;
+    let Some(password_hash_str) = user.password_hash else {
+        return Err(AuthError::WrongCredentials.into());
+    };
     let password_hash: PasswordHash =
         match PasswordHash::new(&password_hash_str) {
             Ok(h) => h,
@@ -57,50 +116,74 @@ pub async fn login(
         .verify_password(password.as_bytes(), &password_hash)
         .is_err()
     {
+        ip_attempt.fail();
+        account_attempt.fail();
+        throttle::record_failure(
+            &state.db_pool,
+            Some(&email),
+            Some(user.user_id),
+            ip,
+            LoginFailure::WrongPassword,
+        )
+        .await;
         return Err(AuthError::WrongCredentials.into());
     }
 
-    // create tokens
-    let user_id = user.user_id;
-    let session_id = create_session_id();
-    let device_id = Uuid::new_v4();
-    let key_pair = KeyPair::from_seed(Seed::generate());
+    if two_factor_enabled(user.user_id, &state.db_pool).await? {
+        let challenge = create_two_factor_challenge(
+            user.user_id,
+            payload.device_name,
+            &state,
+        );
+        info!("User {email} passed the password check, awaiting second factor");
 
-    let private_key = general_purpose::STANDARD.encode(key_pair.sk.to_vec());
+        return Ok(LoginResponse::TwoFactorRequired(challenge));
+    }
 
-    store_public_key(device_id, user_id, key_pair.pk, &state.db_pool).await?;
+    throttle::record_success(&state.login_throttle, user.user_id);
 
-    state
-        .recognized_session_id
-        .insert(session_id.clone(), user.user_id);
+    let response =
+        start_session(user.user_id, payload.device_name, false, &state).await?;
 
     info!("User {email} logged in");
 
-    // Return the tokens
-    Ok(Json(AuthResponse {
-        user_id,
-        session_id,
-        token_type: "Bearer".to_string(),
-        device_id,
-        private_key,
-    }))
+    Ok(LoginResponse::Authenticated(response))
 }
 
-pub async fn register(
-    State(state): State<AppState>,
-    Json(payload): Json<RegisterRequest>,
-) -> Result<(StatusCode, Json<Value>), AppError> {
-    let email = payload.email;
-    let password = payload.password;
+fn hash_password(password: &str) -> Result<String, AppError> {
     let salt = SaltString::generate(&mut OsRng);
     let argon2 = Argon2::default();
-    let password_hash = argon2
+
+    Ok(argon2
         .hash_password(password.as_bytes(), &salt)
         .map_err(|e| {
             error!("error occured while hasing password: {:?}", e);
             AppError::InternalError
         })?
-        .to_string();
+        .to_string())
+}
+
+fn email_conflict(e: sqlx::Error) -> AppError {
+    error!("error occured while registering email: {:?}", e);
+
+    match e {
+        sqlx::Error::Database(db_e) => {
+            if db_e.is_unique_violation() {
+                AuthError::EmailUsed.into()
+            } else {
+                AppError::InternalError
+            }
+        }
+        _ => AppError::InternalError,
+    }
+}
+
+pub async fn register(
+    State(state): State<AppState>,
+    Json(payload): Json<RegisterRequest>,
+) -> Result<(StatusCode, Json<Value>), AppError> {
+    let email = payload.email;
+    let password_hash = hash_password(&payload.password)?;
 
     sqlx::query!(
         "INSERT INTO users(email, password_hash) VALUES ($1, $2)",
@@ -109,20 +192,7 @@ pub async fn register(
     )
     .execute(&state.db_pool)
     .await
-    .map_err(|e| {
-        error!("error occured while registering email: {:?}", e);
-
-        match e {
-            sqlx::Error::Database(db_e) => {
-                if db_e.is_unique_violation() {
-                    AuthError::EmailUsed.into()
-                } else {
-                    AppError::InternalError
-                }
-            }
-            _ => AppError::InternalError,
-        }
-    })?;
+    .map_err(email_conflict)?;
 
     info!("Successfully registered email: {}", email);
 
@@ -131,3 +201,69 @@ pub async fn register(
         Json(json!({ "message": "registration successful" })),
     ))
 }
+
+/// Gives a dependent their own credentials, after which they log in like
+/// anybody else
+pub async fn register_dependent(
+    State(state): State<AppState>,
+    Json(payload): Json<RegisterDependentRequest>,
+) -> Result<(StatusCode, Json<Value>), AppError> {
+    let email = payload.email;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    let dependent_id = query_scalar!(
+        "UPDATE dependent_handovers SET used_at = NOW() WHERE code_hash = $1 \
+         AND used_at IS NULL AND expires_at > NOW() RETURNING dependent_id",
+        hash_handover_code(&payload.code)
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!("Error while redeeming handover code: {:?}", e);
+        AppError::InternalError
+    })?
+    .ok_or_else(|| {
+        warn!("Unknown, used or expired handover code");
+        AppError::from(DatabaseError::RowNotFound)
+    })?;
+
+    // only hashed for a valid code, guessing codes costs no Argon2 round
+    let password_hash = hash_password(&payload.password)?;
+
+    let query_res = query!(
+        "UPDATE users SET email = $1, password_hash = $2 WHERE user_id = $3 \
+         AND password_hash IS NULL",
+        email,
+        password_hash,
+        dependent_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(email_conflict)?;
+
+    if query_res.rows_affected() == 0 {
+        // handed over with another code in the meantime
+        return Err(DatabaseError::RowNotFound.into());
+    }
+
+    audit::record(
+        &mut tx,
+        dependent_id,
+        Some(dependent_id),
+        "dependent.handover",
+        json!({ "email": email }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    info!("Dependent {} registered as {}", dependent_id, email);
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "message": "registration successful",
+            "user_id": dependent_id,
+        })),
+    ))
+}
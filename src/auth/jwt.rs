@@ -0,0 +1,469 @@
+//! Optional stateless authentication with signed access tokens.
+//!
+//! When enabled, logins hand out a short-lived EdDSA-signed access token
+//! carrying everything the extractors need (user, device, roles and
+//! `doctor_id`), so authorizing a request touches neither the session cache
+//! nor the database. The accompanying refresh token is opaque, stored hashed
+//! and bound to the device it was issued to; it stops working as soon as the
+//! device is revoked, and every use replaces it with a new one.
+//!
+//! Signing keys only live in memory. A rotation keeps the retired key around
+//! for [`ACCESS_TOKEN_TTL`] so tokens it signed stay valid until they expire,
+//! and `GET /.well-known/jwks.json` publishes every key that may still be in
+//! use. After a restart clients simply refresh their access token.
+
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use axum::{Json, extract::State};
+use chrono::{DateTime, Utc};
+use data_encoding::{BASE64URL_NOPAD, HEXLOWER};
+use ed25519_compact::{KeyPair, PublicKey};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey, Header, Validation, decode,
+    decode_header, encode,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet,
+        KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+    },
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres, query, query_scalar};
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    auth::{AuthError, create_session_id},
+    error::{APIResult, AppError},
+    policy::{Role, RoleGrants, load_roles},
+};
+
+// 10m
+pub const ACCESS_TOKEN_TTL: Duration = Duration::from_secs(10 * 60);
+// 30d, same as a session
+pub const REFRESH_TOKEN_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Authentication method reference for a password
+pub const AMR_PASSWORD: &str = "pwd";
+/// Authentication method reference for a TOTP or recovery code
+pub const AMR_OTP: &str = "otp";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccessClaims {
+    /// The user
+    pub sub: Uuid,
+    pub device_id: Uuid,
+    pub roles: Vec<Role>,
+    /// Set whenever the user has a doctor profile, approved or not
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub doctor_id: Option<Uuid>,
+    /// How the user authenticated, see [`AMR_PASSWORD`] and [`AMR_OTP`]
+    pub amr: Vec<String>,
+    pub jti: String,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+impl AccessClaims {
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role)
+    }
+
+    /// Whether a second factor was given at login
+    pub fn two_factor(&self) -> bool {
+        self.amr.iter().any(|method| method == AMR_OTP)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// Lifetime of `access_token` in seconds
+    pub expires_in: u64,
+}
+
+struct SigningKey {
+    kid: String,
+    key_pair: KeyPair,
+}
+
+struct RetiredKey {
+    kid: String,
+    public_key: PublicKey,
+    retired_at: DateTime<Utc>,
+}
+
+impl RetiredKey {
+    /// Whether a token signed by this key may not have expired yet
+    fn in_use(&self, now: DateTime<Utc>) -> bool {
+        now < self.retired_at + ACCESS_TOKEN_TTL
+    }
+}
+
+struct KeyRing {
+    current: SigningKey,
+    retired: Vec<RetiredKey>,
+}
+
+impl KeyRing {
+    /// Drops retired keys that can no longer have signed an unexpired token
+    fn prune(&mut self, now: DateTime<Utc>) {
+        self.retired.retain(|key| key.in_use(now));
+    }
+}
+
+/// The keys access tokens are signed and verified with.
+///
+/// Cheap to clone, every clone shares the same key ring.
+#[derive(Clone)]
+pub struct JwtKeys(Arc<RwLock<KeyRing>>);
+
+fn generate_signing_key() -> SigningKey {
+    let key_pair = KeyPair::generate();
+    let digest = Sha256::digest(key_pair.pk.as_ref());
+
+    SigningKey {
+        kid: HEXLOWER.encode(&digest[..8]),
+        key_pair,
+    }
+}
+
+pub(crate) fn jwk(kid: &str, public_key: &PublicKey) -> Jwk {
+    Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(KeyAlgorithm::EdDSA),
+            key_id: Some(kid.to_string()),
+            ..Default::default()
+        },
+        algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+            key_type: OctetKeyPairType::OctetKeyPair,
+            curve: EllipticCurve::Ed25519,
+            x: BASE64URL_NOPAD.encode(public_key.as_ref()),
+        }),
+    }
+}
+
+impl JwtKeys {
+    pub fn generate() -> Self {
+        JwtKeys(Arc::new(RwLock::new(KeyRing {
+            current: generate_signing_key(),
+            retired: Vec::new(),
+        })))
+    }
+
+    /// Starts signing with a new key and returns its `kid`
+    pub fn rotate(&self) -> String {
+        let now = Utc::now();
+        let mut ring = self.0.write().expect("JWT key ring poisoned");
+        let previous =
+            std::mem::replace(&mut ring.current, generate_signing_key());
+
+        ring.retired.push(RetiredKey {
+            kid: previous.kid,
+            public_key: previous.key_pair.pk,
+            retired_at: now,
+        });
+        ring.prune(now);
+
+        ring.current.kid.clone()
+    }
+
+    pub fn sign(&self, claims: &AccessClaims) -> Result<String, AppError> {
+        let ring = self.0.read().expect("JWT key ring poisoned");
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(ring.current.kid.clone());
+
+        let encoding_key =
+            EncodingKey::from_ed_der(&ring.current.key_pair.sk.to_der());
+
+        encode(&header, claims, &encoding_key).map_err(|e| {
+            error!("Error while signing access token: {:?}", e);
+            AppError::InternalError
+        })
+    }
+
+    pub fn verify(&self, token: &str) -> Result<AccessClaims, AuthError> {
+        let kid = decode_header(token)
+            .ok()
+            .and_then(|header| header.kid)
+            .ok_or(AuthError::InvalidToken)?;
+
+        let public_key = {
+            let now = Utc::now();
+            let ring = self.0.read().expect("JWT key ring poisoned");
+
+            if ring.current.kid == kid {
+                ring.current.key_pair.pk
+            } else {
+                ring.retired
+                    .iter()
+                    .find(|key| key.kid == kid && key.in_use(now))
+                    .map(|key| key.public_key)
+                    .ok_or(AuthError::InvalidToken)?
+            }
+        };
+
+        let validation = Validation::new(Algorithm::EdDSA);
+        decode::<AccessClaims>(
+            token,
+            &DecodingKey::from_ed_der(public_key.as_ref()),
+            &validation,
+        )
+        .map(|data| data.claims)
+        .map_err(|e| {
+            warn!("Rejected access token: {:?}", e);
+            AuthError::InvalidToken
+        })
+    }
+
+    /// Every public key that may have signed a token which is still valid
+    pub fn jwks(&self) -> JwkSet {
+        let now = Utc::now();
+        let ring = self.0.read().expect("JWT key ring poisoned");
+
+        let current = jwk(&ring.current.kid, &ring.current.key_pair.pk);
+        let retired = ring
+            .retired
+            .iter()
+            .filter(|key| key.in_use(now))
+            .map(|key| jwk(&key.kid, &key.public_key));
+
+        JwkSet {
+            keys: std::iter::once(current).chain(retired).collect(),
+        }
+    }
+}
+
+fn hash_refresh_token(token: &str) -> String {
+    HEXLOWER.encode(&Sha256::digest(token.as_bytes()))
+}
+
+async fn build_claims(
+    user_id: Uuid,
+    device_id: Uuid,
+    two_factor: bool,
+    db_pool: &Pool<Postgres>,
+) -> Result<AccessClaims, AppError> {
+    let RoleGrants { roles, doctor_id } = load_roles(user_id, db_pool).await?;
+
+    let mut amr = vec![AMR_PASSWORD.to_string()];
+    if two_factor {
+        amr.push(AMR_OTP.to_string());
+    }
+
+    let now = Utc::now();
+
+    Ok(AccessClaims {
+        sub: user_id,
+        device_id,
+        roles,
+        doctor_id,
+        amr,
+        jti: Uuid::new_v4().to_string(),
+        iat: now.timestamp(),
+        exp: (now + ACCESS_TOKEN_TTL).timestamp(),
+    })
+}
+
+/// Signs a fresh access token and stores a new refresh token for the device
+pub async fn issue_tokens(
+    user_id: Uuid,
+    device_id: Uuid,
+    two_factor: bool,
+    keys: &JwtKeys,
+    db_pool: &Pool<Postgres>,
+) -> Result<TokenPair, AppError> {
+    let claims = build_claims(user_id, device_id, two_factor, db_pool).await?;
+    let access_token = keys.sign(&claims)?;
+    let refresh_token = create_session_id();
+
+    query!(
+        "INSERT INTO refresh_tokens (token_hash, device_id, user_id, \
+         two_factor, expires_at) VALUES ($1, $2, $3, $4, $5)",
+        hash_refresh_token(&refresh_token),
+        device_id,
+        user_id,
+        two_factor,
+        Utc::now() + REFRESH_TOKEN_TTL
+    )
+    .execute(db_pool)
+    .await
+    .map_err(|e| {
+        error!(
+            "Error while storing refresh token for {}: {:?}",
+            device_id, e
+        );
+        AppError::InternalError
+    })?;
+
+    Ok(TokenPair {
+        access_token,
+        refresh_token,
+        expires_in: ACCESS_TOKEN_TTL.as_secs(),
+    })
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+    pub device_id: Uuid,
+}
+
+/// Trades a refresh token for a new token pair.
+///
+/// A refresh token can only be used once. Presenting one that was already
+/// used means it leaked, so every outstanding refresh token of the device is
+/// invalidated as well.
+pub async fn refresh_tokens(
+    State(state): State<AppState>,
+    Json(RefreshRequest {
+        refresh_token,
+        device_id,
+    }): Json<RefreshRequest>,
+) -> APIResult<Json<TokenPair>> {
+    let keys = state.jwt.as_ref().ok_or(AuthError::TokensDisabled)?;
+    let token_hash = hash_refresh_token(&refresh_token);
+
+    let used = query!(
+        "UPDATE refresh_tokens r SET used_at = NOW()
+         FROM device_keys d
+         WHERE r.token_hash = $1 AND r.device_id = $2
+             AND r.used_at IS NULL AND r.expires_at > NOW()
+             AND d.device_id = r.device_id AND d.revoked_at IS NULL
+         RETURNING r.user_id, r.two_factor",
+        token_hash,
+        device_id
+    )
+    .fetch_optional(&state.db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while using refresh token of {}: {:?}", device_id, e);
+        AppError::InternalError
+    })?;
+
+    let Some(used) = used else {
+        let reused = query_scalar!(
+            "SELECT used_at IS NOT NULL FROM refresh_tokens
+             WHERE token_hash = $1 AND device_id = $2",
+            token_hash,
+            device_id
+        )
+        .fetch_optional(&state.db_pool)
+        .await
+        .map_err(|e| {
+            error!("Error while checking refresh token reuse: {:?}", e);
+            AppError::InternalError
+        })?
+        .flatten()
+        .unwrap_or(false);
+
+        if reused {
+            warn!("Refresh token of {device_id} was reused, invalidating all");
+
+            query!(
+                "UPDATE refresh_tokens SET used_at = NOW()
+                 WHERE device_id = $1 AND used_at IS NULL",
+                device_id
+            )
+            .execute(&state.db_pool)
+            .await
+            .map_err(|e| {
+                error!(
+                    "Error while invalidating refresh tokens of {}: {:?}",
+                    device_id, e
+                );
+                AppError::InternalError
+            })?;
+        }
+
+        return Err(AuthError::InvalidToken.into());
+    };
+
+    let tokens = issue_tokens(
+        used.user_id,
+        device_id,
+        used.two_factor,
+        keys,
+        &state.db_pool,
+    )
+    .await?;
+
+    Ok(Json(tokens))
+}
+
+pub async fn get_jwks(
+    State(state): State<AppState>,
+) -> APIResult<Json<JwkSet>> {
+    let keys = state.jwt.as_ref().ok_or(AuthError::TokensDisabled)?;
+
+    Ok(Json(keys.jwks()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn claims(exp: DateTime<Utc>) -> AccessClaims {
+        AccessClaims {
+            sub: Uuid::nil(),
+            device_id: Uuid::nil(),
+            roles: vec![Role::Patient, Role::Doctor],
+            doctor_id: Some(Uuid::max()),
+            amr: vec![AMR_PASSWORD.to_string(), AMR_OTP.to_string()],
+            jti: "jti".to_string(),
+            iat: Utc::now().timestamp(),
+            exp: exp.timestamp(),
+        }
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let keys = JwtKeys::generate();
+        let token = keys.sign(&claims(Utc::now() + ACCESS_TOKEN_TTL)).ok();
+        let verified = keys.verify(&token.unwrap()).ok().unwrap();
+
+        assert_eq!(verified.doctor_id, Some(Uuid::max()));
+        assert!(verified.has_role(Role::Doctor));
+        assert!(!verified.has_role(Role::Admin));
+        assert!(verified.two_factor());
+    }
+
+    #[test]
+    fn test_verify_rejects_foreign_and_expired_tokens() {
+        let keys = JwtKeys::generate();
+        let other_keys = JwtKeys::generate();
+
+        let token = other_keys
+            .sign(&claims(Utc::now() + ACCESS_TOKEN_TTL))
+            .ok()
+            .unwrap();
+        assert!(keys.verify(&token).is_err());
+
+        let expired = keys
+            .sign(&claims(Utc::now() - chrono::Duration::hours(1)))
+            .ok()
+            .unwrap();
+        assert!(keys.verify(&expired).is_err());
+    }
+
+    #[test]
+    fn test_rotation_keeps_retired_key() {
+        let keys = JwtKeys::generate();
+        let token = keys
+            .sign(&claims(Utc::now() + ACCESS_TOKEN_TTL))
+            .ok()
+            .unwrap();
+
+        let kid = keys.rotate();
+
+        assert!(keys.verify(&token).is_ok());
+        assert_eq!(keys.jwks().keys.len(), 2);
+        assert_eq!(keys.jwks().keys[0].common.key_id, Some(kid));
+    }
+}
@@ -1,7 +1,7 @@
 use axum::{
     Json, RequestPartsExt,
-    extract::{FromRef, FromRequestParts, OptionalFromRequestParts, State},
-    http::{StatusCode, request::Parts},
+    extract::{FromRef, FromRequestParts, State},
+    http::{StatusCode, header, request::Parts},
     response::{IntoResponse, Response},
 };
 use axum_extra::{
@@ -9,22 +9,28 @@ use axum_extra::{
     headers::{self, authorization::Bearer},
     typed_header::TypedHeaderRejectionReason,
 };
-use chrono::{DateTime, Utc};
-use ed25519_compact::PublicKey;
+use base64::{Engine, engine::general_purpose};
+use ed25519_compact::{KeyPair, PublicKey, Seed};
 use moka::sync::Cache;
 use rand::{Rng, distr::Alphanumeric, rng};
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
-use sqlx::{Pool, Postgres, query, query_as};
+use sqlx::{PgConnection, Pool, Postgres, query};
+use std::time::Duration;
 use tracing::error;
 use uuid::Uuid;
 
 pub mod email;
+pub mod jwt;
+pub mod throttle;
+pub mod totp;
+pub mod two_factor;
 
 use crate::{
     AppState,
+    auth::jwt::{AccessClaims, JwtKeys, TokenPair, issue_tokens},
     error::{AppError, DatabaseError},
-    schema::{DeviceKey, DoctorProfile, User},
+    schema::{DeviceKey, User},
 };
 
 /// Session ID character length
@@ -34,11 +40,15 @@ pub const SESSION_ID_LEN: usize = 64;
 #[derive(Debug, Serialize)]
 pub struct AuthResponse {
     pub user_id: Uuid,
-    pub session_id: String,
+    /// Absent in JWT mode, where `access_token` is used instead
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
     pub token_type: String,
     pub device_id: Uuid,
     // base 64
     pub private_key: String,
+    #[serde(flatten)]
+    pub tokens: Option<TokenPair>,
 }
 
 pub enum AuthError {
@@ -62,6 +72,35 @@ pub enum AuthError {
     ///
     /// Returns `StatusCode::CONFLICT`
     EmailUsed,
+    /// Error for privileged accounts that have not enrolled a second factor
+    ///
+    /// Returns `StatusCode::FORBIDDEN`
+    TwoFactorRequired,
+    /// Error for a wrong, malformed or reused TOTP or recovery code
+    ///
+    /// Returns `StatusCode::UNAUTHORIZED`
+    InvalidTwoFactorCode,
+    /// Error for starting an enrollment while 2FA is already enabled
+    ///
+    /// Returns `StatusCode::CONFLICT`
+    TwoFactorAlreadyEnabled,
+    /// Error for confirming or using 2FA without enrolling first
+    ///
+    /// Returns `StatusCode::NOT_FOUND`
+    TwoFactorNotEnrolled,
+    /// Error for an unknown, expired or exhausted login challenge
+    ///
+    /// Returns `StatusCode::GONE`
+    ChallengeExpired,
+    /// Error for logging in too often after failed attempts, the client may
+    /// try again after `retry_after`
+    ///
+    /// Returns `StatusCode::TOO_MANY_REQUESTS` with a `Retry-After` header
+    TooManyAttempts { retry_after: Duration },
+    /// Error for using token endpoints while JWT mode is disabled
+    ///
+    /// Returns `StatusCode::NOT_FOUND`
+    TokensDisabled,
 }
 
 impl IntoResponse for AuthError {
@@ -82,6 +121,42 @@ impl IntoResponse for AuthError {
             AuthError::EmailUsed => {
                 (StatusCode::CONFLICT, "Email has been registered previously")
             }
+            AuthError::TwoFactorRequired => (
+                StatusCode::FORBIDDEN,
+                "Two-factor authentication is required for this account",
+            ),
+            AuthError::InvalidTwoFactorCode => {
+                (StatusCode::UNAUTHORIZED, "Invalid two-factor code")
+            }
+            AuthError::TwoFactorAlreadyEnabled => (
+                StatusCode::CONFLICT,
+                "Two-factor authentication is already enabled",
+            ),
+            AuthError::TwoFactorNotEnrolled => (
+                StatusCode::NOT_FOUND,
+                "Two-factor authentication has not been enrolled",
+            ),
+            AuthError::TokensDisabled => {
+                (StatusCode::NOT_FOUND, "Token authentication is disabled")
+            }
+            AuthError::ChallengeExpired => {
+                (StatusCode::GONE, "Login challenge has been used or expired")
+            }
+            AuthError::TooManyAttempts { retry_after } => {
+                // round up so clients never retry a moment too early
+                let retry_after = retry_after.as_secs()
+                    + u64::from(retry_after.subsec_nanos() > 0);
+
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, retry_after.to_string())],
+                    Json(serde_json::json!({
+                        "error": "Too many login attempts",
+                        "retry_after": retry_after,
+                    })),
+                )
+                    .into_response();
+            }
         };
 
         let body = Json(serde_json::json!({
@@ -92,16 +167,34 @@ impl IntoResponse for AuthError {
     }
 }
 
+/// What a `session_id` stands for
+#[derive(Debug, Clone, Copy)]
+pub struct Session {
+    pub user_id: Uuid,
+    pub device_id: Uuid,
+    /// Whether a second factor was given at login
+    pub two_factor: bool,
+}
+
 #[derive(Clone)]
 pub struct AuthUser {
     pub user_id: Uuid,
+    pub device_id: Uuid,
+    /// The session, or the `jti` of the access token in JWT mode
     pub session_id: String,
+    /// Whether a second factor was given at login
+    pub two_factor: bool,
+    /// Claims of the access token, if the request was made with one
+    pub claims: Option<AccessClaims>,
 }
 
 impl<S> FromRequestParts<S> for AuthUser
 where
     S: Send + Sync,
-    Cache<String, Uuid>: FromRef<S>,
+    Cache<String, Session>: FromRef<S>,
+    Cache<Uuid, ()>: FromRef<S>,
+    Option<JwtKeys>: FromRef<S>,
+    Pool<Postgres>: FromRef<S>,
 {
     type Rejection = AppError;
 
@@ -109,7 +202,7 @@ where
         parts: &mut Parts,
         state: &S,
     ) -> Result<Self, Self::Rejection> {
-        let recognized_session_id = Cache::<String, Uuid>::from_ref(state);
+        let recognized_session_id = Cache::<String, Session>::from_ref(state);
 
         // get session_id
         let authorization_header = parts
@@ -131,117 +224,68 @@ where
 
         let session_id = authorization_header.token();
 
-        match recognized_session_id.get(session_id) {
-            Some(user_id) => Ok(AuthUser {
-                user_id,
-                session_id: session_id.to_string(),
-            }),
-            None => Err(AuthError::InvalidToken.into()),
+        // session ids are alphanumeric, so anything with a dot is a JWT
+        if session_id.contains('.') {
+            let keys = Option::<JwtKeys>::from_ref(state)
+                .ok_or(AuthError::InvalidToken)?;
+            let claims = keys.verify(session_id)?;
+            touch_device(claims.device_id, state).await;
+
+            return Ok(AuthUser {
+                user_id: claims.sub,
+                device_id: claims.device_id,
+                session_id: claims.jti.clone(),
+                two_factor: claims.two_factor(),
+                claims: Some(claims),
+            });
         }
-    }
-}
 
-#[derive(Clone)]
-pub struct LicensedUser {
-    pub doctor_id: Uuid,
-    pub approved_at: Option<DateTime<Utc>>,
-}
-
-impl<S> OptionalFromRequestParts<S> for LicensedUser
-where
-    S: Send + Sync,
-    Pool<Postgres>: FromRef<S>,
-    Cache<String, Uuid>: FromRef<S>,
-{
-    type Rejection = AppError;
-
-    async fn from_request_parts(
-        parts: &mut Parts,
-        state: &S,
-    ) -> Result<Option<Self>, Self::Rejection> {
-        let db = Pool::<Postgres>::from_ref(state);
-
-        let auth = AuthUser::from_request_parts(parts, state).await?;
-        let doctor_user_id = auth.user_id;
-
-        let doctor_profile = match query_as!(
-            DoctorProfile,
-            "SELECT * FROM doctor_profiles WHERE user_id = $1",
-            doctor_user_id
-        )
-        .fetch_one(&db)
-        .await
-        {
-            Ok(profile) => profile,
-            Err(sqlx::Error::RowNotFound) => return Ok(None),
-            Err(e) => {
-                error!(
-                    "Error occured while fetching for doctor profile: {:?}",
-                    e
-                );
-                return Err(AppError::InternalError);
-            }
+        let Some(Session {
+            user_id,
+            device_id,
+            two_factor,
+        }) = recognized_session_id.get(session_id)
+        else {
+            return Err(AuthError::InvalidToken.into());
         };
-
-        if doctor_profile.approved_at.is_none() {
-            return Ok(None);
-        }
-
-        let doctor_id = doctor_profile.doctor_id;
-        let approved_at = doctor_profile.approved_at;
-
-        Ok(Some(LicensedUser {
-            doctor_id,
-            approved_at,
-        }))
+        touch_device(device_id, state).await;
+
+        Ok(AuthUser {
+            user_id,
+            device_id,
+            session_id: session_id.to_string(),
+            two_factor,
+            claims: None,
+        })
     }
 }
 
-impl<S> FromRequestParts<S> for LicensedUser
+/// Stamps `last_used_at` of the device a request came from, at most once
+/// every [`DEVICE_TOUCH_INTERVAL`](crate::DEVICE_TOUCH_INTERVAL).
+///
+/// Only bookkeeping, so a failed write is logged and the request goes on.
+async fn touch_device<S>(device_id: Uuid, state: &S)
 where
     S: Send + Sync,
+    Cache<Uuid, ()>: FromRef<S>,
     Pool<Postgres>: FromRef<S>,
-    Cache<String, Uuid>: FromRef<S>,
 {
-    type Rejection = AppError;
-
-    async fn from_request_parts(
-        parts: &mut Parts,
-        state: &S,
-    ) -> Result<Self, Self::Rejection> {
-        let db = Pool::<Postgres>::from_ref(state);
-
-        let auth = AuthUser::from_request_parts(parts, state).await?;
-        let doctor_user_id = auth.user_id;
-
-        let doctor_profile = match query_as!(
-            DoctorProfile,
-            "SELECT * FROM doctor_profiles WHERE user_id = $1",
-            doctor_user_id
-        )
-        .fetch_one(&db)
-        .await
-        {
-            Ok(profile) => profile,
-            Err(sqlx::Error::RowNotFound) => {
-                return Err(DatabaseError::RowNotFound.into());
-            }
-            Err(e) => {
-                error!(
-                    "Error occured while fetching for doctor profile: {:?}",
-                    e
-                );
-                return Err(AppError::InternalError);
-            }
-        };
-
-        let doctor_id = doctor_profile.doctor_id;
-        let approved_at = doctor_profile.approved_at;
+    // the first request of an interval inserts, the rest find it there
+    let entry = Cache::<Uuid, ()>::from_ref(state)
+        .entry(device_id)
+        .or_insert(());
+    if !entry.is_fresh() {
+        return;
+    }
 
-        Ok(LicensedUser {
-            doctor_id,
-            approved_at,
-        })
+    if let Err(e) = query!(
+        "UPDATE device_keys SET last_used_at = NOW() WHERE device_id = $1",
+        device_id
+    )
+    .execute(&Pool::<Postgres>::from_ref(state))
+    .await
+    {
+        error!("Error while touching device key {}: {:?}", device_id, e);
     }
 }
 
@@ -255,34 +299,103 @@ fn create_session_id() -> String {
     session_id
 }
 
+/// Registers a fresh device key pair for `user_id` and starts a session on it.
+///
+/// This is the last step of every successful login, regardless of how many
+/// factors were required to get here.
+///
+/// In JWT mode a token pair is issued instead of a session; `two_factor` is
+/// recorded in the tokens so privileged extractors can tell whether a second
+/// factor was given.
+async fn start_session(
+    user_id: Uuid,
+    device_name: Option<String>,
+    two_factor: bool,
+    state: &AppState,
+) -> Result<AuthResponse, AppError> {
+    let device_id = Uuid::new_v4();
+    let key_pair = KeyPair::from_seed(Seed::generate());
+
+    let private_key = general_purpose::STANDARD.encode(key_pair.sk.to_vec());
+
+    store_public_key(
+        device_id,
+        user_id,
+        device_name,
+        key_pair.pk,
+        &state.db_pool,
+    )
+    .await?;
+
+    if let Some(keys) = &state.jwt {
+        let tokens =
+            issue_tokens(user_id, device_id, two_factor, keys, &state.db_pool)
+                .await?;
+
+        return Ok(AuthResponse {
+            user_id,
+            session_id: None,
+            token_type: "Bearer".to_string(),
+            device_id,
+            private_key,
+            tokens: Some(tokens),
+        });
+    }
+
+    let session_id = create_session_id();
+    state.recognized_session_id.insert(
+        session_id.clone(),
+        Session {
+            user_id,
+            device_id,
+            two_factor,
+        },
+    );
+
+    Ok(AuthResponse {
+        user_id,
+        session_id: Some(session_id),
+        token_type: "Bearer".to_string(),
+        device_id,
+        private_key,
+        tokens: None,
+    })
+}
+
 async fn query_user(
     email: &str,
     db_pool: &Pool<Postgres>,
 ) -> Result<User, AppError> {
-    sqlx::query_as!(User, "SELECT * FROM users WHERE email = $1", email)
-        .fetch_one(db_pool)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => AuthError::UserNotFound.into(),
-            e => {
-                error!("Unexpected error while querying for user: {:?}", e);
-                AppError::InternalError
-            }
-        })
+    sqlx::query_as!(
+        User,
+        "SELECT user_id, email, password_hash FROM users WHERE email = $1",
+        email
+    )
+    .fetch_one(db_pool)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::RowNotFound => AuthError::UserNotFound.into(),
+        e => {
+            error!("Unexpected error while querying for user: {:?}", e);
+            AppError::InternalError
+        }
+    })
 }
 
 async fn store_public_key(
     device_id: Uuid,
     user_id: Uuid,
+    device_name: Option<String>,
     public_key: PublicKey,
     db_pool: &Pool<Postgres>,
 ) -> Result<(), AppError> {
     sqlx::query!(
-        "INSERT INTO device_keys (device_id, user_id, public_key_pem) VALUES \
-         ($1, $2, $3)",
+        "INSERT INTO device_keys (device_id, user_id, public_key_pem, \
+         device_name, last_used_at) VALUES ($1, $2, $3, $4, NOW())",
         device_id,
         user_id,
         public_key.to_pem(),
+        device_name,
     )
     .execute(db_pool)
     .await
@@ -312,6 +425,55 @@ pub async fn retrieve_public_key(
     })
 }
 
+/// Ends every session that was started on `device_id`
+pub fn end_device_sessions(cache: &Cache<String, Session>, device_id: Uuid) {
+    for (session_id, session) in cache.iter() {
+        if session.device_id == device_id {
+            cache.invalidate(session_id.as_str());
+        }
+    }
+}
+
+/// Ends every session of `user_id`, on any device
+pub fn end_user_sessions(cache: &Cache<String, Session>, user_id: Uuid) {
+    for (session_id, session) in cache.iter() {
+        if session.user_id == user_id {
+            cache.invalidate(session_id.as_str());
+        }
+    }
+}
+
+/// Revokes a device key owned by `user_id`, whose sessions are to be ended
+/// with [`end_device_sessions`] once the revocation is committed.
+///
+/// Devices of other users are reported as not found, so they cannot be
+/// probed for.
+pub async fn revoke_device(
+    conn: &mut PgConnection,
+    device_id: Uuid,
+    user_id: Uuid,
+) -> Result<(), AppError> {
+    let res = query!(
+        "UPDATE device_keys SET revoked_at = COALESCE(revoked_at, $1)
+         WHERE device_id = $2 AND user_id = $3",
+        chrono::Utc::now(),
+        device_id,
+        user_id
+    )
+    .execute(conn)
+    .await
+    .map_err(|e| {
+        error!("error while revoking device {}: {:?}", device_id, e);
+        AppError::InternalError
+    })?;
+
+    if res.rows_affected() == 0 {
+        return Err(DatabaseError::RowNotFound.into());
+    }
+
+    Ok(())
+}
+
 #[derive(Deserialize)]
 pub struct DeviceIDPayload {
     device_id: Uuid,
@@ -319,22 +481,21 @@ pub struct DeviceIDPayload {
 
 pub async fn logout(
     State(state): State<AppState>,
-    AuthUser { session_id, .. }: AuthUser,
+    AuthUser {
+        user_id,
+        session_id,
+        ..
+    }: AuthUser,
     Json(DeviceIDPayload { device_id }): Json<DeviceIDPayload>,
 ) -> Result<(StatusCode, Json<Value>), AppError> {
-    // remove the refresh token from the whitelist
-    state.recognized_session_id.remove(&session_id);
-    query!(
-        "UPDATE device_keys SET revoked_at = $1 WHERE device_id = $2",
-        chrono::Utc::now(),
-        device_id
-    )
-    .execute(&state.db_pool)
-    .await
-    .map_err(|e| {
-        error!("error while revoking device {}: {:?}", device_id, e);
+    let mut conn = state.db_pool.acquire().await.map_err(|e| {
+        error!("error while acquiring a connection: {:?}", e);
         AppError::InternalError
     })?;
+    revoke_device(&mut conn, device_id, user_id).await?;
+    end_device_sessions(&state.recognized_session_id, device_id);
+    // remove the refresh token from the whitelist
+    state.recognized_session_id.remove(&session_id);
 
     Ok((StatusCode::OK, Json(json!({ "message": "logged out" }))))
 }
@@ -0,0 +1,467 @@
+//! Brute-force protection for the login endpoints.
+//!
+//! Failed attempts are counted per account and per client IP in an in-memory
+//! cache. After a few free attempts every further failure doubles the time
+//! the client has to wait, and past a threshold the account (or IP) is locked
+//! for [`LOCKOUT_DURATION`]. Every attempt is counted before Argon2 is ever
+//! invoked and taken back once it succeeds, so throttled clients cannot burn
+//! server CPU and concurrent tries cannot slip past the limit either.
+//!
+//! Every failed attempt that got to be checked is also written to
+//! `failed_login_attempts` for auditing.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use chrono::{DateTime, Utc};
+use moka::{ops::compute::Op, sync::Cache};
+use sqlx::{Pool, Postgres, query};
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::auth::AuthError;
+
+/// Delay after the first throttled attempt, doubled on every further failure
+pub const BASE_BACKOFF: Duration = Duration::from_secs(1);
+/// Upper bound of the exponential backoff
+pub const MAX_BACKOFF: Duration = Duration::from_secs(15 * 60);
+/// How long an account or IP stays locked once it hits its threshold
+pub const LOCKOUT_DURATION: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Debug, Clone, Copy)]
+pub struct ThrottlePolicy {
+    /// Failures tolerated before the backoff kicks in
+    pub free_attempts: u32,
+    /// Failures after which the subject is locked out
+    pub lockout_threshold: u32,
+}
+
+pub const ACCOUNT_POLICY: ThrottlePolicy = ThrottlePolicy {
+    free_attempts: 3,
+    lockout_threshold: 10,
+};
+
+// a single IP may legitimately serve many users (e.g. a clinic behind NAT)
+pub const IP_POLICY: ThrottlePolicy = ThrottlePolicy {
+    free_attempts: 10,
+    lockout_threshold: 50,
+};
+
+impl ThrottlePolicy {
+    /// How long a client has to wait after `failures` consecutive failures
+    pub fn wait_after(&self, failures: u32) -> Option<Duration> {
+        if failures >= self.lockout_threshold {
+            return Some(LOCKOUT_DURATION);
+        }
+
+        if failures < self.free_attempts {
+            return None;
+        }
+
+        let exponent = failures - self.free_attempts;
+        let backoff =
+            BASE_BACKOFF.saturating_mul(2u32.saturating_pow(exponent));
+
+        Some(backoff.min(MAX_BACKOFF))
+    }
+}
+
+/// Consecutive failures of an account or IP
+#[derive(Debug, Clone, Default)]
+pub struct FailedAttempts {
+    pub failures: u32,
+    pub last_failure: Option<DateTime<Utc>>,
+}
+
+impl FailedAttempts {
+    /// Time left until the next attempt is allowed, if any
+    pub fn retry_after(
+        &self,
+        policy: &ThrottlePolicy,
+        now: DateTime<Utc>,
+    ) -> Option<Duration> {
+        let last_failure = self.last_failure?;
+        let allowed_at = last_failure + policy.wait_after(self.failures)?;
+
+        (allowed_at - now).to_std().ok().filter(|d| !d.is_zero())
+    }
+
+    pub fn record_failure(&self, now: DateTime<Utc>) -> Self {
+        FailedAttempts {
+            failures: self.failures.saturating_add(1),
+            last_failure: Some(now),
+        }
+    }
+
+    /// Takes back an attempt that was counted as a failure before it was
+    /// made, its time stays so attempts in a backoff stay spaced out
+    pub fn release(&self) -> Self {
+        FailedAttempts {
+            failures: self.failures.saturating_sub(1),
+            last_failure: self.last_failure,
+        }
+    }
+
+    fn stamp(&self, now: DateTime<Utc>) -> Self {
+        FailedAttempts {
+            failures: self.failures,
+            last_failure: Some(now),
+        }
+    }
+}
+
+/// Why a login attempt failed, as stored in `failed_login_attempts`
+#[derive(Debug, Clone, Copy)]
+pub enum LoginFailure {
+    UnknownUser,
+    WrongPassword,
+    WrongSecondFactor,
+}
+
+impl LoginFailure {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoginFailure::UnknownUser => "UNKNOWN_USER",
+            LoginFailure::WrongPassword => "WRONG_PASSWORD",
+            LoginFailure::WrongSecondFactor => "WRONG_SECOND_FACTOR",
+        }
+    }
+}
+
+/// Reverse proxies whose `X-Forwarded-For` is believed, from the comma
+/// separated addresses in `MEDIGRAM_TRUSTED_PROXIES`
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Arc<[IpAddr]>);
+
+impl TrustedProxies {
+    pub fn new(proxies: impl IntoIterator<Item = IpAddr>) -> Self {
+        TrustedProxies(proxies.into_iter().collect())
+    }
+
+    pub fn from_env() -> Self {
+        let proxies = std::env::var("MEDIGRAM_TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|proxy| !proxy.is_empty())
+            .filter_map(|proxy| match proxy.parse() {
+                Ok(proxy) => Some(proxy),
+                Err(_) => {
+                    warn!("Ignoring trusted proxy {proxy}, not an IP address");
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        TrustedProxies::new(proxies)
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.0.contains(&ip)
+    }
+}
+
+/// Best-effort address of the client.
+///
+/// The peer address, which the server provides as [`ConnectInfo`]. Only when
+/// the peer is one of the [`TrustedProxies`] is `X-Forwarded-For` read, the
+/// right-most entry that isn't a trusted proxy being the client. Anybody else
+/// could put any address in there to get a fresh throttle. Never rejects,
+/// only a router served without connect info leaves the address unknown.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+    TrustedProxies: FromRef<S>,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(peer) = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip())
+        else {
+            return Ok(ClientIp(None));
+        };
+
+        let trusted_proxies = TrustedProxies::from_ref(state);
+        if !trusted_proxies.contains(peer) {
+            return Ok(ClientIp(Some(peer)));
+        }
+
+        let forwarded = parts
+            .headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .rev()
+            .map(|ip| ip.trim().parse::<IpAddr>().ok())
+            .find(|ip| !ip.is_some_and(|ip| trusted_proxies.contains(ip)))
+            .flatten();
+
+        Ok(ClientIp(Some(forwarded.unwrap_or(peer))))
+    }
+}
+
+fn account_key(user_id: Uuid) -> String {
+    format!("account:{user_id}")
+}
+
+fn ip_key(ip: IpAddr) -> String {
+    format!("ip:{ip}")
+}
+
+fn retry_after(
+    cache: &Cache<String, FailedAttempts>,
+    key: &str,
+    policy: &ThrottlePolicy,
+) -> Option<Duration> {
+    cache
+        .get(key)
+        .and_then(|attempts| attempts.retry_after(policy, Utc::now()))
+}
+
+/// Counts a failure against `key` atomically, so concurrent failures are
+/// never lost to each other
+fn bump(cache: &Cache<String, FailedAttempts>, key: String) {
+    let now = Utc::now();
+    cache.entry(key).and_upsert_with(|entry| {
+        entry
+            .map(|entry| entry.into_value())
+            .unwrap_or_default()
+            .record_failure(now)
+    });
+}
+
+/// An attempt counted as a failure of an account or IP before it is made,
+/// see [`reserve_ip`]. Dropping it takes the attempt back, [`Reserved::fail`]
+/// leaves it counted and restarts the backoff from when it failed.
+#[must_use]
+pub struct Reserved<'a> {
+    cache: &'a Cache<String, FailedAttempts>,
+    key: Option<String>,
+}
+
+impl Reserved<'_> {
+    pub fn fail(mut self) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+
+        let now = Utc::now();
+        self.cache.entry(key).and_compute_with(|entry| match entry {
+            Some(entry) => Op::Put(entry.into_value().stamp(now)),
+            // cleared by a success in the meantime
+            None => Op::Nop,
+        });
+    }
+}
+
+impl Drop for Reserved<'_> {
+    fn drop(&mut self) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+
+        self.cache.entry(key).and_compute_with(|entry| match entry {
+            Some(entry) => Op::Put(entry.into_value().release()),
+            None => Op::Nop,
+        });
+    }
+}
+
+/// Counts an attempt against `key` unless it is backing off, in one atomic
+/// step so concurrent attempts can't all pass the check before any of them
+/// failed
+fn reserve<'a>(
+    cache: &'a Cache<String, FailedAttempts>,
+    key: String,
+    policy: &ThrottlePolicy,
+) -> Result<Reserved<'a>, AuthError> {
+    let now = Utc::now();
+    let mut wait = None;
+    cache.entry(key.clone()).and_upsert_with(|entry| {
+        let attempts =
+            entry.map(|entry| entry.into_value()).unwrap_or_default();
+        wait = attempts.retry_after(policy, now);
+
+        match wait {
+            Some(_) => attempts,
+            None => attempts.record_failure(now),
+        }
+    });
+
+    match wait {
+        Some(retry_after) => Err(AuthError::TooManyAttempts { retry_after }),
+        None => Ok(Reserved {
+            cache,
+            key: Some(key),
+        }),
+    }
+}
+
+/// Reserves an attempt from the IP, rejecting it while the IP is backing off
+/// or locked out
+pub fn reserve_ip(
+    cache: &Cache<String, FailedAttempts>,
+    ip: Option<IpAddr>,
+) -> Result<Reserved<'_>, AuthError> {
+    match ip {
+        Some(ip) => reserve(cache, ip_key(ip), &IP_POLICY),
+        None => Ok(Reserved { cache, key: None }),
+    }
+}
+
+/// Reserves an attempt at the account, rejecting it while the account is
+/// backing off or locked out
+pub fn reserve_account(
+    cache: &Cache<String, FailedAttempts>,
+    user_id: Uuid,
+) -> Result<Reserved<'_>, AuthError> {
+    reserve(cache, account_key(user_id), &ACCOUNT_POLICY)
+}
+
+/// Rejects logins into an account that is currently backing off or locked
+/// out
+pub fn check_account(
+    cache: &Cache<String, FailedAttempts>,
+    user_id: Uuid,
+) -> Result<(), AuthError> {
+    match retry_after(cache, &account_key(user_id), &ACCOUNT_POLICY) {
+        Some(retry_after) => Err(AuthError::TooManyAttempts { retry_after }),
+        None => Ok(()),
+    }
+}
+
+/// Counts a failure that wasn't reserved against the account and the IP
+pub fn count_failure(
+    cache: &Cache<String, FailedAttempts>,
+    user_id: Uuid,
+    ip: Option<IpAddr>,
+) {
+    bump(cache, account_key(user_id));
+    if let Some(ip) = ip {
+        bump(cache, ip_key(ip));
+    }
+}
+
+/// Writes a failed attempt to the audit table. Attempts refused by the
+/// throttle never got to check anything and aren't written, they would only
+/// flood the table for whoever is being targeted.
+pub async fn record_failure(
+    db_pool: &Pool<Postgres>,
+    email: Option<&str>,
+    user_id: Option<Uuid>,
+    ip: Option<IpAddr>,
+    reason: LoginFailure,
+) {
+    warn!(
+        "Failed login attempt for {:?} ({:?}) from {:?}: {}",
+        email,
+        user_id,
+        ip,
+        reason.as_str()
+    );
+
+    if let Err(e) = query!(
+        "INSERT INTO failed_login_attempts (email, user_id, ip_address, \
+         reason) VALUES ($1, $2, $3, $4)",
+        email,
+        user_id,
+        ip.map(|ip| ip.to_string()),
+        reason.as_str()
+    )
+    .execute(db_pool)
+    .await
+    {
+        error!("Error while auditing failed login attempt: {e:?}");
+    }
+}
+
+/// Clears the account's failures. The IP's failures are kept, otherwise an
+/// attacker could reset them by logging into an account of their own.
+pub fn record_success(cache: &Cache<String, FailedAttempts>, user_id: Uuid) {
+    cache.invalidate(&account_key(user_id));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_backoff_is_exponential_and_capped() {
+        assert_eq!(ACCOUNT_POLICY.wait_after(0), None);
+        assert_eq!(ACCOUNT_POLICY.wait_after(2), None);
+        assert_eq!(ACCOUNT_POLICY.wait_after(3), Some(Duration::from_secs(1)));
+        assert_eq!(ACCOUNT_POLICY.wait_after(4), Some(Duration::from_secs(2)));
+        assert_eq!(ACCOUNT_POLICY.wait_after(6), Some(Duration::from_secs(8)));
+        assert_eq!(ACCOUNT_POLICY.wait_after(10), Some(LOCKOUT_DURATION));
+
+        let policy = ThrottlePolicy {
+            free_attempts: 0,
+            lockout_threshold: u32::MAX,
+        };
+        assert_eq!(policy.wait_after(40), Some(MAX_BACKOFF));
+    }
+
+    #[test]
+    fn test_reserved_attempts() {
+        let cache = Cache::new(16);
+        let user_id = Uuid::new_v4();
+
+        // taken back once they succeed
+        for _ in 0..ACCOUNT_POLICY.free_attempts + 1 {
+            assert!(reserve_account(&cache, user_id).is_ok());
+        }
+
+        // concurrent ones count before any of them failed
+        let pending: Vec<_> = (0..ACCOUNT_POLICY.free_attempts)
+            .filter_map(|_| reserve_account(&cache, user_id).ok())
+            .collect();
+        assert_eq!(pending.len() as u32, ACCOUNT_POLICY.free_attempts);
+        assert!(reserve_account(&cache, user_id).is_err());
+
+        pending.into_iter().for_each(Reserved::fail);
+        assert_eq!(
+            cache.get(&account_key(user_id)).unwrap().failures,
+            ACCOUNT_POLICY.free_attempts
+        );
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let now = Utc::now();
+        let mut attempts = FailedAttempts::default();
+
+        for _ in 0..4 {
+            attempts = attempts.record_failure(now);
+        }
+
+        assert_eq!(
+            attempts.retry_after(&ACCOUNT_POLICY, now),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(
+            attempts.retry_after(&ACCOUNT_POLICY, now + Duration::from_secs(2)),
+            None
+        );
+        assert_eq!(
+            FailedAttempts::default().retry_after(&IP_POLICY, now),
+            None
+        );
+    }
+}
@@ -0,0 +1,236 @@
+//! RFC 6238 time-based one-time passwords.
+//!
+//! Only the parameters every authenticator app supports are implemented:
+//! HMAC-SHA1, 6 digits and a 30 seconds time step. The secret is handed to the
+//! client as an unpadded base32 string through an `otpauth://` URI.
+
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::{Rng, RngCore, distr::Alphanumeric, rng};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+/// Issuer shown in the authenticator app
+pub const TOTP_ISSUER: &str = "Medigram";
+/// Length of a time step in seconds
+pub const TOTP_STEP: u64 = 30;
+/// Number of digits of a code
+pub const TOTP_DIGITS: u32 = 6;
+/// Number of time steps before and after the current one that are still
+/// accepted, to make up for clock drift
+pub const TOTP_SKEW: u64 = 1;
+/// Secret length in bytes, as recommended by RFC 4226
+pub const TOTP_SECRET_LEN: usize = 20;
+/// Amount of recovery codes handed out on enrollment
+pub const RECOVERY_CODE_COUNT: usize = 10;
+/// Recovery code character length, excluding the separator
+pub const RECOVERY_CODE_LEN: usize = 10;
+
+/// Generates a random [`TOTP_SECRET_LEN`] bytes long secret
+pub fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; TOTP_SECRET_LEN];
+    rng().fill_bytes(&mut secret);
+    secret
+}
+
+pub fn encode_secret(secret: &[u8]) -> String {
+    BASE32_NOPAD.encode(secret)
+}
+
+pub fn decode_secret(encoded: &str) -> Option<Vec<u8>> {
+    BASE32_NOPAD.decode(encoded.as_bytes()).ok()
+}
+
+/// RFC 4226 HOTP value for `counter`
+pub fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret)
+        .expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    // dynamic truncation
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+
+    binary % 10u32.pow(TOTP_DIGITS)
+}
+
+/// The time step `unix_time` falls into
+pub fn time_step(unix_time: u64) -> u64 {
+    unix_time / TOTP_STEP
+}
+
+/// TOTP code for `unix_time`, zero-padded to [`TOTP_DIGITS`] digits
+pub fn totp_at(secret: &[u8], unix_time: u64) -> String {
+    format!(
+        "{:0width$}",
+        hotp(secret, time_step(unix_time)),
+        width = TOTP_DIGITS as usize
+    )
+}
+
+/// Checks `code` against the steps around `unix_time`.
+///
+/// Returns the matching time step so the caller can refuse to accept the same
+/// step twice.
+pub fn verify(secret: &[u8], code: &str, unix_time: u64) -> Option<u64> {
+    let code = code.trim();
+    if code.len() != TOTP_DIGITS as usize
+        || !code.chars().all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+
+    let current = time_step(unix_time);
+    (current.saturating_sub(TOTP_SKEW)..=current + TOTP_SKEW).find(|step| {
+        let expected = format!(
+            "{:0width$}",
+            hotp(secret, *step),
+            width = TOTP_DIGITS as usize
+        );
+        constant_time_eq(expected.as_bytes(), code.as_bytes())
+    })
+}
+
+/// Key URI understood by authenticator apps, usually rendered as a QR code.
+///
+/// See <https://github.com/google/google-authenticator/wiki/Key-Uri-Format>
+pub fn otpauth_uri(encoded_secret: &str, account: &str) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={encoded_secret}&\
+         issuer={issuer}&algorithm=SHA1&digits={TOTP_DIGITS}&\
+         period={TOTP_STEP}",
+        issuer = TOTP_ISSUER,
+        account = percent_encode(account),
+    )
+}
+
+/// Generates [`RECOVERY_CODE_COUNT`] codes formatted as `xxxxx-xxxxx`
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code: String = rng()
+                .sample_iter(&Alphanumeric)
+                .take(RECOVERY_CODE_LEN)
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .collect();
+            let (head, tail) = code.split_at(RECOVERY_CODE_LEN / 2);
+            format!("{head}-{tail}")
+        })
+        .collect()
+}
+
+/// Hash of a recovery code as stored in the database.
+///
+/// Recovery codes carry enough entropy on their own, so a plain SHA-256 is
+/// used instead of Argon2 to keep login cheap. Separators and casing are
+/// ignored.
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+
+    format!("{:x}", Sha256::digest(normalized.as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | x ^ y) == 0
+}
+
+fn percent_encode(input: &str) -> String {
+    input
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'.'
+            | b'_'
+            | b'~'
+            | b'@' => (b as char).to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // RFC 4226 and RFC 6238 (SHA1) test secret
+    const SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn test_hotp_rfc4226_vectors() {
+        let expected = [
+            755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583,
+            399871, 520489,
+        ];
+
+        for (counter, code) in expected.into_iter().enumerate() {
+            assert_eq!(hotp(SECRET, counter as u64), code);
+        }
+    }
+
+    #[test]
+    fn test_totp_rfc6238_vectors() {
+        assert_eq!(totp_at(SECRET, 59), "287082");
+        assert_eq!(totp_at(SECRET, 1111111109), "081804");
+        assert_eq!(totp_at(SECRET, 1111111111), "050471");
+        assert_eq!(totp_at(SECRET, 1234567890), "005924");
+        assert_eq!(totp_at(SECRET, 2000000000), "279037");
+    }
+
+    #[test]
+    fn test_verify_with_skew() {
+        let code = totp_at(SECRET, 1234567890);
+
+        assert_eq!(verify(SECRET, &code, 1234567890), Some(41152263));
+        assert_eq!(
+            verify(SECRET, &code, 1234567890 + TOTP_STEP),
+            Some(41152263)
+        );
+        assert_eq!(verify(SECRET, &code, 1234567890 + 3 * TOTP_STEP), None);
+        assert_eq!(verify(SECRET, "12345", 1234567890), None);
+        assert_eq!(verify(SECRET, "abcdef", 1234567890), None);
+    }
+
+    #[test]
+    fn test_secret_roundtrip() {
+        let secret = generate_secret();
+        let encoded = encode_secret(&secret);
+
+        assert_eq!(decode_secret(&encoded), Some(secret));
+    }
+
+    #[test]
+    fn test_otpauth_uri() {
+        let uri = otpauth_uri("JBSWY3DPEHPK3PXP", "alice@example.com");
+
+        assert_eq!(
+            uri,
+            "otpauth://totp/Medigram:alice@example.com?secret=JBSWY3DPEHPK3PXP&\
+             issuer=Medigram&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+
+    #[test]
+    fn test_recovery_code_hash_normalization() {
+        let codes = generate_recovery_codes();
+
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert_eq!(
+            hash_recovery_code("abcde-12345"),
+            hash_recovery_code("ABCDE12345")
+        );
+    }
+}
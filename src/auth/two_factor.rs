@@ -0,0 +1,540 @@
+//! Two-factor authentication with TOTP.
+//!
+//! Enrollment is a two step process: `POST /me/two-factor/totp` hands out a
+//! fresh secret, and `POST /me/two-factor/totp/confirm` enables it once the
+//! user proves their authenticator app produces matching codes. Accounts with
+//! 2FA enabled get a login challenge instead of a session from
+//! `POST /login`, which is then exchanged through `POST /login/two-factor`.
+//!
+//! Doctors, admins and every other privileged role are required to enroll
+//! before they can use any of their privileges, see [`crate::policy`].
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicU8, Ordering},
+};
+
+use axum::{Json, extract::State, http::StatusCode};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sqlx::{Pool, Postgres, Transaction, query, query_scalar};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    AppState, TWO_FACTOR_CHALLENGE_TTL,
+    auth::{
+        AuthError, AuthResponse, AuthUser, create_session_id, start_session,
+        throttle::{self, ClientIp, LoginFailure},
+        totp,
+    },
+    error::{APIResult, AppError, DatabaseError},
+};
+
+/// Codes a single login challenge takes before it is dropped
+pub const TWO_FACTOR_MAX_ATTEMPTS: u8 = 5;
+
+/// A login that passed the password check and is waiting for its second
+/// factor
+#[derive(Clone)]
+pub struct TwoFactorChallenge {
+    pub user_id: Uuid,
+    pub device_name: Option<String>,
+    /// Codes tried so far, counted in place rather than by replacing the
+    /// challenge so concurrent tries all count and the expiry stays put
+    pub attempts: Arc<AtomicU8>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TwoFactorChallengeResponse {
+    pub two_factor_required: bool,
+    pub challenge_id: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct TwoFactorStatus {
+    pub enabled: bool,
+    pub required: bool,
+    pub recovery_codes_remaining: i64,
+}
+
+#[derive(Serialize)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct TotpCodePayload {
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorLoginRequest {
+    pub challenge_id: String,
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+/// Whether the account is held to the 2FA policy, i.e. it has a doctor
+/// profile, is an admin or was granted a role
+pub async fn two_factor_required(
+    user_id: Uuid,
+    db_pool: &Pool<Postgres>,
+) -> APIResult<bool> {
+    query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM doctor_profiles WHERE user_id = $1)
+            OR EXISTS(SELECT 1 FROM admins WHERE user_id = $1)
+            OR EXISTS(SELECT 1 FROM user_roles WHERE user_id = $1)",
+        user_id
+    )
+    .fetch_one(db_pool)
+    .await
+    .map(|required| required.unwrap_or(true))
+    .map_err(|e| {
+        error!("Error while checking 2FA policy for {user_id}: {e:?}");
+        AppError::InternalError
+    })
+}
+
+pub async fn two_factor_enabled(
+    user_id: Uuid,
+    db_pool: &Pool<Postgres>,
+) -> APIResult<bool> {
+    query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM user_totp WHERE user_id = $1 AND \
+         enabled_at IS NOT NULL)",
+        user_id
+    )
+    .fetch_one(db_pool)
+    .await
+    .map(|enabled| enabled.unwrap_or(false))
+    .map_err(|e| {
+        error!("Error while checking 2FA status for {user_id}: {e:?}");
+        AppError::InternalError
+    })
+}
+
+pub fn create_two_factor_challenge(
+    user_id: Uuid,
+    device_name: Option<String>,
+    state: &AppState,
+) -> TwoFactorChallengeResponse {
+    let challenge_id = create_session_id();
+    state.two_factor_challenges.insert(
+        challenge_id.clone(),
+        TwoFactorChallenge {
+            user_id,
+            device_name,
+            attempts: Arc::new(AtomicU8::new(0)),
+        },
+    );
+
+    TwoFactorChallengeResponse {
+        two_factor_required: true,
+        challenge_id,
+        expires_at: Utc::now() + TWO_FACTOR_CHALLENGE_TTL,
+    }
+}
+
+/// Checks a TOTP code against the user's enabled secret, consuming its time
+/// step so it cannot be replayed.
+async fn check_totp_code(
+    user_id: Uuid,
+    code: &str,
+    db_pool: &Pool<Postgres>,
+) -> APIResult<()> {
+    let secret = query_scalar!(
+        "SELECT secret FROM user_totp WHERE user_id = $1 AND enabled_at IS \
+         NOT NULL",
+        user_id
+    )
+    .fetch_optional(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while fetching TOTP secret for {user_id}: {e:?}");
+        AppError::InternalError
+    })?
+    .ok_or(AuthError::TwoFactorNotEnrolled)?;
+
+    let secret = totp::decode_secret(&secret).ok_or_else(|| {
+        error!("TOTP secret of {user_id} is not valid base32");
+        AppError::InternalError
+    })?;
+
+    let step = totp::verify(&secret, code, Utc::now().timestamp() as u64)
+        .ok_or(AuthError::InvalidTwoFactorCode)?;
+
+    let res = query!(
+        "UPDATE user_totp SET last_used_step = $2
+         WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)",
+        user_id,
+        step as i64
+    )
+    .execute(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while consuming TOTP step for {user_id}: {e:?}");
+        AppError::InternalError
+    })?;
+
+    if res.rows_affected() == 0 {
+        warn!("{user_id} tried to reuse a TOTP code");
+        return Err(AuthError::InvalidTwoFactorCode.into());
+    }
+
+    Ok(())
+}
+
+async fn use_recovery_code(
+    user_id: Uuid,
+    recovery_code: &str,
+    db_pool: &Pool<Postgres>,
+) -> APIResult<()> {
+    let res = query!(
+        "UPDATE totp_recovery_codes SET used_at = NOW()
+         WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+        user_id,
+        totp::hash_recovery_code(recovery_code)
+    )
+    .execute(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while using recovery code for {user_id}: {e:?}");
+        AppError::InternalError
+    })?;
+
+    if res.rows_affected() == 0 {
+        return Err(AuthError::InvalidTwoFactorCode.into());
+    }
+
+    info!("{user_id} used a recovery code");
+
+    Ok(())
+}
+
+/// Replaces every recovery code of `user_id` with a fresh set
+async fn replace_recovery_codes(
+    user_id: Uuid,
+    tx: &mut Transaction<'_, Postgres>,
+) -> APIResult<Vec<String>> {
+    query!(
+        "DELETE FROM totp_recovery_codes WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut **tx)
+    .await
+    .map_err(|e| {
+        error!("Error while removing recovery codes for {user_id}: {e:?}");
+        AppError::InternalError
+    })?;
+
+    let recovery_codes = totp::generate_recovery_codes();
+    let hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|code| totp::hash_recovery_code(code))
+        .collect();
+
+    query!(
+        "INSERT INTO totp_recovery_codes (user_id, code_hash)
+         SELECT $1, * FROM UNNEST($2::TEXT[])",
+        user_id,
+        &hashes
+    )
+    .execute(&mut **tx)
+    .await
+    .map_err(|e| {
+        error!("Error while storing recovery codes for {user_id}: {e:?}");
+        AppError::InternalError
+    })?;
+
+    Ok(recovery_codes)
+}
+
+pub async fn get_two_factor_status(
+    State(state): State<AppState>,
+    AuthUser { user_id, .. }: AuthUser,
+) -> APIResult<Json<TwoFactorStatus>> {
+    let enabled = two_factor_enabled(user_id, &state.db_pool).await?;
+    let required = two_factor_required(user_id, &state.db_pool).await?;
+    let recovery_codes_remaining = query_scalar!(
+        "SELECT COUNT(*) FROM totp_recovery_codes WHERE user_id = $1 AND \
+         used_at IS NULL",
+        user_id
+    )
+    .fetch_one(&state.db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while counting recovery codes for {user_id}: {e:?}");
+        AppError::InternalError
+    })?
+    .unwrap_or(0);
+
+    Ok(Json(TwoFactorStatus {
+        enabled,
+        required,
+        recovery_codes_remaining,
+    }))
+}
+
+pub async fn begin_totp_enrollment(
+    State(state): State<AppState>,
+    AuthUser { user_id, .. }: AuthUser,
+) -> APIResult<(StatusCode, Json<TotpEnrollment>)> {
+    if two_factor_enabled(user_id, &state.db_pool).await? {
+        return Err(AuthError::TwoFactorAlreadyEnabled.into());
+    }
+
+    let email =
+        // only dependents lack an email, and they can't log in
+        query_scalar!(
+            r#"SELECT email AS "email!" FROM users WHERE user_id = $1"#,
+            user_id
+        )
+            .fetch_one(&state.db_pool)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => DatabaseError::RowNotFound.into(),
+                e => {
+                    error!(
+                        "Error while fetching user for {}: {:?}",
+                        user_id, e
+                    );
+                    AppError::InternalError
+                }
+            })?;
+
+    let secret = totp::encode_secret(&totp::generate_secret());
+
+    // restarting an unfinished enrollment simply replaces the pending secret
+    query!(
+        "INSERT INTO user_totp (user_id, secret) VALUES ($1, $2)
+         ON CONFLICT (user_id) DO UPDATE
+         SET secret = EXCLUDED.secret, created_at = NOW(), last_used_step = NULL
+         WHERE user_totp.enabled_at IS NULL",
+        user_id,
+        secret
+    )
+    .execute(&state.db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while storing TOTP secret for {user_id}: {e:?}");
+        AppError::InternalError
+    })?;
+
+    let otpauth_uri = totp::otpauth_uri(&secret, &email);
+
+    Ok((
+        StatusCode::CREATED,
+        Json(TotpEnrollment {
+            secret,
+            otpauth_uri,
+        }),
+    ))
+}
+
+pub async fn confirm_totp_enrollment(
+    State(state): State<AppState>,
+    AuthUser { user_id, .. }: AuthUser,
+    Json(TotpCodePayload { code }): Json<TotpCodePayload>,
+) -> APIResult<Json<RecoveryCodes>> {
+    let secret = query_scalar!(
+        "SELECT secret FROM user_totp WHERE user_id = $1 AND enabled_at IS \
+         NULL",
+        user_id
+    )
+    .fetch_optional(&state.db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while fetching pending TOTP for {user_id}: {e:?}");
+        AppError::InternalError
+    })?
+    .ok_or(AuthError::TwoFactorNotEnrolled)?;
+
+    let secret = totp::decode_secret(&secret).ok_or_else(|| {
+        error!("TOTP secret of {user_id} is not valid base32");
+        AppError::InternalError
+    })?;
+
+    let step = totp::verify(&secret, &code, Utc::now().timestamp() as u64)
+        .ok_or(AuthError::InvalidTwoFactorCode)?;
+
+    let mut tx: Transaction<Postgres> =
+        state.db_pool.begin().await.map_err(|e| {
+            error!("Error occured while starting a transaction: {:?}", e);
+            AppError::InternalError
+        })?;
+
+    query!(
+        "UPDATE user_totp SET enabled_at = NOW(), last_used_step = $2
+         WHERE user_id = $1",
+        user_id,
+        step as i64
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!("Error while enabling TOTP for {user_id}: {e:?}");
+        AppError::InternalError
+    })?;
+
+    let recovery_codes = replace_recovery_codes(user_id, &mut tx).await?;
+
+    tx.commit().await.map_err(|e| {
+        error!("Error occured while committing transaction: {:?}", e);
+        AppError::InternalError
+    })?;
+
+    info!("{user_id} enabled TOTP");
+
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+pub async fn disable_totp(
+    State(state): State<AppState>,
+    AuthUser { user_id, .. }: AuthUser,
+    Json(TotpCodePayload { code }): Json<TotpCodePayload>,
+) -> APIResult<(StatusCode, Json<Value>)> {
+    if two_factor_required(user_id, &state.db_pool).await? {
+        return Err(AuthError::TwoFactorRequired.into());
+    }
+
+    check_totp_code(user_id, &code, &state.db_pool).await?;
+
+    let mut tx: Transaction<Postgres> =
+        state.db_pool.begin().await.map_err(|e| {
+            error!("Error occured while starting a transaction: {:?}", e);
+            AppError::InternalError
+        })?;
+
+    query!(
+        "DELETE FROM totp_recovery_codes WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!("Error while removing recovery codes for {user_id}: {e:?}");
+        AppError::InternalError
+    })?;
+
+    query!("DELETE FROM user_totp WHERE user_id = $1", user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            error!("Error while removing TOTP for {user_id}: {e:?}");
+            AppError::InternalError
+        })?;
+
+    tx.commit().await.map_err(|e| {
+        error!("Error occured while committing transaction: {:?}", e);
+        AppError::InternalError
+    })?;
+
+    info!("{user_id} disabled TOTP");
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "message": "two-factor authentication disabled" })),
+    ))
+}
+
+pub async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    AuthUser { user_id, .. }: AuthUser,
+    Json(TotpCodePayload { code }): Json<TotpCodePayload>,
+) -> APIResult<Json<RecoveryCodes>> {
+    check_totp_code(user_id, &code, &state.db_pool).await?;
+
+    let mut tx: Transaction<Postgres> =
+        state.db_pool.begin().await.map_err(|e| {
+            error!("Error occured while starting a transaction: {:?}", e);
+            AppError::InternalError
+        })?;
+
+    let recovery_codes = replace_recovery_codes(user_id, &mut tx).await?;
+
+    tx.commit().await.map_err(|e| {
+        error!("Error occured while committing transaction: {:?}", e);
+        AppError::InternalError
+    })?;
+
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+/// Second step of the login for accounts with 2FA enabled.
+///
+/// Accepts either a TOTP `code` or one of the user's unused `recovery_code`s.
+pub async fn login_two_factor(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Json(TwoFactorLoginRequest {
+        challenge_id,
+        code,
+        recovery_code,
+    }): Json<TwoFactorLoginRequest>,
+) -> APIResult<Json<AuthResponse>> {
+    let Some(challenge) = state.two_factor_challenges.get(&challenge_id) else {
+        return Err(AuthError::ChallengeExpired.into());
+    };
+    let user_id = challenge.user_id;
+
+    throttle::check_account(&state.login_throttle, user_id)?;
+
+    // taken before the code is checked, so no number of concurrent tries
+    // gets past the limit
+    let attempts = challenge.attempts.fetch_add(1, Ordering::SeqCst) + 1;
+    if attempts > TWO_FACTOR_MAX_ATTEMPTS {
+        return Err(AuthError::ChallengeExpired.into());
+    }
+
+    let verification = match (code, recovery_code) {
+        (Some(code), _) => {
+            check_totp_code(user_id, &code, &state.db_pool).await
+        }
+        (None, Some(recovery_code)) => {
+            use_recovery_code(user_id, &recovery_code, &state.db_pool).await
+        }
+        (None, None) => return Err(AuthError::MissingCredentials.into()),
+    };
+
+    if let Err(e) = verification {
+        if matches!(e, AppError::Auth(AuthError::InvalidTwoFactorCode)) {
+            throttle::count_failure(&state.login_throttle, user_id, ip);
+            throttle::record_failure(
+                &state.db_pool,
+                None,
+                Some(user_id),
+                ip,
+                LoginFailure::WrongSecondFactor,
+            )
+            .await;
+        }
+
+        if attempts >= TWO_FACTOR_MAX_ATTEMPTS {
+            warn!(
+                "Dropping login challenge of {user_id} after {attempts} tries"
+            );
+            state.two_factor_challenges.remove(&challenge_id);
+        }
+
+        return Err(e);
+    }
+
+    state.two_factor_challenges.remove(&challenge_id);
+    throttle::record_success(&state.login_throttle, user_id);
+
+    let response =
+        start_session(user_id, challenge.device_name, true, &state).await?;
+
+    info!("User {user_id} logged in with a second factor");
+
+    Ok(Json(response))
+}
@@ -0,0 +1,121 @@
+//! Refreshes the WHO growth standards in `growth_standards`.
+//!
+//! ```sh
+//! DATABASE_URL=postgres://... cargo run --bin load_growth_standards -- \
+//!     weight-for-age M wfa_boys_0-to-5-years_zscores.txt
+//! ```
+//!
+//! Takes one table at a time, of `height-for-age`, `weight-for-age` or
+//! `bmi-for-age` for boys (`M`) or girls (`F`): the monthly z-score tables of
+//! the child growth standards up to 5 years (`wfa_boys_0-to-5-years_zscores`,
+//! `lhfa_...`, `bmi_...`) and of the growth reference from 5 to 19 years
+//! (`wfa-boys-z-who-2007-exp`, `hfa-...`, `bmi-...`), as exported from WHO's
+//! spreadsheets to tab or comma separated text. Lines starting with the age
+//! in months followed by L, M and S are loaded, anything else such as the
+//! header is skipped. Rows already present are updated.
+//!
+//! The expanded tables of the growth standards are indexed by day rather than
+//! month and are refused by their `Day` header. The server loads the tables
+//! under `data/growth_standards` by itself at startup, this is only needed to
+//! replace them with newer files without a release.
+
+use std::process::ExitCode;
+
+use medigram::{
+    growth::{is_daily_header, parse_row},
+    schema::GrowthIndicator,
+};
+use sqlx::{postgres::PgPoolOptions, query};
+
+fn parse_indicator(indicator: &str) -> Option<GrowthIndicator> {
+    match indicator {
+        "height-for-age" | "length-for-age" => {
+            Some(GrowthIndicator::HeightForAge)
+        }
+        "weight-for-age" => Some(GrowthIndicator::WeightForAge),
+        "bmi-for-age" => Some(GrowthIndicator::BmiForAge),
+        _ => None,
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    dotenvy::dotenv().ok();
+
+    let Ok(database_url) = std::env::var("DATABASE_URL") else {
+        eprintln!("DATABASE_URL is not set");
+        return ExitCode::FAILURE;
+    };
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [indicator, sex, path] = args.as_slice() else {
+        eprintln!("Usage: load_growth_standards <indicator> <M|F> <table.txt>");
+        return ExitCode::FAILURE;
+    };
+    let Some(indicator) = parse_indicator(indicator) else {
+        eprintln!(
+            "Unknown indicator {indicator}, expected height-for-age, \
+             weight-for-age or bmi-for-age"
+        );
+        return ExitCode::FAILURE;
+    };
+    if sex != "M" && sex != "F" {
+        eprintln!("Unknown sex {sex}, expected M or F");
+        return ExitCode::FAILURE;
+    }
+    let table = match std::fs::read_to_string(path) {
+        Ok(table) => table,
+        Err(e) => {
+            eprintln!("Could not read {path}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if table.lines().any(is_daily_header) {
+        eprintln!(
+            "{path} is indexed by day, load the monthly z-score table instead"
+        );
+        return ExitCode::FAILURE;
+    }
+
+    let db_pool = match PgPoolOptions::new()
+        .max_connections(1)
+        .connect(&database_url)
+        .await
+    {
+        Ok(db_pool) => db_pool,
+        Err(e) => {
+            eprintln!("Could not connect to the database: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let (mut loaded, mut skipped) = (0, 0);
+    for line in table.lines().filter(|line| !line.trim().is_empty()) {
+        let Some((age_in_months, l, m, s)) = parse_row(line) else {
+            skipped += 1;
+            continue;
+        };
+
+        if let Err(e) = query!(
+            "INSERT INTO growth_standards (indicator, sex, age_in_months, l, \
+             m, s) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT (indicator, \
+             sex, age_in_months) DO UPDATE SET l = EXCLUDED.l, m = \
+             EXCLUDED.m, s = EXCLUDED.s",
+            indicator as GrowthIndicator,
+            sex,
+            age_in_months,
+            l,
+            m,
+            s
+        )
+        .execute(&db_pool)
+        .await
+        {
+            eprintln!("Could not load month {age_in_months}: {e}");
+            return ExitCode::FAILURE;
+        }
+        loaded += 1;
+    }
+
+    println!("Loaded {loaded} months, skipped {skipped} lines");
+    ExitCode::SUCCESS
+}
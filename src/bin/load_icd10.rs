@@ -0,0 +1,100 @@
+//! Loads the full ICD-10 classification into `icd10_codes`.
+//!
+//! ```sh
+//! DATABASE_URL=postgres://... cargo run --bin load_icd10 -- codes.csv
+//! ```
+//!
+//! Takes one code per line, followed by a comma or tab and its title, such
+//! as the tabular list published by WHO or the Ministry of Health. A header
+//! line and codes written without a dot (`J069`) are fine. Titles of codes
+//! already present are updated.
+
+use std::process::ExitCode;
+
+use sqlx::{postgres::PgPoolOptions, query};
+
+/// `J069` as `J06.9`, or `None` if it isn't an ICD-10 code at all
+fn normalize_code(code: &str) -> Option<String> {
+    let code: String = code
+        .trim()
+        .trim_matches('"')
+        .to_uppercase()
+        .replace('.', "");
+
+    let mut chars = code.chars();
+    let is_code = chars.next().is_some_and(|c| c.is_ascii_uppercase())
+        && chars.by_ref().take(2).all(|c| c.is_ascii_digit())
+        && (3..=7).contains(&code.len())
+        && code.chars().all(|c| c.is_ascii_alphanumeric());
+    if !is_code {
+        return None;
+    }
+
+    Some(match code.split_at(3) {
+        (category, "") => category.to_string(),
+        (category, subcategory) => format!("{category}.{subcategory}"),
+    })
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    dotenvy::dotenv().ok();
+
+    let Ok(database_url) = std::env::var("DATABASE_URL") else {
+        eprintln!("DATABASE_URL is not set");
+        return ExitCode::FAILURE;
+    };
+    let Some(path) = std::env::args().nth(1) else {
+        eprintln!("Usage: load_icd10 <codes.csv>");
+        return ExitCode::FAILURE;
+    };
+    let codes = match std::fs::read_to_string(&path) {
+        Ok(codes) => codes,
+        Err(e) => {
+            eprintln!("Could not read {path}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let db_pool = match PgPoolOptions::new()
+        .max_connections(1)
+        .connect(&database_url)
+        .await
+    {
+        Ok(db_pool) => db_pool,
+        Err(e) => {
+            eprintln!("Could not connect to the database: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let (mut loaded, mut skipped) = (0, 0);
+    for line in codes.lines().filter(|line| !line.trim().is_empty()) {
+        let Some((code, title)) = line
+            .split_once('\t')
+            .or_else(|| line.split_once(','))
+            .and_then(|(code, title)| Some((normalize_code(code)?, title)))
+        else {
+            skipped += 1;
+            continue;
+        };
+        let title = title.trim().trim_matches('"').replace("\"\"", "\"");
+
+        if let Err(e) = query!(
+            "INSERT INTO icd10_codes (code, title) VALUES ($1, $2) ON \
+             CONFLICT (code) DO UPDATE SET title = EXCLUDED.title",
+            code,
+            title
+        )
+        .execute(&db_pool)
+        .await
+        {
+            eprintln!("Could not load {code}: {e}");
+            return ExitCode::FAILURE;
+        }
+        loaded += 1;
+    }
+
+    println!("Loaded {loaded} codes, skipped {skipped} lines");
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,50 @@
+//! Checks the audit chain straight from the database.
+//!
+//! ```sh
+//! DATABASE_URL=postgres://... cargo run --bin verify_audit
+//! ```
+//!
+//! Exits with 1 if any entry was rewritten, removed or reordered.
+
+use std::process::ExitCode;
+
+use medigram::audit::verify_chain;
+use sqlx::postgres::PgPoolOptions;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    dotenvy::dotenv().ok();
+
+    let Ok(database_url) = std::env::var("DATABASE_URL") else {
+        eprintln!("DATABASE_URL is not set");
+        return ExitCode::FAILURE;
+    };
+
+    let db_pool = match PgPoolOptions::new()
+        .max_connections(1)
+        .connect(&database_url)
+        .await
+    {
+        Ok(db_pool) => db_pool,
+        Err(e) => {
+            eprintln!("Could not connect to the database: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let Ok(report) = verify_chain(&db_pool).await else {
+        eprintln!("Could not read the audit events");
+        return ExitCode::FAILURE;
+    };
+
+    match report.first_invalid_seq {
+        None => {
+            println!("Audit chain intact, {} events checked", report.events);
+            ExitCode::SUCCESS
+        }
+        Some(seq) => {
+            println!("Audit chain broken at event {seq}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,381 @@
+//! Copy of everything Medigram keeps about a user.
+//!
+//! The PDP law (UU 27/2022) lets patients get at their data, so an export is
+//! a ZIP with every table keyed by the user, each as JSON and as CSV. It can
+//! get big, so [`build_export`] runs in the background and the archive is
+//! kept in `data_exports` until [`crate::DATA_EXPORT_TTL`] has passed. A
+//! build that never finished, because the server went down in the middle of
+//! it, is marked failed by [`fail_stale_exports`].
+//!
+//! Secrets are left out: the password hash, the TOTP secret and recovery
+//! codes, refresh tokens. So are consultations the user wrote as a doctor,
+//! those are about other people.
+
+use std::io::{Cursor, Write};
+
+use chrono::Utc;
+use serde_json::Value;
+use sqlx::{Pool, Postgres, query, query_scalar};
+use tracing::{error, info};
+use uuid::Uuid;
+use zip::{ZipWriter, write::SimpleFileOptions};
+
+use crate::{DATA_EXPORT_STALE_AFTER, DATA_EXPORT_TTL};
+
+/// Name of every file in the archive and the query for its rows, `$1` being
+/// the user
+pub const EXPORT_TABLES: &[(&str, &str)] = &[
+    (
+        "account",
+        "SELECT user_id, email, deleted_at FROM users WHERE user_id = $1",
+    ),
+    (
+        "user_details",
+        "SELECT * FROM user_details WHERE user_id = $1",
+    ),
+    (
+        "user_measurements",
+        "SELECT * FROM user_measurements WHERE user_id = $1 ORDER BY \
+         measured_at",
+    ),
+    (
+        "observations",
+        "SELECT * FROM observations WHERE user_id = $1 ORDER BY observed_at",
+    ),
+    ("allergies", "SELECT * FROM allergies WHERE user_id = $1"),
+    (
+        "medical_conditions",
+        "SELECT * FROM medical_conditions WHERE user_id = $1",
+    ),
+    (
+        "condition_status_history",
+        "SELECT h.* FROM condition_status_history AS h JOIN \
+         medical_conditions AS m ON m.condition_id = h.condition_id WHERE \
+         m.user_id = $1 ORDER BY h.changed_at",
+    ),
+    (
+        "consultations",
+        "SELECT * FROM consultations WHERE user_id = $1 ORDER BY created_at",
+    ),
+    (
+        "diagnoses",
+        "SELECT d.* FROM diagnoses AS d JOIN consultations AS c ON \
+         c.consultation_id = d.consultation_id WHERE c.user_id = $1",
+    ),
+    (
+        "prescriptions",
+        "SELECT p.* FROM prescriptions AS p JOIN consultations AS c ON \
+         c.consultation_id = p.consultation_id WHERE c.user_id = $1",
+    ),
+    ("purchases", "SELECT * FROM purchases WHERE user_id = $1"),
+    (
+        "external_encounters",
+        "SELECT * FROM external_encounters WHERE user_id = $1",
+    ),
+    (
+        "fhir_imports",
+        "SELECT * FROM fhir_imports WHERE user_id = $1",
+    ),
+    (
+        "imported_records",
+        "SELECT r.* FROM imported_records AS r JOIN fhir_imports AS i ON \
+         i.import_id = r.import_id WHERE i.user_id = $1",
+    ),
+    (
+        "devices",
+        "SELECT device_id, device_name, public_key_pem, created_at, \
+         last_used_at, revoked_at, generation FROM device_keys WHERE user_id \
+         = $1",
+    ),
+    (
+        "two_factor",
+        "SELECT created_at, enabled_at FROM user_totp WHERE user_id = $1",
+    ),
+    (
+        "failed_login_attempts",
+        "SELECT attempted_at, ip_address, reason FROM failed_login_attempts \
+         WHERE user_id = $1 ORDER BY attempted_at",
+    ),
+    (
+        "doctor_profiles",
+        "SELECT * FROM doctor_profiles WHERE user_id = $1",
+    ),
+    (
+        "practice_locations",
+        "SELECT l.* FROM doctor_practice_locations AS l JOIN doctor_profiles \
+         AS d ON d.doctor_id = l.doctor_id WHERE d.user_id = $1",
+    ),
+    ("roles", "SELECT * FROM user_roles WHERE user_id = $1"),
+    ("admins", "SELECT * FROM admins WHERE user_id = $1"),
+    (
+        "access_log",
+        "SELECT * FROM access_log WHERE user_id = $1 ORDER BY accessed_at",
+    ),
+    (
+        "audit_events",
+        "SELECT seq, actor_id, action, payload, created_at FROM audit_events \
+         WHERE subject_id = $1 ORDER BY seq",
+    ),
+    (
+        "emergency_access_grants",
+        "SELECT * FROM emergency_access_grants WHERE user_id = $1",
+    ),
+    (
+        "emergency_cards",
+        "SELECT * FROM emergency_cards WHERE user_id = $1",
+    ),
+    (
+        "notifications",
+        "SELECT * FROM notifications WHERE user_id = $1 ORDER BY created_at",
+    ),
+    (
+        "guardianships",
+        "SELECT * FROM guardianships WHERE dependent_id = $1 OR guardian_id \
+         = $1",
+    ),
+    (
+        "delegations",
+        "SELECT * FROM delegations WHERE user_id = $1 OR delegate_id = $1",
+    ),
+    (
+        "patient_summaries",
+        "SELECT summary_id, issued_by, sha256, issued_at FROM \
+         patient_summaries WHERE user_id = $1",
+    ),
+    (
+        "account_deletions",
+        "SELECT * FROM account_deletions WHERE user_id = $1",
+    ),
+    (
+        "data_exports",
+        "SELECT export_id, requested_by, requested_at, status, completed_at, \
+         expires_at FROM data_exports WHERE user_id = $1",
+    ),
+];
+
+const README: &str = "\
+Everything Medigram keeps about you, one table per file as JSON and as CSV.
+
+Left out are your password hash, two-factor secret and recovery codes, and \
+refresh tokens, as well as consultations you wrote as a doctor, which are \
+about your patients.
+";
+
+/// Rows of every table in [`EXPORT_TABLES`]
+pub async fn collect(
+    user_id: Uuid,
+    db_pool: &Pool<Postgres>,
+) -> Result<Vec<(&'static str, Vec<Value>)>, sqlx::Error> {
+    let mut tables = Vec::with_capacity(EXPORT_TABLES.len());
+
+    for (name, sql) in EXPORT_TABLES {
+        // the list is data, so unlike everywhere else these can't be checked
+        // at compile time
+        let rows = sqlx::query_scalar::<_, Value>(&format!(
+            "SELECT COALESCE(json_agg(t), '[]'::json) FROM ({sql}) AS t"
+        ))
+        .bind(user_id)
+        .fetch_one(db_pool)
+        .await?;
+
+        let rows = match rows {
+            Value::Array(rows) => rows,
+            _ => Vec::new(),
+        };
+        tables.push((*name, rows));
+    }
+
+    Ok(tables)
+}
+
+fn csv_field(value: &Value) -> String {
+    let text = match value {
+        Value::Null => return String::new(),
+        Value::String(text) => text.clone(),
+        // nested JSON, e.g. audit payloads, stays JSON
+        value => value.to_string(),
+    };
+
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+/// Rows as CSV, with the columns of the first row as header
+pub fn to_csv(rows: &[Value]) -> String {
+    let Some(Value::Object(first)) = rows.first() else {
+        return String::new();
+    };
+    let columns: Vec<&String> = first.keys().collect();
+
+    let mut csv = columns
+        .iter()
+        .map(|column| csv_field(&Value::String(column.to_string())))
+        .collect::<Vec<_>>()
+        .join(",");
+    csv.push_str("\r\n");
+
+    for row in rows {
+        let fields: Vec<String> = columns
+            .iter()
+            .map(|column| csv_field(&row[column.as_str()]))
+            .collect();
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+
+    csv
+}
+
+/// The ZIP of `tables`
+pub fn archive(
+    tables: &[(&'static str, Vec<Value>)],
+) -> zip::result::ZipResult<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default();
+
+    zip.start_file("README.txt", options)?;
+    zip.write_all(README.as_bytes())?;
+
+    for (name, rows) in tables {
+        zip.start_file(format!("{name}.json"), options)?;
+        zip.write_all(&serde_json::to_vec_pretty(rows).unwrap_or_default())?;
+
+        zip.start_file(format!("{name}.csv"), options)?;
+        zip.write_all(to_csv(rows).as_bytes())?;
+    }
+
+    Ok(zip.finish()?.into_inner())
+}
+
+/// Builds the archive of `export_id` and stores it, or marks the export as
+/// failed
+pub async fn build_export(
+    export_id: Uuid,
+    user_id: Uuid,
+    db_pool: Pool<Postgres>,
+) {
+    let archive = match collect(user_id, &db_pool).await {
+        Ok(tables) => archive(&tables).map_err(|e| {
+            error!("Error while zipping export {}: {:?}", export_id, e);
+        }),
+        Err(e) => {
+            error!("Error while collecting export {}: {:?}", export_id, e);
+            Err(())
+        }
+    };
+
+    let res = match archive {
+        Ok(archive) => {
+            query!(
+                "UPDATE data_exports SET status = 'ready', archive = $1, \
+                 completed_at = NOW(), expires_at = $2 WHERE export_id = $3",
+                archive,
+                Utc::now() + DATA_EXPORT_TTL,
+                export_id
+            )
+            .execute(&db_pool)
+            .await
+        }
+        Err(()) => {
+            query!(
+                "UPDATE data_exports SET status = 'failed', completed_at = \
+                 NOW() WHERE export_id = $1",
+                export_id
+            )
+            .execute(&db_pool)
+            .await
+        }
+    };
+
+    match res {
+        Ok(_) => info!("Finished export {} of {}", export_id, user_id),
+        Err(e) => error!("Error while storing export {}: {:?}", export_id, e),
+    }
+}
+
+/// Drops the archives of exports that can no longer be downloaded
+pub async fn drop_expired_exports(
+    db_pool: &Pool<Postgres>,
+) -> Result<u64, sqlx::Error> {
+    query_scalar!(
+        "WITH dropped AS (UPDATE data_exports SET archive = NULL WHERE \
+         expires_at < NOW() AND archive IS NOT NULL RETURNING 1) SELECT \
+         COUNT(*) AS \"count!\" FROM dropped"
+    )
+    .fetch_one(db_pool)
+    .await
+    .map(|count| count as u64)
+}
+
+/// Marks exports pending for longer than [`DATA_EXPORT_STALE_AFTER`] as
+/// failed, so the user knows to request another one
+pub async fn fail_stale_exports(
+    db_pool: &Pool<Postgres>,
+) -> Result<u64, sqlx::Error> {
+    query!(
+        "UPDATE data_exports SET status = 'failed', completed_at = NOW() \
+         WHERE status = 'pending' AND requested_at < $1",
+        Utc::now() - DATA_EXPORT_STALE_AFTER
+    )
+    .execute(db_pool)
+    .await
+    .map(|res| res.rows_affected())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use serde_json::json;
+    use zip::ZipArchive;
+
+    use super::*;
+
+    #[test]
+    fn test_csv_quotes_when_needed() {
+        let rows = vec![
+            json!({ "allergen": "pollen, grass", "note": null }),
+            json!({ "allergen": "say \"nuts\"", "note": { "a": 1 } }),
+        ];
+
+        assert_eq!(
+            to_csv(&rows),
+            "allergen,note\r\n\"pollen, grass\",\r\n\"say \"\"nuts\"\"\",\
+             \"{\"\"a\"\":1}\"\r\n"
+        );
+        assert_eq!(to_csv(&[]), "");
+    }
+
+    #[test]
+    fn test_archive_has_every_table() {
+        let tables = vec![
+            ("allergies", vec![json!({ "allergen": "pollen" })]),
+            ("notifications", vec![]),
+        ];
+        let archive = archive(&tables).unwrap();
+
+        let mut zip = ZipArchive::new(Cursor::new(archive)).unwrap();
+        let mut names: Vec<&str> = zip.file_names().collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "README.txt",
+                "allergies.csv",
+                "allergies.json",
+                "notifications.csv",
+                "notifications.json"
+            ]
+        );
+
+        let mut csv = String::new();
+        zip.by_name("allergies.csv")
+            .unwrap()
+            .read_to_string(&mut csv)
+            .unwrap();
+        assert_eq!(csv, "allergen\r\npollen\r\n");
+    }
+}
@@ -0,0 +1,363 @@
+//! Public emergency medical card.
+//!
+//! A patient may publish a card with what a first responder needs to know:
+//! blood type, severe allergies, the conditions they picked as critical and
+//! who to call. The card is a JWT signed by the server with EdDSA, so it can
+//! be checked offline against the key published at
+//! `GET /.well-known/emergency-card-key`. Online it renders as a page at
+//! `GET /emergency-card/{token}`, which needs no account but only works while
+//! the card is still the one the patient has published.
+//!
+//! Unlike access token keys the card key has to outlive restarts, it is
+//! derived from the hex seed in `MEDIGRAM_CARD_KEY`. Being the one key that
+//! does, it also vouches for printed summaries, see [`crate::summary`].
+
+use chrono::NaiveDate;
+use data_encoding::{HEXLOWER, HEXLOWER_PERMISSIVE};
+use ed25519_compact::{KeyPair, Seed};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey, Header, Validation, decode,
+    decode_header, encode, jwk::JwkSet,
+};
+use qrcode::{QrCode, render::svg};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::{
+    auth::jwt::jwk,
+    error::{APIResult, AppError},
+    schema::AllergySeverity,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BloodType {
+    #[serde(rename = "A+")]
+    APositive,
+    #[serde(rename = "A-")]
+    ANegative,
+    #[serde(rename = "B+")]
+    BPositive,
+    #[serde(rename = "B-")]
+    BNegative,
+    #[serde(rename = "AB+")]
+    AbPositive,
+    #[serde(rename = "AB-")]
+    AbNegative,
+    #[serde(rename = "O+")]
+    OPositive,
+    #[serde(rename = "O-")]
+    ONegative,
+}
+
+impl BloodType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BloodType::APositive => "A+",
+            BloodType::ANegative => "A-",
+            BloodType::BPositive => "B+",
+            BloodType::BNegative => "B-",
+            BloodType::AbPositive => "AB+",
+            BloodType::AbNegative => "AB-",
+            BloodType::OPositive => "O+",
+            BloodType::ONegative => "O-",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmergencyContact {
+    pub name: String,
+    pub phone: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CardAllergy {
+    pub allergen: String,
+    pub severity: AllergySeverity,
+}
+
+/// Claims of a card token
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmergencyCard {
+    /// The patient
+    pub sub: Uuid,
+    /// Changes every time the card is published again
+    pub jti: Uuid,
+    pub iat: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dob: Option<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blood_type: Option<BloodType>,
+    /// Only the severe ones
+    pub allergies: Vec<CardAllergy>,
+    pub conditions: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emergency_contact: Option<EmergencyContact>,
+}
+
+/// Signs cards and knows where they are served from.
+#[derive(Clone)]
+pub struct CardIssuer {
+    kid: String,
+    key_pair: KeyPair,
+    /// Scheme and host the card URLs start with
+    base_url: String,
+}
+
+impl CardIssuer {
+    fn from_key_pair(key_pair: KeyPair, base_url: String) -> Self {
+        let digest = Sha256::digest(key_pair.pk.as_ref());
+
+        CardIssuer {
+            kid: HEXLOWER.encode(&digest[..8]),
+            key_pair,
+            base_url,
+        }
+    }
+
+    /// Issuer with a throwaway key, cards it signs stop verifying once it is
+    /// gone
+    pub fn generate(base_url: &str) -> Self {
+        Self::from_key_pair(KeyPair::generate(), base_url.to_string())
+    }
+
+    /// Issuer with the key derived from a hex encoded 32 byte seed
+    pub fn from_seed(seed_hex: &str, base_url: &str) -> Option<Self> {
+        let bytes = HEXLOWER_PERMISSIVE.decode(seed_hex.as_bytes()).ok()?;
+        // an all-zero seed is rejected with a panic further down
+        if bytes.iter().all(|&byte| byte == 0) {
+            return None;
+        }
+        let seed = Seed::from_slice(&bytes).ok()?;
+
+        Some(Self::from_key_pair(
+            KeyPair::from_seed(seed),
+            base_url.to_string(),
+        ))
+    }
+
+    /// Reads `MEDIGRAM_CARD_KEY` and `MEDIGRAM_PUBLIC_URL`
+    pub fn from_env() -> Self {
+        let base_url = std::env::var("MEDIGRAM_PUBLIC_URL")
+            .unwrap_or_else(|_| "http://127.0.0.1:8000".to_string());
+
+        std::env::var("MEDIGRAM_CARD_KEY")
+            .ok()
+            .and_then(|seed| Self::from_seed(&seed, &base_url))
+            .unwrap_or_else(|| {
+                warn!(
+                    "MEDIGRAM_CARD_KEY is missing or invalid, emergency cards \
+                     won't verify after a restart"
+                );
+                Self::generate(&base_url)
+            })
+    }
+
+    /// Signs `claims` as a JWT under the card key
+    pub fn sign_claims<T: Serialize>(&self, claims: &T) -> APIResult<String> {
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(self.kid.clone());
+
+        let encoding_key = EncodingKey::from_ed_der(&self.key_pair.sk.to_der());
+
+        encode(&header, claims, &encoding_key).map_err(|e| {
+            error!("Error while signing with the card key: {:?}", e);
+            AppError::InternalError
+        })
+    }
+
+    /// Claims of `token`, if signed with the card key
+    pub fn verify_claims<T: DeserializeOwned>(&self, token: &str) -> Option<T> {
+        let kid = decode_header(token).ok().and_then(|header| header.kid);
+        if kid.as_deref() != Some(self.kid.as_str()) {
+            return None;
+        }
+
+        // what is printed doesn't expire, whatever replaces it retires it
+        let mut validation = Validation::new(Algorithm::EdDSA);
+        validation.required_spec_claims.clear();
+        validation.validate_exp = false;
+
+        decode::<T>(
+            token,
+            &DecodingKey::from_ed_der(self.key_pair.pk.as_ref()),
+            &validation,
+        )
+        .map(|data| data.claims)
+        .map_err(|e| warn!("Rejected token signed with card key: {:?}", e))
+        .ok()
+    }
+
+    pub fn sign(&self, card: &EmergencyCard) -> APIResult<String> {
+        self.sign_claims(card)
+    }
+
+    /// Checks the signature only, whether the card was replaced or withdrawn
+    /// is up to the caller
+    pub fn verify(&self, token: &str) -> APIResult<EmergencyCard> {
+        self.verify_claims(token)
+            .ok_or(AppError::InvalidEmergencyCard)
+    }
+
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: vec![jwk(&self.kid, &self.key_pair.pk)],
+        }
+    }
+
+    /// Where `token` renders
+    pub fn url(&self, token: &str) -> String {
+        format!("{}/emergency-card/{token}", self.base_url)
+    }
+
+    /// Where the summary `summary_id` is verified
+    pub fn summary_url(&self, summary_id: Uuid) -> String {
+        format!("{}/summaries/{summary_id}", self.base_url)
+    }
+
+    /// QR code of the URL of `token`, as SVG
+    pub fn qr_svg(&self, token: &str) -> APIResult<String> {
+        let code = QrCode::new(self.url(token).as_bytes()).map_err(|e| {
+            error!("Error while encoding emergency card QR: {:?}", e);
+            AppError::InternalError
+        })?;
+
+        Ok(code.render::<svg::Color>().min_dimensions(256, 256).build())
+    }
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl EmergencyCard {
+    /// The page first responders see, everything on it is patient-provided
+    /// so it is all escaped
+    pub fn render_html(&self) -> String {
+        let mut html = String::from(
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><meta \
+             name=\"viewport\" content=\"width=device-width\"><title>Emergency \
+             medical card</title></head><body><h1>Emergency medical card</h1>",
+        );
+
+        if let Some(name) = &self.name {
+            html.push_str(&format!("<h2>{}</h2>", escape_html(name)));
+        }
+        if let Some(dob) = self.dob {
+            html.push_str(&format!("<p>Born {dob}</p>"));
+        }
+
+        let blood_type = self.blood_type.map_or("unknown", |b| b.as_str());
+        html.push_str(&format!("<p>Blood type: <b>{blood_type}</b></p>"));
+
+        html.push_str("<h3>Severe allergies</h3><ul>");
+        for allergy in &self.allergies {
+            let severity = match allergy.severity {
+                AllergySeverity::AnaphylacticShock => "anaphylactic shock",
+                _ => "severe",
+            };
+            html.push_str(&format!(
+                "<li>{} ({severity})</li>",
+                escape_html(&allergy.allergen)
+            ));
+        }
+        if self.allergies.is_empty() {
+            html.push_str("<li>None known</li>");
+        }
+        html.push_str("</ul>");
+
+        html.push_str("<h3>Critical conditions</h3><ul>");
+        for condition in &self.conditions {
+            html.push_str(&format!("<li>{}</li>", escape_html(condition)));
+        }
+        if self.conditions.is_empty() {
+            html.push_str("<li>None known</li>");
+        }
+        html.push_str("</ul>");
+
+        if let Some(contact) = &self.emergency_contact {
+            html.push_str(&format!(
+                "<h3>Emergency contact</h3><p>{} <a href=\"tel:{}\">{}</a></p>",
+                escape_html(&contact.name),
+                escape_html(&contact.phone),
+                escape_html(&contact.phone)
+            ));
+        }
+
+        html.push_str("</body></html>");
+        html
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn card() -> EmergencyCard {
+        EmergencyCard {
+            sub: Uuid::nil(),
+            jti: Uuid::nil(),
+            iat: 0,
+            name: Some("<script>alert(1)</script>".to_string()),
+            dob: None,
+            blood_type: Some(BloodType::ONegative),
+            allergies: vec![CardAllergy {
+                allergen: "penicillin".to_string(),
+                severity: AllergySeverity::AnaphylacticShock,
+            }],
+            conditions: vec![],
+            emergency_contact: None,
+        }
+    }
+
+    #[test]
+    fn test_card_verifies_only_with_issuer_key() {
+        let issuer = CardIssuer::generate("http://localhost");
+        let token = issuer.sign(&card()).ok().unwrap();
+
+        let verified = issuer.verify(&token).ok().unwrap();
+        assert_eq!(verified.blood_type, Some(BloodType::ONegative));
+
+        let other = CardIssuer::generate("http://localhost");
+        assert!(other.verify(&token).is_err());
+    }
+
+    #[test]
+    fn test_seed_gives_same_key() {
+        let seed = "2a".repeat(32);
+        let issuer = CardIssuer::from_seed(&seed, "http://localhost").unwrap();
+        let token = issuer.sign(&card()).ok().unwrap();
+
+        let restarted =
+            CardIssuer::from_seed(&seed, "http://localhost").unwrap();
+        assert!(restarted.verify(&token).is_ok());
+
+        assert!(
+            CardIssuer::from_seed(&"00".repeat(32), "http://localhost")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_render_escapes_patient_input() {
+        let html = card().render_html();
+
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("penicillin (anaphylactic shock)"));
+    }
+}
@@ -1,7 +1,21 @@
 use axum::{Json, http::StatusCode, response::IntoResponse};
+use tracing::error;
+use uuid::Uuid;
 
 use crate::{auth::AuthError, protocol::ConsentError};
 
+/// Logs a failure to fetch `what` of `user_id` and hides it behind
+/// [`AppError::InternalError`], for handlers reading one table after another
+pub fn internal_error(
+    what: &'static str,
+    user_id: Uuid,
+) -> impl FnOnce(sqlx::Error) -> AppError {
+    move |e| {
+        error!("Error while fetching {} of {}: {:?}", what, user_id, e);
+        AppError::InternalError
+    }
+}
+
 /// Represents all the errors that may occur in the app
 pub enum AppError {
     /// Error for any error that shouldn't be exposed to the user
@@ -12,10 +26,102 @@ pub enum AppError {
     ///
     /// Returns `StatusCode::BAD_REQUEST`
     InvalidNik,
-    /// Error for when a user tries to access another user's data
+    /// Error for when none of the user's roles allow the request, see
+    /// [`crate::policy`]
     ///
     /// Returns `StatusCode::FORBIDDEN`
-    NotTheSameUser,
+    PermissionDenied,
+    /// Error for granting a role that cannot be granted
+    ///
+    /// Returns `StatusCode::BAD_REQUEST`
+    InvalidRole,
+    /// Error for emergency access requested without a proper justification
+    ///
+    /// Returns `StatusCode::BAD_REQUEST`
+    JustificationRequired,
+    /// Error for an emergency card that was not signed by this server
+    ///
+    /// Returns `StatusCode::BAD_REQUEST`
+    InvalidEmergencyCard,
+    /// Error for acting as a user one is not (or no longer) the guardian of
+    ///
+    /// Returns `StatusCode::FORBIDDEN`
+    NotGuardian,
+    /// Error for a delegation without resources that can be delegated, or
+    /// one that is already expired
+    ///
+    /// Returns `StatusCode::BAD_REQUEST`
+    InvalidDelegation,
+    /// Error for an import that is not a FHIR R4 Bundle
+    ///
+    /// Returns `StatusCode::BAD_REQUEST`
+    InvalidFhirBundle,
+    /// Error for deleting the account of an admin, or of the only guardian
+    /// of a dependent
+    ///
+    /// Returns `StatusCode::CONFLICT`
+    AccountDeletionBlocked,
+    /// Error for downloading a data export that is still being built
+    ///
+    /// Returns `StatusCode::CONFLICT`
+    ExportNotReady,
+    /// Error for requesting a data export while another one is being built
+    ///
+    /// Returns `StatusCode::CONFLICT`
+    ExportPending,
+    /// Error for a malformed page cursor, or a limit out of range
+    ///
+    /// Returns `StatusCode::BAD_REQUEST`
+    InvalidPage,
+    /// Error for amending a consultation of another doctor or patient, or
+    /// one that was amended already
+    ///
+    /// Returns `StatusCode::CONFLICT`
+    InvalidAmendment,
+    /// Error for a diagnosis or condition coded with what is not an ICD-10
+    /// code
+    ///
+    /// Returns `StatusCode::BAD_REQUEST`
+    UnknownIcd10Code,
+    /// Error for setting a medical condition to the status it has
+    ///
+    /// Returns `StatusCode::CONFLICT`
+    ConditionStatusUnchanged,
+    /// Error for confirming a medical condition that was diagnosed, or
+    /// confirmed already
+    ///
+    /// Returns `StatusCode::CONFLICT`
+    ConditionAlreadyConfirmed,
+    /// Error for removing a medical condition diagnosed in a consultation,
+    /// which is part of its record
+    ///
+    /// Returns `StatusCode::CONFLICT`
+    ConditionDiagnosed,
+    /// Error for linking an allergy that is not to a drug to an ingredient
+    ///
+    /// Returns `StatusCode::BAD_REQUEST`
+    InvalidAllergyCategory,
+    /// Error for confirming an allergy a clinician confirmed already
+    ///
+    /// Returns `StatusCode::CONFLICT`
+    AllergyAlreadyConfirmed,
+    /// Error for an observation of a type not in `observation_types`
+    ///
+    /// Returns `StatusCode::BAD_REQUEST`
+    UnknownObservationCode,
+    /// Error for an observation outside the range of its type
+    ///
+    /// Returns `StatusCode::BAD_REQUEST`
+    ObservationOutOfRange,
+    /// Error for a trend over a window out of bounds or an empty time range
+    ///
+    /// Returns `StatusCode::BAD_REQUEST`
+    InvalidTrendQuery,
+    /// Error for growth percentiles of someone neither male nor female, whom
+    /// WHO has no curves for
+    ///
+    /// Returns `StatusCode::CONFLICT`
+    NoGrowthReference,
     /// Error for authentication-related issues
     Auth(AuthError),
     /// Error for consent-related issues
@@ -30,10 +136,6 @@ pub enum AppError {
     ///
     /// Returns `StatusCode::FORBIDDEN`
     LocationNotApproved,
-    /// Error from a non-admin user trying to act as one
-    ///
-    /// Returns `StatusCode::FORBIDDEN`
-    NotAdmin,
 }
 
 // actual decoration trait check
@@ -57,17 +159,95 @@ impl IntoResponse for AppError {
                 "An internal error has occured",
             ),
             AppError::InvalidNik => (StatusCode::BAD_REQUEST, "Invalid NIK"),
-            AppError::NotTheSameUser => (
+            AppError::PermissionDenied => (
                 StatusCode::FORBIDDEN,
                 "You are not allowed to request for this",
             ),
+            AppError::InvalidRole => {
+                (StatusCode::BAD_REQUEST, "This role cannot be granted")
+            }
+            AppError::JustificationRequired => (
+                StatusCode::BAD_REQUEST,
+                "Emergency access requires a written justification",
+            ),
+            AppError::InvalidEmergencyCard => {
+                (StatusCode::BAD_REQUEST, "Invalid emergency card")
+            }
+            AppError::NotGuardian => {
+                (StatusCode::FORBIDDEN, "You are not a guardian of this user")
+            }
+            AppError::InvalidDelegation => (
+                StatusCode::BAD_REQUEST,
+                "Only reminders, prescriptions and measurements can be \
+                 delegated, until a time in the future",
+            ),
+            AppError::InvalidFhirBundle => {
+                (StatusCode::BAD_REQUEST, "Not a FHIR R4 Bundle")
+            }
+            AppError::AccountDeletionBlocked => (
+                StatusCode::CONFLICT,
+                "Step down as admin and make sure your dependents have \
+                 another guardian before deleting the account",
+            ),
+            AppError::ExportNotReady => {
+                (StatusCode::CONFLICT, "The export is not ready yet")
+            }
+            AppError::ExportPending => (
+                StatusCode::CONFLICT,
+                "Another export is being built, wait for it to finish",
+            ),
+            AppError::InvalidPage => {
+                (StatusCode::BAD_REQUEST, "Invalid page parameters")
+            }
+            AppError::UnknownIcd10Code => {
+                (StatusCode::BAD_REQUEST, "Unknown ICD-10 code")
+            }
+            AppError::ConditionStatusUnchanged => (
+                StatusCode::CONFLICT,
+                "The medical condition has this status already",
+            ),
+            AppError::ConditionAlreadyConfirmed => (
+                StatusCode::CONFLICT,
+                "Only self-reported medical conditions can be confirmed, and \
+                 only once",
+            ),
+            AppError::ConditionDiagnosed => (
+                StatusCode::CONFLICT,
+                "Diagnosed medical conditions are part of the consultation \
+                 record and cannot be removed",
+            ),
+            AppError::InvalidAllergyCategory => (
+                StatusCode::BAD_REQUEST,
+                "Only drug allergies can be linked to an ingredient",
+            ),
+            AppError::AllergyAlreadyConfirmed => {
+                (StatusCode::CONFLICT, "The allergy is confirmed already")
+            }
+            AppError::UnknownObservationCode => {
+                (StatusCode::BAD_REQUEST, "Unknown observation code")
+            }
+            AppError::ObservationOutOfRange => (
+                StatusCode::BAD_REQUEST,
+                "The observed value is out of range for its type",
+            ),
+            AppError::InvalidTrendQuery => {
+                (StatusCode::BAD_REQUEST, "Invalid trend parameters")
+            }
+            AppError::NoGrowthReference => (
+                StatusCode::CONFLICT,
+                "Growth curves exist for boys and girls only",
+            ),
+            AppError::InvalidAmendment => (
+                StatusCode::CONFLICT,
+                "Only your own consultation with this patient can be amended, \
+                 and only once",
+            ),
             AppError::NotLicensed => {
                 (StatusCode::FORBIDDEN, "You are not a licensed practitioner")
             }
             AppError::LocationNotApproved => {
                 (StatusCode::FORBIDDEN, "This location is not approved")
             }
-            AppError::NotAdmin => (StatusCode::FORBIDDEN, "Not an admin"),
         };
 
         let body = Json(serde_json::json!({
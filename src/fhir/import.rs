@@ -0,0 +1,575 @@
+//! Reading records from other clinics out of a FHIR R4 Bundle.
+//!
+//! Only what Medigram has a place for is taken: AllergyIntolerances become
+//! allergies, Conditions medical conditions, Encounters external encounters,
+//! and body height and weight Observations measurements. A measurement needs
+//! both, so a height is only taken together with a weight of the same time.
+//! Everything else is reported as unmappable rather than dropped silently,
+//! and records that are refuted, entered in error or no longer active are
+//! skipped.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde_json::Value;
+
+use super::REACTION_SNOMED;
+use crate::schema::{AllergyCategory, AllergyReaction, AllergySeverity};
+
+/// LOINC codes taken as body height, the second one measured lying down
+pub const HEIGHT_LOINC: [&str; 2] = ["8302-2", "8306-3"];
+/// LOINC codes taken as body weight, the second one measured
+pub const WEIGHT_LOINC: [&str; 2] = ["29463-7", "3141-9"];
+
+const ANAPHYLAXIS_SNOMED: &str = "39579001";
+
+/// A record the bundle maps onto
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record {
+    Allergy {
+        allergen: String,
+        severity: AllergySeverity,
+        category: Option<AllergyCategory>,
+        reactions: Vec<AllergyReaction>,
+        onset: Option<NaiveDate>,
+    },
+    Condition {
+        condition: String,
+        onset: Option<NaiveDate>,
+    },
+    Measurement {
+        height_in_cm: f32,
+        weight_in_kg: f32,
+        measured_at: DateTime<Utc>,
+    },
+    Encounter {
+        started_at: DateTime<Utc>,
+        ended_at: Option<DateTime<Utc>>,
+        facility: Option<String>,
+        practitioner: Option<String>,
+        reason: Option<String>,
+    },
+}
+
+impl Record {
+    /// What the record is stored as, also used in provenance
+    pub fn target(&self) -> &'static str {
+        match self {
+            Record::Allergy { .. } => "allergy",
+            Record::Condition { .. } => "medical_condition",
+            Record::Measurement { .. } => "measurement",
+            Record::Encounter { .. } => "external_encounter",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Mapped(Record),
+    /// Understood but not worth importing, with the reason
+    Skipped(String),
+    /// Nothing in Medigram to put it in, with the reason
+    Unmappable(String),
+}
+
+/// What became of one resource, or of the two observations of a measurement
+#[derive(Debug)]
+pub struct Entry {
+    pub resource_type: String,
+    /// `fullUrl` of the entries, or `{resourceType}/{id}` without one
+    pub source_ids: Vec<String>,
+    pub outcome: Outcome,
+}
+
+/// Where the bundle comes from: its `meta.source`, `identifier` or `id`
+pub fn bundle_source(bundle: &Value) -> Option<String> {
+    [
+        &bundle["meta"]["source"],
+        &bundle["identifier"]["value"],
+        &bundle["id"],
+    ]
+    .into_iter()
+    .find_map(|value| value.as_str())
+    .map(str::to_string)
+}
+
+/// Text of a CodeableConcept, falling back to the display of its codings
+fn text_of(concept: &Value) -> Option<String> {
+    concept["text"]
+        .as_str()
+        .or_else(|| {
+            concept["coding"]
+                .as_array()?
+                .iter()
+                .find_map(|coding| coding["display"].as_str())
+        })
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .map(str::to_string)
+}
+
+/// Whether a CodeableConcept has a coding with one of `codes`
+fn has_code(concept: &Value, codes: &[&str]) -> bool {
+    concept["coding"].as_array().is_some_and(|codings| {
+        codings.iter().any(|coding| {
+            coding["code"]
+                .as_str()
+                .is_some_and(|code| codes.contains(&code))
+        })
+    })
+}
+
+/// First code of a status CodeableConcept, e.g. `clinicalStatus`
+fn status_code(concept: &Value) -> Option<&str> {
+    concept["coding"].as_array()?.first()?["code"].as_str()
+}
+
+/// A FHIR `dateTime`, dates alone are taken as midnight UTC
+pub fn parse_date_time(value: &Value) -> Option<DateTime<Utc>> {
+    let value = value.as_str()?;
+
+    DateTime::parse_from_rfc3339(value)
+        .map(|date_time| date_time.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .map(|date_time| date_time.and_utc())
+        })
+}
+
+/// Skips records refuted or entered in error, and those no longer active
+fn inactive(resource: &Value) -> Option<String> {
+    if let Some(status @ ("entered-in-error" | "refuted")) =
+        status_code(&resource["verificationStatus"])
+    {
+        return Some(format!("marked {status}"));
+    }
+    if let Some(status @ ("inactive" | "resolved" | "remission")) =
+        status_code(&resource["clinicalStatus"])
+    {
+        return Some(format!("no longer active ({status})"));
+    }
+
+    None
+}
+
+fn map_allergy(resource: &Value) -> Outcome {
+    if let Some(reason) = inactive(resource) {
+        return Outcome::Skipped(reason);
+    }
+    let Some(allergen) = text_of(&resource["code"]) else {
+        return Outcome::Unmappable("no allergen".to_string());
+    };
+
+    let reactions =
+        resource["reaction"].as_array().cloned().unwrap_or_default();
+    let mut kinds = Vec::new();
+    for manifestation in reactions.iter().flat_map(|reaction| {
+        reaction["manifestation"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default()
+    }) {
+        let kind = if has_code(&manifestation, &[ANAPHYLAXIS_SNOMED])
+            || text_of(&manifestation)
+                .is_some_and(|text| text.to_lowercase().contains("anaphyla"))
+        {
+            AllergyReaction::Anaphylaxis
+        } else {
+            REACTION_SNOMED
+                .iter()
+                .find(|(_, code, _)| has_code(&manifestation, &[code]))
+                .map_or(AllergyReaction::Other, |(reaction, ..)| *reaction)
+        };
+        if !kinds.contains(&kind) {
+            kinds.push(kind);
+        }
+    }
+    let anaphylaxis = kinds.contains(&AllergyReaction::Anaphylaxis);
+    let worst_reaction = reactions
+        .iter()
+        .filter_map(|reaction| match reaction["severity"].as_str() {
+            Some("mild") => Some(AllergySeverity::Mild),
+            Some("moderate") => Some(AllergySeverity::Moderate),
+            Some("severe") => Some(AllergySeverity::Severe),
+            _ => None,
+        })
+        .max_by_key(|severity| *severity as u8);
+
+    let severity = if anaphylaxis {
+        AllergySeverity::AnaphylacticShock
+    } else if let Some(severity) = worst_reaction {
+        severity
+    } else {
+        // without a reaction the criticality is all there is to go by
+        match resource["criticality"].as_str() {
+            Some("high") => AllergySeverity::Severe,
+            Some("low") => AllergySeverity::Mild,
+            _ => AllergySeverity::Moderate,
+        }
+    };
+
+    let category = resource["category"].as_array().and_then(|categories| {
+        categories
+            .iter()
+            .find_map(|category| match category.as_str() {
+                Some("medication") => Some(AllergyCategory::Drug),
+                Some("food") => Some(AllergyCategory::Food),
+                Some("environment") => Some(AllergyCategory::Environmental),
+                _ => None,
+            })
+    });
+
+    Outcome::Mapped(Record::Allergy {
+        allergen,
+        severity,
+        category,
+        reactions: kinds,
+        onset: parse_date_time(&resource["onsetDateTime"])
+            .map(|onset| onset.date_naive()),
+    })
+}
+
+fn map_condition(resource: &Value) -> Outcome {
+    if let Some(reason) = inactive(resource) {
+        return Outcome::Skipped(reason);
+    }
+
+    match text_of(&resource["code"]) {
+        Some(condition) => Outcome::Mapped(Record::Condition {
+            condition,
+            onset: parse_date_time(&resource["onsetDateTime"])
+                .map(|onset| onset.date_naive()),
+        }),
+        None => Outcome::Unmappable("no condition".to_string()),
+    }
+}
+
+fn map_encounter(resource: &Value) -> Outcome {
+    if let Some(status @ ("entered-in-error" | "cancelled")) =
+        resource["status"].as_str()
+    {
+        return Outcome::Skipped(format!("marked {status}"));
+    }
+    let Some(started_at) = parse_date_time(&resource["period"]["start"]) else {
+        return Outcome::Unmappable("no start of the period".to_string());
+    };
+
+    let reasons: Vec<String> = resource["reasonCode"]
+        .as_array()
+        .map(|reasons| reasons.iter().filter_map(text_of).collect())
+        .unwrap_or_default();
+
+    Outcome::Mapped(Record::Encounter {
+        started_at,
+        ended_at: parse_date_time(&resource["period"]["end"]),
+        facility: resource["serviceProvider"]["display"]
+            .as_str()
+            .or_else(|| resource["location"][0]["location"]["display"].as_str())
+            .map(str::to_string),
+        practitioner: resource["participant"][0]["individual"]["display"]
+            .as_str()
+            .map(str::to_string),
+        reason: (!reasons.is_empty()).then(|| reasons.join("; ")),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum VitalSign {
+    /// In centimetres
+    Height(f32),
+    /// In kilograms
+    Weight(f32),
+}
+
+struct Vital {
+    source_id: String,
+    sign: VitalSign,
+    at: DateTime<Utc>,
+}
+
+/// Height or weight in Medigram's units, `Err` with the reason otherwise
+fn map_vital_sign(
+    resource: &Value,
+) -> Result<(VitalSign, DateTime<Utc>), Outcome> {
+    if let Some(status @ ("entered-in-error" | "cancelled")) =
+        resource["status"].as_str()
+    {
+        return Err(Outcome::Skipped(format!("marked {status}")));
+    }
+
+    let is_height = has_code(&resource["code"], &HEIGHT_LOINC);
+    if !is_height && !has_code(&resource["code"], &WEIGHT_LOINC) {
+        return Err(Outcome::Unmappable(
+            "only body height and weight are supported".to_string(),
+        ));
+    }
+
+    let quantity = &resource["valueQuantity"];
+    let Some(value) = quantity["value"].as_f64() else {
+        return Err(Outcome::Unmappable("no value".to_string()));
+    };
+    let unit = quantity["code"]
+        .as_str()
+        .or_else(|| quantity["unit"].as_str())
+        .unwrap_or_default();
+
+    let sign = match (is_height, unit) {
+        (true, "cm") => VitalSign::Height(value as f32),
+        (true, "m") => VitalSign::Height((value * 100.0) as f32),
+        (true, "mm") => VitalSign::Height((value / 10.0) as f32),
+        (true, "[in_i]" | "in") => VitalSign::Height((value * 2.54) as f32),
+        (false, "kg") => VitalSign::Weight(value as f32),
+        (false, "g") => VitalSign::Weight((value / 1000.0) as f32),
+        (false, "[lb_av]" | "lb" | "lbs") => {
+            VitalSign::Weight((value * 0.453_592_37) as f32)
+        }
+        (_, unit) => {
+            return Err(Outcome::Unmappable(format!(
+                "unsupported unit {unit:?}"
+            )));
+        }
+    };
+
+    let Some(at) = parse_date_time(&resource["effectiveDateTime"])
+        .or_else(|| parse_date_time(&resource["effectivePeriod"]["start"]))
+        .or_else(|| parse_date_time(&resource["issued"]))
+    else {
+        return Err(Outcome::Unmappable("no time of measurement".to_string()));
+    };
+
+    Ok((sign, at))
+}
+
+/// Pairs every height with a weight of the same time
+fn pair_vitals(vitals: Vec<Vital>) -> Vec<Entry> {
+    let (heights, mut weights): (Vec<Vital>, Vec<Vital>) = vitals
+        .into_iter()
+        .partition(|vital| matches!(vital.sign, VitalSign::Height(_)));
+    let mut entries = Vec::new();
+
+    for height in heights {
+        let weight = weights
+            .iter()
+            .position(|weight| weight.at == height.at)
+            .map(|index| weights.remove(index));
+
+        entries.push(match (height.sign, weight) {
+            (
+                VitalSign::Height(height_in_cm),
+                Some(Vital {
+                    source_id,
+                    sign: VitalSign::Weight(weight_in_kg),
+                    ..
+                }),
+            ) => Entry {
+                resource_type: "Observation".to_string(),
+                source_ids: vec![height.source_id, source_id],
+                outcome: Outcome::Mapped(Record::Measurement {
+                    height_in_cm,
+                    weight_in_kg,
+                    measured_at: height.at,
+                }),
+            },
+            _ => Entry {
+                resource_type: "Observation".to_string(),
+                source_ids: vec![height.source_id],
+                outcome: Outcome::Unmappable(
+                    "no weight measured at the same time".to_string(),
+                ),
+            },
+        });
+    }
+
+    entries.extend(weights.into_iter().map(|weight| Entry {
+        resource_type: "Observation".to_string(),
+        source_ids: vec![weight.source_id],
+        outcome: Outcome::Unmappable(
+            "no height measured at the same time".to_string(),
+        ),
+    }));
+
+    entries
+}
+
+/// What every entry of `bundle` maps onto, `None` if it is not a Bundle
+pub fn map_bundle(bundle: &Value) -> Option<Vec<Entry>> {
+    if bundle["resourceType"] != "Bundle" {
+        return None;
+    }
+    let bundle_entries = match &bundle["entry"] {
+        Value::Array(entries) => entries.as_slice(),
+        Value::Null => &[],
+        _ => return None,
+    };
+
+    let mut entries = Vec::new();
+    let mut vitals = Vec::new();
+
+    for (index, bundle_entry) in bundle_entries.iter().enumerate() {
+        let resource = &bundle_entry["resource"];
+        let resource_type =
+            resource["resourceType"].as_str().unwrap_or("unknown");
+        let source_id = bundle_entry["fullUrl"]
+            .as_str()
+            .map(str::to_string)
+            .or_else(|| {
+                resource["id"]
+                    .as_str()
+                    .map(|id| format!("{resource_type}/{id}"))
+            })
+            .unwrap_or_else(|| format!("entry/{index}"));
+
+        let outcome = match resource_type {
+            "Patient" => {
+                Outcome::Skipped("the patient is the one importing".to_string())
+            }
+            "AllergyIntolerance" => map_allergy(resource),
+            "Condition" => map_condition(resource),
+            "Encounter" => map_encounter(resource),
+            "Observation" => match map_vital_sign(resource) {
+                Ok((sign, at)) => {
+                    vitals.push(Vital {
+                        source_id,
+                        sign,
+                        at,
+                    });
+                    continue;
+                }
+                Err(outcome) => outcome,
+            },
+            resource_type => {
+                Outcome::Unmappable(format!("{resource_type} is not supported"))
+            }
+        };
+
+        entries.push(Entry {
+            resource_type: resource_type.to_string(),
+            source_ids: vec![source_id],
+            outcome,
+        });
+    }
+
+    entries.extend(pair_vitals(vitals));
+
+    Some(entries)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    fn outcomes(bundle: Value) -> Vec<Outcome> {
+        map_bundle(&bundle)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.outcome)
+            .collect()
+    }
+
+    #[test]
+    fn test_allergy_severity() {
+        let outcomes = outcomes(json!({
+            "resourceType": "Bundle",
+            "entry": [
+                { "resource": {
+                    "resourceType": "AllergyIntolerance",
+                    "code": { "coding": [{ "display": "Penicillin" }] },
+                    "category": ["medication"],
+                    "onsetDateTime": "2019-03-04",
+                    "reaction": [{
+                        "manifestation": [
+                            { "text": "Anaphylaxis" },
+                            { "coding": [{ "code": "126485001" }] },
+                        ],
+                    }],
+                }},
+                { "resource": {
+                    "resourceType": "AllergyIntolerance",
+                    "code": { "text": "Peanuts" },
+                    "reaction": [
+                        { "manifestation": [], "severity": "mild" },
+                        { "manifestation": [], "severity": "moderate" },
+                    ],
+                }},
+                { "resource": {
+                    "resourceType": "AllergyIntolerance",
+                    "code": { "text": "Latex" },
+                    "verificationStatus": { "coding": [{ "code": "refuted" }] },
+                }},
+            ],
+        }));
+
+        assert_eq!(
+            outcomes[0],
+            Outcome::Mapped(Record::Allergy {
+                allergen: "Penicillin".to_string(),
+                severity: AllergySeverity::AnaphylacticShock,
+                category: Some(AllergyCategory::Drug),
+                reactions: vec![
+                    AllergyReaction::Anaphylaxis,
+                    AllergyReaction::Hives,
+                ],
+                onset: NaiveDate::from_ymd_opt(2019, 3, 4),
+            })
+        );
+        assert_eq!(
+            outcomes[1],
+            Outcome::Mapped(Record::Allergy {
+                allergen: "Peanuts".to_string(),
+                severity: AllergySeverity::Moderate,
+                category: None,
+                reactions: vec![],
+                onset: None,
+            })
+        );
+        assert!(matches!(outcomes[2], Outcome::Skipped(_)));
+    }
+
+    #[test]
+    fn test_height_needs_weight() {
+        let observation = |code: &str, value: f64, unit: &str, at: &str| {
+            json!({ "resource": {
+                "resourceType": "Observation",
+                "status": "final",
+                "code": { "coding": [{ "system": "http://loinc.org", "code": code }] },
+                "valueQuantity": { "value": value, "code": unit },
+                "effectiveDateTime": at,
+            }})
+        };
+
+        let outcomes = outcomes(json!({
+            "resourceType": "Bundle",
+            "entry": [
+                observation("8302-2", 1.7, "m", "2024-01-01"),
+                observation("29463-7", 143.3, "[lb_av]", "2024-01-01"),
+                observation("8302-2", 171.0, "cm", "2024-02-01"),
+                observation("8867-4", 70.0, "/min", "2024-02-01"),
+            ],
+        }));
+
+        assert!(matches!(outcomes[0], Outcome::Unmappable(_)));
+        let Outcome::Mapped(Record::Measurement {
+            height_in_cm,
+            weight_in_kg,
+            ..
+        }) = outcomes[1]
+        else {
+            panic!("height and weight were not paired");
+        };
+        assert_eq!(height_in_cm, 170.0);
+        assert!((weight_in_kg - 65.0).abs() < 0.1);
+        assert!(matches!(outcomes[2], Outcome::Unmappable(_)));
+    }
+
+    #[test]
+    fn test_not_a_bundle() {
+        assert!(map_bundle(&json!({ "resourceType": "Patient" })).is_none());
+        assert_eq!(
+            bundle_source(&json!({ "resourceType": "Bundle", "id": "abc" })),
+            Some("abc".to_string())
+        );
+    }
+}
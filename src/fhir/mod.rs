@@ -0,0 +1,496 @@
+//! FHIR R4 representation of a patient's record.
+//!
+//! Records map onto FHIR resources one by one: `user_details` is the
+//! Patient, `allergies` AllergyIntolerances, `medical_conditions` and
+//! `diagnoses` Conditions, `user_measurements` Observations (one for height
+//! and one for weight) as are `observations`, `consultations` Encounters and
+//! `prescriptions`
+//! MedicationRequests. A resource keeps the id of its row so references can be
+//! resolved within a `collection` [`bundle`] through `urn:uuid:` URLs.
+//!
+//! Only what the tables hold is exported, most of it as plain text since
+//! Medigram does not code diagnoses or medicines yet. Reading bundles from
+//! elsewhere is up to [`import`].
+
+pub mod import;
+
+use chrono::{DateTime, Utc};
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+use crate::schema::{
+    Allergy, AllergyCategory, AllergyReaction, AllergySeverity,
+    AllergyVerification, ConditionSource, ConditionStatus, Consultation,
+    Diagnosis, DiagnosisSeverity, MedicalCondition, Observation, Prescription,
+    UserDetail, UserMeasurement,
+};
+
+/// Media type of FHIR resources in JSON
+pub const FHIR_JSON: &str = "application/fhir+json";
+
+/// Identifier system of the Indonesian national identity number
+pub const NIK_SYSTEM: &str = "https://fhir.kemkes.go.id/id/nik";
+pub const DOCTOR_SYSTEM: &str = "urn:medigram:doctor-id";
+pub const LOCATION_SYSTEM: &str = "urn:medigram:location-id";
+
+const LOINC: &str = "http://loinc.org";
+const SNOMED: &str = "http://snomed.info/sct";
+const ICD_10: &str = "http://hl7.org/fhir/sid/icd-10";
+const UCUM: &str = "http://unitsofmeasure.org";
+pub const BODY_HEIGHT_LOINC: &str = "8302-2";
+pub const BODY_WEIGHT_LOINC: &str = "29463-7";
+
+fn reference(id: Uuid) -> Value {
+    json!({ "reference": format!("urn:uuid:{id}") })
+}
+
+/// Patient with nothing but their id, for readers not allowed to see the
+/// personal details
+pub fn patient_reference_only(user_id: Uuid) -> Value {
+    json!({ "resourceType": "Patient", "id": user_id })
+}
+
+pub fn patient(detail: &UserDetail) -> Value {
+    let gender = match detail.gender {
+        'M' => "male",
+        'F' => "female",
+        _ => "unknown",
+    };
+
+    json!({
+        "resourceType": "Patient",
+        "id": detail.user_id,
+        "identifier": [{
+            "system": NIK_SYSTEM,
+            "value": detail.nik.to_string(),
+        }],
+        "name": [{ "text": detail.name }],
+        "gender": gender,
+        "birthDate": detail.dob,
+    })
+}
+
+/// SNOMED CT codes of the reactions allergies are recorded with, `Other`
+/// has none
+pub const REACTION_SNOMED: &[(AllergyReaction, &str, &str)] = &[
+    (AllergyReaction::Rash, "271807003", "Eruption of skin"),
+    (AllergyReaction::Hives, "126485001", "Urticaria"),
+    (AllergyReaction::Itching, "418290006", "Itching"),
+    (AllergyReaction::Angioedema, "41291007", "Angioedema"),
+    (AllergyReaction::Wheezing, "56018004", "Wheezing"),
+    (AllergyReaction::ShortnessOfBreath, "267036007", "Dyspnea"),
+    (AllergyReaction::Nausea, "422587007", "Nausea"),
+    (AllergyReaction::Vomiting, "422400008", "Vomiting"),
+    (AllergyReaction::Diarrhea, "62315008", "Diarrhea"),
+    (AllergyReaction::Anaphylaxis, "39579001", "Anaphylaxis"),
+];
+
+fn manifestation(reaction: AllergyReaction) -> Value {
+    match REACTION_SNOMED
+        .iter()
+        .find(|(known, ..)| *known == reaction)
+    {
+        Some((_, code, display)) => json!({
+            "coding": [{ "system": SNOMED, "code": code, "display": display }],
+            "text": display,
+        }),
+        None => json!({ "text": "Other reaction" }),
+    }
+}
+
+pub fn allergy_intolerance(allergy: &Allergy) -> Value {
+    let (criticality, severity) = match allergy.severity {
+        AllergySeverity::Mild => ("low", "mild"),
+        AllergySeverity::Moderate => ("low", "moderate"),
+        AllergySeverity::Severe => ("high", "severe"),
+        AllergySeverity::AnaphylacticShock => ("high", "severe"),
+    };
+
+    let mut reactions = allergy.reactions.clone();
+    if allergy.severity == AllergySeverity::AnaphylacticShock
+        && !reactions.contains(&AllergyReaction::Anaphylaxis)
+    {
+        reactions.push(AllergyReaction::Anaphylaxis);
+    }
+    let manifestations: Vec<Value> = if reactions.is_empty() {
+        vec![json!({ "text": "Allergic reaction" })]
+    } else {
+        reactions.into_iter().map(manifestation).collect()
+    };
+
+    let category = allergy.category.map(|category| match category {
+        AllergyCategory::Drug => json!(["medication"]),
+        AllergyCategory::Food => json!(["food"]),
+        AllergyCategory::Environmental => json!(["environment"]),
+    });
+    let verification = match allergy.verification {
+        AllergyVerification::SelfReported => "unconfirmed",
+        AllergyVerification::ClinicianConfirmed => "confirmed",
+    };
+
+    json!({
+        "resourceType": "AllergyIntolerance",
+        "id": allergy.allergy_id,
+        "clinicalStatus": {
+            "coding": [{
+                "system": "http://terminology.hl7.org/CodeSystem/allergyintolerance-clinical",
+                "code": "active",
+            }],
+        },
+        "verificationStatus": {
+            "coding": [{
+                "system": "http://terminology.hl7.org/CodeSystem/allergyintolerance-verification",
+                "code": verification,
+            }],
+        },
+        "category": category,
+        "criticality": criticality,
+        "code": { "text": allergy.allergen },
+        "patient": reference(allergy.user_id),
+        "onsetDateTime": allergy.onset,
+        "reaction": [{
+            "manifestation": manifestations,
+            "severity": severity,
+        }],
+    })
+}
+
+fn condition_category(code: &str) -> Value {
+    json!([{
+        "coding": [{
+            "system": "http://terminology.hl7.org/CodeSystem/condition-category",
+            "code": code,
+        }],
+    }])
+}
+
+/// Code of a condition, ICD-10 when it was coded
+fn condition_code(text: &str, icd10_code: Option<&str>) -> Value {
+    match icd10_code {
+        Some(code) => json!({
+            "coding": [{ "system": ICD_10, "code": code }],
+            "text": text,
+        }),
+        None => json!({ "text": text }),
+    }
+}
+
+fn clinical_status(status: ConditionStatus) -> Value {
+    let code = match status {
+        ConditionStatus::Active => "active",
+        ConditionStatus::Resolved => "resolved",
+        ConditionStatus::InRemission => "remission",
+    };
+
+    json!({
+        "coding": [{
+            "system": "http://terminology.hl7.org/CodeSystem/condition-clinical",
+            "code": code,
+        }],
+    })
+}
+
+/// A condition on the patient's list, diagnosed in a consultation or
+/// reported by the patient, who a doctor may have confirmed it for
+pub fn problem(condition: &MedicalCondition) -> Value {
+    let verified = condition.source == ConditionSource::Diagnosed
+        || condition.confirmed_at.is_some();
+
+    json!({
+        "resourceType": "Condition",
+        "id": condition.condition_id,
+        "clinicalStatus": clinical_status(condition.status),
+        "verificationStatus": {
+            "coding": [{
+                "system": "http://terminology.hl7.org/CodeSystem/condition-ver-status",
+                "code": if verified { "confirmed" } else { "unconfirmed" },
+            }],
+        },
+        "category": condition_category("problem-list-item"),
+        "code": condition_code(
+            &condition.condition,
+            condition.icd10_code.as_deref(),
+        ),
+        "subject": reference(condition.user_id),
+        "encounter": condition.consultation_id.map(reference),
+        "onsetDateTime": condition.onset,
+        "recordedDate": condition.created_at,
+    })
+}
+
+/// Severity from the value set FHIR binds `Condition.severity` to
+fn condition_severity(severity: DiagnosisSeverity) -> Value {
+    let (code, display) = match severity {
+        DiagnosisSeverity::Mild => ("255604002", "Mild"),
+        DiagnosisSeverity::Moderate => ("6736007", "Moderate"),
+        DiagnosisSeverity::Severe => ("24484000", "Severe"),
+    };
+
+    json!({
+        "coding": [{ "system": SNOMED, "code": code, "display": display }],
+        "text": display,
+    })
+}
+
+/// A condition diagnosed during `consultation`
+pub fn diagnosis(diagnosis: &Diagnosis, consultation: &Consultation) -> Value {
+    json!({
+        "resourceType": "Condition",
+        "id": diagnosis.diagnosis_id,
+        "category": condition_category("encounter-diagnosis"),
+        "severity": diagnosis.severity.map(condition_severity),
+        "code": condition_code(
+            &diagnosis.diagnosis,
+            diagnosis.icd10_code.as_deref(),
+        ),
+        "subject": reference(consultation.user_id),
+        "encounter": reference(consultation.consultation_id),
+        "recordedDate": consultation.created_at,
+    })
+}
+
+fn quantity_observation(
+    id: Uuid,
+    user_id: Uuid,
+    code: &str,
+    display: &str,
+    value: Value,
+    unit: &str,
+    measured_at: DateTime<Utc>,
+) -> Value {
+    json!({
+        "resourceType": "Observation",
+        "id": id,
+        "status": "final",
+        "category": [{
+            "coding": [{
+                "system": "http://terminology.hl7.org/CodeSystem/observation-category",
+                "code": "vital-signs",
+            }],
+        }],
+        "code": {
+            "coding": [{ "system": LOINC, "code": code, "display": display }],
+            "text": display,
+        },
+        "subject": reference(user_id),
+        "effectiveDateTime": measured_at,
+        "valueQuantity": {
+            "value": value,
+            "unit": unit,
+            "system": UCUM,
+            "code": unit,
+        },
+    })
+}
+
+/// Height and weight of a measurement, their ids derived from the
+/// measurement's so exporting again gives the same ones
+pub fn observations(measurement: &UserMeasurement) -> [Value; 2] {
+    let id = measurement.measurement_id;
+
+    [
+        quantity_observation(
+            Uuid::new_v5(&id, b"height"),
+            measurement.user_id,
+            BODY_HEIGHT_LOINC,
+            "Body height",
+            json!(measurement.height_in_cm),
+            "cm",
+            measurement.measured_at,
+        ),
+        quantity_observation(
+            Uuid::new_v5(&id, b"weight"),
+            measurement.user_id,
+            BODY_WEIGHT_LOINC,
+            "Body weight",
+            json!(measurement.weight_in_kg),
+            "kg",
+            measurement.measured_at,
+        ),
+    ]
+}
+
+pub fn observation(observation: &Observation) -> Value {
+    let mut resource = quantity_observation(
+        observation.observation_id,
+        observation.user_id,
+        &observation.code,
+        &observation.name,
+        json!(observation.value),
+        &observation.unit,
+        observation.observed_at,
+    );
+    resource["category"][0]["coding"][0]["code"] = json!(observation.category);
+    if let Some(consultation_id) = observation.consultation_id {
+        resource["encounter"] = reference(consultation_id);
+    }
+
+    resource
+}
+
+pub fn encounter(consultation: &Consultation) -> Value {
+    json!({
+        "resourceType": "Encounter",
+        "id": consultation.consultation_id,
+        "status": "finished",
+        "class": {
+            "system": "http://terminology.hl7.org/CodeSystem/v3-ActCode",
+            "code": "AMB",
+            "display": "ambulatory",
+        },
+        "subject": reference(consultation.user_id),
+        "participant": [{
+            "individual": {
+                "identifier": {
+                    "system": DOCTOR_SYSTEM,
+                    "value": consultation.doctor_id,
+                },
+            },
+        }],
+        "period": { "start": consultation.created_at },
+        "reasonCode": [{ "text": consultation.symptoms }],
+        "location": [{
+            "location": {
+                "identifier": {
+                    "system": LOCATION_SYSTEM,
+                    "value": consultation.location_id,
+                },
+            },
+        }],
+    })
+}
+
+/// How often a day's regimen is taken, FHIR only counts in whole repetitions.
+/// `None` for a regimen that isn't positive, which has no repetition at all
+fn timing(regimen_per_day: f64) -> Option<Value> {
+    if !(regimen_per_day > 0.0 && regimen_per_day.is_finite()) {
+        return None;
+    }
+
+    Some(
+        if regimen_per_day >= 1.0 && regimen_per_day.fract() == 0.0 {
+            json!({
+                "frequency": regimen_per_day as u32,
+                "period": 1,
+                "periodUnit": "d",
+            })
+        } else {
+            json!({
+                "frequency": 1,
+                "period": 1.0 / regimen_per_day,
+                "periodUnit": "d",
+            })
+        },
+    )
+}
+
+pub fn medication_request(
+    prescription: &Prescription,
+    consultation: &Consultation,
+) -> Value {
+    let status = match prescription.purchased_at {
+        Some(_) => "completed",
+        None => "active",
+    };
+
+    // what is taken at once, e.g. 2 of the 500 mg tablets
+    let mut dosage = json!({
+        "text": prescription.instruction,
+        "doseAndRate": [{
+            "doseQuantity": {
+                "value": prescription.quantity_per_dose,
+                "unit": format!("x {} mg", prescription.doses_in_mg),
+            },
+        }],
+    });
+    if let Some(timing) = timing(prescription.regimen_per_day) {
+        dosage["timing"] = json!({ "repeat": timing });
+    }
+
+    json!({
+        "resourceType": "MedicationRequest",
+        "id": prescription.prescription_id,
+        "status": status,
+        "intent": "order",
+        "medicationCodeableConcept": { "text": prescription.drug_name },
+        "subject": reference(consultation.user_id),
+        "encounter": reference(consultation.consultation_id),
+        "authoredOn": consultation.created_at,
+        "requester": {
+            "identifier": {
+                "system": DOCTOR_SYSTEM,
+                "value": consultation.doctor_id,
+            },
+        },
+        "dosageInstruction": [dosage],
+    })
+}
+
+/// A `collection` Bundle of `resources`, each reachable at `urn:uuid:{id}`
+pub fn bundle(resources: Vec<Value>, timestamp: DateTime<Utc>) -> Value {
+    let entry: Vec<Value> = resources
+        .into_iter()
+        .map(|resource| {
+            let full_url = match resource["id"].as_str() {
+                Some(id) => format!("urn:uuid:{id}"),
+                None => String::new(),
+            };
+            json!({ "fullUrl": full_url, "resource": resource })
+        })
+        .collect();
+
+    json!({
+        "resourceType": "Bundle",
+        "id": Uuid::new_v4(),
+        "type": "collection",
+        "timestamp": timestamp,
+        "entry": entry,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn measurement() -> UserMeasurement {
+        UserMeasurement {
+            measurement_id: Uuid::new_v4(),
+            user_id: Uuid::nil(),
+            height_in_cm: 170.0,
+            weight_in_kg: 65.5,
+            measured_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_observation_ids_are_stable() {
+        let measurement = measurement();
+
+        let [height, weight] = observations(&measurement);
+        let [height_again, _] = observations(&measurement);
+
+        assert_eq!(height["id"], height_again["id"]);
+        assert_ne!(height["id"], weight["id"]);
+        assert_eq!(height["code"]["coding"][0]["code"], BODY_HEIGHT_LOINC);
+        assert_eq!(weight["valueQuantity"]["value"], 65.5);
+    }
+
+    #[test]
+    fn test_timing_of_fractional_regimen() {
+        assert_eq!(timing(3.0).unwrap()["frequency"], 3);
+        assert_eq!(timing(0.5).unwrap()["period"], 2.0);
+        assert_eq!(timing(0.0), None);
+    }
+
+    #[test]
+    fn test_bundle_entries_resolve_by_id() {
+        let bundle =
+            bundle(vec![patient_reference_only(Uuid::nil())], Utc::now());
+
+        assert_eq!(bundle["type"], "collection");
+        assert_eq!(
+            bundle["entry"][0]["fullUrl"],
+            format!("urn:uuid:{}", Uuid::nil())
+        );
+    }
+}
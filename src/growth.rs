@@ -0,0 +1,346 @@
+//! Children's growth against the WHO growth standards.
+//!
+//! WHO publishes its curves as LMS parameters per age: the Box-Cox power `L`,
+//! median `M` and coefficient of variation `S`, from which a measurement's
+//! z-score follows as in the WHO Anthro and AnthroPlus software. Monthly
+//! parameters are kept in `growth_standards` and interpolated between months.
+//! WHO's monthly tables ship under `data/growth_standards` and are loaded at
+//! startup by [`load_bundled_standards`], `src/bin/load_growth_standards.rs`
+//! refreshes them from newer files.
+
+use chrono::NaiveDate;
+use sqlx::{Pool, Postgres, query};
+use tracing::warn;
+
+use crate::schema::{GrowthIndicator, GrowthStandard};
+
+/// Average length of a month WHO counts ages in
+pub const DAYS_PER_MONTH: f64 = 30.4375;
+
+/// Age the growth reference ends at
+pub const MAX_AGE_IN_MONTHS: f64 = 228.0;
+
+/// Age in months on `date` of someone born on `dob`
+pub fn age_in_months(dob: NaiveDate, date: NaiveDate) -> f64 {
+    (date - dob).num_days() as f64 / DAYS_PER_MONTH
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lms {
+    pub l: f64,
+    pub m: f64,
+    pub s: f64,
+}
+
+impl Lms {
+    /// The measurement `z` standard deviations from the median
+    fn value_at(&self, z: f64) -> f64 {
+        let Lms { l, m, s } = *self;
+        if l == 0.0 {
+            m * (s * z).exp()
+        } else {
+            m * (1.0 + l * s * z).powf(1.0 / l)
+        }
+    }
+
+    fn z_score(&self, value: f64) -> f64 {
+        let Lms { l, m, s } = *self;
+        if l == 0.0 {
+            (value / m).ln() / s
+        } else {
+            ((value / m).powf(l) - 1.0) / (l * s)
+        }
+    }
+
+    /// z-score of `value`, which past 3 standard deviations WHO measures in
+    /// the distance between the second and third one for weight and BMI so
+    /// the skewed tail doesn't pull extremes towards the median
+    pub fn restricted_z_score(
+        &self,
+        indicator: GrowthIndicator,
+        value: f64,
+    ) -> f64 {
+        let z = self.z_score(value);
+        if indicator == GrowthIndicator::HeightForAge || z.abs() <= 3.0 {
+            return z;
+        }
+
+        let sign = z.signum();
+        let sd3 = self.value_at(3.0 * sign);
+        let sd23 = (sd3 - self.value_at(2.0 * sign)).abs();
+        3.0 * sign + (value - sd3) / sd23
+    }
+
+    fn interpolate(&self, other: &Lms, fraction: f64) -> Lms {
+        let between = |a: f64, b: f64| a + (b - a) * fraction;
+        Lms {
+            l: between(self.l, other.l),
+            m: between(self.m, other.m),
+            s: between(self.s, other.s),
+        }
+    }
+}
+
+/// Parameters of `indicator` at `age_in_months`, `None` past the standards
+pub fn lms_at(
+    standards: &[GrowthStandard],
+    indicator: GrowthIndicator,
+    age_in_months: f64,
+) -> Option<Lms> {
+    let month = |age: i32| {
+        standards
+            .iter()
+            .find(|standard| {
+                standard.indicator == indicator && standard.age_in_months == age
+            })
+            .map(|standard| Lms {
+                l: standard.l,
+                m: standard.m,
+                s: standard.s,
+            })
+    };
+
+    if !(0.0..=MAX_AGE_IN_MONTHS).contains(&age_in_months) {
+        return None;
+    }
+    let completed = age_in_months.floor();
+    let lower = month(completed as i32)?;
+    if completed == age_in_months {
+        return Some(lower);
+    }
+
+    let upper = month(completed as i32 + 1)?;
+    Some(lower.interpolate(&upper, age_in_months - completed))
+}
+
+/// One of WHO's monthly z-score tables, as exported to tab separated text
+pub struct BundledTable {
+    pub name: &'static str,
+    pub indicator: GrowthIndicator,
+    pub sex: &'static str,
+    pub table: &'static str,
+}
+
+macro_rules! bundled_table {
+    ($name:literal, $indicator:ident, $sex:literal) => {
+        BundledTable {
+            name: $name,
+            indicator: GrowthIndicator::$indicator,
+            sex: $sex,
+            table: include_str!(concat!(
+                "../data/growth_standards/",
+                $name,
+                ".txt"
+            )),
+        }
+    };
+}
+
+/// The child growth standards up to 5 years and the growth reference from 5
+/// to 19, weight-for-age of which ends at 10
+pub const BUNDLED_TABLES: &[BundledTable] = &[
+    bundled_table!("lhfa_boys_0-to-5-years_zscores", HeightForAge, "M"),
+    bundled_table!("lhfa_girls_0-to-5-years_zscores", HeightForAge, "F"),
+    bundled_table!("wfa_boys_0-to-5-years_zscores", WeightForAge, "M"),
+    bundled_table!("wfa_girls_0-to-5-years_zscores", WeightForAge, "F"),
+    bundled_table!("bmi_boys_0-to-5-years_zscores", BmiForAge, "M"),
+    bundled_table!("bmi_girls_0-to-5-years_zscores", BmiForAge, "F"),
+    bundled_table!("hfa-boys-z-who-2007-exp", HeightForAge, "M"),
+    bundled_table!("hfa-girls-z-who-2007-exp", HeightForAge, "F"),
+    bundled_table!("wfa-boys-z-who-2007-exp", WeightForAge, "M"),
+    bundled_table!("wfa-girls-z-who-2007-exp", WeightForAge, "F"),
+    bundled_table!("bmi-boys-z-who-2007-exp", BmiForAge, "M"),
+    bundled_table!("bmi-girls-z-who-2007-exp", BmiForAge, "F"),
+];
+
+/// Whether `line` is the header of a table indexed by day of age
+pub fn is_daily_header(line: &str) -> bool {
+    line.split(['\t', ',', ' '])
+        .map(|field| field.trim().trim_matches('"'))
+        .find(|field| !field.is_empty())
+        .is_some_and(|field| field.eq_ignore_ascii_case("day"))
+}
+
+/// Age in months with its L, M and S, or `None` for a line that isn't a row
+pub fn parse_row(line: &str) -> Option<(i32, f64, f64, f64)> {
+    let mut fields = line
+        .split(['\t', ',', ' '])
+        .map(|field| field.trim().trim_matches('"'))
+        .filter(|field| !field.is_empty());
+
+    let age_in_months = fields.next()?.parse().ok()?;
+    let l = fields.next()?.parse().ok()?;
+    let m = fields.next()?.parse().ok()?;
+    let s = fields.next()?.parse().ok()?;
+
+    Some((age_in_months, l, m, s))
+}
+
+/// Loads [`BUNDLED_TABLES`] into `growth_standards`, keeping the months that
+/// are already there so a refresh by the loader outlives restarts
+pub async fn load_bundled_standards(
+    db_pool: &Pool<Postgres>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+
+    for bundled in BUNDLED_TABLES {
+        let rows: Vec<_> =
+            bundled.table.lines().filter_map(parse_row).collect();
+        if rows.is_empty() {
+            warn!(
+                "Growth standard {} ships without rows, its months stay null",
+                bundled.name
+            );
+            continue;
+        }
+
+        query!(
+            "INSERT INTO growth_standards (indicator, sex, age_in_months, l, \
+             m, s) SELECT $1, $2, * FROM UNNEST($3::INTEGER[], \
+             $4::DOUBLE PRECISION[], $5::DOUBLE PRECISION[], \
+             $6::DOUBLE PRECISION[]) ON CONFLICT (indicator, sex, \
+             age_in_months) DO NOTHING",
+            bundled.indicator as GrowthIndicator,
+            bundled.sex,
+            &rows.iter().map(|row| row.0).collect::<Vec<_>>(),
+            &rows.iter().map(|row| row.1).collect::<Vec<_>>(),
+            &rows.iter().map(|row| row.2).collect::<Vec<_>>(),
+            &rows.iter().map(|row| row.3).collect::<Vec<_>>(),
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await
+}
+
+/// Share of children in percent measuring less than a z-score of `z`
+pub fn percentile(z: f64) -> f64 {
+    50.0 * (1.0 + erf(z / std::f64::consts::SQRT_2))
+}
+
+/// Abramowitz and Stegun 7.1.26, within 1.5e-7
+fn erf(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let polynomial = t
+        * (0.254829592
+            + t * (-0.284496736
+                + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    (1.0 - polynomial * (-x * x).exp()).copysign(x)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn standard(age_in_months: i32, m: f64) -> GrowthStandard {
+        GrowthStandard {
+            indicator: GrowthIndicator::WeightForAge,
+            sex: "M".to_string(),
+            age_in_months,
+            l: 0.2,
+            m,
+            s: 0.1,
+        }
+    }
+
+    #[test]
+    fn z_scores_from_lms() {
+        let lms = Lms {
+            l: 1.0,
+            m: 10.0,
+            s: 0.1,
+        };
+        assert!((lms.z_score(11.0) - 1.0).abs() < 1e-9);
+        assert!((lms.value_at(-2.0) - 8.0).abs() < 1e-9);
+
+        let log_normal = Lms { l: 0.0, ..lms };
+        assert!((log_normal.z_score(10.0 * 0.2_f64.exp()) - 2.0).abs() < 1e-9);
+
+        // 4 standard deviations out on the skewed curve, counted further out
+        // in distances between the second and third for weight
+        let skewed = Lms {
+            l: -1.0,
+            m: 10.0,
+            s: 0.1,
+        };
+        let value = skewed.value_at(4.0);
+        let sd3 = skewed.value_at(3.0);
+        let sd2 = skewed.value_at(2.0);
+        let weight =
+            skewed.restricted_z_score(GrowthIndicator::WeightForAge, value);
+        assert!((weight - (3.0 + (value - sd3) / (sd3 - sd2))).abs() < 1e-9);
+        assert!(weight > 4.0);
+        let height =
+            skewed.restricted_z_score(GrowthIndicator::HeightForAge, value);
+        assert!((height - 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn interpolated_between_months() {
+        let standards = [standard(12, 9.0), standard(13, 10.0)];
+
+        let lms =
+            lms_at(&standards, GrowthIndicator::WeightForAge, 12.25).unwrap();
+        assert!((lms.m - 9.25).abs() < 1e-9);
+        assert_eq!(
+            lms_at(&standards, GrowthIndicator::WeightForAge, 13.0)
+                .unwrap()
+                .m,
+            10.0
+        );
+        assert!(
+            lms_at(&standards, GrowthIndicator::WeightForAge, 13.5).is_none()
+        );
+        assert!(lms_at(&standards, GrowthIndicator::BmiForAge, 12.0).is_none());
+    }
+
+    #[test]
+    fn percentiles_of_the_normal_distribution() {
+        assert!((percentile(0.0) - 50.0).abs() < 1e-6);
+        assert!((percentile(1.959964) - 97.5).abs() < 1e-4);
+        assert!((percentile(-1.0) - 15.8655).abs() < 1e-3);
+    }
+
+    #[test]
+    fn bundled_tables_are_monthly() {
+        for bundled in BUNDLED_TABLES {
+            assert!(
+                !bundled.table.lines().any(is_daily_header),
+                "{}",
+                bundled.name
+            );
+
+            for (age_in_months, _, m, s) in
+                bundled.table.lines().filter_map(parse_row)
+            {
+                assert!(
+                    (0..=MAX_AGE_IN_MONTHS as i32).contains(&age_in_months)
+                        && m > 0.0
+                        && s > 0.0,
+                    "{} at {age_in_months} months",
+                    bundled.name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rows_of_who_tables() {
+        assert_eq!(
+            parse_row("12\t0.1\t9.5\t0.1\t7.0"),
+            Some((12, 0.1, 9.5, 0.1))
+        );
+        assert_eq!(parse_row("Month\tL\tM\tS"), None);
+        assert!(is_daily_header("Day\tL\tM\tS"));
+        assert!(!is_daily_header("Month\tL\tM\tS"));
+    }
+
+    #[test]
+    fn ages_in_months() {
+        let dob = NaiveDate::from_ymd_opt(2024, 1, 15).unwrap();
+        assert_eq!(age_in_months(dob, dob), 0.0);
+        let birthday = NaiveDate::from_ymd_opt(2025, 1, 15).unwrap();
+        assert!((age_in_months(dob, birthday) - 366.0 / 30.4375).abs() < 1e-9);
+    }
+}
@@ -1,21 +1,36 @@
+pub mod audit;
 pub mod auth;
 pub mod canonical_json;
+pub mod data_export;
+pub mod emergency_card;
 pub mod error;
+pub mod fhir;
+pub mod growth;
 pub mod model;
+pub mod pagination;
+pub mod policy;
 pub mod protocol;
 pub mod route;
 pub mod schema;
+pub mod summary;
 
+use auth::{
+    Session,
+    jwt::JwtKeys,
+    throttle::{FailedAttempts, TrustedProxies},
+    two_factor::TwoFactorChallenge,
+};
 use axum::{
     Router,
     extract::FromRef,
     routing::{delete, get, patch, post, put},
 };
+use emergency_card::CardIssuer;
 use protocol::Nonce;
 
 use std::time::Duration;
-use uuid::Uuid;
 
+use chrono::{DateTime, Utc};
 use moka::sync::Cache;
 use sqlx::Pool;
 use sqlx::postgres::Postgres;
@@ -24,30 +39,79 @@ use tower_http::{
     services::ServeDir,
     trace::TraceLayer,
 };
+use uuid::Uuid;
 
+use auth::{jwt, two_factor};
 use route::{
-    admin::{approve_location, promote_to_admin},
+    access_log::get_own_access_log,
+    account::{
+        cancel_own_account_deletion, download_own_data_export,
+        get_own_account_deletion, get_own_data_exports,
+        request_own_account_deletion, request_own_data_export,
+    },
+    admin::{
+        approve_location, grant_role, promote_to_admin, revoke_role,
+        rotate_signing_key,
+    },
     allergy::{
-        add_own_allergy, get_own_allergies, get_user_allergies,
-        remove_own_allergy,
+        add_own_allergy, confirm_user_allergy, get_own_allergies,
+        get_user_allergies, remove_own_allergy, update_own_allergy,
     },
+    audit::{get_audit_events, verify_audit_events},
     consultation::{
-        add_user_consultation, get_consultation_diagnoses,
-        get_consultation_prescriptions, get_doctor_consultations_with_user,
-        get_own_consultation_single, get_own_consultations,
-        get_own_consultations_as_doctor, get_user_consultations,
+        add_user_consultation, get_consultation_detail,
+        get_consultation_diagnoses, get_consultation_prescriptions,
+        get_doctor_consultations_with_user, get_own_consultation_single,
+        get_own_consultations, get_own_consultations_as_doctor,
+        get_own_prescriptions, get_own_reminders, get_user_consultations,
+        get_user_prescriptions, get_user_reminders,
         set_prescriptions_purchased_at, set_reminder,
     },
+    delegation::{
+        add_own_delegation, get_own_delegations, get_received_delegations,
+        revoke_own_delegation,
+    },
+    dependent::{
+        add_dependent_guardian, add_own_dependent, create_dependent_handover,
+        get_own_dependents, remove_dependent_guardian,
+    },
+    device::{
+        get_own_devices, logout_everywhere, revoke_own_device,
+        rotate_device_key,
+    },
     doctor_profile::{
         add_doctor_practice_location, delete_doctor_practice_location,
         get_doctor_profile, get_doctor_profile_by_user_id, set_doctor_profile,
     },
+    emergency::{
+        get_emergency_access_grants, open_emergency_access,
+        review_emergency_access,
+    },
+    emergency_card::{
+        get_emergency_card_key, get_own_emergency_card,
+        get_own_emergency_card_qr, publish_own_emergency_card,
+        render_emergency_card, withdraw_own_emergency_card,
+    },
+    export::{get_own_fhir_export, get_user_fhir_export},
+    growth::{get_own_growth, get_user_growth},
+    icd10::search_icd10_codes,
+    import::{get_own_external_encounters, get_own_imports, import_own_fhir},
     medical_condition::{
-        delete_own_conditions, get_own_conditions, get_user_conditions,
-        post_own_conditions,
+        confirm_user_condition, delete_own_conditions,
+        get_own_condition_history, get_own_conditions,
+        get_user_condition_history, get_user_conditions, post_own_conditions,
+        put_own_condition_status,
+    },
+    notification::{get_own_notifications, mark_notification_read},
+    observation::{
+        add_own_observations, get_observation_types, get_own_observations,
+        get_user_observations,
     },
     purchase::{add_own_purchase, get_own_purchases},
     request_nonce,
+    search::{search_own_history, search_user_history},
+    summary::{get_own_summaries, issue_own_summary_pdf, verify_summary},
+    trend::{get_own_trends, get_user_trends},
     user::{get_own_info, get_user_info},
     user_detail::{get_own_details, get_user_details, set_own_details},
     user_measurement::{
@@ -58,32 +122,81 @@ use route::{
 // 7d
 pub const NONCE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
 pub const SESSION_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+// 5m
+pub const TWO_FACTOR_CHALLENGE_TTL: Duration = Duration::from_secs(5 * 60);
+// 5m, how often `last_used_at` of a device in use is written
+pub const DEVICE_TOUCH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// 1h, how long a break-the-glass grant lasts
+pub const EMERGENCY_ACCESS_TTL: Duration = Duration::from_secs(60 * 60);
+// 7d, how long a guardian's handover code can be redeemed
+pub const HANDOVER_CODE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+// 1d, failed login attempts are forgotten after a day without new ones
+pub const LOGIN_THROTTLE_TTI: Duration = Duration::from_secs(24 * 60 * 60);
+// 30d, how long a deleted account can still be restored
+pub const ACCOUNT_DELETION_GRACE: Duration =
+    Duration::from_secs(30 * 24 * 60 * 60);
+// 7d, how long a personal data export can be downloaded
+pub const DATA_EXPORT_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+// 1h, after which an export still being built is assumed lost, e.g. to a
+// restart, and marked failed
+pub const DATA_EXPORT_STALE_AFTER: Duration = Duration::from_secs(60 * 60);
+// 1h, how often accounts past their grace period are looked for
+pub const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 #[derive(Clone)]
 pub struct AppState {
-    pub nonce_cache: Cache<Nonce, ()>,
+    /// Issue time of every nonce that has not been used yet
+    pub nonce_cache: Cache<Nonce, DateTime<Utc>>,
     pub db_pool: Pool<Postgres>,
-    pub recognized_session_id: Cache<String, Uuid>,
+    pub recognized_session_id: Cache<String, Session>,
+    pub two_factor_challenges: Cache<String, TwoFactorChallenge>,
+    pub login_throttle: Cache<String, FailedAttempts>,
+    /// Signing keys for access tokens, `None` unless JWT mode is enabled
+    pub jwt: Option<JwtKeys>,
+    pub card_issuer: CardIssuer,
+    /// Proxies the client address is taken from `X-Forwarded-For` behind
+    pub trusted_proxies: TrustedProxies,
+    /// Devices whose `last_used_at` was written within
+    /// [`DEVICE_TOUCH_INTERVAL`]
+    pub touched_devices: Cache<Uuid, ()>,
 }
 
-impl FromRef<AppState> for Cache<String, Uuid> {
+impl FromRef<AppState> for Cache<String, Session> {
     fn from_ref(input: &AppState) -> Self {
         input.recognized_session_id.clone()
     }
 }
 
+impl FromRef<AppState> for Option<JwtKeys> {
+    fn from_ref(input: &AppState) -> Self {
+        input.jwt.clone()
+    }
+}
+
 impl FromRef<AppState> for Pool<Postgres> {
     fn from_ref(input: &AppState) -> Self {
         input.db_pool.clone()
     }
 }
 
-impl FromRef<AppState> for Cache<Nonce, ()> {
+impl FromRef<AppState> for Cache<Nonce, DateTime<Utc>> {
     fn from_ref(input: &AppState) -> Self {
         input.nonce_cache.clone()
     }
 }
 
+impl FromRef<AppState> for Cache<Uuid, ()> {
+    fn from_ref(input: &AppState) -> Self {
+        input.touched_devices.clone()
+    }
+}
+
+impl FromRef<AppState> for TrustedProxies {
+    fn from_ref(input: &AppState) -> Self {
+        input.trusted_proxies.clone()
+    }
+}
+
 pub async fn health_check() -> String {
     "It works!".to_owned()
 }
@@ -100,7 +213,12 @@ pub fn app(state: AppState) -> Router {
         .route("/me/allergies", get(get_own_allergies))
         .route("/users/{user_id}/allergies", get(get_user_allergies))
         .route("/me/allergies", post(add_own_allergy))
+        .route("/me/allergies/{allergy_id}", put(update_own_allergy))
         .route("/me/allergies/{allergy_id}", delete(remove_own_allergy))
+        .route(
+            "/users/{user_id}/allergies/{allergy_id}/confirm",
+            post(confirm_user_allergy),
+        )
         // =================== CONSULTATIONS ===================
         .route("/me/consultations", get(get_own_consultations))
         .route(
@@ -124,6 +242,10 @@ pub fn app(state: AppState) -> Router {
             "/doctors/{doctor_id}/users/{user_id}/consultations",
             get(get_doctor_consultations_with_user),
         )
+        .route(
+            "/consultations/{consultation_id}",
+            get(get_consultation_detail),
+        )
         .route(
             "/consultations/{consultation_id}/diagnoses",
             get(get_consultation_diagnoses),
@@ -140,6 +262,21 @@ pub fn app(state: AppState) -> Router {
             "/consultations/{consultation_id}/reminder",
             put(set_reminder),
         )
+        .route("/me/prescriptions", get(get_own_prescriptions))
+        .route(
+            "/users/{user_id}/prescriptions",
+            get(get_user_prescriptions),
+        )
+        .route("/me/reminders", get(get_own_reminders))
+        .route("/users/{user_id}/reminders", get(get_user_reminders))
+        // =================== DELEGATIONS ===================
+        .route("/me/delegations", get(get_own_delegations))
+        .route("/me/delegations", post(add_own_delegation))
+        .route("/me/delegations/received", get(get_received_delegations))
+        .route(
+            "/me/delegations/{delegation_id}",
+            delete(revoke_own_delegation),
+        )
         // =================== USER INFORMATION ===================
         .route("/me", get(get_own_info))
         .route("/users/{user_id}", get(get_user_info))
@@ -149,8 +286,69 @@ pub fn app(state: AppState) -> Router {
         .route("/users/{user_id}/measurements", get(get_user_measurements))
         .route("/me/measurements", get(get_own_measurements))
         .route("/me/measurements", post(add_own_measurement))
+        .route("/users/{user_id}/measurements/trends", get(get_user_trends))
+        .route("/me/measurements/trends", get(get_own_trends))
+        .route("/users/{user_id}/growth", get(get_user_growth))
+        .route("/me/growth", get(get_own_growth))
+        .route("/observation-types", get(get_observation_types))
+        .route("/users/{user_id}/observations", get(get_user_observations))
+        .route("/me/observations", get(get_own_observations))
+        .route("/me/observations", post(add_own_observations))
         .route("/me/purchases", get(get_own_purchases))
         .route("/me/purchases", post(add_own_purchase))
+        .route("/me/access-log", get(get_own_access_log))
+        .route("/me/notifications", get(get_own_notifications))
+        .route(
+            "/me/notifications/{notification_id}/read",
+            post(mark_notification_read),
+        )
+        .route(
+            "/users/{user_id}/emergency-access",
+            post(open_emergency_access),
+        )
+        .route("/me/dependents", get(get_own_dependents))
+        .route("/me/dependents", post(add_own_dependent))
+        .route(
+            "/me/dependents/{dependent_id}/guardians",
+            post(add_dependent_guardian),
+        )
+        .route(
+            "/me/dependents/{dependent_id}/guardians/{guardian_id}",
+            delete(remove_dependent_guardian),
+        )
+        .route(
+            "/me/dependents/{dependent_id}/handover",
+            post(create_dependent_handover),
+        )
+        .route("/me/export/fhir", get(get_own_fhir_export))
+        .route("/users/{user_id}/export/fhir", get(get_user_fhir_export))
+        .route("/me/import/fhir", post(import_own_fhir))
+        .route("/me/imports", get(get_own_imports))
+        .route("/me/external-encounters", get(get_own_external_encounters))
+        .route("/me/summary/pdf", post(issue_own_summary_pdf))
+        .route("/me/summaries", get(get_own_summaries))
+        .route("/summaries/{summary_id}", get(verify_summary))
+        .route("/icd10", get(search_icd10_codes))
+        .route("/me/search", get(search_own_history))
+        .route("/users/{user_id}/search", get(search_user_history))
+        .route("/me/data-exports", post(request_own_data_export))
+        .route("/me/data-exports", get(get_own_data_exports))
+        .route(
+            "/me/data-exports/{export_id}/archive",
+            get(download_own_data_export),
+        )
+        .route("/me/account/deletion", post(request_own_account_deletion))
+        .route("/me/account/deletion", get(get_own_account_deletion))
+        .route("/me/account/deletion", delete(cancel_own_account_deletion))
+        .route("/me/emergency-card", get(get_own_emergency_card))
+        .route("/me/emergency-card", put(publish_own_emergency_card))
+        .route("/me/emergency-card", delete(withdraw_own_emergency_card))
+        .route("/me/emergency-card/qr", get(get_own_emergency_card_qr))
+        .route("/emergency-card/{token}", get(render_emergency_card))
+        .route(
+            "/.well-known/emergency-card-key",
+            get(get_emergency_card_key),
+        )
         // =================== DOCTOR PROFILES ===================
         .route("/doctors/{doctor_id}/profile", get(get_doctor_profile))
         .route(
@@ -173,21 +371,69 @@ pub fn app(state: AppState) -> Router {
             "/me/medical-conditions/{condition_id}",
             delete(delete_own_conditions),
         )
+        .route(
+            "/me/medical-conditions/{condition_id}/status",
+            put(put_own_condition_status),
+        )
+        .route(
+            "/me/medical-conditions/{condition_id}/history",
+            get(get_own_condition_history),
+        )
         .route(
             "/users/{user_id}/medical-conditions",
             get(get_user_conditions),
         )
+        .route(
+            "/users/{user_id}/medical-conditions/{condition_id}/history",
+            get(get_user_condition_history),
+        )
+        .route(
+            "/users/{user_id}/medical-conditions/{condition_id}/confirm",
+            post(confirm_user_condition),
+        )
         // =================== AUTH ===================
         .route("/login", post(auth::email::login))
+        .route("/login/two-factor", post(two_factor::login_two_factor))
         .route("/register", post(auth::email::register))
+        .route("/register/dependent", post(auth::email::register_dependent))
         .route("/logout", post(auth::logout))
+        .route("/token/refresh", post(jwt::refresh_tokens))
+        .route("/.well-known/jwks.json", get(jwt::get_jwks))
+        .route("/me/devices", get(get_own_devices))
+        .route("/me/devices/{device_id}", delete(revoke_own_device))
+        .route("/me/devices/logout-all", post(logout_everywhere))
+        .route("/me/devices/{device_id}/rotate", post(rotate_device_key))
         .route("/request-nonce", get(request_nonce))
+        .route("/me/two-factor", get(two_factor::get_two_factor_status))
+        .route(
+            "/me/two-factor/totp",
+            post(two_factor::begin_totp_enrollment),
+        )
+        .route("/me/two-factor/totp", delete(two_factor::disable_totp))
+        .route(
+            "/me/two-factor/totp/confirm",
+            post(two_factor::confirm_totp_enrollment),
+        )
+        .route(
+            "/me/two-factor/recovery-codes",
+            post(two_factor::regenerate_recovery_codes),
+        )
         // =================== ADMIN ===================
         .route("/users/{user_id}/promote-to-admin", post(promote_to_admin))
+        .route("/users/{user_id}/roles", post(grant_role))
+        .route("/users/{user_id}/roles/{role}", delete(revoke_role))
         .route(
             "/doctor/practice-location/{location_id}/approve",
             post(approve_location),
         )
+        .route("/admin/signing-key/rotate", post(rotate_signing_key))
+        .route("/audit-events", get(get_audit_events))
+        .route("/audit-events/verify", get(verify_audit_events))
+        .route("/admin/emergency-access", get(get_emergency_access_grants))
+        .route(
+            "/admin/emergency-access/{grant_id}/review",
+            post(review_emergency_access),
+        )
         // =================== STATIC FOR DOCS ===================
         .nest_service("/static/api", ServeDir::new("./static/api"))
         .layer(cors)
@@ -21,11 +21,47 @@
 //    - pakai informasi KTP (NIK) dan Nomor Telp
 //    - bisa tambah informasi kesehatan lain (berat badan, tinggi, alergi, dll)
 
-use medigram::{AppState, NONCE_TTL, SESSION_TTL};
+use medigram::{
+    AppState, DEVICE_TOUCH_INTERVAL, LOGIN_THROTTLE_TTI, NONCE_TTL,
+    PURGE_INTERVAL, SESSION_TTL, TWO_FACTOR_CHALLENGE_TTL,
+    auth::{jwt::JwtKeys, throttle::TrustedProxies},
+    data_export::{drop_expired_exports, fail_stale_exports},
+    emergency_card::CardIssuer,
+    growth::load_bundled_standards,
+    route::account::purge_due_accounts,
+};
+use std::net::SocketAddr;
+
+use axum::Router;
 use moka::sync::Cache;
+use shuttle_runtime::CustomError;
 use sqlx::Pool;
 use sqlx::postgres::Postgres;
-use std::time::Duration;
+
+/// Serves the app like `shuttle_axum::AxumService`, but with the peer address
+/// of every connection at hand as `ConnectInfo`, which the login throttle
+/// counts by unless the peer is a trusted proxy
+struct MedigramService(Router);
+
+#[shuttle_runtime::async_trait]
+impl shuttle_runtime::Service for MedigramService {
+    async fn bind(
+        self,
+        addr: SocketAddr,
+    ) -> Result<(), shuttle_runtime::Error> {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .map_err(CustomError::new)?;
+        axum::serve(
+            listener,
+            self.0.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .map_err(CustomError::new)?;
+
+        Ok(())
+    }
+}
 
 #[shuttle_runtime::main]
 async fn axum(
@@ -33,11 +69,14 @@ async fn axum(
         local_uri = "postgres://postgres@127.0.0.1:5432/medigram"
     )]
     db_pool: Pool<Postgres>,
-) -> shuttle_axum::ShuttleAxum {
+) -> Result<MedigramService, shuttle_runtime::Error> {
     sqlx::migrate!()
         .run(&db_pool)
         .await
         .expect("migration failed");
+    load_bundled_standards(&db_pool)
+        .await
+        .expect("loading the growth standards failed");
 
     let state = AppState {
         nonce_cache: Cache::builder().time_to_live(NONCE_TTL).build(),
@@ -45,9 +84,44 @@ async fn axum(
         recognized_session_id: Cache::builder()
             .time_to_live(SESSION_TTL)
             .build(),
+        two_factor_challenges: Cache::builder()
+            .time_to_live(TWO_FACTOR_CHALLENGE_TTL)
+            .build(),
+        login_throttle: Cache::builder()
+            .time_to_idle(LOGIN_THROTTLE_TTI)
+            .build(),
+        // stateless access tokens instead of server-side sessions
+        jwt: std::env::var("MEDIGRAM_JWT_MODE")
+            .is_ok_and(|value| value == "1" || value == "true")
+            .then(JwtKeys::generate),
+        card_issuer: CardIssuer::from_env(),
+        trusted_proxies: TrustedProxies::from_env(),
+        touched_devices: Cache::builder()
+            .time_to_live(DEVICE_TOUCH_INTERVAL)
+            .build(),
     };
 
+    // accounts past their grace period, exports past their expiry and
+    // exports whose build was lost
+    let sweeper = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            // the cause is logged where it happened
+            if purge_due_accounts(&sweeper).await.is_err() {
+                tracing::error!("Purging accounts failed, retrying next round");
+            }
+            if let Err(e) = drop_expired_exports(&sweeper.db_pool).await {
+                tracing::error!("Error while dropping exports: {:?}", e);
+            }
+            if let Err(e) = fail_stale_exports(&sweeper.db_pool).await {
+                tracing::error!("Error while failing stale exports: {:?}", e);
+            }
+        }
+    });
+
     let app = medigram::app(state);
 
-    Ok(app.into())
+    Ok(MedigramService(app))
 }
@@ -0,0 +1,347 @@
+//! Paging, filtering and sorting of list endpoints.
+//!
+//! Lists are ordered by a timestamp, with the row id breaking ties, and paged
+//! by keyset: the cursor is the position of the last row handed out, so rows
+//! added in the meantime don't shift the pages after it. Handlers take a
+//! [`Page`] from the query string, pass its bounds to their query and wrap the
+//! rows with [`Page::paginate`]. The body stays a plain array, the next page
+//! is announced in the `Link` and `X-Next-Cursor` headers.
+
+use axum::{
+    Json,
+    extract::{FromRequestParts, Query},
+    http::{HeaderName, HeaderValue, header, request::Parts},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use data_encoding::BASE64URL_NOPAD;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::error::AppError;
+
+pub const DEFAULT_PAGE_LIMIT: i64 = 50;
+pub const MAX_PAGE_LIMIT: i64 = 200;
+
+/// Header carrying the cursor of the next page, absent on the last one
+pub const NEXT_CURSOR_HEADER: HeaderName =
+    HeaderName::from_static("x-next-cursor");
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    Asc,
+    /// Latest first
+    #[default]
+    Desc,
+}
+
+#[derive(Deserialize)]
+pub struct PageParams {
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+    /// Inclusive
+    pub from: Option<DateTime<Utc>>,
+    /// Exclusive
+    pub to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub order: Order,
+}
+
+/// Position of a row in a list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    pub at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    /// Opaque to clients, Postgres keeps microseconds so that is what is kept
+    pub fn encode(&self) -> String {
+        BASE64URL_NOPAD.encode(
+            format!("{}.{}", self.at.timestamp_micros(), self.id).as_bytes(),
+        )
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let decoded = BASE64URL_NOPAD.decode(cursor.as_bytes()).ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        let (at, id) = decoded.split_once('.')?;
+
+        Some(Cursor {
+            at: DateTime::from_timestamp_micros(at.parse().ok()?)?,
+            id: id.parse().ok()?,
+        })
+    }
+}
+
+/// Which part of a list to return, see the module documentation
+#[derive(Debug, Clone)]
+pub struct Page {
+    pub limit: i64,
+    pub after: Option<Cursor>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub order: Order,
+    /// Path and query of the request, to link the next page from
+    path_and_query: String,
+}
+
+impl Page {
+    pub fn descending(&self) -> bool {
+        self.order == Order::Desc
+    }
+
+    pub fn after_at(&self) -> Option<DateTime<Utc>> {
+        self.after.map(|cursor| cursor.at)
+    }
+
+    pub fn after_id(&self) -> Option<Uuid> {
+        self.after.map(|cursor| cursor.id)
+    }
+
+    /// Rows to fetch, one more than asked for to tell whether there is a next
+    /// page
+    pub fn fetch_limit(&self) -> i64 {
+        self.limit + 1
+    }
+
+    /// Cuts `rows`, fetched with [`Page::fetch_limit`], down to the page and
+    /// points at the next one
+    pub fn paginate<T>(
+        &self,
+        mut rows: Vec<T>,
+        key: impl Fn(&T) -> (DateTime<Utc>, Uuid),
+    ) -> Paginated<T> {
+        let has_next = rows.len() as i64 > self.limit;
+        rows.truncate(self.limit as usize);
+
+        let next_cursor = rows.last().filter(|_| has_next).map(|row| {
+            let (at, id) = key(row);
+            Cursor { at, id }.encode()
+        });
+        let next_link = next_cursor
+            .as_ref()
+            .map(|cursor| self.link_with_cursor(cursor));
+
+        Paginated {
+            items: rows,
+            next_cursor,
+            next_link,
+        }
+    }
+
+    /// The request again, with `cursor` in place of its own
+    fn link_with_cursor(&self, cursor: &str) -> String {
+        let (path, query) = self
+            .path_and_query
+            .split_once('?')
+            .unwrap_or((&self.path_and_query, ""));
+
+        let mut params: Vec<&str> = query
+            .split('&')
+            .filter(|param| !param.is_empty() && !param.starts_with("cursor="))
+            .collect();
+        let cursor = format!("cursor={cursor}");
+        params.push(&cursor);
+
+        format!("<{path}?{}>; rel=\"next\"", params.join("&"))
+    }
+}
+
+impl<S> FromRequestParts<S> for Page
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let Query(PageParams {
+            limit,
+            cursor,
+            from,
+            to,
+            order,
+        }) = Query::<PageParams>::try_from_uri(&parts.uri)
+            .map_err(|_| AppError::InvalidPage)?;
+
+        let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+        if !(1..=MAX_PAGE_LIMIT).contains(&limit) {
+            return Err(AppError::InvalidPage);
+        }
+
+        let after = match cursor {
+            Some(cursor) => {
+                Some(Cursor::decode(&cursor).ok_or(AppError::InvalidPage)?)
+            }
+            None => None,
+        };
+
+        Ok(Page {
+            limit,
+            after,
+            from,
+            to,
+            order,
+            path_and_query: parts
+                .uri
+                .path_and_query()
+                .map(|path_and_query| path_and_query.as_str().to_string())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+/// Fetches a [`Page`] of `$record`s, [`Page::fetch_limit`] rows of them.
+///
+/// `$select` is the query up to its `WHERE` conditions, with its own
+/// parameters numbered from `$6` on and bound by `$args`: the bounds, cursor
+/// and limit of the page are appended as `$1` to `$5`, ordered by the `$at`
+/// column and the `$id` one. Each direction is its own static query so the
+/// `ORDER BY` and cursor comparison stay plain enough for an index on
+/// `($at, $id)`.
+macro_rules! fetch_page {
+    (
+        $record:ty,
+        $page:expr,
+        $executor:expr,
+        $select:tt,
+        $at:tt,
+        $id:tt
+        $(, $arg:expr)* $(,)?
+    ) => {{
+        let page: &$crate::pagination::Page = $page;
+        if page.descending() {
+            ::sqlx::query_as!(
+                $record,
+                $select
+                    + " AND ($1::TIMESTAMPTZ IS NULL OR " + $at + " >= $1)"
+                    + " AND ($2::TIMESTAMPTZ IS NULL OR " + $at + " < $2)"
+                    + " AND ($3::TIMESTAMPTZ IS NULL OR (" + $at + ", " + $id
+                    + ") < ($3, $4::UUID))"
+                    + " ORDER BY " + $at + " DESC, " + $id + " DESC LIMIT $5",
+                page.from,
+                page.to,
+                page.after_at(),
+                page.after_id(),
+                page.fetch_limit()
+                $(, $arg)*
+            )
+            .fetch_all($executor)
+            .await
+        } else {
+            ::sqlx::query_as!(
+                $record,
+                $select
+                    + " AND ($1::TIMESTAMPTZ IS NULL OR " + $at + " >= $1)"
+                    + " AND ($2::TIMESTAMPTZ IS NULL OR " + $at + " < $2)"
+                    + " AND ($3::TIMESTAMPTZ IS NULL OR (" + $at + ", " + $id
+                    + ") > ($3, $4::UUID))"
+                    + " ORDER BY " + $at + ", " + $id + " LIMIT $5",
+                page.from,
+                page.to,
+                page.after_at(),
+                page.after_id(),
+                page.fetch_limit()
+                $(, $arg)*
+            )
+            .fetch_all($executor)
+            .await
+        }
+    }};
+}
+pub(crate) use fetch_page;
+
+/// A page of a list, responds with the rows as JSON array
+pub struct Paginated<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+    next_link: Option<String>,
+}
+
+impl<T> Paginated<T> {
+    /// The same page with every row converted, e.g. to drop the columns only
+    /// fetched to order by
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Paginated<U> {
+        Paginated {
+            items: self.items.into_iter().map(f).collect(),
+            next_cursor: self.next_cursor,
+            next_link: self.next_link,
+        }
+    }
+}
+
+impl<T: Serialize> IntoResponse for Paginated<T> {
+    fn into_response(self) -> Response {
+        let mut response = Json(self.items).into_response();
+
+        let headers = response.headers_mut();
+        if let Some(cursor) = self
+            .next_cursor
+            .and_then(|cursor| HeaderValue::from_str(&cursor).ok())
+        {
+            headers.insert(NEXT_CURSOR_HEADER, cursor);
+        }
+        if let Some(link) = self
+            .next_link
+            .and_then(|link| HeaderValue::from_str(&link).ok())
+        {
+            headers.insert(header::LINK, link);
+        }
+
+        response
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn page(limit: i64, path_and_query: &str) -> Page {
+        Page {
+            limit,
+            after: None,
+            from: None,
+            to: None,
+            order: Order::Desc,
+            path_and_query: path_and_query.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_cursor_round_trips() {
+        let cursor = Cursor {
+            at: DateTime::from_timestamp_micros(1_751_360_400_123_456).unwrap(),
+            id: Uuid::new_v4(),
+        };
+
+        assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+        assert_eq!(Cursor::decode("not a cursor"), None);
+    }
+
+    #[test]
+    fn test_paginate_links_next_page() {
+        let at = Utc::now();
+        let ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+
+        let page = page(2, "/me/measurements?order=asc&cursor=old");
+        let paginated = page.paginate(ids.clone(), |id| (at, *id));
+        assert_eq!(paginated.items, ids[..2]);
+
+        let cursor = Cursor { at, id: ids[1] }.encode();
+        assert_eq!(paginated.next_cursor.as_deref(), Some(cursor.as_str()));
+        assert_eq!(
+            paginated.next_link.unwrap(),
+            format!(
+                "</me/measurements?order=asc&cursor={cursor}>; rel=\"next\""
+            )
+        );
+
+        let last = page.paginate(ids[..2].to_vec(), |id| (at, *id));
+        assert_eq!(last.next_cursor, None);
+        assert_eq!(last.next_link, None);
+    }
+}
@@ -0,0 +1,1015 @@
+//! Who may do what.
+//!
+//! Every authenticated request is made by a [`Principal`] holding one or more
+//! [`Role`]s. Each role grants a fixed set of [`Action`]s on [`Resource`]s,
+//! limited to a [`Scope`]: a patient may do most things to their own data, a
+//! doctor may read the records of the patients they attend, and so on.
+//! Handlers state what they are about to do through [`Principal::require`]
+//! and never inspect roles themselves.
+//!
+//! Every role except patient is privileged and only counts once the user gave
+//! a second factor at login.
+//!
+//! Patients and doctors are implied by the account and an approved doctor
+//! profile, admins live in `admins`, and pharmacists and auditors are granted
+//! by an admin through `user_roles`.
+//!
+//! A guardian may act as one of their dependents by sending the
+//! [`ACTING_AS_HEADER`], the principal is then the dependent with nothing but
+//! the patient role, see [`Principal::act_for`].
+//!
+//! Patients may also let a trusted contact read some of their records until a
+//! given time, which [`Principal::patient_scope`] resolves to
+//! [`Scope::Delegated`].
+
+use std::str::FromStr;
+
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use moka::sync::Cache;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, query, query_scalar};
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::{
+    auth::{AuthError, AuthUser, Session, jwt::JwtKeys},
+    error::{AppError, DatabaseError},
+};
+
+/// Header naming the dependent a guardian acts as
+pub const ACTING_AS_HEADER: &str = "x-acting-as";
+
+/// Age at which guardians lose control over a dependent
+pub const AGE_OF_MAJORITY: i32 = 18;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Patient,
+    /// Only held once the doctor profile has been approved
+    Doctor,
+    Pharmacist,
+    Admin,
+    Auditor,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Patient => "patient",
+            Role::Doctor => "doctor",
+            Role::Pharmacist => "pharmacist",
+            Role::Admin => "admin",
+            Role::Auditor => "auditor",
+        }
+    }
+
+    /// Whether holding the role requires a second factor
+    pub fn is_privileged(&self) -> bool {
+        !matches!(self, Role::Patient)
+    }
+
+    /// Whether the role is granted through `user_roles` rather than implied
+    pub fn is_assignable(&self) -> bool {
+        matches!(self, Role::Pharmacist | Role::Auditor)
+    }
+
+    fn grants(&self) -> &'static [Grant] {
+        match self {
+            Role::Patient => PATIENT_GRANTS,
+            Role::Doctor => DOCTOR_GRANTS,
+            Role::Pharmacist => PHARMACIST_GRANTS,
+            Role::Admin => ADMIN_GRANTS,
+            Role::Auditor => AUDITOR_GRANTS,
+        }
+    }
+
+    /// Whether the role allows `action` on `resource` within `scope`
+    pub fn permits(
+        &self,
+        action: Action,
+        resource: Resource,
+        scope: Scope,
+    ) -> bool {
+        self.grants().iter().any(|grant| {
+            grant.resource == resource
+                && grant.actions.contains(&action)
+                && grant.scope.covers(scope)
+        })
+    }
+}
+
+impl FromStr for Role {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "patient" => Ok(Role::Patient),
+            "doctor" => Ok(Role::Doctor),
+            "pharmacist" => Ok(Role::Pharmacist),
+            "admin" => Ok(Role::Admin),
+            "auditor" => Ok(Role::Auditor),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resource {
+    /// Account info and personal details
+    Profile,
+    Allergy,
+    Measurement,
+    MedicalCondition,
+    Consultation,
+    Diagnosis,
+    Prescription,
+    Purchase,
+    Device,
+    DoctorProfile,
+    PracticeLocation,
+    /// Roles of other users, including admin
+    Role,
+    /// Key that signs access tokens
+    SigningKey,
+    AuditLog,
+    /// Break-the-glass grants, see [`crate::route::emergency`]
+    EmergencyAccess,
+    Notification,
+    /// Public card with what first responders need to know
+    EmergencyCard,
+    /// Dependents the user is the guardian of
+    Dependent,
+    /// Medicine to take, from the prescriptions of consultations the patient
+    /// set a reminder for
+    Reminder,
+    /// Read access the patient handed to somebody they trust
+    Delegation,
+    /// Consultations held at other clinics, only ever imported
+    ExternalEncounter,
+    /// Printable summary of the whole record
+    Summary,
+    /// The account as a whole, exported or deleted at once
+    Account,
+}
+
+impl Resource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Resource::Profile => "profile",
+            Resource::Allergy => "allergy",
+            Resource::Measurement => "measurement",
+            Resource::MedicalCondition => "medical_condition",
+            Resource::Consultation => "consultation",
+            Resource::Diagnosis => "diagnosis",
+            Resource::Prescription => "prescription",
+            Resource::Purchase => "purchase",
+            Resource::Device => "device",
+            Resource::DoctorProfile => "doctor_profile",
+            Resource::PracticeLocation => "practice_location",
+            Resource::Role => "role",
+            Resource::SigningKey => "signing_key",
+            Resource::AuditLog => "audit_log",
+            Resource::EmergencyAccess => "emergency_access",
+            Resource::Notification => "notification",
+            Resource::EmergencyCard => "emergency_card",
+            Resource::Dependent => "dependent",
+            Resource::Reminder => "reminder",
+            Resource::Delegation => "delegation",
+            Resource::ExternalEncounter => "external_encounter",
+            Resource::Summary => "summary",
+            Resource::Account => "account",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Read,
+    Create,
+    Update,
+    Delete,
+    Approve,
+}
+
+/// Whose data an action touches, as seen from the principal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Data about the principal themselves
+    Own,
+    /// Records of a patient the principal held a consultation with, or of
+    /// a single consultation the principal was the doctor of
+    Attending,
+    /// Records of a patient the principal opened emergency access to
+    Emergency,
+    /// Records a patient delegated reading to the principal, see
+    /// [`crate::route::delegation`]
+    Delegated,
+    /// Records of a patient with a prescription still to be dispensed, as
+    /// seen by a pharmacist
+    Dispensing,
+    /// Anybody's data
+    Any,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Own => "own",
+            Scope::Attending => "attending",
+            Scope::Emergency => "emergency",
+            Scope::Delegated => "delegated",
+            Scope::Dispensing => "dispensing",
+            Scope::Any => "any",
+        }
+    }
+
+    /// Whether a grant with this scope also allows `other`
+    fn covers(&self, other: Scope) -> bool {
+        match self {
+            Scope::Any => true,
+            Scope::Attending => other == Scope::Attending,
+            Scope::Emergency => other == Scope::Emergency,
+            Scope::Delegated => other == Scope::Delegated,
+            Scope::Dispensing => other == Scope::Dispensing,
+            Scope::Own => other == Scope::Own,
+        }
+    }
+}
+
+struct Grant {
+    resource: Resource,
+    actions: &'static [Action],
+    scope: Scope,
+}
+
+const READ: &[Action] = &[Action::Read];
+const READ_CREATE: &[Action] = &[Action::Read, Action::Create];
+const ALL: &[Action] =
+    &[Action::Read, Action::Create, Action::Update, Action::Delete];
+
+const PATIENT_GRANTS: &[Grant] = &[
+    Grant {
+        resource: Resource::Profile,
+        actions: ALL,
+        scope: Scope::Own,
+    },
+    Grant {
+        resource: Resource::Allergy,
+        actions: ALL,
+        scope: Scope::Own,
+    },
+    Grant {
+        resource: Resource::Measurement,
+        actions: ALL,
+        scope: Scope::Own,
+    },
+    Grant {
+        resource: Resource::MedicalCondition,
+        actions: ALL,
+        scope: Scope::Own,
+    },
+    // consultations are written by doctors, patients only set reminders
+    Grant {
+        resource: Resource::Consultation,
+        actions: &[Action::Read, Action::Update],
+        scope: Scope::Own,
+    },
+    Grant {
+        resource: Resource::Diagnosis,
+        actions: READ,
+        scope: Scope::Own,
+    },
+    Grant {
+        resource: Resource::Prescription,
+        actions: &[Action::Read, Action::Update],
+        scope: Scope::Own,
+    },
+    Grant {
+        resource: Resource::Purchase,
+        actions: READ_CREATE,
+        scope: Scope::Own,
+    },
+    Grant {
+        resource: Resource::Device,
+        actions: ALL,
+        scope: Scope::Own,
+    },
+    // applying to become a doctor
+    Grant {
+        resource: Resource::DoctorProfile,
+        actions: READ_CREATE,
+        scope: Scope::Own,
+    },
+    Grant {
+        resource: Resource::PracticeLocation,
+        actions: ALL,
+        scope: Scope::Own,
+    },
+    Grant {
+        resource: Resource::Notification,
+        actions: &[Action::Read, Action::Update],
+        scope: Scope::Own,
+    },
+    Grant {
+        resource: Resource::EmergencyCard,
+        actions: ALL,
+        scope: Scope::Own,
+    },
+    Grant {
+        resource: Resource::Dependent,
+        actions: ALL,
+        scope: Scope::Own,
+    },
+    Grant {
+        resource: Resource::Reminder,
+        actions: READ,
+        scope: Scope::Own,
+    },
+    Grant {
+        resource: Resource::Delegation,
+        actions: ALL,
+        scope: Scope::Own,
+    },
+    // brought in by a FHIR import and never edited
+    Grant {
+        resource: Resource::ExternalEncounter,
+        actions: READ_CREATE,
+        scope: Scope::Own,
+    },
+    Grant {
+        resource: Resource::Summary,
+        actions: READ_CREATE,
+        scope: Scope::Own,
+    },
+    Grant {
+        resource: Resource::Account,
+        actions: ALL,
+        scope: Scope::Own,
+    },
+    // whatever a trusted contact was let to see, the delegation itself
+    // decides which of these apply
+    Grant {
+        resource: Resource::Reminder,
+        actions: READ,
+        scope: Scope::Delegated,
+    },
+    Grant {
+        resource: Resource::Prescription,
+        actions: READ,
+        scope: Scope::Delegated,
+    },
+    Grant {
+        resource: Resource::Measurement,
+        actions: READ,
+        scope: Scope::Delegated,
+    },
+    // approved doctors are public to every user
+    Grant {
+        resource: Resource::DoctorProfile,
+        actions: READ,
+        scope: Scope::Any,
+    },
+];
+
+const DOCTOR_GRANTS: &[Grant] = &[
+    Grant {
+        resource: Resource::Profile,
+        actions: READ,
+        scope: Scope::Attending,
+    },
+    // confirming what the patient reported themselves
+    Grant {
+        resource: Resource::Allergy,
+        actions: &[Action::Read, Action::Update],
+        scope: Scope::Attending,
+    },
+    Grant {
+        resource: Resource::Measurement,
+        actions: READ,
+        scope: Scope::Attending,
+    },
+    // confirming what the patient reported themselves
+    Grant {
+        resource: Resource::MedicalCondition,
+        actions: &[Action::Read, Action::Update],
+        scope: Scope::Attending,
+    },
+    Grant {
+        resource: Resource::Consultation,
+        actions: READ,
+        scope: Scope::Attending,
+    },
+    // the signed consent of the patient is what allows this
+    Grant {
+        resource: Resource::Consultation,
+        actions: &[Action::Create],
+        scope: Scope::Any,
+    },
+    Grant {
+        resource: Resource::Diagnosis,
+        actions: READ,
+        scope: Scope::Attending,
+    },
+    Grant {
+        resource: Resource::Prescription,
+        actions: READ,
+        scope: Scope::Attending,
+    },
+    // break the glass, only what is needed to treat a patient in an
+    // emergency
+    Grant {
+        resource: Resource::EmergencyAccess,
+        actions: &[Action::Create],
+        scope: Scope::Any,
+    },
+    Grant {
+        resource: Resource::Allergy,
+        actions: READ,
+        scope: Scope::Emergency,
+    },
+    Grant {
+        resource: Resource::MedicalCondition,
+        actions: READ,
+        scope: Scope::Emergency,
+    },
+];
+
+// checking a prescription against the patient before handing it out
+const PHARMACIST_GRANTS: &[Grant] = &[
+    Grant {
+        resource: Resource::Profile,
+        actions: READ,
+        scope: Scope::Dispensing,
+    },
+    Grant {
+        resource: Resource::Allergy,
+        actions: READ,
+        scope: Scope::Dispensing,
+    },
+    // dispensing marks the prescription as purchased
+    Grant {
+        resource: Resource::Prescription,
+        actions: &[Action::Read, Action::Update],
+        scope: Scope::Any,
+    },
+];
+
+const ADMIN_GRANTS: &[Grant] = &[
+    Grant {
+        resource: Resource::Role,
+        actions: &[Action::Read, Action::Create, Action::Delete],
+        scope: Scope::Any,
+    },
+    Grant {
+        resource: Resource::PracticeLocation,
+        actions: &[Action::Approve],
+        scope: Scope::Any,
+    },
+    Grant {
+        resource: Resource::SigningKey,
+        actions: &[Action::Update],
+        scope: Scope::Any,
+    },
+    Grant {
+        resource: Resource::AuditLog,
+        actions: READ,
+        scope: Scope::Any,
+    },
+    Grant {
+        resource: Resource::EmergencyAccess,
+        actions: &[Action::Read, Action::Approve],
+        scope: Scope::Any,
+    },
+];
+
+const AUDITOR_GRANTS: &[Grant] = &[Grant {
+    resource: Resource::AuditLog,
+    actions: READ,
+    scope: Scope::Any,
+}];
+
+/// Roles a user holds according to the database
+pub struct RoleGrants {
+    pub roles: Vec<Role>,
+    /// Set whenever the user has a doctor profile, approved or not
+    pub doctor_id: Option<Uuid>,
+}
+
+pub async fn load_roles(
+    user_id: Uuid,
+    db_pool: &Pool<Postgres>,
+) -> Result<RoleGrants, AppError> {
+    let record = query!(
+        r#"SELECT
+            d.doctor_id AS "doctor_id?",
+            d.approved_at IS NOT NULL AS "doctor_approved!",
+            EXISTS(SELECT 1 FROM admins WHERE user_id = $1) AS "is_admin!",
+            ARRAY(SELECT role FROM user_roles WHERE user_id = $1
+                ORDER BY role) AS "granted!"
+        FROM (SELECT $1::UUID AS user_id) AS u
+        LEFT JOIN doctor_profiles AS d ON d.user_id = u.user_id"#,
+        user_id
+    )
+    .fetch_one(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while loading roles of {}: {:?}", user_id, e);
+        AppError::InternalError
+    })?;
+
+    let mut roles = vec![Role::Patient];
+    if record.doctor_approved {
+        roles.push(Role::Doctor);
+    }
+    if record.is_admin {
+        roles.push(Role::Admin);
+    }
+    roles.extend(
+        record
+            .granted
+            .iter()
+            .filter_map(|role| role.parse::<Role>().ok()),
+    );
+
+    Ok(RoleGrants {
+        roles,
+        doctor_id: record.doctor_id,
+    })
+}
+
+/// The authenticated caller together with everything they are allowed to do
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub user_id: Uuid,
+    pub device_id: Uuid,
+    /// The session, or the `jti` of the access token in JWT mode
+    pub session_id: String,
+    pub roles: Vec<Role>,
+    /// Set whenever the user has a doctor profile, approved or not
+    pub doctor_id: Option<Uuid>,
+    /// Whether the privileged roles are usable
+    pub two_factor: bool,
+    /// The guardian acting as `user_id`, if any
+    pub guardian_id: Option<Uuid>,
+}
+
+/// Whether `guardian_id` currently looks after `dependent_id`, which ends by
+/// itself once the dependent comes of age
+pub async fn is_active_guardian(
+    guardian_id: Uuid,
+    dependent_id: Uuid,
+    db_pool: &Pool<Postgres>,
+) -> Result<bool, AppError> {
+    query_scalar!(
+        r#"SELECT EXISTS(
+            SELECT 1 FROM guardianships AS g
+            JOIN user_details AS ud ON ud.user_id = g.dependent_id
+            WHERE g.guardian_id = $1 AND g.dependent_id = $2
+                AND ud.dob + make_interval(years => $3) > CURRENT_DATE
+        ) AS "active!""#,
+        guardian_id,
+        dependent_id,
+        AGE_OF_MAJORITY
+    )
+    .fetch_one(db_pool)
+    .await
+    .map_err(|e| {
+        error!(
+            "Error while checking guardianship of {} over {}: {:?}",
+            guardian_id, dependent_id, e
+        );
+        AppError::InternalError
+    })
+}
+
+impl Principal {
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role)
+    }
+
+    /// Who to hold accountable for what this principal does, the guardian
+    /// when acting as a dependent
+    pub fn actor_id(&self) -> Uuid {
+        self.guardian_id.unwrap_or(self.user_id)
+    }
+
+    /// The principal of `dependent_id` as seen through their guardian.
+    ///
+    /// Only the patient role carries over, a guardian's other roles are theirs
+    /// and not the dependent's.
+    pub async fn act_for(
+        self,
+        dependent_id: Uuid,
+        db_pool: &Pool<Postgres>,
+    ) -> Result<Principal, AppError> {
+        if !is_active_guardian(self.user_id, dependent_id, db_pool).await? {
+            warn!("{} tried to act as {}", self.user_id, dependent_id);
+            return Err(AppError::NotGuardian);
+        }
+
+        Ok(Principal {
+            user_id: dependent_id,
+            device_id: self.device_id,
+            session_id: self.session_id,
+            roles: vec![Role::Patient],
+            doctor_id: None,
+            two_factor: false,
+            guardian_id: Some(self.user_id),
+        })
+    }
+
+    /// [`Scope::Own`] for the principal's own data, [`Scope::Any`] otherwise
+    pub fn scope_of(&self, owner: Uuid) -> Scope {
+        if owner == self.user_id {
+            Scope::Own
+        } else {
+            Scope::Any
+        }
+    }
+
+    /// Whether `patient_id` currently lets the principal read their
+    /// `resource`
+    pub async fn is_delegate(
+        &self,
+        patient_id: Uuid,
+        resource: Resource,
+        db_pool: &Pool<Postgres>,
+    ) -> Result<bool, AppError> {
+        query_scalar!(
+            r#"SELECT EXISTS(
+                SELECT 1 FROM delegations
+                WHERE delegate_id = $1 AND user_id = $2
+                    AND $3 = ANY(resources)
+                    AND expires_at > NOW() AND revoked_at IS NULL
+            ) AS "delegated!""#,
+            self.user_id,
+            patient_id,
+            resource.as_str()
+        )
+        .fetch_one(db_pool)
+        .await
+        .map_err(|e| {
+            error!(
+                "Error while checking delegation of {} to {}: {:?}",
+                patient_id, self.user_id, e
+            );
+            AppError::InternalError
+        })
+    }
+
+    /// How the principal relates to `patient_id` when it comes to
+    /// `resource`: themselves, a delegate, a doctor attending the patient or
+    /// with emergency access, a pharmacist dispensing to them, or anybody
+    /// else
+    pub async fn patient_scope(
+        &self,
+        patient_id: Uuid,
+        resource: Resource,
+        db_pool: &Pool<Postgres>,
+    ) -> Result<Scope, AppError> {
+        if patient_id == self.user_id {
+            return Ok(Scope::Own);
+        }
+
+        // before attending, a doctor may be a delegate for what they can't
+        // read as the doctor
+        if self.is_delegate(patient_id, resource, db_pool).await? {
+            return Ok(Scope::Delegated);
+        }
+
+        let Some(doctor_id) = self.doctor_id else {
+            return self.dispensing_scope(patient_id, db_pool).await;
+        };
+
+        let record = query!(
+            r#"SELECT
+                EXISTS(SELECT 1 FROM consultations
+                    WHERE doctor_id = $1 AND user_id = $2) AS "attending!",
+                EXISTS(SELECT 1 FROM emergency_access_grants
+                    WHERE doctor_id = $1 AND user_id = $2
+                        AND expires_at > NOW()) AS "emergency!""#,
+            doctor_id,
+            patient_id
+        )
+        .fetch_one(db_pool)
+        .await
+        .map_err(|e| {
+            error!(
+                "Error while relating doctor {} to {}: {:?}",
+                doctor_id, patient_id, e
+            );
+            AppError::InternalError
+        })?;
+
+        if record.attending {
+            Ok(Scope::Attending)
+        } else if record.emergency {
+            Ok(Scope::Emergency)
+        } else {
+            self.dispensing_scope(patient_id, db_pool).await
+        }
+    }
+
+    /// [`Scope::Dispensing`] for a pharmacist while `patient_id` has a
+    /// prescription that wasn't purchased yet, [`Scope::Any`] otherwise
+    async fn dispensing_scope(
+        &self,
+        patient_id: Uuid,
+        db_pool: &Pool<Postgres>,
+    ) -> Result<Scope, AppError> {
+        if !self.roles.contains(&Role::Pharmacist) {
+            return Ok(Scope::Any);
+        }
+
+        let dispensing = query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM prescriptions
+                JOIN consultations USING (consultation_id)
+                WHERE consultations.user_id = $1
+                    AND prescriptions.purchased_at IS NULL) AS "dispensing!""#,
+            patient_id
+        )
+        .fetch_one(db_pool)
+        .await
+        .map_err(|e| {
+            error!(
+                "Error while looking for prescriptions of {} to dispense: {:?}",
+                patient_id, e
+            );
+            AppError::InternalError
+        })?;
+
+        Ok(if dispensing {
+            Scope::Dispensing
+        } else {
+            Scope::Any
+        })
+    }
+
+    /// Rejects the request unless one of the principal's roles allows
+    /// `action` on `resource` within `scope`, returns the role that did
+    pub fn require(
+        &self,
+        action: Action,
+        resource: Resource,
+        scope: Scope,
+    ) -> Result<Role, AppError> {
+        let mut needs_two_factor = false;
+
+        for role in &self.roles {
+            if !role.permits(action, resource, scope) {
+                continue;
+            }
+
+            if role.is_privileged() && !self.two_factor {
+                needs_two_factor = true;
+                continue;
+            }
+
+            return Ok(*role);
+        }
+
+        if needs_two_factor {
+            warn!(
+                "{} tried to {:?} {:?} without 2FA enrolled",
+                self.user_id, action, resource
+            );
+            return Err(AuthError::TwoFactorRequired.into());
+        }
+
+        Err(AppError::PermissionDenied)
+    }
+
+    /// The principal's doctor profile, approved or not.
+    ///
+    /// Applicants are held to the 2FA policy as well, so this fails without a
+    /// second factor even though no privileged role is involved yet.
+    pub fn doctor_profile(&self) -> Result<Uuid, AppError> {
+        let doctor_id = self.doctor_id.ok_or(DatabaseError::RowNotFound)?;
+
+        if !self.two_factor {
+            warn!("{} used their doctor profile without 2FA", self.user_id);
+            return Err(AuthError::TwoFactorRequired.into());
+        }
+
+        Ok(doctor_id)
+    }
+}
+
+impl<S> FromRequestParts<S> for Principal
+where
+    S: Send + Sync,
+    Cache<String, Session>: FromRef<S>,
+    Cache<Uuid, ()>: FromRef<S>,
+    Option<JwtKeys>: FromRef<S>,
+    Pool<Postgres>: FromRef<S>,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let AuthUser {
+            user_id,
+            device_id,
+            session_id,
+            two_factor,
+            claims,
+        } = AuthUser::from_request_parts(parts, state).await?;
+
+        let acting_as = match parts.headers.get(ACTING_AS_HEADER) {
+            Some(value) => Some(
+                value
+                    .to_str()
+                    .ok()
+                    .and_then(|value| value.parse::<Uuid>().ok())
+                    .ok_or(AppError::NotGuardian)?,
+            ),
+            None => None,
+        };
+        let db_pool = Pool::<Postgres>::from_ref(state);
+
+        // access tokens carry the roles, no need to ask the database
+        let principal = if let Some(claims) = claims {
+            Principal {
+                user_id,
+                device_id,
+                session_id,
+                two_factor,
+                roles: claims.roles,
+                doctor_id: claims.doctor_id,
+                guardian_id: None,
+            }
+        } else {
+            let RoleGrants { roles, doctor_id } =
+                load_roles(user_id, &db_pool).await?;
+
+            Principal {
+                user_id,
+                device_id,
+                session_id,
+                roles,
+                doctor_id,
+                two_factor,
+                guardian_id: None,
+            }
+        };
+
+        match acting_as {
+            Some(dependent_id) => {
+                principal.act_for(dependent_id, &db_pool).await
+            }
+            None => Ok(principal),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn principal(roles: Vec<Role>, two_factor: bool) -> Principal {
+        Principal {
+            user_id: Uuid::nil(),
+            device_id: Uuid::nil(),
+            session_id: String::new(),
+            roles,
+            doctor_id: None,
+            two_factor,
+            guardian_id: None,
+        }
+    }
+
+    #[test]
+    fn test_patient_only_touches_own_data() {
+        let patient = principal(vec![Role::Patient], false);
+
+        assert!(
+            patient
+                .require(Action::Delete, Resource::Allergy, Scope::Own)
+                .is_ok()
+        );
+        assert!(
+            patient
+                .require(Action::Read, Resource::Allergy, Scope::Any)
+                .is_err()
+        );
+        assert!(
+            patient
+                .require(Action::Create, Resource::Consultation, Scope::Own)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_doctor_reads_records_only_when_attending() {
+        let doctor = principal(vec![Role::Patient, Role::Doctor], true);
+
+        assert!(
+            doctor
+                .require(Action::Read, Resource::Allergy, Scope::Attending)
+                .is_ok()
+        );
+        assert!(
+            doctor
+                .require(Action::Read, Resource::Allergy, Scope::Any)
+                .is_err()
+        );
+        assert!(
+            doctor
+                .require(Action::Read, Resource::Diagnosis, Scope::Attending)
+                .is_ok()
+        );
+        assert!(
+            doctor
+                .require(Action::Read, Resource::Diagnosis, Scope::Any)
+                .is_err()
+        );
+        assert!(
+            doctor
+                .require(Action::Update, Resource::Allergy, Scope::Any)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_emergency_access_is_limited() {
+        let doctor = principal(vec![Role::Patient, Role::Doctor], true);
+
+        assert!(
+            doctor
+                .require(Action::Read, Resource::Allergy, Scope::Emergency)
+                .is_ok()
+        );
+        assert!(
+            doctor
+                .require(Action::Read, Resource::Consultation, Scope::Emergency)
+                .is_err()
+        );
+        assert!(
+            doctor
+                .require(Action::Update, Resource::Allergy, Scope::Emergency)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_delegates_only_read() {
+        let delegate = principal(vec![Role::Patient], false);
+
+        assert!(
+            delegate
+                .require(Action::Read, Resource::Reminder, Scope::Delegated)
+                .is_ok()
+        );
+        assert!(
+            delegate
+                .require(Action::Read, Resource::Allergy, Scope::Delegated)
+                .is_err()
+        );
+        assert!(
+            delegate
+                .require(
+                    Action::Update,
+                    Resource::Prescription,
+                    Scope::Delegated
+                )
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_privileged_roles_need_two_factor() {
+        let doctor = principal(vec![Role::Patient, Role::Doctor], false);
+
+        assert!(matches!(
+            doctor.require(Action::Read, Resource::Allergy, Scope::Attending),
+            Err(AppError::Auth(AuthError::TwoFactorRequired))
+        ));
+        // the patient role still works without it
+        assert!(
+            doctor
+                .require(Action::Read, Resource::Allergy, Scope::Own)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_role_round_trip() {
+        for role in [
+            Role::Patient,
+            Role::Doctor,
+            Role::Pharmacist,
+            Role::Admin,
+            Role::Auditor,
+        ] {
+            assert_eq!(role.as_str().parse::<Role>(), Ok(role));
+        }
+    }
+}
@@ -88,6 +88,37 @@ impl Consent {
     }
 }
 
+/// Hand-over of a device to a new key pair.
+///
+/// The device signs the new public key together with a fresh nonce using its
+/// current private key, proving that whoever installs the new key also held
+/// the old one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyRotation {
+    pub public_key_pem: String,
+    pub nonce: Nonce,
+
+    #[serde(
+        serialize_with = "serialize_signature",
+        deserialize_with = "deserialize_signature"
+    )]
+    pub signature: Signature,
+}
+
+impl KeyRotation {
+    /// Verify that the current key of `device_id` signed the hand-over.
+    pub fn verify(&self, device_id: Uuid, current_pk: &PublicKey) -> bool {
+        let message =
+            to_string(&(device_id, &self.public_key_pem, &self.nonce));
+
+        match message {
+            Ok(msg) => current_pk.verify(msg, &self.signature).is_ok(),
+
+            Err(_) => false,
+        }
+    }
+}
+
 pub enum ConsentError {
     /// Error from user not consenting to the procedure
     ///
@@ -109,6 +140,10 @@ pub enum ConsentError {
     ///
     /// Returns `StatusCode::GONE`
     KeyExpired,
+    /// Error from a public key that cannot be parsed
+    ///
+    /// Returns `StatusCode::BAD_REQUEST`
+    InvalidPublicKey,
 }
 
 impl IntoResponse for ConsentError {
@@ -129,6 +164,9 @@ impl IntoResponse for ConsentError {
             ConsentError::KeyExpired => {
                 (StatusCode::GONE, "Consesnt has expired")
             }
+            ConsentError::InvalidPublicKey => {
+                (StatusCode::BAD_REQUEST, "Invalid public key")
+            }
         };
 
         let body = Json(serde_json::json!({
@@ -198,6 +236,7 @@ where
     ed25519_compact::Signature::from_slice(&decoded).map_err(D::Error::custom)
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
 struct ConsultationData {
     diagnoses: String,
@@ -208,6 +247,7 @@ struct ConsultationData {
     patient_consent: Consent,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
 struct PrescriptionData {
     doctor_consent: Consent,
@@ -275,4 +315,25 @@ mod test {
 
         assert!(!consent.verify(&another_keypair.pk));
     }
+
+    #[test]
+    fn test_key_rotation_verification() {
+        let old_keypair = KeyPair::generate();
+        let new_keypair = KeyPair::generate();
+        let nonce = String::from("abcdefghijklmnop");
+        let public_key_pem = new_keypair.pk.to_pem();
+        let message =
+            to_string(&(Uuid::nil(), &public_key_pem, &nonce)).unwrap();
+        let signature = old_keypair.sk.sign(message, None);
+
+        let rotation = KeyRotation {
+            public_key_pem,
+            nonce,
+            signature,
+        };
+
+        assert!(rotation.verify(Uuid::nil(), &old_keypair.pk));
+        assert!(!rotation.verify(Uuid::nil(), &new_keypair.pk));
+        assert!(!rotation.verify(Uuid::max(), &old_keypair.pk));
+    }
 }
@@ -0,0 +1,114 @@
+//! Who read a patient's records.
+//!
+//! Handlers that return somebody else's data go through [`authorize_read`],
+//! which writes an entry to the append-only `access_log` table whenever the
+//! reader is not the owner. Patients see those entries through
+//! `GET /me/access-log`.
+
+use axum::extract::State;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{Pool, Postgres, query};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    error::{APIResult, AppError},
+    pagination::{Page, Paginated, fetch_page},
+    policy::{Action, Principal, Resource, Role, Scope},
+};
+
+#[derive(Serialize)]
+pub struct AccessLogEntry {
+    pub log_id: Uuid,
+    pub actor_id: Uuid,
+    /// Absent when the reader has not filled in their details
+    pub actor_name: Option<String>,
+    pub role: String,
+    pub scope: String,
+    pub resource: String,
+    pub accessed_at: DateTime<Utc>,
+}
+
+/// Requires read access to `owner`'s `resource` and records it in the access
+/// log unless the principal is the owner
+pub async fn authorize_read(
+    principal: &Principal,
+    resource: Resource,
+    owner: Uuid,
+    scope: Scope,
+    db_pool: &Pool<Postgres>,
+) -> APIResult<()> {
+    let role = principal.require(Action::Read, resource, scope)?;
+
+    log_read(principal, role, resource, owner, scope, db_pool).await
+}
+
+/// Records a read already authorized as `role`, for handlers that only log
+/// once they found something to return.
+///
+/// A guardian acting for a dependent is the one recorded, and reading the
+/// dependent's records is logged like anybody else's.
+pub async fn log_read(
+    principal: &Principal,
+    role: Role,
+    resource: Resource,
+    owner: Uuid,
+    scope: Scope,
+    db_pool: &Pool<Postgres>,
+) -> APIResult<()> {
+    let actor_id = principal.actor_id();
+    if owner == actor_id {
+        return Ok(());
+    }
+
+    query!(
+        "INSERT INTO access_log (user_id, actor_id, role, scope, resource) \
+         VALUES ($1, $2, $3, $4, $5)",
+        owner,
+        actor_id,
+        role.as_str(),
+        scope.as_str(),
+        resource.as_str()
+    )
+    .execute(db_pool)
+    .await
+    .map_err(|e| {
+        error!(
+            "Error while logging access of {} to {}: {:?}",
+            actor_id, owner, e
+        );
+        AppError::InternalError
+    })?;
+
+    Ok(())
+}
+
+pub async fn get_own_access_log(
+    State(state): State<AppState>,
+    principal: Principal,
+    page: Page,
+) -> APIResult<Paginated<AccessLogEntry>> {
+    principal.require(Action::Read, Resource::Profile, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    fetch_page!(
+        AccessLogEntry,
+        &page,
+        &state.db_pool,
+        r#"SELECT a.log_id, a.actor_id, ud.name AS "actor_name?", a.role,
+            a.scope, a.resource, a.accessed_at
+        FROM access_log AS a
+        LEFT JOIN user_details AS ud ON ud.user_id = a.actor_id
+        WHERE a.user_id = $6"#,
+        "a.accessed_at",
+        "a.log_id",
+        user_id
+    )
+    .map(|rows| page.paginate(rows, |entry| (entry.accessed_at, entry.log_id)))
+    .map_err(|e| {
+        error!("Error while retrieving access log for {}: {:?}", user_id, e);
+        AppError::InternalError
+    })
+}
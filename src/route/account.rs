@@ -0,0 +1,463 @@
+//! The account as a whole: exporting and deleting it.
+//!
+//! Under the PDP law (UU 27/2022) a patient may take a copy of their data
+//! (see [`crate::data_export`]) and have it erased. Deleting an account ends
+//! every session and revokes every device right away, but the data is only
+//! purged once [`ACCOUNT_DELETION_GRACE`] has passed, until then logging in
+//! again and cancelling restores everything.
+//!
+//! Purging erases whatever the user recorded themselves. Consultations with
+//! their diagnoses, prescriptions, observations and the conditions diagnosed
+//! in them were written by doctors and have to be retained, as do purchases,
+//! the access log and audit events. These keep referring to the user id, but
+//! with details, email and password gone, and the payloads of the audit
+//! events about the user scrubbed, nothing ties it to a person anymore.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::{StatusCode, header},
+    response::IntoResponse,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{Value, json};
+use sqlx::{query, query_as, query_scalar};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    ACCOUNT_DELETION_GRACE, AppState, audit,
+    auth::end_user_sessions,
+    data_export::build_export,
+    error::{APIResult, AppError, DatabaseError},
+    pagination::{Page, Paginated, fetch_page},
+    policy::{Action, Principal, Resource, Scope},
+};
+
+#[derive(Serialize)]
+pub struct DataExport {
+    pub export_id: Uuid,
+    /// The guardian when asked for a dependent
+    pub requested_by: Uuid,
+    pub requested_at: DateTime<Utc>,
+    /// `pending`, `ready` or `failed`
+    pub status: String,
+    pub completed_at: Option<DateTime<Utc>>,
+    /// Until when the archive can be downloaded
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct AccountDeletion {
+    pub requested_by: Uuid,
+    pub requested_at: DateTime<Utc>,
+    pub purge_after: DateTime<Utc>,
+}
+
+/// Starts building an export of the caller's data in the background
+pub async fn request_own_data_export(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> APIResult<(StatusCode, Json<Value>)> {
+    principal.require(Action::Create, Resource::Account, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    let export_id = query_scalar!(
+        "INSERT INTO data_exports (user_id, requested_by) VALUES ($1, $2) \
+         RETURNING export_id",
+        user_id,
+        principal.actor_id()
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| match e {
+        // only one is built at a time
+        sqlx::Error::Database(e) if e.is_unique_violation() => {
+            AppError::ExportPending
+        }
+        e => {
+            error!("Error while requesting export of {}: {:?}", user_id, e);
+            AppError::InternalError
+        }
+    })?;
+
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "data_export.request",
+        json!({ "export_id": export_id }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    tokio::spawn(build_export(export_id, user_id, state.db_pool.clone()));
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(json!({
+            "message": "export requested",
+            "export_id": export_id,
+        })),
+    ))
+}
+
+pub async fn get_own_data_exports(
+    State(state): State<AppState>,
+    principal: Principal,
+    page: Page,
+) -> APIResult<Paginated<DataExport>> {
+    principal.require(Action::Read, Resource::Account, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    fetch_page!(
+        DataExport,
+        &page,
+        &state.db_pool,
+        "SELECT export_id, requested_by, requested_at, status, completed_at,
+            expires_at
+         FROM data_exports
+         WHERE user_id = $6",
+        "requested_at",
+        "export_id",
+        user_id
+    )
+    .map(|rows| {
+        page.paginate(rows, |export| (export.requested_at, export.export_id))
+    })
+    .map_err(|e| {
+        error!("Error while retrieving exports of {}: {:?}", user_id, e);
+        AppError::InternalError
+    })
+}
+
+pub async fn download_own_data_export(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(export_id): Path<Uuid>,
+) -> APIResult<impl IntoResponse> {
+    principal.require(Action::Read, Resource::Account, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    let export = query!(
+        "SELECT status, archive, expires_at FROM data_exports WHERE \
+         export_id = $1 AND user_id = $2",
+        export_id,
+        user_id
+    )
+    .fetch_optional(&state.db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while retrieving export {}: {:?}", export_id, e);
+        AppError::InternalError
+    })?
+    .ok_or(DatabaseError::RowNotFound)?;
+
+    if export.status == "pending" {
+        return Err(AppError::ExportNotReady);
+    }
+    // failed, or expired and dropped
+    let archive = export
+        .archive
+        .filter(|_| export.expires_at.is_some_and(|at| at > Utc::now()))
+        .ok_or(DatabaseError::RowNotFound)?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"medigram-{export_id}.zip\""),
+            ),
+        ],
+        archive,
+    ))
+}
+
+/// Schedules the caller's account for purging and signs them out everywhere
+pub async fn request_own_account_deletion(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> APIResult<(StatusCode, Json<Value>)> {
+    principal.require(Action::Delete, Resource::Account, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    // admins have to step down first, and dependents need somebody else to
+    // look after them
+    let blocked = query_scalar!(
+        r#"SELECT
+            EXISTS(SELECT 1 FROM admins WHERE user_id = $1)
+            OR EXISTS(
+                SELECT 1 FROM guardianships AS g
+                JOIN users AS u ON u.user_id = g.dependent_id
+                WHERE g.guardian_id = $1 AND u.email IS NULL
+                    AND NOT EXISTS(
+                        SELECT 1 FROM guardianships AS other
+                        WHERE other.dependent_id = g.dependent_id
+                            AND other.guardian_id <> $1
+                    )
+            ) AS "blocked!""#,
+        user_id
+    )
+    .fetch_one(&state.db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while checking deletion of {}: {:?}", user_id, e);
+        AppError::InternalError
+    })?;
+
+    if blocked {
+        return Err(AppError::AccountDeletionBlocked);
+    }
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    // asking again keeps the original schedule
+    let purge_after = query_scalar!(
+        "INSERT INTO account_deletions (user_id, requested_by, purge_after) \
+         VALUES ($1, $2, $3) ON CONFLICT (user_id) DO UPDATE SET \
+         purge_after = account_deletions.purge_after RETURNING purge_after",
+        user_id,
+        principal.actor_id(),
+        Utc::now() + ACCOUNT_DELETION_GRACE
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!("Error while scheduling deletion of {}: {:?}", user_id, e);
+        AppError::InternalError
+    })?;
+
+    let revoked = query!(
+        "UPDATE device_keys SET revoked_at = NOW() WHERE user_id = $1 AND \
+         revoked_at IS NULL",
+        user_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!("Error while revoking devices of {}: {:?}", user_id, e);
+        AppError::InternalError
+    })?;
+
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "account.deletion_request",
+        json!({
+            "purge_after": purge_after,
+            "revoked_devices": revoked.rows_affected(),
+        }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    end_user_sessions(&state.recognized_session_id, user_id);
+
+    info!(
+        "{} asked to delete their account by {}",
+        user_id, purge_after
+    );
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(json!({
+            "message": "account scheduled for deletion",
+            "purge_after": purge_after,
+        })),
+    ))
+}
+
+pub async fn get_own_account_deletion(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> APIResult<Json<AccountDeletion>> {
+    principal.require(Action::Read, Resource::Account, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    query_as!(
+        AccountDeletion,
+        "SELECT requested_by, requested_at, purge_after FROM \
+         account_deletions WHERE user_id = $1 AND purged_at IS NULL",
+        user_id
+    )
+    .fetch_optional(&state.db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while retrieving deletion of {}: {:?}", user_id, e);
+        AppError::InternalError
+    })?
+    .map(Json)
+    .ok_or(DatabaseError::RowNotFound.into())
+}
+
+pub async fn cancel_own_account_deletion(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> APIResult<(StatusCode, Json<Value>)> {
+    principal.require(Action::Delete, Resource::Account, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    query!(
+        "DELETE FROM account_deletions WHERE user_id = $1 AND purged_at IS \
+         NULL RETURNING user_id",
+        user_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!("Error while cancelling deletion of {}: {:?}", user_id, e);
+        AppError::InternalError
+    })?
+    .ok_or(DatabaseError::RowNotFound)?;
+
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "account.deletion_cancel",
+        json!({}),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "message": "account deletion cancelled" })),
+    ))
+}
+
+/// Erases one account, see the module documentation for what is retained
+async fn purge_account(
+    user_id: Uuid,
+    requested_by: Uuid,
+    state: &AppState,
+) -> APIResult<()> {
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    // what is left after the grace period: sessions started since, and
+    // everything the user recorded themselves
+    let statements = [
+        query!(
+            "DELETE FROM failed_login_attempts WHERE user_id = $1 OR email = \
+             (SELECT email FROM users WHERE user_id = $1)",
+            user_id
+        ),
+        query!("DELETE FROM refresh_tokens WHERE user_id = $1", user_id),
+        query!("DELETE FROM device_keys WHERE user_id = $1", user_id),
+        query!(
+            "DELETE FROM totp_recovery_codes WHERE user_id = $1",
+            user_id
+        ),
+        query!("DELETE FROM user_totp WHERE user_id = $1", user_id),
+        query!("DELETE FROM user_roles WHERE user_id = $1", user_id),
+        query!("DELETE FROM user_details WHERE user_id = $1", user_id),
+        query!("DELETE FROM user_measurements WHERE user_id = $1", user_id),
+        // the ones taken during a consultation are part of its record
+        query!(
+            "DELETE FROM observations WHERE user_id = $1 AND consultation_id \
+             IS NULL",
+            user_id
+        ),
+        query!("DELETE FROM allergies WHERE user_id = $1", user_id),
+        // as are the conditions diagnosed in one, with their history
+        query!(
+            "DELETE FROM medical_conditions WHERE user_id = $1 AND source = \
+             'SELF_REPORTED'",
+            user_id
+        ),
+        query!("DELETE FROM notifications WHERE user_id = $1", user_id),
+        query!("DELETE FROM emergency_cards WHERE user_id = $1", user_id),
+        query!("DELETE FROM patient_summaries WHERE user_id = $1", user_id),
+        query!("DELETE FROM data_exports WHERE user_id = $1", user_id),
+        query!(
+            "DELETE FROM delegations WHERE user_id = $1 OR delegate_id = $1",
+            user_id
+        ),
+        query!(
+            "DELETE FROM guardianships WHERE dependent_id = $1 OR \
+             guardian_id = $1",
+            user_id
+        ),
+        query!(
+            "DELETE FROM dependent_handovers WHERE dependent_id = $1",
+            user_id
+        ),
+        query!(
+            "DELETE FROM imported_records WHERE import_id IN (SELECT \
+             import_id FROM fhir_imports WHERE user_id = $1)",
+            user_id
+        ),
+        query!(
+            "DELETE FROM external_encounters WHERE user_id = $1",
+            user_id
+        ),
+        query!("DELETE FROM fhir_imports WHERE user_id = $1", user_id),
+        query!(
+            "UPDATE users SET email = NULL, password_hash = NULL, deleted_at \
+             = NOW() WHERE user_id = $1",
+            user_id
+        ),
+        query!(
+            "UPDATE account_deletions SET purged_at = NOW() WHERE user_id = $1",
+            user_id
+        ),
+    ];
+
+    for statement in statements {
+        statement.execute(&mut *tx).await.map_err(|e| {
+            error!("Error while purging {}: {:?}", user_id, e);
+            AppError::InternalError
+        })?;
+    }
+    audit::scrub_payloads(&mut tx, user_id).await?;
+
+    audit::record(
+        &mut tx,
+        requested_by,
+        Some(user_id),
+        "account.purge",
+        json!({}),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    end_user_sessions(&state.recognized_session_id, user_id);
+
+    info!("Purged account {}", user_id);
+
+    Ok(())
+}
+
+/// Purges every account whose grace period has passed, returns how many
+pub async fn purge_due_accounts(state: &AppState) -> APIResult<u64> {
+    let due = query!(
+        "SELECT user_id, requested_by FROM account_deletions WHERE \
+         purge_after <= NOW() AND purged_at IS NULL"
+    )
+    .fetch_all(&state.db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while retrieving accounts to purge: {:?}", e);
+        AppError::InternalError
+    })?;
+
+    let mut purged = 0;
+    for account in due {
+        // one failing shouldn't hold up the rest
+        if purge_account(account.user_id, account.requested_by, state)
+            .await
+            .is_ok()
+        {
+            purged += 1;
+        }
+    }
+
+    Ok(purged)
+}
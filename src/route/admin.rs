@@ -1,31 +1,31 @@
 use axum::{
-    Json, RequestPartsExt,
-    extract::{FromRef, FromRequestParts, Path, State},
-    http::{StatusCode, request::Parts},
-};
-use axum_extra::{
-    TypedHeader,
-    headers::{self, authorization::Bearer},
-    typed_header::TypedHeaderRejectionReason,
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
 };
 use chrono::Utc;
-use moka::sync::Cache;
+use serde::Deserialize;
 use serde_json::{Value, json};
-use sqlx::{PgPool, Pool, Postgres};
-use tracing::error;
+use sqlx::PgPool;
+use tracing::{error, info};
 use uuid::Uuid;
 
 use crate::{
+    AppState, audit,
     auth::AuthError,
     error::{APIResult, AppError, DatabaseError},
+    policy::{Action, Principal, Resource, Role, Scope},
 };
 
 pub async fn promote_to_admin(
     State(pool): State<PgPool>,
     Path(target_user_id): Path<Uuid>,
-    admin_user: AdminUser,
+    principal: Principal,
 ) -> APIResult<(StatusCode, Json<Value>)> {
-    let admin_id = admin_user.user_id;
+    principal.require(Action::Create, Resource::Role, Scope::Any)?;
+    let admin_id = principal.user_id;
+
+    let mut tx = audit::begin(&pool).await?;
 
     sqlx::query!(
         r#"
@@ -36,7 +36,7 @@ pub async fn promote_to_admin(
         admin_id,
         Utc::now()
     )
-    .execute(&pool)
+    .execute(&mut *tx)
     .await
     .map_err(|e| {
         error!("Error while promoting {target_user_id} to admin: {e:?}");
@@ -44,6 +44,16 @@ pub async fn promote_to_admin(
         AppError::InternalError
     })?;
 
+    audit::record(
+        &mut tx,
+        principal.user_id,
+        Some(target_user_id),
+        "admin.promote",
+        json!({ "user_id": target_user_id }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
     Ok((
         StatusCode::CREATED,
         Json(json!({ "message": "User promoted to admin" })),
@@ -53,9 +63,14 @@ pub async fn promote_to_admin(
 pub async fn approve_location(
     State(pool): State<PgPool>,
     Path(location_id): Path<Uuid>,
-    admin_user: AdminUser,
+    principal: Principal,
 ) -> APIResult<(StatusCode, Json<Value>)> {
-    let admin_id = admin_user.user_id;
+    principal.require(
+        Action::Approve,
+        Resource::PracticeLocation,
+        Scope::Any,
+    )?;
+    let admin_id = principal.user_id;
 
     let exists: bool = sqlx::query_scalar!(
         "SELECT EXISTS(SELECT 1 FROM doctor_practice_locations WHERE \
@@ -78,6 +93,8 @@ pub async fn approve_location(
         return Err(DatabaseError::RowNotFound.into());
     }
 
+    let mut tx = audit::begin(&pool).await?;
+
     let record = sqlx::query!(
         "UPDATE doctor_practice_locations
         SET approved_by = $1, approved_at = $2
@@ -86,7 +103,7 @@ pub async fn approve_location(
         Utc::now(),
         location_id
     )
-    .fetch_one(&pool)
+    .fetch_one(&mut *tx)
     .await
     .map_err(|e| {
         error!("Error while trying to approve location {location_id}: {e:?}");
@@ -102,7 +119,7 @@ pub async fn approve_location(
         admin_id,
         doctor_id
     )
-    .execute(&pool)
+    .execute(&mut *tx)
     .await
     .map_err(|e| {
         error!("Error while trying to approve doctor {doctor_id}: {e:?}");
@@ -110,77 +127,168 @@ pub async fn approve_location(
         AppError::InternalError
     })?;
 
+    audit::record(
+        &mut tx,
+        principal.user_id,
+        None,
+        "practice_location.approve",
+        json!({
+            "location_id": location_id,
+            "doctor_id": doctor_id,
+        }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
     Ok((
         StatusCode::OK,
         Json(json!({ "message": "Practice location approved" })),
     ))
 }
 
-#[derive(Clone)]
-pub struct AdminUser {
-    pub user_id: Uuid,
-    pub session_id: String,
+/// Starts signing access tokens with a new key, the previous one is still
+/// accepted until the tokens it signed have expired
+pub async fn rotate_signing_key(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> APIResult<Json<Value>> {
+    principal.require(Action::Update, Resource::SigningKey, Scope::Any)?;
+    let keys = state.jwt.as_ref().ok_or(AuthError::TokensDisabled)?;
+    let kid = keys.rotate();
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+    audit::record(
+        &mut tx,
+        principal.user_id,
+        None,
+        "signing_key.rotate",
+        json!({ "kid": kid }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    info!(
+        "{} rotated the token signing key to {kid}",
+        principal.user_id
+    );
+
+    Ok(Json(
+        json!({ "message": "Signing key rotated", "kid": kid }),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct RoleGrantPayload {
+    pub role: Role,
 }
 
-impl<S> FromRequestParts<S> for AdminUser
-where
-    S: Send + Sync,
-    Cache<String, Uuid>: FromRef<S>,
-    Pool<Postgres>: FromRef<S>,
-{
-    type Rejection = AppError;
-
-    async fn from_request_parts(
-        parts: &mut Parts,
-        state: &S,
-    ) -> Result<Self, Self::Rejection> {
-        let recognized_session_id = Cache::<String, Uuid>::from_ref(state);
-        let pool = Pool::<Postgres>::from_ref(state);
-
-        // get session_id
-        let authorization_header = parts
-            .extract::<TypedHeader<headers::Authorization<Bearer>>>()
-            .await
-            .map_err(|e| {
-                error!("Error while extracting authorization header: {:?}", e);
-
-                match e.reason() {
-                    TypedHeaderRejectionReason::Missing => {
-                        AuthError::MissingCredentials.into()
-                    }
-                    TypedHeaderRejectionReason::Error(_) => {
-                        AppError::InternalError
-                    }
-                    _ => AppError::InternalError,
-                }
-            })?;
-
-        let session_id = authorization_header.token();
-
-        let Some(admin_id) = recognized_session_id.get(session_id) else {
-            return Err(AuthError::InvalidToken.into());
-        };
-
-        let is_admin: bool = sqlx::query_scalar!(
-            "SELECT EXISTS(SELECT 1 FROM admins WHERE user_id = $1)",
-            admin_id
-        )
-        .fetch_one(&pool)
-        .await
-        .map_err(|e| {
-            error!("Error while checking if {admin_id} is admin: {e:?}");
+/// Grants one of the roles that are not implied by the account, i.e.
+/// pharmacist or auditor
+pub async fn grant_role(
+    State(pool): State<PgPool>,
+    Path(target_user_id): Path<Uuid>,
+    principal: Principal,
+    Json(RoleGrantPayload { role }): Json<RoleGrantPayload>,
+) -> APIResult<(StatusCode, Json<Value>)> {
+    principal.require(Action::Create, Resource::Role, Scope::Any)?;
 
-            AppError::InternalError
-        })?
-        .unwrap_or(false);
+    if !role.is_assignable() {
+        return Err(AppError::InvalidRole);
+    }
+
+    let mut tx = audit::begin(&pool).await?;
 
-        if !is_admin {
-            return Err(AppError::NotAdmin);
+    sqlx::query!(
+        "INSERT INTO user_roles (user_id, role, granted_by) VALUES ($1, $2, \
+         $3) ON CONFLICT (user_id, role) DO NOTHING",
+        target_user_id,
+        role.as_str(),
+        principal.user_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(db_e) if db_e.is_foreign_key_violation() => {
+            DatabaseError::ForeignKeyViolation.into()
         }
+        e => {
+            error!(
+                "Error while granting {} to {target_user_id}: {e:?}",
+                role.as_str()
+            );
+            AppError::InternalError
+        }
+    })?;
 
-        Ok(AdminUser {
-            user_id: admin_id,
-            session_id: session_id.to_string(),
-        })
+    audit::record(
+        &mut tx,
+        principal.user_id,
+        Some(target_user_id),
+        "role.grant",
+        json!({ "role": role }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    info!(
+        "{} granted {} to {target_user_id}",
+        principal.user_id,
+        role.as_str()
+    );
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({ "message": "Role granted", "role": role })),
+    ))
+}
+
+pub async fn revoke_role(
+    State(pool): State<PgPool>,
+    Path((target_user_id, role)): Path<(Uuid, Role)>,
+    principal: Principal,
+) -> APIResult<(StatusCode, Json<Value>)> {
+    principal.require(Action::Delete, Resource::Role, Scope::Any)?;
+
+    if !role.is_assignable() {
+        return Err(AppError::InvalidRole);
+    }
+
+    let mut tx = audit::begin(&pool).await?;
+
+    let res = sqlx::query!(
+        "DELETE FROM user_roles WHERE user_id = $1 AND role = $2",
+        target_user_id,
+        role.as_str()
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!(
+            "Error while revoking {} from {target_user_id}: {e:?}",
+            role.as_str()
+        );
+        AppError::InternalError
+    })?;
+
+    if res.rows_affected() == 0 {
+        return Err(DatabaseError::RowNotFound.into());
     }
+
+    audit::record(
+        &mut tx,
+        principal.user_id,
+        Some(target_user_id),
+        "role.revoke",
+        json!({ "role": role }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    info!(
+        "{} revoked {} from {target_user_id}",
+        principal.user_id,
+        role.as_str()
+    );
+
+    Ok((StatusCode::OK, Json(json!({ "message": "Role revoked" }))))
 }
@@ -3,23 +3,62 @@ use axum::{
     extract::{Path, State},
     http::StatusCode,
 };
+use chrono::NaiveDate;
 use serde::Deserialize;
 use serde_json::{Value, json};
-use sqlx::{query, query_as};
+use sqlx::{Pool, Postgres, query, query_as, query_scalar};
 use tracing::error;
 use uuid::Uuid;
 
 use crate::{
-    AppState,
-    auth::{AuthUser, LicensedUser},
+    AppState, audit,
     error::{APIResult, AppError, DatabaseError},
-    schema::{Allergy, AllergySeverity},
+    policy::{Action, Principal, Resource, Scope},
+    route::access_log::authorize_read,
+    schema::{
+        Allergy, AllergyCategory, AllergyReaction, AllergySeverity,
+        AllergyVerification,
+    },
 };
 
 #[derive(Deserialize)]
 pub struct AllergyPayload {
     pub allergen: String,
     pub severity: AllergySeverity,
+    #[serde(default)]
+    pub category: Option<AllergyCategory>,
+    /// Only for drug allergies, the category may be left out then
+    #[serde(default)]
+    pub medicine_ingredient_id: Option<Uuid>,
+    #[serde(default)]
+    pub reactions: Vec<AllergyReaction>,
+    #[serde(default)]
+    pub onset: Option<NaiveDate>,
+}
+
+impl AllergyPayload {
+    /// Settles the category against the ingredient, and lists each reaction
+    /// once
+    fn validate(mut self) -> APIResult<Self> {
+        if self.medicine_ingredient_id.is_some() {
+            match self.category {
+                None | Some(AllergyCategory::Drug) => {
+                    self.category = Some(AllergyCategory::Drug)
+                }
+                Some(_) => return Err(AppError::InvalidAllergyCategory),
+            }
+        }
+
+        let mut reactions = Vec::with_capacity(self.reactions.len());
+        for reaction in self.reactions {
+            if !reactions.contains(&reaction) {
+                reactions.push(reaction);
+            }
+        }
+        self.reactions = reactions;
+
+        Ok(self)
+    }
 }
 
 #[derive(Deserialize)]
@@ -27,69 +66,130 @@ pub struct AllergyIDPayload {
     pub allergy_id: Uuid,
 }
 
-pub async fn get_user_allergies(
-    State(state): State<AppState>,
-    auth: AuthUser,
-    doctor: Option<LicensedUser>,
-    Path(user_id): Path<Uuid>,
-) -> APIResult<Json<Vec<Allergy>>> {
-    if user_id != auth.user_id && doctor.is_none() {
-        return Err(AppError::NotTheSameUser);
-    }
-
+/// Allergies of `user_id`, by allergen
+pub async fn fetch_allergies(
+    user_id: Uuid,
+    db_pool: &Pool<Postgres>,
+) -> APIResult<Vec<Allergy>> {
     query_as!(
         Allergy,
-        "SELECT allergy_id, user_id, allergen, severity AS \"severity: \
-         AllergySeverity\" FROM allergies WHERE user_id = $1",
+        r#"SELECT allergy_id, user_id, allergen,
+            severity AS "severity: AllergySeverity",
+            category AS "category: AllergyCategory", medicine_ingredient_id,
+            reactions AS "reactions: Vec<AllergyReaction>", onset,
+            verification AS "verification: AllergyVerification",
+            verified_by, verified_at
+        FROM allergies WHERE user_id = $1 ORDER BY allergen"#,
         user_id
     )
-    .fetch_all(&state.db_pool)
+    .fetch_all(db_pool)
     .await
-    .map(Json)
     .map_err(|e| {
         error!("Error while retrieving allergies for {}: {:?}", user_id, e);
         AppError::InternalError
     })
 }
 
-pub async fn get_own_allergies(
+/// Unknown ingredients are reported as such
+fn write_error(
+    allergy: &str,
+    user_id: Uuid,
+) -> impl FnOnce(sqlx::Error) -> AppError {
+    move |e| match e {
+        sqlx::Error::Database(db_e) if db_e.is_foreign_key_violation() => {
+            DatabaseError::ForeignKeyViolation.into()
+        }
+        e => {
+            error!(
+                "Error while writing allergy {} for {}: {:?}",
+                allergy, user_id, e
+            );
+            AppError::InternalError
+        }
+    }
+}
+
+pub async fn get_user_allergies(
     State(state): State<AppState>,
-    AuthUser { user_id, .. }: AuthUser,
+    principal: Principal,
+    Path(user_id): Path<Uuid>,
 ) -> APIResult<Json<Vec<Allergy>>> {
-    query_as!(
-        Allergy,
-        "SELECT allergy_id, user_id, allergen, severity AS \"severity: \
-         AllergySeverity\" FROM allergies WHERE user_id = $1",
-        user_id
+    authorize_read(
+        &principal,
+        Resource::Allergy,
+        user_id,
+        principal
+            .patient_scope(user_id, Resource::Allergy, &state.db_pool)
+            .await?,
+        &state.db_pool,
     )
-    .fetch_all(&state.db_pool)
-    .await
-    .map(Json)
-    .map_err(|e| {
-        error!("Error while retrieving allergies for {}: {:?}", user_id, e);
-        AppError::InternalError
-    })
+    .await?;
+
+    fetch_allergies(user_id, &state.db_pool).await.map(Json)
+}
+
+pub async fn get_own_allergies(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> APIResult<Json<Vec<Allergy>>> {
+    principal.require(Action::Read, Resource::Allergy, Scope::Own)?;
+
+    fetch_allergies(principal.user_id, &state.db_pool)
+        .await
+        .map(Json)
 }
 
 pub async fn add_own_allergy(
     State(state): State<AppState>,
-    AuthUser { user_id, .. }: AuthUser,
-    Json(AllergyPayload { allergen, severity }): Json<AllergyPayload>,
+    principal: Principal,
+    Json(payload): Json<AllergyPayload>,
 ) -> APIResult<(StatusCode, Json<Value>)> {
-    let _ = query!(
-        "INSERT INTO allergies (user_id, allergen, severity) VALUES ($1, $2, \
-         $3)",
+    principal.require(Action::Create, Resource::Allergy, Scope::Own)?;
+    let user_id = principal.user_id;
+    let AllergyPayload {
+        allergen,
+        severity,
+        category,
+        medicine_ingredient_id,
+        reactions,
+        onset,
+    } = payload.validate()?;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    let allergy_id = query_scalar!(
+        "INSERT INTO allergies (user_id, allergen, severity, category, \
+         medicine_ingredient_id, reactions, onset) VALUES ($1, $2, $3, $4, \
+         $5, $6, $7) RETURNING allergy_id",
         user_id,
         allergen,
-        severity as AllergySeverity
+        severity as AllergySeverity,
+        category as Option<AllergyCategory>,
+        medicine_ingredient_id,
+        &reactions as &[AllergyReaction],
+        onset
     )
-    .execute(&state.db_pool)
+    .fetch_one(&mut *tx)
     .await
-    .map(Json)
-    .map_err(|e| {
-        error!("Error while adding allergy for {}: {:?}", user_id, e);
-        AppError::InternalError
-    })?;
+    .map_err(write_error("new", user_id))?;
+
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "allergy.create",
+        json!({
+            "allergy_id": allergy_id,
+            "allergen": allergen,
+            "severity": severity,
+            "category": category,
+            "medicine_ingredient_id": medicine_ingredient_id,
+            "reactions": reactions,
+            "onset": onset,
+        }),
+    )
+    .await?;
+    audit::commit(tx).await?;
 
     Ok((
         StatusCode::CREATED,
@@ -97,17 +197,183 @@ pub async fn add_own_allergy(
     ))
 }
 
+/// Replaces the allergy. Changing what a clinician confirmed, the allergen,
+/// category, ingredient, severity or reactions, takes back their confirmation
+pub async fn update_own_allergy(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(allergy_id): Path<Uuid>,
+    Json(payload): Json<AllergyPayload>,
+) -> APIResult<Json<Value>> {
+    principal.require(Action::Update, Resource::Allergy, Scope::Own)?;
+    let user_id = principal.user_id;
+    let AllergyPayload {
+        allergen,
+        severity,
+        category,
+        medicine_ingredient_id,
+        reactions,
+        onset,
+    } = payload.validate()?;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    let current = query!(
+        r#"SELECT allergen, category AS "category: AllergyCategory",
+            medicine_ingredient_id, severity AS "severity: AllergySeverity",
+            reactions AS "reactions: Vec<AllergyReaction>"
+        FROM allergies WHERE allergy_id = $1 AND user_id = $2 FOR UPDATE"#,
+        allergy_id,
+        user_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!(
+            "Error while fetching allergy {} for {}: {:?}",
+            allergy_id, user_id, e
+        );
+        AppError::InternalError
+    })?
+    .ok_or(DatabaseError::RowNotFound)?;
+
+    // reactions are kept in the order given, which isn't part of what was
+    // confirmed
+    let same_reactions = current.reactions.len() == reactions.len()
+        && reactions
+            .iter()
+            .all(|reaction| current.reactions.contains(reaction));
+    let as_confirmed = current.allergen == allergen
+        && current.category == category
+        && current.medicine_ingredient_id == medicine_ingredient_id
+        && current.severity == severity
+        && same_reactions;
+
+    let verification = query_scalar!(
+        r#"UPDATE allergies SET allergen = $2, severity = $3, category = $4,
+            medicine_ingredient_id = $5, reactions = $6, onset = $7,
+            verification = CASE WHEN $8 THEN verification
+                ELSE 'SELF_REPORTED' END,
+            verified_by = CASE WHEN $8 THEN verified_by END,
+            verified_at = CASE WHEN $8 THEN verified_at END
+        WHERE allergy_id = $1
+        RETURNING verification AS "verification: AllergyVerification""#,
+        allergy_id,
+        allergen,
+        severity as AllergySeverity,
+        category as Option<AllergyCategory>,
+        medicine_ingredient_id,
+        &reactions as &[AllergyReaction],
+        onset,
+        as_confirmed
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(write_error(&allergy_id.to_string(), user_id))?;
+
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "allergy.update",
+        json!({
+            "allergy_id": allergy_id,
+            "allergen": allergen,
+            "severity": severity,
+            "category": category,
+            "medicine_ingredient_id": medicine_ingredient_id,
+            "reactions": reactions,
+            "onset": onset,
+            "verification": verification,
+        }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    Ok(Json(json!({ "message": "allergy updated" })))
+}
+
+/// A doctor attending the patient confirms an allergy they reported
+/// themselves
+pub async fn confirm_user_allergy(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path((user_id, allergy_id)): Path<(Uuid, Uuid)>,
+) -> APIResult<Json<Value>> {
+    let scope = principal
+        .patient_scope(user_id, Resource::Allergy, &state.db_pool)
+        .await?;
+    principal.require(Action::Update, Resource::Allergy, scope)?;
+    let doctor_id = principal.doctor_id.ok_or(AppError::NotLicensed)?;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    let verification = query_scalar!(
+        r#"SELECT verification AS "verification: AllergyVerification"
+        FROM allergies WHERE allergy_id = $1 AND user_id = $2 FOR UPDATE"#,
+        allergy_id,
+        user_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!(
+            "Error while fetching allergy {} for {}: {:?}",
+            allergy_id, user_id, e
+        );
+        AppError::InternalError
+    })?
+    .ok_or(DatabaseError::RowNotFound)?;
+
+    if verification == AllergyVerification::ClinicianConfirmed {
+        return Err(AppError::AllergyAlreadyConfirmed);
+    }
+
+    query!(
+        "UPDATE allergies SET verification = 'CLINICIAN_CONFIRMED', \
+         verified_by = $1, verified_at = NOW() WHERE allergy_id = $2",
+        doctor_id,
+        allergy_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!("Error while confirming allergy {}: {:?}", allergy_id, e);
+        AppError::InternalError
+    })?;
+
+    audit::record(
+        &mut tx,
+        principal.user_id,
+        Some(user_id),
+        "allergy.confirm",
+        json!({
+            "allergy_id": allergy_id,
+            "doctor_id": doctor_id,
+        }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    Ok(Json(json!({ "message": "allergy confirmed" })))
+}
+
 pub async fn remove_own_allergy(
     State(state): State<AppState>,
-    AuthUser { user_id, .. }: AuthUser,
+    principal: Principal,
     Path(allergy_id): Path<Uuid>,
 ) -> APIResult<(StatusCode, Json<Value>)> {
+    principal.require(Action::Delete, Resource::Allergy, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
     let query_res: sqlx::postgres::PgQueryResult = query!(
         "DELETE FROM allergies WHERE allergy_id = $1 AND user_id = $2",
         allergy_id,
         user_id
     )
-    .execute(&state.db_pool)
+    .execute(&mut *tx)
     .await
     .map_err(|e| {
         error!(
@@ -122,6 +388,16 @@ pub async fn remove_own_allergy(
         return Err(DatabaseError::RowNotFound.into());
     }
 
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "allergy.delete",
+        json!({ "allergy_id": allergy_id }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
     Ok((
         StatusCode::OK,
         Json(json!({ "message": "allergy removed" })),
@@ -0,0 +1,68 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use serde::Deserialize;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    audit::{AuditEvent, ChainReport, verify_chain},
+    error::{APIResult, AppError},
+    pagination::{Page, Paginated, fetch_page},
+    policy::{Action, Principal, Resource, Scope},
+};
+
+#[derive(Deserialize)]
+pub struct AuditEventFilter {
+    /// Only events that were (or were not) flagged for review
+    pub flagged: Option<bool>,
+}
+
+pub async fn get_audit_events(
+    State(state): State<AppState>,
+    principal: Principal,
+    Query(AuditEventFilter { flagged }): Query<AuditEventFilter>,
+    page: Page,
+) -> APIResult<Paginated<AuditEvent>> {
+    principal.require(Action::Read, Resource::AuditLog, Scope::Any)?;
+
+    // events have no uuid, their sequence number stands in for one
+    fetch_page!(
+        AuditEvent,
+        &page,
+        &state.db_pool,
+        "SELECT seq, actor_id, subject_id, action, payload, payload_hash,
+            created_at, prev_hash, hash, flagged
+         FROM audit_events
+         WHERE ($6::BOOLEAN IS NULL OR flagged = $6)",
+        "created_at",
+        "LPAD(TO_HEX(seq), 32, '0')::UUID",
+        flagged
+    )
+    .map(|rows| {
+        page.paginate(rows, |event| {
+            (event.created_at, Uuid::from_u128(event.seq as u128))
+        })
+    })
+    .map_err(|e| {
+        error!("Error while retrieving audit events: {:?}", e);
+        AppError::InternalError
+    })
+}
+
+pub async fn verify_audit_events(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> APIResult<Json<ChainReport>> {
+    principal.require(Action::Read, Resource::AuditLog, Scope::Any)?;
+
+    let report = verify_chain(&state.db_pool).await?;
+
+    if let Some(seq) = report.first_invalid_seq {
+        warn!("Audit chain broken at event {seq}");
+    }
+
+    Ok(Json(report))
+}
@@ -4,47 +4,89 @@ use axum::{
     http::StatusCode,
 };
 use chrono::{DateTime, Utc};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
-use sqlx::{Pool, Postgres, Transaction, query, query_as};
+use sqlx::{
+    Pool, Postgres, Transaction, query, query_as, query_scalar,
+    types::Json as SqlJson,
+};
 use tracing::{error, warn};
 use uuid::Uuid;
 
 use crate::{
-    AppState,
-    auth::{AuthUser, LicensedUser},
+    AppState, audit,
     error::{APIResult, AppError, DatabaseError},
-    protocol::{Consent, ConsentError},
-    route::verify_consent,
-    schema::{Consultation, Diagnosis, DoctorPracticeLocation, Prescription},
+    pagination::{Page, Paginated, fetch_page},
+    policy::{Action, Principal, Resource, Scope},
+    protocol::Consent,
+    route::{
+        access_log::{authorize_read, log_read},
+        icd10::{check_icd10_codes, normalize_icd10_code},
+        medical_condition::record_transition,
+        observation::{
+            ObservationPayload, check_observations, insert_observations,
+        },
+        verify_consent,
+    },
+    schema::{
+        ConditionSource, ConditionStatus, Consultation, Diagnosis,
+        DiagnosisSeverity, DoctorPracticeLocation, Observation, Prescription,
+    },
 };
 
-pub async fn get_own_consultations(
-    State(state): State<AppState>,
-    AuthUser { user_id, .. }: AuthUser,
-) -> APIResult<Json<Vec<Consultation>>> {
-    query_as!(
+/// Consultations of a patient, with a doctor, or both, a page at a time
+async fn fetch_consultations(
+    user_id: Option<Uuid>,
+    doctor_id: Option<Uuid>,
+    page: &Page,
+    db_pool: &Pool<Postgres>,
+) -> APIResult<Paginated<Consultation>> {
+    fetch_page!(
         Consultation,
-        "SELECT * FROM consultations WHERE user_id = $1",
-        user_id
+        page,
+        db_pool,
+        "SELECT * FROM consultations
+         WHERE ($6::UUID IS NULL OR user_id = $6)
+            AND ($7::UUID IS NULL OR doctor_id = $7)",
+        "created_at",
+        "consultation_id",
+        user_id,
+        doctor_id
     )
-    .fetch_all(&state.db_pool)
-    .await
-    .map(Json)
+    .map(|rows| {
+        page.paginate(rows, |consultation| {
+            (consultation.created_at, consultation.consultation_id)
+        })
+    })
     .map_err(|e| {
         error!(
-            "Error while retrieving consultations for {}: {:?}",
-            user_id, e
+            "Error while retrieving consultations for user {:?} and doctor \
+             {:?}: {:?}",
+            user_id, doctor_id, e
         );
         AppError::InternalError
     })
 }
 
+pub async fn get_own_consultations(
+    State(state): State<AppState>,
+    principal: Principal,
+    page: Page,
+) -> APIResult<Paginated<Consultation>> {
+    principal.require(Action::Read, Resource::Consultation, Scope::Own)?;
+
+    fetch_consultations(Some(principal.user_id), None, &page, &state.db_pool)
+        .await
+}
+
 pub async fn get_own_consultation_single(
     State(state): State<AppState>,
-    AuthUser { user_id, .. }: AuthUser,
+    principal: Principal,
     Path(consultation_id): Path<Uuid>,
 ) -> APIResult<Json<Consultation>> {
+    principal.require(Action::Read, Resource::Consultation, Scope::Own)?;
+    let user_id = principal.user_id;
+
     query_as!(
         Consultation,
         "SELECT * FROM consultations WHERE user_id = $1 AND consultation_id = \
@@ -66,95 +108,88 @@ pub async fn get_own_consultation_single(
 
 pub async fn get_user_consultations(
     State(state): State<AppState>,
-    auth: AuthUser,
-    doctor: Option<LicensedUser>,
+    principal: Principal,
     Path(user_id): Path<Uuid>,
-) -> APIResult<Json<Vec<Consultation>>> {
-    if auth.user_id != user_id && doctor.is_none() {
-        return Err(AppError::NotTheSameUser);
-    }
-
-    query_as!(
-        Consultation,
-        "SELECT * FROM consultations WHERE user_id = $1",
-        user_id
+    page: Page,
+) -> APIResult<Paginated<Consultation>> {
+    authorize_read(
+        &principal,
+        Resource::Consultation,
+        user_id,
+        principal
+            .patient_scope(user_id, Resource::Consultation, &state.db_pool)
+            .await?,
+        &state.db_pool,
     )
-    .fetch_all(&state.db_pool)
-    .await
-    .map(Json)
-    .map_err(|e| {
-        error!(
-            "Error while retrieving consultations for {}: {:?}",
-            user_id, e
-        );
-        AppError::InternalError
-    })
+    .await?;
+
+    fetch_consultations(Some(user_id), None, &page, &state.db_pool).await
 }
 
 pub async fn get_own_consultations_as_doctor(
     State(state): State<AppState>,
-    doctor: Option<LicensedUser>,
-) -> APIResult<Json<Vec<Consultation>>> {
-    let doctor = doctor.ok_or(AppError::NotTheSameUser)?;
-
-    query_as!(
-        Consultation,
-        "SELECT * FROM consultations WHERE doctor_id = $1",
-        doctor.doctor_id
-    )
-    .fetch_all(&state.db_pool)
-    .await
-    .map(Json)
-    .map_err(|e| {
-        error!(
-            "Error while retrieving consultations for doctor {}: {:?}",
-            doctor.doctor_id, e
-        );
-        AppError::InternalError
-    })
+    principal: Principal,
+    page: Page,
+) -> APIResult<Paginated<Consultation>> {
+    principal.require(
+        Action::Read,
+        Resource::Consultation,
+        Scope::Attending,
+    )?;
+    let doctor_id = principal.doctor_id.ok_or(AppError::PermissionDenied)?;
+
+    fetch_consultations(None, Some(doctor_id), &page, &state.db_pool).await
 }
 
 pub async fn get_doctor_consultations_with_user(
     State(state): State<AppState>,
-    auth: AuthUser,
-    doctor: Option<LicensedUser>,
+    principal: Principal,
     Path((doctor_id, user_id)): Path<(Uuid, Uuid)>,
-) -> APIResult<Json<Vec<Consultation>>> {
-    if user_id != auth.user_id && doctor.is_none() {
-        return Err(AppError::NotTheSameUser);
-    }
-
-    let doctor_unwrap = doctor.unwrap();
-    if doctor_unwrap.doctor_id != doctor_id {
-        return Err(AppError::NotTheSameUser);
+    page: Page,
+) -> APIResult<Paginated<Consultation>> {
+    let scope = principal
+        .patient_scope(user_id, Resource::Consultation, &state.db_pool)
+        .await?;
+    let role =
+        principal.require(Action::Read, Resource::Consultation, scope)?;
+
+    let consultations = fetch_consultations(
+        Some(user_id),
+        Some(doctor_id),
+        &page,
+        &state.db_pool,
+    )
+    .await?;
+
+    // an empty page read nothing of the patient
+    if !consultations.items.is_empty() {
+        log_read(
+            &principal,
+            role,
+            Resource::Consultation,
+            user_id,
+            scope,
+            &state.db_pool,
+        )
+        .await?;
     }
 
-    query_as!(
-        Consultation,
-        "SELECT * FROM consultations WHERE user_id = $1 AND doctor_id = $2",
-        user_id,
-        doctor_id
-    )
-    .fetch_all(&state.db_pool)
-    .await
-    .map(Json)
-    .map_err(|e| {
-        error!(
-            "Error while retrieving consultations between doctor {} and user \
-             {}: {:?}",
-            doctor_id, user_id, e
-        );
-        AppError::InternalError
-    })
+    Ok(consultations)
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct DiagnosisPayload {
     diagnosis: String,
-    severity: String,
+    severity: DiagnosisSeverity,
+    /// One of `GET /icd10`, e.g. `J00`
+    #[serde(default)]
+    icd10_code: Option<String>,
+    /// Also lists the diagnosis among the patient's medical conditions
+    #[serde(default)]
+    ongoing: bool,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct PrescriptionPayload {
     drug_name: String,
     doses_in_mg: f64,
@@ -171,11 +206,17 @@ pub struct ConsultationPayload {
     diagnoses: Vec<DiagnosisPayload>,
     symptoms: String,
     prescriptions: Vec<PrescriptionPayload>,
+    /// Vital signs taken during the visit
+    #[serde(default)]
+    observations: Vec<ObservationPayload>,
+    /// The caller's earlier consultation with the patient this one corrects
+    #[serde(default)]
+    amends: Option<Uuid>,
 }
 
 pub async fn add_user_consultation(
     State(state): State<AppState>,
-    doctor: Option<LicensedUser>,
+    principal: Principal,
     Path(user_id): Path<Uuid>,
     Json(ConsultationPayload {
         consent,
@@ -184,12 +225,13 @@ pub async fn add_user_consultation(
         diagnoses,
         symptoms,
         prescriptions,
+        observations,
+        amends,
     }): Json<ConsultationPayload>,
 ) -> APIResult<(StatusCode, Json<Value>)> {
-    if doctor.is_none() {
-        return Err(AppError::NotLicensed);
-    }
-    let doctor_id = doctor.unwrap().doctor_id;
+    principal.require(Action::Create, Resource::Consultation, Scope::Any)?;
+    let doctor_id = principal.doctor_id.ok_or(AppError::NotLicensed)?;
+
     let location_query: DoctorPracticeLocation = query_as!(
         DoctorPracticeLocation,
         "SELECT * FROM doctor_practice_locations WHERE doctor_id = $1 AND \
@@ -221,6 +263,20 @@ pub async fn add_user_consultation(
         return Err(AppError::LocationNotApproved);
     }
 
+    let diagnoses: Vec<DiagnosisPayload> = diagnoses
+        .into_iter()
+        .map(|diagnosis| DiagnosisPayload {
+            icd10_code: normalize_icd10_code(diagnosis.icd10_code),
+            ..diagnosis
+        })
+        .collect();
+    let codes: Vec<String> = diagnoses
+        .iter()
+        .filter_map(|diagnosis| diagnosis.icd10_code.clone())
+        .collect();
+    check_icd10_codes(&codes, &state.db_pool).await?;
+    check_observations(&observations, &state.db_pool).await?;
+
     verify_consent(consent, user_id, &state.db_pool, &state.nonce_cache)
         .await?;
 
@@ -230,35 +286,99 @@ pub async fn add_user_consultation(
             AppError::InternalError
         })?;
 
+    if let Some(amends) = amends {
+        let amendable = query_scalar!(
+            r#"SELECT EXISTS(
+                SELECT 1 FROM consultations
+                WHERE consultation_id = $1 AND doctor_id = $2 AND user_id = $3
+                    AND NOT EXISTS(
+                        SELECT 1 FROM consultations WHERE amends = $1
+                    )
+            ) AS "amendable!""#,
+            amends,
+            doctor_id,
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| {
+            error!("Error occured while checking amended {}: {:?}", amends, e);
+            AppError::InternalError
+        })?;
+
+        if !amendable {
+            return Err(AppError::InvalidAmendment);
+        }
+    }
+
     let consultation = query_as!(
         Consultation,
         "INSERT INTO consultations (doctor_id, user_id, location_id, \
-         symptoms) VALUES ($1, $2, $3, $4) RETURNING consultation_id, \
-         doctor_id, user_id, location_id, symptoms, created_at, reminded",
+         symptoms, amends) VALUES ($1, $2, $3, $4, $5) RETURNING \
+         consultation_id, doctor_id, user_id, location_id, symptoms, \
+         created_at, reminded, amends",
         doctor_id,
         user_id,
         location_id,
-        symptoms
+        symptoms,
+        amends
     )
     .fetch_one(&mut *tx)
     .await
     .map_err(|e| {
         error!("Error occured while inserting into records: {:?}", e);
-        AppError::InternalError
+        match e {
+            // amended by another request in the meantime
+            sqlx::Error::Database(db_e) if db_e.is_unique_violation() => {
+                AppError::InvalidAmendment
+            }
+            _ => AppError::InternalError,
+        }
     })?;
 
+    audit::record(
+        &mut tx,
+        principal.user_id,
+        Some(user_id),
+        "consultation.create",
+        json!({
+            "consultation_id": consultation.consultation_id,
+            "doctor_id": doctor_id,
+            "location_id": location_id,
+            "symptoms": consultation.symptoms,
+            "amends": amends,
+            "diagnoses": diagnoses,
+            "prescriptions": prescriptions,
+            "observations": observations,
+        }),
+    )
+    .await?;
+
+    insert_observations(
+        &mut tx,
+        user_id,
+        &observations,
+        consultation.created_at,
+        principal.user_id,
+        Some(consultation.consultation_id),
+    )
+    .await?;
+
     for diagnosis in diagnoses {
         let DiagnosisPayload {
             diagnosis,
             severity,
+            icd10_code,
+            ongoing,
         } = diagnosis;
 
         query!(
-            "INSERT INTO diagnoses (consultation_id, diagnosis, severity) \
-             VALUES ($1, $2, $3)",
+            "INSERT INTO diagnoses (consultation_id, diagnosis, severity, \
+             icd10_code) VALUES ($1, $2, $3, $4)",
             consultation.consultation_id,
             diagnosis,
-            severity
+            severity as DiagnosisSeverity,
+            icd10_code
         )
         .execute(&mut *tx)
         .await
@@ -266,6 +386,35 @@ pub async fn add_user_consultation(
             error!("Error occured while inserting a diagnosis: {:?}", e);
             AppError::InternalError
         })?;
+
+        if ongoing {
+            let condition_id = query_scalar!(
+                "INSERT INTO medical_conditions (user_id, condition, onset, \
+                 icd10_code, source, consultation_id) VALUES ($1, $2, $3, \
+                 $4, $5, $6) RETURNING condition_id",
+                user_id,
+                diagnosis,
+                consultation.created_at.date_naive(),
+                icd10_code,
+                ConditionSource::Diagnosed as ConditionSource,
+                consultation.consultation_id
+            )
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(|e| {
+                error!("Error occured while listing a condition: {:?}", e);
+                AppError::InternalError
+            })?;
+
+            record_transition(
+                &mut tx,
+                condition_id,
+                None,
+                ConditionStatus::Active,
+                principal.user_id,
+            )
+            .await?;
+        }
     }
 
     for prescription in prescriptions {
@@ -307,12 +456,15 @@ pub async fn add_user_consultation(
     ))
 }
 
-pub async fn check_user(
-    user_id: Uuid,
-    doctor: Option<LicensedUser>,
+/// The patient of a consultation and how the principal relates to it when it
+/// comes to `resource`: the patient, the doctor who held it, a delegate of the
+/// patient, or neither
+pub async fn consultation_scope(
+    principal: &Principal,
     consultation_id: Uuid,
+    resource: Resource,
     db_pool: &Pool<Postgres>,
-) -> APIResult<()> {
+) -> APIResult<(Uuid, Scope)> {
     let consultation = query_as!(
         Consultation,
         "SELECT * FROM consultations WHERE consultation_id = $1",
@@ -333,30 +485,48 @@ pub async fn check_user(
         }
     })?;
 
-    if let Some(doctor) = doctor {
-        if consultation.doctor_id == doctor.doctor_id {
-            return Ok(());
-        }
-    }
-
-    if user_id != consultation.user_id {
-        return Err(AppError::NotTheSameUser);
-    }
+    let scope = if consultation.user_id == principal.user_id {
+        Scope::Own
+    } else if principal.doctor_id == Some(consultation.doctor_id) {
+        Scope::Attending
+    } else if principal
+        .is_delegate(consultation.user_id, resource, db_pool)
+        .await?
+    {
+        Scope::Delegated
+    } else {
+        Scope::Any
+    };
 
-    Ok(())
+    Ok((consultation.user_id, scope))
 }
 
 pub async fn get_consultation_diagnoses(
     State(state): State<AppState>,
-    AuthUser { user_id, .. }: AuthUser,
-    doctor: Option<LicensedUser>,
+    principal: Principal,
     Path(consultation_id): Path<Uuid>,
 ) -> APIResult<Json<Vec<Diagnosis>>> {
-    check_user(user_id, doctor, consultation_id, &state.db_pool).await?;
+    let (patient_id, scope) = consultation_scope(
+        &principal,
+        consultation_id,
+        Resource::Diagnosis,
+        &state.db_pool,
+    )
+    .await?;
+    authorize_read(
+        &principal,
+        Resource::Diagnosis,
+        patient_id,
+        scope,
+        &state.db_pool,
+    )
+    .await?;
 
     query_as!(
         Diagnosis,
-        "SELECT * FROM diagnoses WHERE consultation_id = $1",
+        "SELECT diagnosis_id, consultation_id, diagnosis, severity AS \
+         \"severity: DiagnosisSeverity\", icd10_code FROM diagnoses WHERE \
+         consultation_id = $1",
         consultation_id
     )
     .fetch_all(&state.db_pool)
@@ -371,11 +541,24 @@ pub async fn get_consultation_diagnoses(
 
 pub async fn get_consultation_prescriptions(
     State(state): State<AppState>,
-    AuthUser { user_id, .. }: AuthUser,
-    doctor: Option<LicensedUser>,
+    principal: Principal,
     Path(consultation_id): Path<Uuid>,
 ) -> APIResult<Json<Vec<Prescription>>> {
-    check_user(user_id, doctor, consultation_id, &state.db_pool).await?;
+    let (patient_id, scope) = consultation_scope(
+        &principal,
+        consultation_id,
+        Resource::Prescription,
+        &state.db_pool,
+    )
+    .await?;
+    authorize_read(
+        &principal,
+        Resource::Prescription,
+        patient_id,
+        scope,
+        &state.db_pool,
+    )
+    .await?;
 
     query_as!(
         Prescription,
@@ -392,6 +575,304 @@ pub async fn get_consultation_prescriptions(
     })
 }
 
+#[derive(Serialize)]
+pub struct ConsultationDoctor {
+    pub doctor_id: Uuid,
+    pub user_id: Uuid,
+    /// Absent when the doctor has not filled in their details
+    pub name: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ConsultationLocation {
+    pub location_id: Uuid,
+    pub practice_permit: String,
+    pub practice_address: String,
+}
+
+/// Everything the record page shows of one visit
+#[derive(Serialize)]
+pub struct ConsultationDetail {
+    pub consultation_id: Uuid,
+    pub user_id: Uuid,
+    pub symptoms: String,
+    pub created_at: DateTime<Utc>,
+    pub reminded: bool,
+    pub doctor: ConsultationDoctor,
+    pub location: ConsultationLocation,
+    pub diagnoses: Vec<Diagnosis>,
+    pub prescriptions: Vec<Prescription>,
+    /// Vital signs taken during the visit
+    pub observations: Vec<Observation>,
+    /// Whether something was prescribed and all of it purchased
+    pub dispensed: bool,
+    /// The consultation this one corrects
+    pub amends: Option<Uuid>,
+    /// The consultation correcting this one, which supersedes it
+    pub amended_by: Option<Uuid>,
+}
+
+/// One consultation with its doctor, location, diagnoses, prescriptions and
+/// observations
+pub async fn get_consultation_detail(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(consultation_id): Path<Uuid>,
+) -> APIResult<Json<ConsultationDetail>> {
+    let db_pool = &state.db_pool;
+
+    let (patient_id, scope) = consultation_scope(
+        &principal,
+        consultation_id,
+        Resource::Consultation,
+        db_pool,
+    )
+    .await?;
+    // diagnoses, prescriptions and observations come along, so they have to
+    // be readable too
+    principal.require(Action::Read, Resource::Diagnosis, scope)?;
+    principal.require(Action::Read, Resource::Prescription, scope)?;
+    principal.require(Action::Read, Resource::Measurement, scope)?;
+    authorize_read(
+        &principal,
+        Resource::Consultation,
+        patient_id,
+        scope,
+        db_pool,
+    )
+    .await?;
+
+    let row = query!(
+        r#"SELECT c.consultation_id, c.user_id, c.symptoms, c.created_at,
+            c.reminded, c.amends,
+            (SELECT a.consultation_id FROM consultations AS a
+                WHERE a.amends = c.consultation_id) AS amended_by,
+            d.doctor_id, d.user_id AS doctor_user_id,
+            ud.name AS "doctor_name?",
+            l.location_id, l.practice_permit, l.practice_address,
+            COALESCE(
+                (SELECT json_agg(dg ORDER BY dg.diagnosis)
+                FROM diagnoses AS dg
+                WHERE dg.consultation_id = c.consultation_id),
+                '[]'
+            ) AS "diagnoses!: SqlJson<Vec<Diagnosis>>",
+            COALESCE(
+                (SELECT json_agg(p ORDER BY p.drug_name)
+                FROM prescriptions AS p
+                WHERE p.consultation_id = c.consultation_id),
+                '[]'
+            ) AS "prescriptions!: SqlJson<Vec<Prescription>>",
+            COALESCE(
+                (SELECT json_agg(json_build_object(
+                    'observation_id', o.observation_id,
+                    'user_id', o.user_id,
+                    'code', o.code,
+                    'name', t.name,
+                    'unit', t.unit,
+                    'category', t.category,
+                    'value', o.value,
+                    'observed_at', o.observed_at,
+                    'recorded_by', o.recorded_by,
+                    'consultation_id', o.consultation_id
+                ) ORDER BY t.name)
+                FROM observations AS o
+                JOIN observation_types AS t ON t.code = o.code
+                WHERE o.consultation_id = c.consultation_id),
+                '[]'
+            ) AS "observations!: SqlJson<Vec<Observation>>"
+        FROM consultations AS c
+        JOIN doctor_profiles AS d ON d.doctor_id = c.doctor_id
+        JOIN doctor_practice_locations AS l ON l.location_id = c.location_id
+        LEFT JOIN user_details AS ud ON ud.user_id = d.user_id
+        WHERE c.consultation_id = $1"#,
+        consultation_id
+    )
+    .fetch_one(db_pool)
+    .await
+    .map_err(|e| {
+        error!(
+            "Error while retrieving consultation {}: {:?}",
+            consultation_id, e
+        );
+        AppError::InternalError
+    })?;
+
+    let SqlJson(diagnoses) = row.diagnoses;
+    let SqlJson(prescriptions) = row.prescriptions;
+    let SqlJson(observations) = row.observations;
+
+    Ok(Json(ConsultationDetail {
+        consultation_id: row.consultation_id,
+        user_id: row.user_id,
+        symptoms: row.symptoms,
+        created_at: row.created_at,
+        reminded: row.reminded,
+        doctor: ConsultationDoctor {
+            doctor_id: row.doctor_id,
+            user_id: row.doctor_user_id,
+            name: row.doctor_name,
+        },
+        location: ConsultationLocation {
+            location_id: row.location_id,
+            practice_permit: row.practice_permit,
+            practice_address: row.practice_address,
+        },
+        dispensed: !prescriptions.is_empty()
+            && prescriptions
+                .iter()
+                .all(|prescription| prescription.purchased_at.is_some()),
+        diagnoses,
+        prescriptions,
+        observations,
+        amends: row.amends,
+        amended_by: row.amended_by,
+    }))
+}
+
+/// A medicine to take, from a prescription of a consultation the patient set
+/// a reminder for
+#[derive(Serialize)]
+pub struct Reminder {
+    pub prescription_id: Uuid,
+    pub consultation_id: Uuid,
+    pub drug_name: String,
+    pub regimen_per_day: f64,
+    pub quantity_per_dose: f64,
+    pub instruction: String,
+    /// When the consultation took place, what reminders are ordered by
+    pub prescribed_at: DateTime<Utc>,
+}
+
+/// A prescription with the time of its consultation, to order by
+struct ListedPrescription {
+    prescribed_at: DateTime<Utc>,
+    prescription: SqlJson<Prescription>,
+}
+
+/// Prescriptions of a patient, a page at a time by consultation
+async fn fetch_prescriptions(
+    user_id: Uuid,
+    page: &Page,
+    db_pool: &Pool<Postgres>,
+) -> APIResult<Paginated<Prescription>> {
+    fetch_page!(
+        ListedPrescription,
+        page,
+        db_pool,
+        r#"SELECT c.created_at AS prescribed_at,
+            to_json(p) AS "prescription!: SqlJson<Prescription>"
+        FROM prescriptions AS p
+        JOIN consultations AS c ON c.consultation_id = p.consultation_id
+        WHERE c.user_id = $6"#,
+        "c.created_at",
+        "p.prescription_id",
+        user_id
+    )
+    .map(|rows| {
+        page.paginate(rows, |row| {
+            (row.prescribed_at, row.prescription.prescription_id)
+        })
+        .map(|row| row.prescription.0)
+    })
+    .map_err(|e| {
+        error!(
+            "Error while retrieving prescriptions for {}: {:?}",
+            user_id, e
+        );
+        AppError::InternalError
+    })
+}
+
+async fn fetch_reminders(
+    user_id: Uuid,
+    page: &Page,
+    db_pool: &Pool<Postgres>,
+) -> APIResult<Paginated<Reminder>> {
+    fetch_page!(
+        Reminder,
+        page,
+        db_pool,
+        "SELECT p.prescription_id, p.consultation_id, p.drug_name,
+            p.regimen_per_day, p.quantity_per_dose, p.instruction,
+            c.created_at AS prescribed_at
+         FROM prescriptions AS p
+         JOIN consultations AS c ON c.consultation_id = p.consultation_id
+         WHERE c.user_id = $6 AND c.reminded",
+        "c.created_at",
+        "p.prescription_id",
+        user_id
+    )
+    .map(|rows| {
+        page.paginate(rows, |reminder| {
+            (reminder.prescribed_at, reminder.prescription_id)
+        })
+    })
+    .map_err(|e| {
+        error!("Error while retrieving reminders for {}: {:?}", user_id, e);
+        AppError::InternalError
+    })
+}
+
+pub async fn get_own_prescriptions(
+    State(state): State<AppState>,
+    principal: Principal,
+    page: Page,
+) -> APIResult<Paginated<Prescription>> {
+    principal.require(Action::Read, Resource::Prescription, Scope::Own)?;
+
+    fetch_prescriptions(principal.user_id, &page, &state.db_pool).await
+}
+
+pub async fn get_user_prescriptions(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(user_id): Path<Uuid>,
+    page: Page,
+) -> APIResult<Paginated<Prescription>> {
+    authorize_read(
+        &principal,
+        Resource::Prescription,
+        user_id,
+        principal
+            .patient_scope(user_id, Resource::Prescription, &state.db_pool)
+            .await?,
+        &state.db_pool,
+    )
+    .await?;
+
+    fetch_prescriptions(user_id, &page, &state.db_pool).await
+}
+
+pub async fn get_own_reminders(
+    State(state): State<AppState>,
+    principal: Principal,
+    page: Page,
+) -> APIResult<Paginated<Reminder>> {
+    principal.require(Action::Read, Resource::Reminder, Scope::Own)?;
+
+    fetch_reminders(principal.user_id, &page, &state.db_pool).await
+}
+
+pub async fn get_user_reminders(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(user_id): Path<Uuid>,
+    page: Page,
+) -> APIResult<Paginated<Reminder>> {
+    authorize_read(
+        &principal,
+        Resource::Reminder,
+        user_id,
+        principal
+            .patient_scope(user_id, Resource::Reminder, &state.db_pool)
+            .await?,
+        &state.db_pool,
+    )
+    .await?;
+
+    fetch_reminders(user_id, &page, &state.db_pool).await
+}
+
 #[derive(Deserialize)]
 pub struct PrescriptionPurchasedAt {
     purchased_at: DateTime<Utc>,
@@ -399,12 +880,14 @@ pub struct PrescriptionPurchasedAt {
 
 pub async fn set_prescriptions_purchased_at(
     State(state): State<AppState>,
-    AuthUser { user_id, .. }: AuthUser,
+    principal: Principal,
     Path(prescription_id): Path<Uuid>,
     Json(PrescriptionPurchasedAt { purchased_at }): Json<
         PrescriptionPurchasedAt,
     >,
 ) -> APIResult<(StatusCode, Json<Value>)> {
+    let mut tx = audit::begin(&state.db_pool).await?;
+
     let record = query!(
         "SELECT u.user_id FROM users as u
          JOIN consultations AS c ON c.user_id = u.user_id
@@ -412,12 +895,12 @@ pub async fn set_prescriptions_purchased_at(
          WHERE p.prescription_id = $1",
         prescription_id
     )
-    .fetch_optional(&state.db_pool)
+    .fetch_optional(&mut *tx)
     .await
     .map_err(|e| {
         error!(
             "Error while checking for prescription {} user {}: {}",
-            prescription_id, user_id, e
+            prescription_id, principal.user_id, e
         );
 
         AppError::InternalError
@@ -427,9 +910,11 @@ pub async fn set_prescriptions_purchased_at(
         return Err(DatabaseError::RowNotFound.into());
     };
 
-    if record.user_id != user_id {
-        return Err(AppError::NotTheSameUser);
-    }
+    principal.require(
+        Action::Update,
+        Resource::Prescription,
+        principal.scope_of(record.user_id),
+    )?;
 
     query!(
         "UPDATE prescriptions
@@ -438,7 +923,7 @@ pub async fn set_prescriptions_purchased_at(
         purchased_at,
         prescription_id
     )
-    .execute(&state.db_pool)
+    .execute(&mut *tx)
     .await
     .map_err(|e| {
         error!(
@@ -448,6 +933,19 @@ pub async fn set_prescriptions_purchased_at(
         AppError::InternalError
     })?;
 
+    audit::record(
+        &mut tx,
+        principal.user_id,
+        Some(record.user_id),
+        "prescription.purchase",
+        json!({
+            "prescription_id": prescription_id,
+            "purchased_at": purchased_at,
+        }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
     Ok((
         StatusCode::OK,
         Json(json!({ "message": "Prescription marked as purchased" })),
@@ -456,9 +954,14 @@ pub async fn set_prescriptions_purchased_at(
 
 pub async fn set_reminder(
     State(state): State<AppState>,
-    AuthUser { user_id, .. }: AuthUser,
+    principal: Principal,
     Path(consultation_id): Path<Uuid>,
 ) -> APIResult<(StatusCode, Json<Value>)> {
+    principal.require(Action::Update, Resource::Consultation, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
     let query_res: sqlx::postgres::PgQueryResult = query!(
         "UPDATE consultations
          SET reminded = true
@@ -466,7 +969,7 @@ pub async fn set_reminder(
         consultation_id,
         user_id
     )
-    .execute(&state.db_pool)
+    .execute(&mut *tx)
     .await
     .map_err(|e| {
         error!(
@@ -481,5 +984,15 @@ pub async fn set_reminder(
         return Err(DatabaseError::RowNotFound.into());
     }
 
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "consultation.remind",
+        json!({ "consultation_id": consultation_id }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
     Ok((StatusCode::OK, Json(json!({ "message": "reminded" }))))
 }
@@ -0,0 +1,256 @@
+//! Read access for trusted contacts.
+//!
+//! A patient may let a spouse or caregiver read some of their records, e.g.
+//! to keep an eye on their reminders, without handing over control. A
+//! delegation names the contact, the [`DELEGABLE_RESOURCES`] they may read and
+//! when that ends; the patient can revoke it before. While it holds the
+//! contact reads through the usual `/users/{user_id}/...` routes under
+//! [`Scope::Delegated`], and every read shows up in the patient's access log.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sqlx::query_scalar;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    AppState, audit,
+    error::{APIResult, AppError, DatabaseError},
+    pagination::{Page, Paginated, fetch_page},
+    policy::{Action, Principal, Resource, Scope},
+    route::notification::notify,
+};
+
+/// What a patient can let somebody else read
+pub const DELEGABLE_RESOURCES: [Resource; 3] = [
+    Resource::Reminder,
+    Resource::Prescription,
+    Resource::Measurement,
+];
+
+#[derive(Deserialize)]
+pub struct DelegationPayload {
+    pub delegate_id: Uuid,
+    pub resources: Vec<Resource>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct Delegation {
+    pub delegation_id: Uuid,
+    /// The patient
+    pub user_id: Uuid,
+    pub delegate_id: Uuid,
+    pub resources: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+pub async fn add_own_delegation(
+    State(state): State<AppState>,
+    principal: Principal,
+    Json(DelegationPayload {
+        delegate_id,
+        mut resources,
+        expires_at,
+    }): Json<DelegationPayload>,
+) -> APIResult<(StatusCode, Json<Value>)> {
+    principal.require(Action::Create, Resource::Delegation, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    resources.sort_by_key(Resource::as_str);
+    resources.dedup();
+
+    if resources.is_empty()
+        || resources
+            .iter()
+            .any(|resource| !DELEGABLE_RESOURCES.contains(resource))
+        || expires_at <= Utc::now()
+        || delegate_id == user_id
+    {
+        return Err(AppError::InvalidDelegation);
+    }
+
+    let resource_names: Vec<String> = resources
+        .iter()
+        .map(|resource| resource.as_str().to_string())
+        .collect();
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    let delegation_id = query_scalar!(
+        "INSERT INTO delegations (user_id, delegate_id, resources, \
+         expires_at) VALUES ($1, $2, $3, $4) RETURNING delegation_id",
+        user_id,
+        delegate_id,
+        &resource_names,
+        expires_at
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(db_e) if db_e.is_foreign_key_violation() => {
+            DatabaseError::ForeignKeyViolation.into()
+        }
+        e => {
+            error!(
+                "Error while delegating {} to {}: {:?}",
+                user_id, delegate_id, e
+            );
+            AppError::InternalError
+        }
+    })?;
+
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "delegation.create",
+        json!({
+            "delegation_id": delegation_id,
+            "delegate_id": delegate_id,
+            "resources": resources,
+            "expires_at": expires_at,
+        }),
+    )
+    .await?;
+    notify(
+        &mut tx,
+        delegate_id,
+        "delegation",
+        json!({
+            "delegation_id": delegation_id,
+            "user_id": user_id,
+            "resources": resources,
+            "expires_at": expires_at,
+        }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    info!(
+        "{} delegated reading {:?} to {} until {}",
+        user_id, resource_names, delegate_id, expires_at
+    );
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "message": "delegation created",
+            "delegation_id": delegation_id,
+        })),
+    ))
+}
+
+/// Every delegation the caller issued, including revoked and expired ones
+pub async fn get_own_delegations(
+    State(state): State<AppState>,
+    principal: Principal,
+    page: Page,
+) -> APIResult<Paginated<Delegation>> {
+    principal.require(Action::Read, Resource::Delegation, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    fetch_page!(
+        Delegation,
+        &page,
+        &state.db_pool,
+        "SELECT * FROM delegations WHERE user_id = $6",
+        "created_at",
+        "delegation_id",
+        user_id
+    )
+    .map(|rows| {
+        page.paginate(rows, |delegation| {
+            (delegation.created_at, delegation.delegation_id)
+        })
+    })
+    .map_err(|e| {
+        error!("Error while retrieving delegations of {}: {:?}", user_id, e);
+        AppError::InternalError
+    })
+}
+
+/// Delegations to the caller that still hold
+pub async fn get_received_delegations(
+    State(state): State<AppState>,
+    principal: Principal,
+    page: Page,
+) -> APIResult<Paginated<Delegation>> {
+    principal.require(Action::Read, Resource::Delegation, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    fetch_page!(
+        Delegation,
+        &page,
+        &state.db_pool,
+        "SELECT * FROM delegations
+         WHERE delegate_id = $6 AND expires_at > NOW() AND revoked_at IS NULL",
+        "created_at",
+        "delegation_id",
+        user_id
+    )
+    .map(|rows| {
+        page.paginate(rows, |delegation| {
+            (delegation.created_at, delegation.delegation_id)
+        })
+    })
+    .map_err(|e| {
+        error!("Error while retrieving delegations to {}: {:?}", user_id, e);
+        AppError::InternalError
+    })
+}
+
+pub async fn revoke_own_delegation(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(delegation_id): Path<Uuid>,
+) -> APIResult<(StatusCode, Json<Value>)> {
+    principal.require(Action::Delete, Resource::Delegation, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    let delegate_id = query_scalar!(
+        "UPDATE delegations SET revoked_at = NOW() WHERE delegation_id = $1 \
+         AND user_id = $2 AND revoked_at IS NULL RETURNING delegate_id",
+        delegation_id,
+        user_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!(
+            "Error while revoking delegation {} of {}: {:?}",
+            delegation_id, user_id, e
+        );
+        AppError::InternalError
+    })?
+    // unknown, somebody else's or already revoked
+    .ok_or(DatabaseError::RowNotFound)?;
+
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "delegation.revoke",
+        json!({
+            "delegation_id": delegation_id,
+            "delegate_id": delegate_id,
+        }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "message": "delegation revoked" })),
+    ))
+}
@@ -0,0 +1,381 @@
+//! Dependents and their guardians.
+//!
+//! Children and elderly patients may not hold a device key of their own. A
+//! guardian creates a dependent profile for them, which has no credentials,
+//! and then manages it by sending the `X-Acting-As` header with the `/me`
+//! routes (see [`crate::policy::Principal::act_for`]). Consents signed by the
+//! device of an active guardian count as the dependent's own.
+//!
+//! Guardianship ends by itself once the dependent comes of age. To take over,
+//! the dependent redeems a handover code from one of their guardians at
+//! `POST /register/dependent` and from then on logs in like anybody else.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use data_encoding::HEXLOWER;
+use rand::distr::SampleString;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use sqlx::{query, query_as, query_scalar};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    AppState, HANDOVER_CODE_TTL, audit,
+    error::{APIResult, AppError, DatabaseError},
+    policy::{
+        AGE_OF_MAJORITY, Action, Principal, Resource, Scope, is_active_guardian,
+    },
+    protocol::{NIK_LOWERBOUND, NIK_UPPERBOUND},
+    route::user_detail::UserDetailPayload,
+};
+
+/// Handover code character length
+pub const HANDOVER_CODE_LEN: usize = 16;
+
+#[derive(Serialize)]
+pub struct Dependent {
+    pub user_id: Uuid,
+    pub name: String,
+    pub dob: NaiveDate,
+    /// Guardians no longer act for dependents who came of age
+    pub of_age: bool,
+    /// Whether the dependent can log in themselves
+    pub handed_over: bool,
+    pub guardians: Vec<Uuid>,
+}
+
+#[derive(Deserialize)]
+pub struct GuardianPayload {
+    pub guardian_id: Uuid,
+}
+
+pub fn hash_handover_code(code: &str) -> String {
+    HEXLOWER.encode(&Sha256::digest(code.as_bytes()))
+}
+
+/// Dependents can't look after anybody, whoever acts as one is turned away
+fn require_guardian(principal: &Principal, action: Action) -> APIResult<Uuid> {
+    principal.require(action, Resource::Dependent, Scope::Own)?;
+
+    if principal.guardian_id.is_some() {
+        return Err(AppError::PermissionDenied);
+    }
+
+    Ok(principal.user_id)
+}
+
+pub async fn add_own_dependent(
+    State(state): State<AppState>,
+    principal: Principal,
+    Json(payload): Json<UserDetailPayload>,
+) -> APIResult<(StatusCode, Json<Value>)> {
+    let guardian_id = require_guardian(&principal, Action::Create)?;
+
+    if !(NIK_LOWERBOUND..=NIK_UPPERBOUND).contains(&payload.nik) {
+        return Err(AppError::InvalidNik);
+    }
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    let dependent_id =
+        query_scalar!("INSERT INTO users DEFAULT VALUES RETURNING user_id")
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(|e| {
+                error!(
+                    "Error while creating dependent of {}: {:?}",
+                    guardian_id, e
+                );
+                AppError::InternalError
+            })?;
+
+    query!(
+        "INSERT INTO user_details (user_id, nik, name, dob, gender) VALUES \
+         ($1, $2, $3, $4, $5)",
+        dependent_id,
+        payload.nik,
+        payload.name,
+        payload.dob,
+        payload.gender as i8,
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!(
+            "Error while setting user_detail for {}: {:?}",
+            dependent_id, e
+        );
+        AppError::InternalError
+    })?;
+
+    query!(
+        "INSERT INTO guardianships (dependent_id, guardian_id, added_by) \
+         VALUES ($1, $2, $2)",
+        dependent_id,
+        guardian_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!(
+            "Error while adding {} as guardian of {}: {:?}",
+            guardian_id, dependent_id, e
+        );
+        AppError::InternalError
+    })?;
+
+    audit::record(
+        &mut tx,
+        guardian_id,
+        Some(dependent_id),
+        "dependent.create",
+        json!({
+            "nik": payload.nik,
+            "name": payload.name,
+            "dob": payload.dob,
+            "gender": payload.gender,
+        }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    info!("{} created dependent {}", guardian_id, dependent_id);
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "message": "dependent created",
+            "user_id": dependent_id,
+        })),
+    ))
+}
+
+pub async fn get_own_dependents(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> APIResult<Json<Vec<Dependent>>> {
+    let guardian_id = require_guardian(&principal, Action::Read)?;
+
+    query_as!(
+        Dependent,
+        r#"SELECT ud.user_id, ud.name, ud.dob,
+            ud.dob + make_interval(years => $2) <= CURRENT_DATE AS "of_age!",
+            u.password_hash IS NOT NULL AS "handed_over!",
+            ARRAY(SELECT guardian_id FROM guardianships
+                WHERE dependent_id = ud.user_id
+                ORDER BY created_at) AS "guardians!"
+        FROM guardianships AS g
+        JOIN user_details AS ud ON ud.user_id = g.dependent_id
+        JOIN users AS u ON u.user_id = g.dependent_id
+        WHERE g.guardian_id = $1
+        ORDER BY ud.dob"#,
+        guardian_id,
+        AGE_OF_MAJORITY
+    )
+    .fetch_all(&state.db_pool)
+    .await
+    .map(Json)
+    .map_err(|e| {
+        error!(
+            "Error while retrieving dependents of {}: {:?}",
+            guardian_id, e
+        );
+        AppError::InternalError
+    })
+}
+
+pub async fn add_dependent_guardian(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(dependent_id): Path<Uuid>,
+    Json(GuardianPayload {
+        guardian_id: new_guardian_id,
+    }): Json<GuardianPayload>,
+) -> APIResult<(StatusCode, Json<Value>)> {
+    let guardian_id = require_guardian(&principal, Action::Update)?;
+
+    if !is_active_guardian(guardian_id, dependent_id, &state.db_pool).await? {
+        return Err(AppError::NotGuardian);
+    }
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    query!(
+        "INSERT INTO guardianships (dependent_id, guardian_id, added_by) \
+         VALUES ($1, $2, $3) ON CONFLICT (dependent_id, guardian_id) DO \
+         NOTHING",
+        dependent_id,
+        new_guardian_id,
+        guardian_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(db_e) if db_e.is_foreign_key_violation() => {
+            DatabaseError::ForeignKeyViolation.into()
+        }
+        e => {
+            error!(
+                "Error while adding {} as guardian of {}: {:?}",
+                new_guardian_id, dependent_id, e
+            );
+            AppError::InternalError
+        }
+    })?;
+
+    audit::record(
+        &mut tx,
+        guardian_id,
+        Some(dependent_id),
+        "guardian.add",
+        json!({ "guardian_id": new_guardian_id }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({ "message": "guardian added" })),
+    ))
+}
+
+pub async fn remove_dependent_guardian(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path((dependent_id, removed_guardian_id)): Path<(Uuid, Uuid)>,
+) -> APIResult<(StatusCode, Json<Value>)> {
+    let guardian_id = require_guardian(&principal, Action::Update)?;
+
+    if !is_active_guardian(guardian_id, dependent_id, &state.db_pool).await? {
+        return Err(AppError::NotGuardian);
+    }
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    // a dependent that is not handed over yet must keep somebody in charge
+    let removed = query_scalar!(
+        r#"DELETE FROM guardianships
+        WHERE dependent_id = $1 AND guardian_id = $2
+            AND (
+                EXISTS(SELECT 1 FROM users
+                    WHERE user_id = $1 AND password_hash IS NOT NULL)
+                OR (SELECT COUNT(*) FROM guardianships
+                    WHERE dependent_id = $1) > 1
+            )
+        RETURNING guardian_id"#,
+        dependent_id,
+        removed_guardian_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!(
+            "Error while removing {} as guardian of {}: {:?}",
+            removed_guardian_id, dependent_id, e
+        );
+        AppError::InternalError
+    })?;
+
+    if removed.is_none() {
+        // not a guardian, or the last one
+        return Err(DatabaseError::RowNotFound.into());
+    }
+
+    audit::record(
+        &mut tx,
+        guardian_id,
+        Some(dependent_id),
+        "guardian.remove",
+        json!({ "guardian_id": removed_guardian_id }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "message": "guardian removed" })),
+    ))
+}
+
+/// Issues a one-time code the dependent sets their own credentials with.
+///
+/// Still allowed once the dependent came of age, it is how they take over an
+/// account nobody can log in to otherwise.
+pub async fn create_dependent_handover(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(dependent_id): Path<Uuid>,
+) -> APIResult<(StatusCode, Json<Value>)> {
+    let guardian_id = require_guardian(&principal, Action::Update)?;
+
+    let record = query!(
+        r#"SELECT u.password_hash IS NOT NULL AS "handed_over!"
+        FROM guardianships AS g
+        JOIN users AS u ON u.user_id = g.dependent_id
+        WHERE g.guardian_id = $1 AND g.dependent_id = $2"#,
+        guardian_id,
+        dependent_id
+    )
+    .fetch_optional(&state.db_pool)
+    .await
+    .map_err(|e| {
+        error!(
+            "Error while checking guardianship of {} over {}: {:?}",
+            guardian_id, dependent_id, e
+        );
+        AppError::InternalError
+    })?
+    .ok_or(AppError::NotGuardian)?;
+
+    if record.handed_over {
+        return Err(DatabaseError::RowNotFound.into());
+    }
+
+    let code = rand::distr::Alphanumeric
+        .sample_string(&mut rand::rng(), HANDOVER_CODE_LEN);
+    let expires_at: DateTime<Utc> = Utc::now() + HANDOVER_CODE_TTL;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    query!(
+        "INSERT INTO dependent_handovers (code_hash, dependent_id, \
+         created_by, expires_at) VALUES ($1, $2, $3, $4)",
+        hash_handover_code(&code),
+        dependent_id,
+        guardian_id,
+        expires_at
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!(
+            "Error while creating handover for {}: {:?}",
+            dependent_id, e
+        );
+        AppError::InternalError
+    })?;
+
+    audit::record(
+        &mut tx,
+        guardian_id,
+        Some(dependent_id),
+        "dependent.handover_issue",
+        json!({ "expires_at": expires_at }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "code": code,
+            "expires_at": expires_at,
+        })),
+    ))
+}
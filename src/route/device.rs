@@ -0,0 +1,260 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use chrono::{DateTime, Utc};
+use ed25519_compact::PublicKey;
+use serde::Serialize;
+use serde_json::{Value, json};
+use sqlx::{query, query_as};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    AppState, audit,
+    auth::{end_device_sessions, end_user_sessions, revoke_device},
+    error::{APIResult, AppError, DatabaseError},
+    policy::{Action, Principal, Resource, Scope},
+    protocol::{ConsentError, KeyRotation},
+    schema::DeviceKey,
+};
+
+#[derive(Serialize)]
+pub struct DeviceInfo {
+    pub device_id: Uuid,
+    pub device_name: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// Last request or consent from the device, up to
+    /// [`DEVICE_TOUCH_INTERVAL`](crate::DEVICE_TOUCH_INTERVAL) behind
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub generation: i32,
+    /// Whether this is the device the request was made from
+    pub current: bool,
+}
+
+pub async fn get_own_devices(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> APIResult<Json<Vec<DeviceInfo>>> {
+    principal.require(Action::Read, Resource::Device, Scope::Own)?;
+    let user_id = principal.user_id;
+    let device_id = principal.device_id;
+
+    let devices = query!(
+        "SELECT device_id, device_name, created_at, last_used_at, revoked_at,
+         generation FROM device_keys WHERE user_id = $1
+         ORDER BY created_at DESC",
+        user_id
+    )
+    .fetch_all(&state.db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while retrieving devices for {}: {:?}", user_id, e);
+        AppError::InternalError
+    })?;
+
+    Ok(Json(
+        devices
+            .into_iter()
+            .map(|device| DeviceInfo {
+                device_id: device.device_id,
+                device_name: device.device_name,
+                created_at: device.created_at,
+                last_used_at: device.last_used_at,
+                revoked_at: device.revoked_at,
+                generation: device.generation,
+                current: device.device_id == device_id,
+            })
+            .collect(),
+    ))
+}
+
+pub async fn revoke_own_device(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(device_id): Path<Uuid>,
+) -> APIResult<(StatusCode, Json<Value>)> {
+    principal.require(Action::Delete, Resource::Device, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+    revoke_device(&mut tx, device_id, user_id).await?;
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "device.revoke",
+        json!({ "device_id": device_id }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    end_device_sessions(&state.recognized_session_id, device_id);
+
+    info!("{user_id} revoked device {device_id}");
+
+    Ok((StatusCode::OK, Json(json!({ "message": "device revoked" }))))
+}
+
+/// Revokes every device of the caller, including the current one
+pub async fn logout_everywhere(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> APIResult<(StatusCode, Json<Value>)> {
+    principal.require(Action::Delete, Resource::Device, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    let res = query!(
+        "UPDATE device_keys SET revoked_at = $1
+         WHERE user_id = $2 AND revoked_at IS NULL",
+        Utc::now(),
+        user_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!("Error while revoking all devices of {}: {:?}", user_id, e);
+        AppError::InternalError
+    })?;
+
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "device.revoke_all",
+        json!({ "revoked_devices": res.rows_affected() }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    end_user_sessions(&state.recognized_session_id, user_id);
+
+    info!("{user_id} logged out of {} devices", res.rows_affected());
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "message": "logged out everywhere",
+            "revoked_devices": res.rows_affected(),
+        })),
+    ))
+}
+
+/// Replaces the key of a device with one signed over by its current key.
+///
+/// The replaced key is kept in `device_key_generations`, so consents over
+/// nonces issued before the rotation are still checked against it.
+pub async fn rotate_device_key(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(device_id): Path<Uuid>,
+    Json(rotation): Json<KeyRotation>,
+) -> APIResult<Json<Value>> {
+    principal.require(Action::Update, Resource::Device, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    state
+        .nonce_cache
+        .remove(rotation.nonce.as_str())
+        .ok_or(ConsentError::NonceExpired)?;
+
+    let new_pk = PublicKey::from_pem(&rotation.public_key_pem)
+        .map_err(|_| ConsentError::InvalidPublicKey)?;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    // lock the row so two concurrent rotations cannot both hand over the
+    // same generation
+    let device_key = query_as!(
+        DeviceKey,
+        "SELECT * FROM device_keys WHERE device_id = $1 AND user_id = $2
+         FOR UPDATE",
+        device_id,
+        user_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::RowNotFound => DatabaseError::RowNotFound.into(),
+        e => {
+            error!("Error while fetching device key {}: {:?}", device_id, e);
+            AppError::InternalError
+        }
+    })?;
+
+    if device_key.revoked_at.is_some() {
+        return Err(ConsentError::KeyExpired.into());
+    }
+
+    let current_pk =
+        PublicKey::from_pem(&device_key.public_key_pem).map_err(|e| {
+            error!("Error while parsing key of {}: {:?}", device_id, e);
+            AppError::InternalError
+        })?;
+
+    if !rotation.verify(device_id, &current_pk) {
+        warn!("{user_id} sent an unsigned key rotation for {device_id}");
+        return Err(ConsentError::NonConsent.into());
+    }
+
+    let now = Utc::now();
+    let generation = device_key.generation + 1;
+
+    query!(
+        "INSERT INTO device_key_generations (device_id, generation, \
+         public_key_pem, created_at, retired_at) VALUES ($1, $2, $3, $4, $5)",
+        device_id,
+        device_key.generation,
+        device_key.public_key_pem,
+        device_key.key_created_at,
+        now
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!("Error while retiring key of {}: {:?}", device_id, e);
+        AppError::InternalError
+    })?;
+
+    query!(
+        "UPDATE device_keys SET public_key_pem = $1, generation = $2, \
+         key_created_at = $3 WHERE device_id = $4",
+        new_pk.to_pem(),
+        generation,
+        now,
+        device_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!("Error while storing new key of {}: {:?}", device_id, e);
+        AppError::InternalError
+    })?;
+
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "device.rotate",
+        json!({
+            "device_id": device_id,
+            "generation": generation,
+            "public_key_pem": new_pk.to_pem(),
+        }),
+    )
+    .await?;
+
+    audit::commit(tx).await?;
+
+    info!("{user_id} rotated device {device_id} to generation {generation}");
+
+    Ok(Json(json!({
+        "device_id": device_id,
+        "generation": generation,
+        "key_created_at": now,
+    })))
+}
@@ -6,14 +6,14 @@ use axum::{
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
-use sqlx::{query, query_as};
+use sqlx::{query, query_as, query_scalar};
 use tracing::{error, warn};
 use uuid::Uuid;
 
 use crate::{
-    AppState,
-    auth::{AuthUser, LicensedUser},
+    AppState, audit,
     error::{APIResult, AppError, DatabaseError},
+    policy::{Action, Principal, Resource, Scope},
     schema::{DoctorPracticeLocation, DoctorProfile},
 };
 
@@ -34,9 +34,11 @@ pub struct DoctorProfilePublic {
 
 pub async fn get_doctor_profile(
     State(state): State<AppState>,
-    AuthUser { .. }: AuthUser,
+    principal: Principal,
     Path(doctor_id): Path<Uuid>,
 ) -> APIResult<Json<DoctorProfilePublic>> {
+    principal.require(Action::Read, Resource::DoctorProfile, Scope::Any)?;
+
     let profile = query!(
         "SELECT d.doctor_id, d.user_id, d.created_at, d.approved_at, ud.name
             FROM doctor_profiles AS d
@@ -99,9 +101,15 @@ pub async fn get_doctor_profile(
 
 pub async fn get_doctor_profile_by_user_id(
     State(state): State<AppState>,
-    _: AuthUser,
+    principal: Principal,
     Path(user_id): Path<Uuid>,
 ) -> APIResult<Json<DoctorProfilePublic>> {
+    principal.require(
+        Action::Read,
+        Resource::DoctorProfile,
+        principal.scope_of(user_id),
+    )?;
+
     let profile = query!(
         "SELECT d.doctor_id, d.user_id, d.created_at, d.approved_at, ud.name
             FROM doctor_profiles AS d
@@ -161,8 +169,11 @@ pub async fn get_doctor_profile_by_user_id(
 
 pub async fn set_doctor_profile(
     State(state): State<AppState>,
-    AuthUser { user_id, .. }: AuthUser,
+    principal: Principal,
 ) -> APIResult<(StatusCode, Json<Value>)> {
+    principal.require(Action::Create, Resource::DoctorProfile, Scope::Own)?;
+    let user_id = principal.user_id;
+
     let profile = query_as!(
         DoctorProfile,
         "SELECT * FROM doctor_profiles WHERE user_id = $1",
@@ -180,27 +191,42 @@ pub async fn set_doctor_profile(
         return Err(DatabaseError::ForeignKeyViolation.into());
     }
 
-    query!("INSERT INTO doctor_profiles (user_id) VALUES ($1)", user_id,)
-        .execute(&state.db_pool)
-        .await
-        .map_err(|e| {
-            error!(
-                "Error while inserting doctor_profile for {}:
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    let doctor_id = query_scalar!(
+        "INSERT INTO doctor_profiles (user_id) VALUES ($1) RETURNING doctor_id",
+        user_id,
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!(
+            "Error while inserting doctor_profile for {}:
              {:?}",
-                user_id, e
-            );
+            user_id, e
+        );
 
-            match e {
-                sqlx::Error::Database(db_e) => {
-                    if db_e.is_foreign_key_violation() {
-                        DatabaseError::ForeignKeyViolation.into()
-                    } else {
-                        AppError::InternalError
-                    }
+        match e {
+            sqlx::Error::Database(db_e) => {
+                if db_e.is_foreign_key_violation() {
+                    DatabaseError::ForeignKeyViolation.into()
+                } else {
+                    AppError::InternalError
                 }
-                _ => AppError::InternalError,
             }
-        })?;
+            _ => AppError::InternalError,
+        }
+    })?;
+
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "doctor_profile.create",
+        json!({ "doctor_id": doctor_id }),
+    )
+    .await?;
+    audit::commit(tx).await?;
 
     Ok((
         StatusCode::CREATED,
@@ -219,30 +245,54 @@ pub struct PracticeAddressPayload {
 
 pub async fn add_doctor_practice_location(
     State(state): State<AppState>,
-    doctor: LicensedUser,
+    principal: Principal,
     Json(PracticeAddressPayload {
         practice_permit,
         practice_address,
     }): Json<PracticeAddressPayload>,
 ) -> APIResult<(StatusCode, Json<Value>)> {
-    query!(
+    principal.require(
+        Action::Create,
+        Resource::PracticeLocation,
+        Scope::Own,
+    )?;
+    let doctor_id = principal.doctor_profile()?;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    let location_id = query_scalar!(
         "INSERT INTO doctor_practice_locations (doctor_id, practice_permit, \
-         practice_address) VALUES ($1, $2, $3)",
-        doctor.doctor_id,
+         practice_address) VALUES ($1, $2, $3) RETURNING location_id",
+        doctor_id,
         practice_permit,
         practice_address
     )
-    .execute(&state.db_pool)
+    .fetch_one(&mut *tx)
     .await
     .map_err(|e| {
         error!(
             "Error while adding a practice address for {}: {}",
-            doctor.doctor_id, e
+            doctor_id, e
         );
 
         AppError::InternalError
     })?;
 
+    audit::record(
+        &mut tx,
+        principal.user_id,
+        Some(principal.user_id),
+        "practice_location.create",
+        json!({
+            "location_id": location_id,
+            "doctor_id": doctor_id,
+            "practice_permit": practice_permit,
+            "practice_address": practice_address,
+        }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
     Ok((
         StatusCode::CREATED,
         Json(json!({ "message": "Successfully submitted a practice address" })),
@@ -251,21 +301,30 @@ pub async fn add_doctor_practice_location(
 
 pub async fn delete_doctor_practice_location(
     State(state): State<AppState>,
-    doctor: LicensedUser,
+    principal: Principal,
     Path(location_id): Path<Uuid>,
 ) -> APIResult<(StatusCode, Json<Value>)> {
+    principal.require(
+        Action::Delete,
+        Resource::PracticeLocation,
+        Scope::Own,
+    )?;
+    let doctor_id = principal.doctor_profile()?;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
     let res = query!(
         "DELETE FROM doctor_practice_locations WHERE location_id = $1 AND \
          doctor_id = $2",
         location_id,
-        doctor.doctor_id,
+        doctor_id,
     )
-    .execute(&state.db_pool)
+    .execute(&mut *tx)
     .await
     .map_err(|e| {
         error!(
             "Error while deleting a practice address {} for {}: {}",
-            location_id, doctor.doctor_id, e
+            location_id, doctor_id, e
         );
 
         AppError::InternalError
@@ -275,6 +334,19 @@ pub async fn delete_doctor_practice_location(
         return Err(DatabaseError::RowNotFound.into());
     }
 
+    audit::record(
+        &mut tx,
+        principal.user_id,
+        Some(principal.user_id),
+        "practice_location.delete",
+        json!({
+            "location_id": location_id,
+            "doctor_id": doctor_id,
+        }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
     Ok((
         StatusCode::OK,
         Json(
@@ -0,0 +1,231 @@
+//! Break-the-glass access.
+//!
+//! A doctor who has to treat a patient they never held a consultation with,
+//! and who can't get the patient's consent, may open emergency access with a
+//! written justification. For [`EMERGENCY_ACCESS_TTL`] they can then read the
+//! patient's allergies and medical conditions, nothing else and nothing
+//! written (see [`Scope::Emergency`]).
+//!
+//! Opening access is recorded as a flagged audit event, the patient is
+//! notified, and every grant stays on the admins' review list until one of
+//! them has looked at it.
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sqlx::{query, query_scalar};
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::{
+    AppState, EMERGENCY_ACCESS_TTL, audit,
+    error::{APIResult, AppError, DatabaseError},
+    pagination::{Page, Paginated, fetch_page},
+    policy::{Action, Principal, Resource, Scope},
+    route::notification::notify,
+};
+
+/// Anything shorter is not taken as a justification
+pub const MIN_JUSTIFICATION_LEN: usize = 20;
+
+/// What a doctor can read under [`Scope::Emergency`]
+pub const EMERGENCY_RESOURCES: [Resource; 2] =
+    [Resource::Allergy, Resource::MedicalCondition];
+
+#[derive(Deserialize)]
+pub struct EmergencyAccessPayload {
+    pub justification: String,
+}
+
+#[derive(Deserialize)]
+pub struct EmergencyReviewPayload {
+    pub note: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct EmergencyGrantFilter {
+    /// Only grants that were (or were not) reviewed yet
+    pub reviewed: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct EmergencyAccessGrant {
+    pub grant_id: Uuid,
+    pub doctor_id: Uuid,
+    pub user_id: Uuid,
+    pub justification: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub reviewed_by: Option<Uuid>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub review_note: Option<String>,
+}
+
+pub async fn open_emergency_access(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(user_id): Path<Uuid>,
+    Json(EmergencyAccessPayload { justification }): Json<
+        EmergencyAccessPayload,
+    >,
+) -> APIResult<(StatusCode, Json<Value>)> {
+    principal.require(Action::Create, Resource::EmergencyAccess, Scope::Any)?;
+    let doctor_id = principal.doctor_id.ok_or(AppError::NotLicensed)?;
+
+    let justification = justification.trim();
+    if justification.chars().count() < MIN_JUSTIFICATION_LEN {
+        return Err(AppError::JustificationRequired);
+    }
+
+    let expires_at = Utc::now() + EMERGENCY_ACCESS_TTL;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    let grant_id = query_scalar!(
+        "INSERT INTO emergency_access_grants (doctor_id, user_id, \
+         justification, expires_at) VALUES ($1, $2, $3, $4) RETURNING \
+         grant_id",
+        doctor_id,
+        user_id,
+        justification,
+        expires_at
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(db_e) if db_e.is_foreign_key_violation() => {
+            DatabaseError::ForeignKeyViolation.into()
+        }
+        e => {
+            error!(
+                "Error while opening emergency access to {} for {}: {:?}",
+                user_id, doctor_id, e
+            );
+            AppError::InternalError
+        }
+    })?;
+
+    audit::record_flagged(
+        &mut tx,
+        principal.user_id,
+        Some(user_id),
+        "emergency_access.open",
+        json!({
+            "grant_id": grant_id,
+            "doctor_id": doctor_id,
+            "justification": justification,
+            "expires_at": expires_at,
+        }),
+    )
+    .await?;
+    notify(
+        &mut tx,
+        user_id,
+        "emergency_access",
+        json!({
+            "grant_id": grant_id,
+            "doctor_id": doctor_id,
+            "justification": justification,
+            "expires_at": expires_at,
+            "resources": EMERGENCY_RESOURCES,
+        }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    warn!(
+        "{} opened emergency access to {} until {}",
+        principal.user_id, user_id, expires_at
+    );
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "grant_id": grant_id,
+            "expires_at": expires_at,
+            "resources": EMERGENCY_RESOURCES,
+        })),
+    ))
+}
+
+pub async fn get_emergency_access_grants(
+    State(state): State<AppState>,
+    principal: Principal,
+    Query(EmergencyGrantFilter { reviewed }): Query<EmergencyGrantFilter>,
+    page: Page,
+) -> APIResult<Paginated<EmergencyAccessGrant>> {
+    principal.require(Action::Read, Resource::EmergencyAccess, Scope::Any)?;
+
+    fetch_page!(
+        EmergencyAccessGrant,
+        &page,
+        &state.db_pool,
+        "SELECT * FROM emergency_access_grants
+         WHERE ($6::BOOLEAN IS NULL OR (reviewed_at IS NOT NULL) = $6)",
+        "created_at",
+        "grant_id",
+        reviewed
+    )
+    .map(|rows| page.paginate(rows, |grant| (grant.created_at, grant.grant_id)))
+    .map_err(|e| {
+        error!("Error while retrieving emergency access grants: {:?}", e);
+        AppError::InternalError
+    })
+}
+
+pub async fn review_emergency_access(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(grant_id): Path<Uuid>,
+    Json(EmergencyReviewPayload { note }): Json<EmergencyReviewPayload>,
+) -> APIResult<(StatusCode, Json<Value>)> {
+    principal.require(
+        Action::Approve,
+        Resource::EmergencyAccess,
+        Scope::Any,
+    )?;
+    let admin_id = principal.user_id;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    let patient_id = query!(
+        "UPDATE emergency_access_grants SET reviewed_by = $1, reviewed_at = \
+         NOW(), review_note = $2 WHERE grant_id = $3 AND reviewed_at IS NULL \
+         RETURNING user_id",
+        admin_id,
+        note,
+        grant_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!(
+            "Error while reviewing emergency access {}: {:?}",
+            grant_id, e
+        );
+        AppError::InternalError
+    })?
+    // unknown or already reviewed
+    .ok_or(DatabaseError::RowNotFound)?
+    .user_id;
+
+    audit::record(
+        &mut tx,
+        admin_id,
+        Some(patient_id),
+        "emergency_access.review",
+        json!({ "grant_id": grant_id, "note": note }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "message": "emergency access reviewed" })),
+    ))
+}
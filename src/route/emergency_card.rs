@@ -0,0 +1,280 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::{StatusCode, header},
+    response::{Html, IntoResponse},
+};
+use chrono::{DateTime, Utc};
+use jsonwebtoken::jwk::JwkSet;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sqlx::{query, query_as, query_scalar};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    AppState, audit,
+    emergency_card::{BloodType, CardAllergy, EmergencyCard, EmergencyContact},
+    error::{APIResult, AppError, DatabaseError},
+    policy::{Action, Principal, Resource, Scope},
+    schema::AllergySeverity,
+};
+
+#[derive(Deserialize)]
+pub struct EmergencyCardPayload {
+    pub blood_type: Option<BloodType>,
+    /// Conditions to show on the card, every one must be the caller's
+    #[serde(default)]
+    pub condition_ids: Vec<Uuid>,
+    pub emergency_contact: Option<EmergencyContact>,
+}
+
+#[derive(Serialize)]
+pub struct PublishedCard {
+    pub card_id: Uuid,
+    pub token: String,
+    /// Where the card renders without an account
+    pub url: String,
+    pub published_at: DateTime<Utc>,
+    pub card: EmergencyCard,
+}
+
+/// Signs a new card from the caller's records, replacing any card published
+/// before
+pub async fn publish_own_emergency_card(
+    State(state): State<AppState>,
+    principal: Principal,
+    Json(EmergencyCardPayload {
+        blood_type,
+        condition_ids,
+        emergency_contact,
+    }): Json<EmergencyCardPayload>,
+) -> APIResult<(StatusCode, Json<PublishedCard>)> {
+    principal.require(Action::Create, Resource::EmergencyCard, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    let allergies = query_as!(
+        CardAllergy,
+        r#"SELECT allergen, severity AS "severity: AllergySeverity"
+        FROM allergies
+        WHERE user_id = $1 AND severity IN ('SEVERE', 'ANAPHYLACTIC_SHOCK')
+        ORDER BY allergen"#,
+        user_id
+    )
+    .fetch_all(&state.db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while retrieving allergies for {}: {:?}", user_id, e);
+        AppError::InternalError
+    })?;
+
+    let conditions = query_scalar!(
+        "SELECT condition FROM medical_conditions
+         WHERE user_id = $1 AND condition_id = ANY($2)
+         ORDER BY condition",
+        user_id,
+        &condition_ids
+    )
+    .fetch_all(&state.db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while retrieving conditions for {}: {:?}", user_id, e);
+        AppError::InternalError
+    })?;
+
+    if conditions.len() != condition_ids.len() {
+        // unknown or somebody else's
+        return Err(DatabaseError::RowNotFound.into());
+    }
+
+    let details = query!(
+        "SELECT name, dob FROM user_details WHERE user_id = $1",
+        user_id
+    )
+    .fetch_optional(&state.db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while retrieving details for {}: {:?}", user_id, e);
+        AppError::InternalError
+    })?;
+
+    let card = EmergencyCard {
+        sub: user_id,
+        jti: Uuid::new_v4(),
+        iat: Utc::now().timestamp(),
+        name: details.as_ref().map(|details| details.name.clone()),
+        dob: details.map(|details| details.dob),
+        blood_type,
+        allergies,
+        conditions,
+        emergency_contact,
+    };
+    let token = state.card_issuer.sign(&card)?;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    let published_at = query_scalar!(
+        "INSERT INTO emergency_cards (user_id, card_id, token) VALUES ($1, \
+         $2, $3) ON CONFLICT (user_id) DO UPDATE SET card_id = $2, token = \
+         $3, published_at = NOW() RETURNING published_at",
+        user_id,
+        card.jti,
+        token
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!("Error while publishing card for {}: {:?}", user_id, e);
+        AppError::InternalError
+    })?;
+
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "emergency_card.publish",
+        json!({ "card_id": card.jti }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(PublishedCard {
+            card_id: card.jti,
+            url: state.card_issuer.url(&token),
+            token,
+            published_at,
+            card,
+        }),
+    ))
+}
+
+pub async fn get_own_emergency_card(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> APIResult<Json<PublishedCard>> {
+    principal.require(Action::Read, Resource::EmergencyCard, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    let published = query!(
+        "SELECT card_id, token, published_at FROM emergency_cards WHERE \
+         user_id = $1",
+        user_id
+    )
+    .fetch_optional(&state.db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while retrieving card for {}: {:?}", user_id, e);
+        AppError::InternalError
+    })?
+    .ok_or(DatabaseError::RowNotFound)?;
+
+    Ok(Json(PublishedCard {
+        card_id: published.card_id,
+        url: state.card_issuer.url(&published.token),
+        card: state.card_issuer.verify(&published.token)?,
+        token: published.token,
+        published_at: published.published_at,
+    }))
+}
+
+/// QR code of the card URL, to print or keep on a lock screen
+pub async fn get_own_emergency_card_qr(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> APIResult<impl IntoResponse> {
+    principal.require(Action::Read, Resource::EmergencyCard, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    let token = query_scalar!(
+        "SELECT token FROM emergency_cards WHERE user_id = $1",
+        user_id
+    )
+    .fetch_optional(&state.db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while retrieving card for {}: {:?}", user_id, e);
+        AppError::InternalError
+    })?
+    .ok_or(DatabaseError::RowNotFound)?;
+
+    Ok((
+        [(header::CONTENT_TYPE, "image/svg+xml")],
+        state.card_issuer.qr_svg(&token)?,
+    ))
+}
+
+pub async fn withdraw_own_emergency_card(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> APIResult<(StatusCode, Json<Value>)> {
+    principal.require(Action::Delete, Resource::EmergencyCard, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    let card_id = query_scalar!(
+        "DELETE FROM emergency_cards WHERE user_id = $1 RETURNING card_id",
+        user_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!("Error while withdrawing card for {}: {:?}", user_id, e);
+        AppError::InternalError
+    })?
+    .ok_or(DatabaseError::RowNotFound)?;
+
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "emergency_card.withdraw",
+        json!({ "card_id": card_id }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "message": "emergency card withdrawn" })),
+    ))
+}
+
+/// The card as a page, for whoever scanned the QR code
+pub async fn render_emergency_card(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> APIResult<Html<String>> {
+    let card = state.card_issuer.verify(&token)?;
+
+    let current = query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM emergency_cards WHERE user_id = $1 AND \
+         card_id = $2) AS \"current!\"",
+        card.sub,
+        card.jti
+    )
+    .fetch_one(&state.db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while looking up card {}: {:?}", card.jti, e);
+        AppError::InternalError
+    })?;
+
+    if !current {
+        // withdrawn or published again since
+        return Err(DatabaseError::RowNotFound.into());
+    }
+
+    info!("Emergency card {} of {} was viewed", card.jti, card.sub);
+
+    Ok(Html(card.render_html()))
+}
+
+/// Key to verify cards with offline
+pub async fn get_emergency_card_key(
+    State(state): State<AppState>,
+) -> Json<JwkSet> {
+    Json(state.card_issuer.jwks())
+}
@@ -0,0 +1,258 @@
+//! Moving a patient's record out of Medigram.
+//!
+//! The export is a FHIR R4 Bundle (see [`crate::fhir`]) of whatever the
+//! caller may read about the patient: a patient exporting themselves gets
+//! everything, a doctor what they could read through the other
+//! `/users/{user_id}/...` routes, a delegate only what was delegated. Every
+//! part read is logged in the patient's access log like any other read.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::header,
+    response::IntoResponse,
+};
+use chrono::Utc;
+use serde_json::Value;
+use sqlx::{Pool, Postgres, query, query_as};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    error::{APIResult, AppError, internal_error},
+    fhir,
+    policy::{Action, Principal, Resource, Scope},
+    route::{
+        access_log::authorize_read, allergy::fetch_allergies,
+        medical_condition::fetch_conditions,
+    },
+    schema::{
+        Consultation, Diagnosis, DiagnosisSeverity, Observation, Prescription,
+        UserDetail, UserMeasurement,
+    },
+};
+
+/// How the principal may read `resource` of `patient_id`, if at all, the
+/// read is logged when they may
+async fn readable(
+    principal: &Principal,
+    patient_id: Uuid,
+    resource: Resource,
+    db_pool: &Pool<Postgres>,
+) -> APIResult<Option<Scope>> {
+    let scope = principal
+        .patient_scope(patient_id, resource, db_pool)
+        .await?;
+
+    if principal.require(Action::Read, resource, scope).is_err() {
+        return Ok(None);
+    }
+    authorize_read(principal, resource, patient_id, scope, db_pool).await?;
+
+    Ok(Some(scope))
+}
+
+/// The doctor to limit diagnoses and prescriptions to, attending doctors only
+/// see those of their own consultations
+pub fn attending_doctor(principal: &Principal, scope: Scope) -> Option<Uuid> {
+    match scope {
+        Scope::Attending => principal.doctor_id,
+        _ => None,
+    }
+}
+
+async fn export_fhir(
+    principal: &Principal,
+    patient_id: Uuid,
+    db_pool: &Pool<Postgres>,
+) -> APIResult<Value> {
+    let mut resources = Vec::new();
+    let mut readable_any = false;
+
+    match readable(principal, patient_id, Resource::Profile, db_pool).await? {
+        Some(_) => {
+            readable_any = true;
+            let detail = query!(
+                "SELECT user_id, nik, name, dob, gender FROM user_details \
+                 WHERE user_id = $1",
+                patient_id
+            )
+            .fetch_optional(db_pool)
+            .await
+            .map_err(internal_error("details", patient_id))?
+            .map(|row| UserDetail {
+                user_id: row.user_id,
+                nik: row.nik,
+                name: row.name,
+                dob: row.dob,
+                gender: row.gender.chars().next().unwrap_or('U'),
+            });
+
+            resources.push(match detail {
+                Some(detail) => fhir::patient(&detail),
+                None => fhir::patient_reference_only(patient_id),
+            });
+        }
+        None => resources.push(fhir::patient_reference_only(patient_id)),
+    }
+
+    if readable(principal, patient_id, Resource::Allergy, db_pool)
+        .await?
+        .is_some()
+    {
+        readable_any = true;
+        let allergies = fetch_allergies(patient_id, db_pool).await?;
+
+        resources.extend(allergies.iter().map(fhir::allergy_intolerance));
+    }
+
+    if readable(principal, patient_id, Resource::MedicalCondition, db_pool)
+        .await?
+        .is_some()
+    {
+        readable_any = true;
+        let conditions = fetch_conditions(patient_id, db_pool).await?;
+
+        resources.extend(conditions.iter().map(fhir::problem));
+    }
+
+    if readable(principal, patient_id, Resource::Measurement, db_pool)
+        .await?
+        .is_some()
+    {
+        readable_any = true;
+        let measurements = query_as!(
+            UserMeasurement,
+            "SELECT * FROM user_measurements WHERE user_id = $1 ORDER BY \
+             measured_at",
+            patient_id
+        )
+        .fetch_all(db_pool)
+        .await
+        .map_err(internal_error("measurements", patient_id))?;
+
+        resources.extend(measurements.iter().flat_map(fhir::observations));
+
+        let observations = query_as!(
+            Observation,
+            "SELECT o.observation_id, o.user_id, o.code, t.name, t.unit,
+                t.category, o.value, o.observed_at, o.recorded_by,
+                o.consultation_id
+             FROM observations AS o
+             JOIN observation_types AS t ON t.code = o.code
+             WHERE o.user_id = $1 ORDER BY o.observed_at, o.observation_id",
+            patient_id
+        )
+        .fetch_all(db_pool)
+        .await
+        .map_err(internal_error("observations", patient_id))?;
+
+        resources.extend(observations.iter().map(fhir::observation));
+    }
+
+    // diagnoses and prescriptions refer to their consultation either way
+    let consultations = query_as!(
+        Consultation,
+        "SELECT * FROM consultations WHERE user_id = $1 ORDER BY created_at",
+        patient_id
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(internal_error("consultations", patient_id))?;
+    let consultation_of = |consultation_id: Uuid| {
+        consultations.iter().find(|consultation| {
+            consultation.consultation_id == consultation_id
+        })
+    };
+
+    if readable(principal, patient_id, Resource::Consultation, db_pool)
+        .await?
+        .is_some()
+    {
+        readable_any = true;
+        resources.extend(consultations.iter().map(fhir::encounter));
+    }
+
+    if let Some(scope) =
+        readable(principal, patient_id, Resource::Diagnosis, db_pool).await?
+    {
+        readable_any = true;
+        let diagnoses = query_as!(
+            Diagnosis,
+            "SELECT d.diagnosis_id, d.consultation_id, d.diagnosis,
+                d.severity AS \"severity: DiagnosisSeverity\", d.icd10_code
+             FROM diagnoses AS d
+             JOIN consultations AS c ON c.consultation_id = d.consultation_id
+             WHERE c.user_id = $1 AND ($2::UUID IS NULL OR c.doctor_id = $2)
+             ORDER BY c.created_at",
+            patient_id,
+            attending_doctor(principal, scope)
+        )
+        .fetch_all(db_pool)
+        .await
+        .map_err(internal_error("diagnoses", patient_id))?;
+
+        resources.extend(diagnoses.iter().filter_map(|diagnosis| {
+            consultation_of(diagnosis.consultation_id)
+                .map(|consultation| fhir::diagnosis(diagnosis, consultation))
+        }));
+    }
+
+    if let Some(scope) =
+        readable(principal, patient_id, Resource::Prescription, db_pool).await?
+    {
+        readable_any = true;
+        let prescriptions = query_as!(
+            Prescription,
+            "SELECT p.* FROM prescriptions AS p
+             JOIN consultations AS c ON c.consultation_id = p.consultation_id
+             WHERE c.user_id = $1 AND ($2::UUID IS NULL OR c.doctor_id = $2)
+             ORDER BY c.created_at, p.drug_name",
+            patient_id,
+            attending_doctor(principal, scope)
+        )
+        .fetch_all(db_pool)
+        .await
+        .map_err(internal_error("prescriptions", patient_id))?;
+
+        resources.extend(prescriptions.iter().filter_map(|prescription| {
+            consultation_of(prescription.consultation_id).map(|consultation| {
+                fhir::medication_request(prescription, consultation)
+            })
+        }));
+    }
+
+    if !readable_any {
+        // tells apart a missing second factor from no grant at all
+        principal.require(
+            Action::Read,
+            Resource::Profile,
+            principal
+                .patient_scope(patient_id, Resource::Profile, db_pool)
+                .await?,
+        )?;
+        return Err(AppError::PermissionDenied);
+    }
+
+    Ok(fhir::bundle(resources, Utc::now()))
+}
+
+pub async fn get_own_fhir_export(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> APIResult<impl IntoResponse> {
+    let bundle =
+        export_fhir(&principal, principal.user_id, &state.db_pool).await?;
+
+    Ok(([(header::CONTENT_TYPE, fhir::FHIR_JSON)], Json(bundle)))
+}
+
+pub async fn get_user_fhir_export(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(user_id): Path<Uuid>,
+) -> APIResult<impl IntoResponse> {
+    let bundle = export_fhir(&principal, user_id, &state.db_pool).await?;
+
+    Ok(([(header::CONTENT_TYPE, fhir::FHIR_JSON)], Json(bundle)))
+}
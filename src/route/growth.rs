@@ -0,0 +1,177 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use sqlx::{Pool, Postgres, query, query_as};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    error::{APIResult, AppError, DatabaseError},
+    growth::{MAX_AGE_IN_MONTHS, age_in_months, lms_at, percentile},
+    policy::{Action, Principal, Resource, Scope},
+    route::access_log::authorize_read,
+    schema::{GrowthIndicator, GrowthStandard, UserMeasurement},
+};
+
+/// How a measurement compares to children of the same age and sex
+#[derive(Debug, Serialize)]
+pub struct GrowthScore {
+    pub value: f64,
+    pub z_score: f64,
+    pub percentile: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GrowthPoint {
+    pub measurement_id: Uuid,
+    pub measured_at: DateTime<Utc>,
+    pub age_in_months: f64,
+    /// `None` where WHO has no curve for the age
+    pub height_for_age: Option<GrowthScore>,
+    pub weight_for_age: Option<GrowthScore>,
+    pub bmi_for_age: Option<GrowthScore>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Growth {
+    pub dob: NaiveDate,
+    pub gender: char,
+    /// Measurements taken before 19 years of age, oldest first
+    pub points: Vec<GrowthPoint>,
+}
+
+async fn fetch_growth(
+    user_id: Uuid,
+    db_pool: &Pool<Postgres>,
+) -> APIResult<Json<Growth>> {
+    let detail = query!(
+        "SELECT dob, gender FROM user_details WHERE user_id = $1",
+        user_id
+    )
+    .fetch_optional(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while fetching details of {}: {:?}", user_id, e);
+        AppError::InternalError
+    })?
+    .ok_or(DatabaseError::RowNotFound)?;
+
+    let gender = detail.gender.chars().next().unwrap_or('U');
+    if !matches!(gender, 'M' | 'F') {
+        return Err(AppError::NoGrowthReference);
+    }
+
+    let standards = query_as!(
+        GrowthStandard,
+        r#"SELECT indicator AS "indicator: GrowthIndicator", sex,
+            age_in_months, l, m, s
+        FROM growth_standards WHERE sex = $1"#,
+        detail.gender
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while fetching growth standards: {:?}", e);
+        AppError::InternalError
+    })?;
+
+    let measurements = query_as!(
+        UserMeasurement,
+        "SELECT * FROM user_measurements WHERE user_id = $1 ORDER BY \
+         measured_at",
+        user_id
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while fetching measurements of {}: {:?}", user_id, e);
+        AppError::InternalError
+    })?;
+
+    let score = |indicator, age_in_months, value: f64| {
+        let z_score = lms_at(&standards, indicator, age_in_months)?
+            .restricted_z_score(indicator, value);
+        Some(GrowthScore {
+            value,
+            z_score: (z_score * 100.0).round() / 100.0,
+            percentile: (percentile(z_score) * 10.0).round() / 10.0,
+        })
+    };
+
+    let points = measurements
+        .into_iter()
+        .filter_map(|measurement| {
+            let age_in_months =
+                age_in_months(detail.dob, measurement.measured_at.date_naive());
+            if !(0.0..=MAX_AGE_IN_MONTHS).contains(&age_in_months) {
+                return None;
+            }
+
+            let height = f64::from(measurement.height_in_cm);
+            let weight = f64::from(measurement.weight_in_kg);
+            let bmi = weight / (height / 100.0).powi(2);
+
+            Some(GrowthPoint {
+                measurement_id: measurement.measurement_id,
+                measured_at: measurement.measured_at,
+                age_in_months: (age_in_months * 100.0).round() / 100.0,
+                height_for_age: score(
+                    GrowthIndicator::HeightForAge,
+                    age_in_months,
+                    height,
+                ),
+                weight_for_age: score(
+                    GrowthIndicator::WeightForAge,
+                    age_in_months,
+                    weight,
+                ),
+                bmi_for_age: score(
+                    GrowthIndicator::BmiForAge,
+                    age_in_months,
+                    bmi,
+                ),
+            })
+        })
+        .collect();
+
+    Ok(Json(Growth {
+        dob: detail.dob,
+        gender,
+        points,
+    }))
+}
+
+pub async fn get_user_growth(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(user_id): Path<Uuid>,
+) -> APIResult<Json<Growth>> {
+    let scope = principal
+        .patient_scope(user_id, Resource::Measurement, &state.db_pool)
+        .await?;
+    // age and sex come from the profile
+    principal.require(Action::Read, Resource::Profile, scope)?;
+    authorize_read(
+        &principal,
+        Resource::Measurement,
+        user_id,
+        scope,
+        &state.db_pool,
+    )
+    .await?;
+
+    fetch_growth(user_id, &state.db_pool).await
+}
+
+pub async fn get_own_growth(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> APIResult<Json<Growth>> {
+    principal.require(Action::Read, Resource::Measurement, Scope::Own)?;
+
+    fetch_growth(principal.user_id, &state.db_pool).await
+}
@@ -0,0 +1,93 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use serde::Deserialize;
+use sqlx::{Pool, Postgres, query_as, query_scalar};
+use tracing::{error, warn};
+
+use crate::{
+    AppState,
+    error::{APIResult, AppError},
+    policy::Principal,
+    schema::Icd10Code,
+};
+
+/// Most codes suggested at once
+pub const ICD10_SUGGESTIONS: i64 = 20;
+
+#[derive(Deserialize)]
+pub struct Icd10Search {
+    /// Start of a code, e.g. `J0`, or words of its title
+    pub q: String,
+}
+
+/// Codes to pick from while typing a diagnosis, those starting with `q`
+/// first, then those with `q` in their title
+pub async fn search_icd10_codes(
+    State(state): State<AppState>,
+    _principal: Principal,
+    Query(Icd10Search { q }): Query<Icd10Search>,
+) -> APIResult<Json<Vec<Icd10Code>>> {
+    // what is typed is matched literally
+    let q = q.replace(['%', '_', '\\'], "");
+    let q = q.trim();
+    if q.is_empty() {
+        return Ok(Json(Vec::new()));
+    }
+
+    query_as!(
+        Icd10Code,
+        r#"SELECT code, title FROM icd10_codes
+        WHERE code LIKE upper($1) || '%'
+            OR title ILIKE '%' || $1 || '%'
+        ORDER BY code LIKE upper($1) || '%' DESC, code
+        LIMIT $2"#,
+        q,
+        ICD10_SUGGESTIONS
+    )
+    .fetch_all(&state.db_pool)
+    .await
+    .map(Json)
+    .map_err(|e| {
+        error!("Error while searching ICD-10 codes for {:?}: {:?}", q, e);
+        AppError::InternalError
+    })
+}
+
+/// Codes are written upper case, with a dot after the category
+pub fn normalize_icd10_code(code: Option<String>) -> Option<String> {
+    code.map(|code| code.trim().to_uppercase())
+        .filter(|code| !code.is_empty())
+}
+
+/// Rejects `codes` unless every one of them is known
+pub async fn check_icd10_codes(
+    codes: &[String],
+    db_pool: &Pool<Postgres>,
+) -> APIResult<()> {
+    if codes.is_empty() {
+        return Ok(());
+    }
+
+    let unknown = query_scalar!(
+        r#"SELECT code AS "code!" FROM UNNEST($1::TEXT[]) AS code
+        WHERE code NOT IN (SELECT code FROM icd10_codes)
+        LIMIT 1"#,
+        codes
+    )
+    .fetch_optional(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error occured while looking up ICD-10 codes: {:?}", e);
+        AppError::InternalError
+    })?;
+
+    match unknown {
+        Some(code) => {
+            warn!("Unknown ICD-10 code {}", code);
+            Err(AppError::UnknownIcd10Code)
+        }
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,455 @@
+//! Bringing in records from other clinics.
+//!
+//! `POST /me/import/fhir` takes a FHIR R4 Bundle and creates whatever
+//! [`crate::fhir::import`] maps it onto, leaving out what the patient has
+//! recorded already. With `?dry_run=true` nothing is written and the report
+//! tells what would be created, skipped or can't be mapped.
+//!
+//! Every import is kept in `fhir_imports`, and `imported_records` tells which
+//! resource of which import a row was created from.
+
+use std::collections::HashSet;
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::StatusCode,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sqlx::{PgConnection, query, query_scalar};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    AppState, audit,
+    error::{APIResult, AppError},
+    fhir::import::{Outcome, Record, bundle_source, map_bundle},
+    pagination::{Page, Paginated, fetch_page},
+    policy::{Action, Principal, Resource, Scope},
+    schema::{AllergyCategory, AllergyReaction, AllergySeverity},
+};
+
+#[derive(Deserialize)]
+pub struct ImportParams {
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Serialize)]
+pub struct ReportItem {
+    pub resource_type: String,
+    pub source_ids: Vec<String>,
+    /// What the resource is stored as
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<&'static str>,
+    /// The row created, absent on a dry run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub row_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ImportReport {
+    /// Absent on a dry run
+    pub import_id: Option<Uuid>,
+    pub dry_run: bool,
+    pub created: Vec<ReportItem>,
+    pub skipped: Vec<ReportItem>,
+    pub unmappable: Vec<ReportItem>,
+}
+
+#[derive(Serialize)]
+pub struct FhirImport {
+    pub import_id: Uuid,
+    /// The guardian when imported for a dependent
+    pub imported_by: Uuid,
+    pub source: Option<String>,
+    pub imported_at: DateTime<Utc>,
+    /// `resource_type`, `source_id`, `target` and `row_id` of every row
+    pub records: Value,
+}
+
+#[derive(Serialize)]
+pub struct ExternalEncounter {
+    pub encounter_id: Uuid,
+    pub import_id: Uuid,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub facility: Option<String>,
+    pub practitioner: Option<String>,
+    pub reason: Option<String>,
+}
+
+/// What the patient has recorded already, imports included
+struct Existing {
+    allergens: HashSet<String>,
+    conditions: HashSet<String>,
+    measured_at: HashSet<DateTime<Utc>>,
+    encounters_started_at: HashSet<DateTime<Utc>>,
+}
+
+impl Existing {
+    async fn load(conn: &mut PgConnection, user_id: Uuid) -> APIResult<Self> {
+        let record = query!(
+            r#"SELECT
+                ARRAY(SELECT LOWER(allergen) FROM allergies
+                    WHERE user_id = $1) AS "allergens!",
+                ARRAY(SELECT LOWER(condition) FROM medical_conditions
+                    WHERE user_id = $1) AS "conditions!",
+                ARRAY(SELECT measured_at FROM user_measurements
+                    WHERE user_id = $1) AS "measured_at!",
+                ARRAY(SELECT started_at FROM external_encounters
+                    WHERE user_id = $1) AS "encounters_started_at!""#,
+            user_id
+        )
+        .fetch_one(conn)
+        .await
+        .map_err(|e| {
+            error!("Error while loading records of {}: {:?}", user_id, e);
+            AppError::InternalError
+        })?;
+
+        Ok(Existing {
+            allergens: record.allergens.into_iter().collect(),
+            conditions: record.conditions.into_iter().collect(),
+            measured_at: record.measured_at.into_iter().collect(),
+            encounters_started_at: record
+                .encounters_started_at
+                .into_iter()
+                .collect(),
+        })
+    }
+
+    /// Remembers `record`, `false` if it was known already
+    fn insert(&mut self, record: &Record) -> bool {
+        match record {
+            Record::Allergy { allergen, .. } => {
+                self.allergens.insert(allergen.to_lowercase())
+            }
+            Record::Condition { condition, .. } => {
+                self.conditions.insert(condition.to_lowercase())
+            }
+            Record::Measurement { measured_at, .. } => {
+                self.measured_at.insert(*measured_at)
+            }
+            Record::Encounter { started_at, .. } => {
+                self.encounters_started_at.insert(*started_at)
+            }
+        }
+    }
+}
+
+async fn create(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    import_id: Uuid,
+    record: &Record,
+) -> Result<Uuid, sqlx::Error> {
+    match record {
+        Record::Allergy {
+            allergen,
+            severity,
+            category,
+            reactions,
+            onset,
+        } => {
+            query_scalar!(
+                "INSERT INTO allergies (user_id, allergen, severity, \
+                 category, reactions, onset) VALUES ($1, $2, $3, $4, $5, $6) \
+                 RETURNING allergy_id",
+                user_id,
+                allergen,
+                *severity as AllergySeverity,
+                *category as Option<AllergyCategory>,
+                reactions as &[AllergyReaction],
+                *onset
+            )
+            .fetch_one(conn)
+            .await
+        }
+        Record::Condition { condition, onset } => {
+            query_scalar!(
+                r#"WITH condition AS (
+                    INSERT INTO medical_conditions (user_id, condition, onset)
+                    VALUES ($1, $2, $3) RETURNING condition_id, status
+                )
+                INSERT INTO condition_status_history
+                    (condition_id, to_status, changed_by)
+                SELECT condition_id, status, imported_by
+                FROM condition, fhir_imports WHERE import_id = $4
+                RETURNING condition_id"#,
+                user_id,
+                condition,
+                *onset,
+                import_id
+            )
+            .fetch_one(conn)
+            .await
+        }
+        Record::Measurement {
+            height_in_cm,
+            weight_in_kg,
+            measured_at,
+        } => {
+            query_scalar!(
+                "INSERT INTO user_measurements (user_id, height_in_cm, \
+                 weight_in_kg, measured_at) VALUES ($1, $2, $3, $4) \
+                 RETURNING measurement_id",
+                user_id,
+                height_in_cm,
+                weight_in_kg,
+                measured_at
+            )
+            .fetch_one(conn)
+            .await
+        }
+        Record::Encounter {
+            started_at,
+            ended_at,
+            facility,
+            practitioner,
+            reason,
+        } => {
+            query_scalar!(
+                "INSERT INTO external_encounters (user_id, import_id, \
+                 started_at, ended_at, facility, practitioner, reason) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING encounter_id",
+                user_id,
+                import_id,
+                started_at,
+                *ended_at,
+                facility.as_deref(),
+                practitioner.as_deref(),
+                reason.as_deref()
+            )
+            .fetch_one(conn)
+            .await
+        }
+    }
+}
+
+pub async fn import_own_fhir(
+    State(state): State<AppState>,
+    principal: Principal,
+    Query(ImportParams { dry_run }): Query<ImportParams>,
+    Json(bundle): Json<Value>,
+) -> APIResult<(StatusCode, Json<ImportReport>)> {
+    for resource in [
+        Resource::Allergy,
+        Resource::MedicalCondition,
+        Resource::Measurement,
+        Resource::ExternalEncounter,
+    ] {
+        principal.require(Action::Create, resource, Scope::Own)?;
+    }
+    let user_id = principal.user_id;
+
+    let entries = map_bundle(&bundle).ok_or(AppError::InvalidFhirBundle)?;
+    let source = bundle_source(&bundle);
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+    let mut existing = Existing::load(&mut tx, user_id).await?;
+
+    let import_id = if dry_run {
+        None
+    } else {
+        let import_id = query_scalar!(
+            "INSERT INTO fhir_imports (user_id, imported_by, source) VALUES \
+             ($1, $2, $3) RETURNING import_id",
+            user_id,
+            principal.actor_id(),
+            source
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| {
+            error!("Error while starting import for {}: {:?}", user_id, e);
+            AppError::InternalError
+        })?;
+        Some(import_id)
+    };
+
+    let mut report = ImportReport {
+        import_id,
+        dry_run,
+        created: Vec::new(),
+        skipped: Vec::new(),
+        unmappable: Vec::new(),
+    };
+
+    for entry in entries {
+        let mut item = ReportItem {
+            resource_type: entry.resource_type,
+            source_ids: entry.source_ids,
+            target: None,
+            row_id: None,
+            reason: None,
+        };
+
+        let record = match entry.outcome {
+            Outcome::Mapped(record) => record,
+            Outcome::Skipped(reason) => {
+                item.reason = Some(reason);
+                report.skipped.push(item);
+                continue;
+            }
+            Outcome::Unmappable(reason) => {
+                item.reason = Some(reason);
+                report.unmappable.push(item);
+                continue;
+            }
+        };
+        item.target = Some(record.target());
+
+        if !existing.insert(&record) {
+            item.reason = Some("already recorded".to_string());
+            report.skipped.push(item);
+            continue;
+        }
+
+        if let Some(import_id) = import_id {
+            let row_id = create(&mut tx, user_id, import_id, &record)
+                .await
+                .map_err(|e| {
+                    error!(
+                        "Error while importing {} for {}: {:?}",
+                        record.target(),
+                        user_id,
+                        e
+                    );
+                    AppError::InternalError
+                })?;
+
+            for source_id in &item.source_ids {
+                query!(
+                    "INSERT INTO imported_records (import_id, source_id, \
+                     resource_type, target, row_id) VALUES ($1, $2, $3, $4, \
+                     $5) ON CONFLICT (import_id, source_id) DO NOTHING",
+                    import_id,
+                    source_id,
+                    item.resource_type,
+                    record.target(),
+                    row_id
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!(
+                        "Error while recording provenance of {}: {:?}",
+                        row_id, e
+                    );
+                    AppError::InternalError
+                })?;
+            }
+            item.row_id = Some(row_id);
+        }
+
+        report.created.push(item);
+    }
+
+    let Some(import_id) = import_id else {
+        // a dry run leaves nothing behind
+        return Ok((StatusCode::OK, Json(report)));
+    };
+
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "fhir.import",
+        json!({
+            "import_id": import_id,
+            "source": source,
+            "created": report.created.len(),
+            "skipped": report.skipped.len(),
+            "unmappable": report.unmappable.len(),
+        }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    info!(
+        "Imported {} records for {} from {:?}",
+        report.created.len(),
+        user_id,
+        source
+    );
+
+    Ok((StatusCode::CREATED, Json(report)))
+}
+
+pub async fn get_own_imports(
+    State(state): State<AppState>,
+    principal: Principal,
+    page: Page,
+) -> APIResult<Paginated<FhirImport>> {
+    principal.require(Action::Read, Resource::Profile, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    fetch_page!(
+        FhirImport,
+        &page,
+        &state.db_pool,
+        r#"SELECT i.import_id, i.imported_by, i.source, i.imported_at,
+            COALESCE(
+                (SELECT json_agg(json_build_object(
+                    'resource_type', r.resource_type,
+                    'source_id', r.source_id,
+                    'target', r.target,
+                    'row_id', r.row_id
+                ) ORDER BY r.source_id)
+                FROM imported_records AS r
+                WHERE r.import_id = i.import_id),
+                '[]'
+            ) AS "records!"
+        FROM fhir_imports AS i
+        WHERE i.user_id = $6"#,
+        "i.imported_at",
+        "i.import_id",
+        user_id
+    )
+    .map(|rows| {
+        page.paginate(rows, |import| (import.imported_at, import.import_id))
+    })
+    .map_err(|e| {
+        error!("Error while retrieving imports of {}: {:?}", user_id, e);
+        AppError::InternalError
+    })
+}
+
+pub async fn get_own_external_encounters(
+    State(state): State<AppState>,
+    principal: Principal,
+    page: Page,
+) -> APIResult<Paginated<ExternalEncounter>> {
+    principal.require(Action::Read, Resource::ExternalEncounter, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    fetch_page!(
+        ExternalEncounter,
+        &page,
+        &state.db_pool,
+        "SELECT encounter_id, import_id, started_at, ended_at, facility,
+            practitioner, reason
+         FROM external_encounters
+         WHERE user_id = $6",
+        "started_at",
+        "encounter_id",
+        user_id
+    )
+    .map(|rows| {
+        page.paginate(rows, |encounter| {
+            (encounter.started_at, encounter.encounter_id)
+        })
+    })
+    .map_err(|e| {
+        error!(
+            "Error while retrieving external encounters of {}: {:?}",
+            user_id, e
+        );
+        AppError::InternalError
+    })
+}
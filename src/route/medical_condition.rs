@@ -3,85 +3,226 @@ use axum::{
     extract::{Path, State},
     http::StatusCode,
 };
+use chrono::NaiveDate;
 use serde::Deserialize;
 use serde_json::{Value, json};
-use sqlx::{query, query_as};
+use sqlx::{PgConnection, Pool, Postgres, query, query_as, query_scalar};
 use tracing::error;
 use uuid::Uuid;
 
 use crate::{
-    AppState,
-    auth::{AuthUser, LicensedUser},
+    AppState, audit,
     error::{APIResult, AppError, DatabaseError},
-    schema::MedicalCondition,
+    policy::{Action, Principal, Resource, Scope},
+    route::{
+        access_log::authorize_read,
+        icd10::{check_icd10_codes, normalize_icd10_code},
+    },
+    schema::{
+        ConditionSource, ConditionStatus, ConditionTransition, MedicalCondition,
+    },
 };
 
-pub async fn get_user_conditions(
-    State(state): State<AppState>,
-    auth: AuthUser,
-    doctor: Option<LicensedUser>,
-    Path(user_id): Path<Uuid>,
-) -> APIResult<Json<Vec<MedicalCondition>>> {
-    if user_id != auth.user_id && doctor.is_none() {
-        return Err(AppError::NotTheSameUser);
-    }
-
+/// Conditions of `user_id`, by name
+pub async fn fetch_conditions(
+    user_id: Uuid,
+    db_pool: &Pool<Postgres>,
+) -> APIResult<Vec<MedicalCondition>> {
     query_as!(
         MedicalCondition,
-        "SELECT * FROM medical_conditions WHERE user_id = $1",
+        r#"SELECT condition_id, user_id, condition, onset,
+            status AS "status: ConditionStatus", icd10_code,
+            source AS "source: ConditionSource", consultation_id,
+            confirmed_by, confirmed_at, created_at
+        FROM medical_conditions WHERE user_id = $1
+        ORDER BY condition, created_at"#,
         user_id
     )
-    .fetch_all(&state.db_pool)
+    .fetch_all(db_pool)
     .await
-    .map(Json)
-    .map_err(|e| match e {
-        sqlx::Error::RowNotFound => DatabaseError::RowNotFound.into(),
-        e => {
-            error!("Error while fetching user for {}: {:?}", user_id, e);
-            AppError::InternalError
-        }
+    .map_err(|e| {
+        error!("Error while fetching conditions of {}: {:?}", user_id, e);
+        AppError::InternalError
     })
 }
 
+/// Adds a status `condition_id` went through to its history, `from` is
+/// `None` for the status it was recorded with
+pub async fn record_transition(
+    conn: &mut PgConnection,
+    condition_id: Uuid,
+    from: Option<ConditionStatus>,
+    to: ConditionStatus,
+    changed_by: Uuid,
+) -> APIResult<()> {
+    query!(
+        "INSERT INTO condition_status_history (condition_id, from_status, \
+         to_status, changed_by) VALUES ($1, $2, $3, $4)",
+        condition_id,
+        from as Option<ConditionStatus>,
+        to as ConditionStatus,
+        changed_by
+    )
+    .execute(conn)
+    .await
+    .map(|_| ())
+    .map_err(|e| {
+        error!(
+            "Error while recording status of condition {}: {:?}",
+            condition_id, e
+        );
+        AppError::InternalError
+    })
+}
+
+async fn fetch_history(
+    user_id: Uuid,
+    condition_id: Uuid,
+    db_pool: &Pool<Postgres>,
+) -> APIResult<Vec<ConditionTransition>> {
+    let history = query_as!(
+        ConditionTransition,
+        r#"SELECT h.transition_id, h.condition_id,
+            h.from_status AS "from_status: ConditionStatus",
+            h.to_status AS "to_status: ConditionStatus",
+            h.changed_by, h.changed_at
+        FROM condition_status_history AS h
+        JOIN medical_conditions AS m ON m.condition_id = h.condition_id
+        WHERE h.condition_id = $1 AND m.user_id = $2
+        ORDER BY h.changed_at, h.transition_id"#,
+        condition_id,
+        user_id
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        error!(
+            "Error while fetching history of condition {}: {:?}",
+            condition_id, e
+        );
+        AppError::InternalError
+    })?;
+
+    // every condition has at least the status it was recorded with
+    if history.is_empty() {
+        return Err(DatabaseError::RowNotFound.into());
+    }
+
+    Ok(history)
+}
+
+pub async fn get_user_conditions(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(user_id): Path<Uuid>,
+) -> APIResult<Json<Vec<MedicalCondition>>> {
+    authorize_read(
+        &principal,
+        Resource::MedicalCondition,
+        user_id,
+        principal
+            .patient_scope(user_id, Resource::MedicalCondition, &state.db_pool)
+            .await?,
+        &state.db_pool,
+    )
+    .await?;
+
+    fetch_conditions(user_id, &state.db_pool).await.map(Json)
+}
+
 pub async fn get_own_conditions(
     State(state): State<AppState>,
-    AuthUser { user_id, .. }: AuthUser,
+    principal: Principal,
 ) -> APIResult<Json<Vec<MedicalCondition>>> {
-    query_as!(
-        MedicalCondition,
-        "SELECT * FROM medical_conditions WHERE user_id = $1",
-        user_id
+    principal.require(Action::Read, Resource::MedicalCondition, Scope::Own)?;
+
+    fetch_conditions(principal.user_id, &state.db_pool)
+        .await
+        .map(Json)
+}
+
+pub async fn get_user_condition_history(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path((user_id, condition_id)): Path<(Uuid, Uuid)>,
+) -> APIResult<Json<Vec<ConditionTransition>>> {
+    authorize_read(
+        &principal,
+        Resource::MedicalCondition,
+        user_id,
+        principal
+            .patient_scope(user_id, Resource::MedicalCondition, &state.db_pool)
+            .await?,
+        &state.db_pool,
     )
-    .fetch_all(&state.db_pool)
-    .await
-    .map(Json)
-    .map_err(|e| match e {
-        sqlx::Error::RowNotFound => DatabaseError::RowNotFound.into(),
-        e => {
-            error!("Error while fetching user for {}: {:?}", user_id, e);
-            AppError::InternalError
-        }
-    })
+    .await?;
+
+    fetch_history(user_id, condition_id, &state.db_pool)
+        .await
+        .map(Json)
+}
+
+pub async fn get_own_condition_history(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(condition_id): Path<Uuid>,
+) -> APIResult<Json<Vec<ConditionTransition>>> {
+    principal.require(Action::Read, Resource::MedicalCondition, Scope::Own)?;
+
+    fetch_history(principal.user_id, condition_id, &state.db_pool)
+        .await
+        .map(Json)
 }
 
 #[derive(Deserialize)]
 pub struct MedicalConditionPayload {
     pub condition: String,
+    #[serde(default)]
+    pub onset: Option<NaiveDate>,
+    #[serde(default = "active")]
+    pub status: ConditionStatus,
+    /// One of `GET /icd10`, e.g. `J45`
+    #[serde(default)]
+    pub icd10_code: Option<String>,
+}
+
+fn active() -> ConditionStatus {
+    ConditionStatus::Active
 }
 
 pub async fn post_own_conditions(
     State(state): State<AppState>,
-    AuthUser { user_id, .. }: AuthUser,
-    Json(MedicalConditionPayload { condition }): Json<MedicalConditionPayload>,
+    principal: Principal,
+    Json(MedicalConditionPayload {
+        condition,
+        onset,
+        status,
+        icd10_code,
+    }): Json<MedicalConditionPayload>,
 ) -> APIResult<(StatusCode, Json<Value>)> {
-    let _ = query!(
-        "INSERT INTO medical_conditions (user_id, condition) VALUES ($1, $2)",
+    principal.require(
+        Action::Create,
+        Resource::MedicalCondition,
+        Scope::Own,
+    )?;
+    let user_id = principal.user_id;
+
+    let icd10_code = normalize_icd10_code(icd10_code);
+    check_icd10_codes(icd10_code.as_slice(), &state.db_pool).await?;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    let condition_id = query_scalar!(
+        "INSERT INTO medical_conditions (user_id, condition, onset, status, \
+         icd10_code) VALUES ($1, $2, $3, $4, $5) RETURNING condition_id",
         user_id,
-        condition
+        condition,
+        onset,
+        status as ConditionStatus,
+        icd10_code
     )
-    .execute(&state.db_pool)
+    .fetch_one(&mut *tx)
     .await
-    .map(Json)
     .map_err(|e| {
         error!(
             "Error while adding medical condition for {}: {:?}",
@@ -90,24 +231,243 @@ pub async fn post_own_conditions(
         AppError::InternalError
     })?;
 
+    record_transition(
+        &mut tx,
+        condition_id,
+        None,
+        status,
+        principal.actor_id(),
+    )
+    .await?;
+
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "medical_condition.create",
+        json!({
+            "condition_id": condition_id,
+            "condition": condition,
+            "onset": onset,
+            "status": status,
+            "icd10_code": icd10_code,
+        }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
     Ok((
         StatusCode::CREATED,
         Json(json!({ "message": "medical condition added" })),
     ))
 }
 
+#[derive(Deserialize)]
+pub struct ConditionStatusPayload {
+    pub status: ConditionStatus,
+}
+
+pub async fn put_own_condition_status(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(condition_id): Path<Uuid>,
+    Json(ConditionStatusPayload { status }): Json<ConditionStatusPayload>,
+) -> APIResult<Json<Value>> {
+    principal.require(
+        Action::Update,
+        Resource::MedicalCondition,
+        Scope::Own,
+    )?;
+    let user_id = principal.user_id;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    let current = query_scalar!(
+        r#"SELECT status AS "status: ConditionStatus" FROM medical_conditions
+        WHERE condition_id = $1 AND user_id = $2 FOR UPDATE"#,
+        condition_id,
+        user_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!(
+            "Error while fetching medical condition {} for {}: {:?}",
+            condition_id, user_id, e
+        );
+        AppError::InternalError
+    })?
+    .ok_or(DatabaseError::RowNotFound)?;
+
+    if current == status {
+        return Err(AppError::ConditionStatusUnchanged);
+    }
+
+    query!(
+        "UPDATE medical_conditions SET status = $1 WHERE condition_id = $2",
+        status as ConditionStatus,
+        condition_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!(
+            "Error while updating status of condition {}: {:?}",
+            condition_id, e
+        );
+        AppError::InternalError
+    })?;
+
+    record_transition(
+        &mut tx,
+        condition_id,
+        Some(current),
+        status,
+        principal.actor_id(),
+    )
+    .await?;
+
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "medical_condition.status",
+        json!({
+            "condition_id": condition_id,
+            "from": current,
+            "to": status,
+        }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    Ok(Json(
+        json!({ "message": "medical condition status updated" }),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmConditionPayload {
+    /// Codes the condition while confirming it, one of `GET /icd10`
+    #[serde(default)]
+    pub icd10_code: Option<String>,
+}
+
+/// A doctor attending the patient vouches for a condition they reported
+/// themselves
+pub async fn confirm_user_condition(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path((user_id, condition_id)): Path<(Uuid, Uuid)>,
+    Json(ConfirmConditionPayload { icd10_code }): Json<ConfirmConditionPayload>,
+) -> APIResult<Json<Value>> {
+    let scope = principal
+        .patient_scope(user_id, Resource::MedicalCondition, &state.db_pool)
+        .await?;
+    principal.require(Action::Update, Resource::MedicalCondition, scope)?;
+    let doctor_id = principal.doctor_id.ok_or(AppError::NotLicensed)?;
+
+    let icd10_code = normalize_icd10_code(icd10_code);
+    check_icd10_codes(icd10_code.as_slice(), &state.db_pool).await?;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    let record = query!(
+        r#"SELECT source AS "source: ConditionSource", confirmed_at
+        FROM medical_conditions
+        WHERE condition_id = $1 AND user_id = $2 FOR UPDATE"#,
+        condition_id,
+        user_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!(
+            "Error while fetching medical condition {} for {}: {:?}",
+            condition_id, user_id, e
+        );
+        AppError::InternalError
+    })?
+    .ok_or(DatabaseError::RowNotFound)?;
+
+    if record.source != ConditionSource::SelfReported
+        || record.confirmed_at.is_some()
+    {
+        return Err(AppError::ConditionAlreadyConfirmed);
+    }
+
+    query!(
+        "UPDATE medical_conditions SET confirmed_by = $1, confirmed_at = \
+         NOW(), icd10_code = COALESCE($2, icd10_code) WHERE condition_id = $3",
+        doctor_id,
+        icd10_code,
+        condition_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!("Error while confirming condition {}: {:?}", condition_id, e);
+        AppError::InternalError
+    })?;
+
+    audit::record(
+        &mut tx,
+        principal.user_id,
+        Some(user_id),
+        "medical_condition.confirm",
+        json!({
+            "condition_id": condition_id,
+            "doctor_id": doctor_id,
+            "icd10_code": icd10_code,
+        }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    Ok(Json(json!({ "message": "medical condition confirmed" })))
+}
+
 pub async fn delete_own_conditions(
     State(state): State<AppState>,
-    AuthUser { user_id, .. }: AuthUser,
+    principal: Principal,
     Path(condition_id): Path<Uuid>,
 ) -> APIResult<(StatusCode, Json<Value>)> {
-    let query_res: sqlx::postgres::PgQueryResult = query!(
-        "DELETE FROM medical_conditions WHERE condition_id = $1 AND user_id = \
-         $2",
+    principal.require(
+        Action::Delete,
+        Resource::MedicalCondition,
+        Scope::Own,
+    )?;
+    let user_id = principal.user_id;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    let source = query_scalar!(
+        r#"SELECT source AS "source: ConditionSource" FROM medical_conditions
+        WHERE condition_id = $1 AND user_id = $2 FOR UPDATE"#,
         condition_id,
         user_id
     )
-    .execute(&state.db_pool)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!(
+            "Error while fetching medical condition {} for {}: {:?}",
+            condition_id, user_id, e
+        );
+        AppError::InternalError
+    })?
+    .ok_or(DatabaseError::RowNotFound)?;
+
+    // a doctor's diagnosis stays, the patient can mark it resolved instead
+    if source == ConditionSource::Diagnosed {
+        return Err(AppError::ConditionDiagnosed);
+    }
+
+    query!(
+        "DELETE FROM medical_conditions WHERE condition_id = $1",
+        condition_id
+    )
+    .execute(&mut *tx)
     .await
     .map_err(|e| {
         error!(
@@ -117,10 +477,15 @@ pub async fn delete_own_conditions(
         AppError::InternalError
     })?;
 
-    if query_res.rows_affected() == 0 {
-        // assume it doesnt exist
-        return Err(DatabaseError::RowNotFound.into());
-    }
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "medical_condition.delete",
+        json!({ "condition_id": condition_id }),
+    )
+    .await?;
+    audit::commit(tx).await?;
 
     Ok((
         StatusCode::OK,
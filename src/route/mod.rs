@@ -1,9 +1,26 @@
+pub mod access_log;
+pub mod account;
 pub mod admin;
 pub mod allergy;
+pub mod audit;
 pub mod consultation;
+pub mod delegation;
+pub mod dependent;
+pub mod device;
 pub mod doctor_profile;
+pub mod emergency;
+pub mod emergency_card;
+pub mod export;
+pub mod growth;
+pub mod icd10;
+pub mod import;
 pub mod medical_condition;
+pub mod notification;
+pub mod observation;
 pub mod purchase;
+pub mod search;
+pub mod summary;
+pub mod trend;
 pub mod user;
 pub mod user_detail;
 pub mod user_measurement;
@@ -14,30 +31,31 @@ use ed25519_compact::PublicKey;
 use moka::sync::Cache;
 use rand::distr::SampleString;
 use serde_json::{Value, json};
-use sqlx::{Pool, Postgres, query_as};
+use sqlx::{Pool, Postgres, query, query_as, query_scalar};
 use tracing::{error, trace};
 use uuid::Uuid;
 
 use crate::{
     AppState, NONCE_TTL,
-    auth::{AuthUser, retrieve_public_key},
-    canonical_json::CanonicalJson,
+    auth::retrieve_public_key,
     error::AppError,
     model::ExampleConsentRequired,
+    policy::{Principal, is_active_guardian},
     protocol::{Consent, ConsentError, Nonce},
     schema::DeviceKey,
 };
 
-pub async fn handler(user: AuthUser) -> Html<String> {
-    Html(format!("<h1>Hello, {}!</h1>", user.user_id))
+pub async fn handler(principal: Principal) -> Html<String> {
+    Html(format!("<h1>Hello, {}!</h1>", principal.user_id))
 }
 
 pub async fn request_nonce(State(state): State<AppState>) -> Json<Value> {
     // let mut nonce: Nonce = [0u8; 16];
     let nonce: String =
         rand::distr::Alphanumeric.sample_string(&mut rand::rng(), 16);
-    let expiration_date = chrono::Utc::now() + NONCE_TTL;
-    state.nonce_cache.insert(nonce.clone(), ());
+    let issued_at = Utc::now();
+    let expiration_date = issued_at + NONCE_TTL;
+    state.nonce_cache.insert(nonce.clone(), issued_at);
 
     trace!("nonce requested: {:?}", nonce);
 
@@ -79,6 +97,7 @@ pub async fn consent_required_example(
     }
 }
 
+#[allow(clippy::collapsible_if)]
 fn key_expired(key_revoked_time: Option<DateTime<Utc>>) -> bool {
     if let Some(t) = key_revoked_time {
         if Utc::now() - NONCE_TTL > t {
@@ -89,19 +108,45 @@ fn key_expired(key_revoked_time: Option<DateTime<Utc>>) -> bool {
     false
 }
 
+/// The key `device_key` held when a nonce was issued at `issued_at`.
+///
+/// That is the oldest generation which had not been rotated away from yet, so
+/// consents over nonces handed out before a rotation stay valid for as long as
+/// the nonce does.
+async fn public_key_at(
+    device_key: &DeviceKey,
+    issued_at: DateTime<Utc>,
+    db_pool: &Pool<Postgres>,
+) -> Result<String, AppError> {
+    let retired_key = query_scalar!(
+        "SELECT public_key_pem FROM device_key_generations
+         WHERE device_id = $1 AND retired_at > $2
+         ORDER BY generation LIMIT 1",
+        device_key.device_id,
+        issued_at
+    )
+    .fetch_optional(db_pool)
+    .await
+    .map_err(|e| {
+        error!(
+            "Error while fetching key generations of {}: {:?}",
+            device_key.device_id, e
+        );
+        AppError::InternalError
+    })?;
+
+    Ok(retired_key.unwrap_or_else(|| device_key.public_key_pem.clone()))
+}
+
 pub async fn verify_consent(
     consent: Consent,
     signer: Uuid,
     db_pool: &Pool<Postgres>,
-    nonce_cache: &Cache<Nonce, ()>,
+    nonce_cache: &Cache<Nonce, DateTime<Utc>>,
 ) -> Result<(), AppError> {
-    {
-        let nonce = consent.nonce.as_str();
-        if !nonce_cache.contains_key(nonce) {
-            return Err(ConsentError::NonceExpired.into());
-        }
-        nonce_cache.remove(nonce);
-    }
+    let issued_at = nonce_cache
+        .remove(consent.nonce.as_str())
+        .ok_or(ConsentError::NonceExpired)?;
 
     let device_id = consent.signer_device_id;
     let device_key = query_as!(
@@ -126,14 +171,18 @@ pub async fn verify_consent(
         return Err(ConsentError::KeyExpired.into());
     }
 
-    if device_key.user_id != signer {
+    // guardians consent on behalf of their dependents
+    if device_key.user_id != signer
+        && !is_active_guardian(device_key.user_id, signer, db_pool).await?
+    {
         return Err(ConsentError::UserDeviceMismatch.into());
     }
 
-    let pk = PublicKey::from_pem(&device_key.public_key_pem).map_err(|e| {
+    let public_key_pem = public_key_at(&device_key, issued_at, db_pool).await?;
+    let pk = PublicKey::from_pem(&public_key_pem).map_err(|e| {
         error!(
             "Error occured while parsing pem to pk: pem: {}\nerror: {:?}",
-            device_key.public_key_pem, e
+            public_key_pem, e
         );
         AppError::InternalError
     })?;
@@ -142,5 +191,16 @@ pub async fn verify_consent(
         return Err(ConsentError::NonConsent.into());
     }
 
+    query!(
+        "UPDATE device_keys SET last_used_at = NOW() WHERE device_id = $1",
+        device_id
+    )
+    .execute(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while touching device key {}: {:?}", device_id, e);
+        AppError::InternalError
+    })?;
+
     Ok(())
 }
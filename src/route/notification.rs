@@ -0,0 +1,126 @@
+//! Things a user should know about that happened to their records.
+//!
+//! Notifications are written by [`notify`] alongside the change they are
+//! about, e.g. a doctor opening emergency access, and listed through
+//! `GET /me/notifications`.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{Value, json};
+use sqlx::{PgConnection, query};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    error::{APIResult, AppError, DatabaseError},
+    pagination::{Page, Paginated, fetch_page},
+    policy::{Action, Principal, Resource, Scope},
+};
+
+#[derive(Serialize)]
+pub struct Notification {
+    pub notification_id: Uuid,
+    /// What happened, e.g. `emergency_access`
+    pub kind: String,
+    pub payload: Value,
+    pub created_at: DateTime<Utc>,
+    pub read_at: Option<DateTime<Utc>>,
+}
+
+/// Leaves a notification for `user_id`, meant to share the transaction of
+/// the change it is about
+pub async fn notify(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    kind: &str,
+    payload: Value,
+) -> APIResult<()> {
+    query!(
+        "INSERT INTO notifications (user_id, kind, payload) VALUES ($1, $2, \
+         $3)",
+        user_id,
+        kind,
+        payload
+    )
+    .execute(conn)
+    .await
+    .map_err(|e| {
+        error!("Error while notifying {} of {}: {:?}", user_id, kind, e);
+        AppError::InternalError
+    })?;
+
+    Ok(())
+}
+
+pub async fn get_own_notifications(
+    State(state): State<AppState>,
+    principal: Principal,
+    page: Page,
+) -> APIResult<Paginated<Notification>> {
+    principal.require(Action::Read, Resource::Notification, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    fetch_page!(
+        Notification,
+        &page,
+        &state.db_pool,
+        "SELECT notification_id, kind, payload, created_at, read_at
+         FROM notifications
+         WHERE user_id = $6",
+        "created_at",
+        "notification_id",
+        user_id
+    )
+    .map(|rows| {
+        page.paginate(rows, |notification| {
+            (notification.created_at, notification.notification_id)
+        })
+    })
+    .map_err(|e| {
+        error!(
+            "Error while retrieving notifications for {}: {:?}",
+            user_id, e
+        );
+        AppError::InternalError
+    })
+}
+
+pub async fn mark_notification_read(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(notification_id): Path<Uuid>,
+) -> APIResult<(StatusCode, Json<Value>)> {
+    principal.require(Action::Update, Resource::Notification, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    let query_res = query!(
+        "UPDATE notifications SET read_at = COALESCE(read_at, NOW()) WHERE \
+         notification_id = $1 AND user_id = $2",
+        notification_id,
+        user_id
+    )
+    .execute(&state.db_pool)
+    .await
+    .map_err(|e| {
+        error!(
+            "Error while marking notification {} read for {}: {:?}",
+            notification_id, user_id, e
+        );
+        AppError::InternalError
+    })?;
+
+    if query_res.rows_affected() == 0 {
+        return Err(DatabaseError::RowNotFound.into());
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "message": "notification marked as read" })),
+    ))
+}
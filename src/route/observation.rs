@@ -0,0 +1,257 @@
+//! Vital signs and lab values other than body height and weight, which stay
+//! in [`crate::route::user_measurement`].
+//!
+//! What can be observed is listed in `observation_types` by LOINC code, each
+//! with its UCUM unit and the range a value has to be in. Patients record
+//! their own, doctors record them along with a consultation.
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sqlx::{PgConnection, Pool, Postgres, query, query_as, query_scalar};
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::{
+    AppState, audit,
+    error::{APIResult, AppError},
+    pagination::{Page, Paginated, fetch_page},
+    policy::{Action, Principal, Resource, Scope},
+    route::access_log::authorize_read,
+    schema::{Observation, ObservationType},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObservationPayload {
+    /// One of `GET /observation-types`, e.g. `8867-4` for the heart rate
+    pub code: String,
+    /// In the unit of the type
+    pub value: f64,
+}
+
+/// Rejects `observations` unless each has a known code and a value in the
+/// range of its type
+pub async fn check_observations(
+    observations: &[ObservationPayload],
+    db_pool: &Pool<Postgres>,
+) -> APIResult<()> {
+    if observations.is_empty() {
+        return Ok(());
+    }
+
+    let codes: Vec<String> = observations
+        .iter()
+        .map(|observation| observation.code.clone())
+        .collect();
+    let types = query!(
+        "SELECT code, min_value, max_value FROM observation_types WHERE code \
+         = ANY($1)",
+        &codes
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while looking up observation types: {:?}", e);
+        AppError::InternalError
+    })?;
+
+    for observation in observations {
+        let Some(kind) =
+            types.iter().find(|kind| kind.code == observation.code)
+        else {
+            warn!("Unknown observation code {}", observation.code);
+            return Err(AppError::UnknownObservationCode);
+        };
+
+        if !(kind.min_value..=kind.max_value).contains(&observation.value) {
+            warn!(
+                "Observation {} of {} out of range",
+                observation.code, observation.value
+            );
+            return Err(AppError::ObservationOutOfRange);
+        }
+    }
+
+    Ok(())
+}
+
+/// Records `observations` of `user_id`, checked with [`check_observations`]
+pub async fn insert_observations(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    observations: &[ObservationPayload],
+    observed_at: DateTime<Utc>,
+    recorded_by: Uuid,
+    consultation_id: Option<Uuid>,
+) -> APIResult<Vec<Uuid>> {
+    let (codes, values): (Vec<String>, Vec<f64>) = observations
+        .iter()
+        .map(|observation| (observation.code.clone(), observation.value))
+        .unzip();
+
+    query_scalar!(
+        r#"INSERT INTO observations
+            (user_id, code, value, observed_at, recorded_by, consultation_id)
+        SELECT $1, code, value, $4, $5, $6
+        FROM UNNEST($2::TEXT[], $3::DOUBLE PRECISION[]) AS o (code, value)
+        RETURNING observation_id"#,
+        user_id,
+        &codes,
+        &values,
+        observed_at,
+        recorded_by,
+        consultation_id
+    )
+    .fetch_all(conn)
+    .await
+    .map_err(|e| {
+        error!("Error while recording observations of {}: {:?}", user_id, e);
+        AppError::InternalError
+    })
+}
+
+#[derive(Deserialize)]
+pub struct ObservationFilter {
+    /// Only observations of this type
+    pub code: Option<String>,
+}
+
+async fn fetch_observations(
+    user_id: Uuid,
+    code: Option<String>,
+    page: &Page,
+    db_pool: &Pool<Postgres>,
+) -> APIResult<Paginated<Observation>> {
+    fetch_page!(
+        Observation,
+        page,
+        db_pool,
+        "SELECT o.observation_id, o.user_id, o.code, t.name, t.unit,
+            t.category, o.value, o.observed_at, o.recorded_by,
+            o.consultation_id
+         FROM observations AS o
+         JOIN observation_types AS t ON t.code = o.code
+         WHERE o.user_id = $6 AND ($7::TEXT IS NULL OR o.code = $7)",
+        "o.observed_at",
+        "o.observation_id",
+        user_id,
+        code
+    )
+    .map(|rows| {
+        page.paginate(rows, |observation| {
+            (observation.observed_at, observation.observation_id)
+        })
+    })
+    .map_err(|e| {
+        error!("Error while fetching observations for {}: {:?}", user_id, e);
+        AppError::InternalError
+    })
+}
+
+/// What can be observed
+pub async fn get_observation_types(
+    State(state): State<AppState>,
+    _principal: Principal,
+) -> APIResult<Json<Vec<ObservationType>>> {
+    query_as!(
+        ObservationType,
+        "SELECT * FROM observation_types ORDER BY category DESC, name"
+    )
+    .fetch_all(&state.db_pool)
+    .await
+    .map(Json)
+    .map_err(|e| {
+        error!("Error while fetching observation types: {:?}", e);
+        AppError::InternalError
+    })
+}
+
+pub async fn get_user_observations(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(user_id): Path<Uuid>,
+    Query(ObservationFilter { code }): Query<ObservationFilter>,
+    page: Page,
+) -> APIResult<Paginated<Observation>> {
+    authorize_read(
+        &principal,
+        Resource::Measurement,
+        user_id,
+        principal
+            .patient_scope(user_id, Resource::Measurement, &state.db_pool)
+            .await?,
+        &state.db_pool,
+    )
+    .await?;
+
+    fetch_observations(user_id, code, &page, &state.db_pool).await
+}
+
+pub async fn get_own_observations(
+    State(state): State<AppState>,
+    principal: Principal,
+    Query(ObservationFilter { code }): Query<ObservationFilter>,
+    page: Page,
+) -> APIResult<Paginated<Observation>> {
+    principal.require(Action::Read, Resource::Measurement, Scope::Own)?;
+
+    fetch_observations(principal.user_id, code, &page, &state.db_pool).await
+}
+
+#[derive(Deserialize)]
+pub struct ObservationsPayload {
+    /// Taken together, e.g. the systolic and diastolic blood pressure
+    pub observations: Vec<ObservationPayload>,
+    pub observed_at: Option<DateTime<Utc>>,
+}
+
+pub async fn add_own_observations(
+    State(state): State<AppState>,
+    principal: Principal,
+    Json(ObservationsPayload {
+        observations,
+        observed_at,
+    }): Json<ObservationsPayload>,
+) -> APIResult<(StatusCode, Json<Value>)> {
+    principal.require(Action::Create, Resource::Measurement, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    check_observations(&observations, &state.db_pool).await?;
+
+    let observed_at = observed_at.unwrap_or(Utc::now());
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    let observation_ids = insert_observations(
+        &mut tx,
+        user_id,
+        &observations,
+        observed_at,
+        principal.actor_id(),
+        None,
+    )
+    .await?;
+
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "observation.create",
+        json!({
+            "observation_ids": observation_ids,
+            "observations": observations,
+            "observed_at": observed_at,
+        }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({ "message": "observations added" })),
+    ))
+}
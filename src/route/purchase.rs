@@ -1,29 +1,40 @@
 use axum::{Json, extract::State, http::StatusCode};
 use serde::Deserialize;
 use serde_json::{Value, json};
-use sqlx::{query, query_as};
+use sqlx::query_scalar;
 use tracing::error;
 use uuid::Uuid;
 
 use crate::{
-    AppState,
-    auth::AuthUser,
+    AppState, audit,
     error::{APIResult, AppError},
+    pagination::{Page, Paginated, fetch_page},
+    policy::{Action, Principal, Resource, Scope},
     schema::Purchase,
 };
 
 pub async fn get_own_purchases(
     State(state): State<AppState>,
-    AuthUser { user_id, .. }: AuthUser,
-) -> APIResult<Json<Vec<Purchase>>> {
-    query_as!(
+    principal: Principal,
+    page: Page,
+) -> APIResult<Paginated<Purchase>> {
+    principal.require(Action::Read, Resource::Purchase, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    fetch_page!(
         Purchase,
-        "SELECT * FROM purchases WHERE user_id = $1",
+        &page,
+        &state.db_pool,
+        "SELECT * FROM purchases WHERE user_id = $6",
+        "purchased_at",
+        "purchase_id",
         user_id
     )
-    .fetch_all(&state.db_pool)
-    .await
-    .map(Json)
+    .map(|rows| {
+        page.paginate(rows, |purchase| {
+            (purchase.purchased_at, purchase.purchase_id)
+        })
+    })
     .map_err(|e| {
         error!("Error while retrieving purchases for {}: {:?}", user_id, e);
         AppError::InternalError
@@ -38,26 +49,45 @@ pub struct PurchasePayload {
 
 pub async fn add_own_purchase(
     State(state): State<AppState>,
-    AuthUser { user_id, .. }: AuthUser,
+    principal: Principal,
     Json(PurchasePayload {
         medicine_id,
         quantity,
     }): Json<PurchasePayload>,
 ) -> APIResult<(StatusCode, Json<Value>)> {
-    query!(
+    principal.require(Action::Create, Resource::Purchase, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    let purchase_id = query_scalar!(
         "INSERT INTO purchases (user_id, medicine_id, quantity) VALUES ($1, \
-         $2, $3)",
+         $2, $3) RETURNING purchase_id",
         user_id,
         medicine_id,
         quantity
     )
-    .fetch_all(&state.db_pool)
+    .fetch_one(&mut *tx)
     .await
     .map_err(|e| {
-        error!("Error while retrieving purchases for {}: {:?}", user_id, e);
+        error!("Error while adding purchase for {}: {:?}", user_id, e);
         AppError::InternalError
     })?;
 
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "purchase.create",
+        json!({
+            "purchase_id": purchase_id,
+            "medicine_id": medicine_id,
+            "quantity": quantity,
+        }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
     Ok((
         StatusCode::CREATED,
         Json(json!({ "message": "purchase added" })),
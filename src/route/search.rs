@@ -0,0 +1,174 @@
+//! Full-text search over a patient's history.
+//!
+//! Matches symptoms of consultations, diagnoses, prescribed drugs with their
+//! instructions and medical conditions, best match first. Each kind of record
+//! is only searched when the caller may read it, so a delegate who was handed
+//! prescriptions finds those and nothing else, and an attending doctor only
+//! finds what was recorded in their own consultations. Reading somebody
+//! else's records this way is logged like any other read.
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::query_as;
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    error::{APIResult, AppError},
+    policy::{Action, Principal, Resource},
+    route::{access_log::authorize_read, export::attending_doctor},
+};
+
+/// Most hits returned at once
+pub const SEARCH_LIMIT: i64 = 50;
+
+#[derive(Deserialize)]
+pub struct SearchParams {
+    /// Words to look for, quotes and `-` work as in web search engines
+    pub q: String,
+}
+
+#[derive(Serialize)]
+pub struct SearchHit {
+    /// `consultation`, `diagnosis`, `prescription` or `medical_condition`
+    pub kind: String,
+    pub record_id: Uuid,
+    /// Consultation the record belongs to, absent for conditions
+    pub consultation_id: Option<Uuid>,
+    /// The text that matched
+    pub text: String,
+    /// When the consultation took place, absent for conditions
+    pub recorded_at: Option<DateTime<Utc>>,
+    pub rank: f32,
+}
+
+/// Searches `user_id`'s history, in whatever of it the caller may read
+async fn search_history(
+    principal: &Principal,
+    user_id: Uuid,
+    q: &str,
+    state: &AppState,
+) -> APIResult<Vec<SearchHit>> {
+    let db_pool = &state.db_pool;
+
+    let mut readable = [false; 4];
+    let mut doctors = [None; 4];
+    let resources = [
+        Resource::Consultation,
+        Resource::Diagnosis,
+        Resource::Prescription,
+        Resource::MedicalCondition,
+    ];
+    for ((readable, doctor), resource) in
+        readable.iter_mut().zip(&mut doctors).zip(resources)
+    {
+        let scope = principal.patient_scope(user_id, resource, db_pool).await?;
+        if principal.require(Action::Read, resource, scope).is_ok() {
+            authorize_read(principal, resource, user_id, scope, db_pool)
+                .await?;
+            *readable = true;
+            *doctor = attending_doctor(principal, scope);
+        }
+    }
+    if !readable.contains(&true) {
+        return Err(AppError::PermissionDenied);
+    }
+    let [consultations, diagnoses, prescriptions, conditions] = readable;
+    let [
+        consultation_doctor,
+        diagnosis_doctor,
+        prescription_doctor,
+        _,
+    ] = doctors;
+
+    query_as!(
+        SearchHit,
+        r#"SELECT kind AS "kind!", record_id AS "record_id!", consultation_id,
+            text AS "text!", recorded_at, rank AS "rank!"
+        FROM (
+            SELECT 'consultation' AS kind, c.consultation_id AS record_id,
+                c.consultation_id, c.symptoms AS text,
+                c.created_at AS recorded_at,
+                ts_rank(to_tsvector('simple', c.symptoms), q) AS rank
+            FROM consultations AS c, websearch_to_tsquery('simple', $2) AS q
+            WHERE $3 AND c.user_id = $1
+                AND ($8::UUID IS NULL OR c.doctor_id = $8)
+                AND to_tsvector('simple', c.symptoms) @@ q
+            UNION ALL
+            SELECT 'diagnosis', d.diagnosis_id, d.consultation_id,
+                d.diagnosis, c.created_at,
+                ts_rank(to_tsvector('simple', d.diagnosis), q)
+            FROM diagnoses AS d
+            JOIN consultations AS c ON c.consultation_id = d.consultation_id,
+                websearch_to_tsquery('simple', $2) AS q
+            WHERE $4 AND c.user_id = $1
+                AND ($9::UUID IS NULL OR c.doctor_id = $9)
+                AND to_tsvector('simple', d.diagnosis) @@ q
+            UNION ALL
+            SELECT 'prescription', p.prescription_id, p.consultation_id,
+                p.drug_name || ': ' || p.instruction, c.created_at,
+                ts_rank(
+                    to_tsvector('simple', p.drug_name || ' ' || p.instruction),
+                    q
+                )
+            FROM prescriptions AS p
+            JOIN consultations AS c ON c.consultation_id = p.consultation_id,
+                websearch_to_tsquery('simple', $2) AS q
+            WHERE $5 AND c.user_id = $1
+                AND ($10::UUID IS NULL OR c.doctor_id = $10)
+                AND to_tsvector('simple', p.drug_name || ' ' || p.instruction)
+                    @@ q
+            UNION ALL
+            SELECT 'medical_condition', m.condition_id, NULL, m.condition,
+                NULL, ts_rank(to_tsvector('simple', m.condition), q)
+            FROM medical_conditions AS m,
+                websearch_to_tsquery('simple', $2) AS q
+            WHERE $6 AND m.user_id = $1
+                AND to_tsvector('simple', m.condition) @@ q
+        ) AS hits
+        ORDER BY rank DESC, recorded_at DESC NULLS LAST
+        LIMIT $7"#,
+        user_id,
+        q,
+        consultations,
+        diagnoses,
+        prescriptions,
+        conditions,
+        SEARCH_LIMIT,
+        consultation_doctor,
+        diagnosis_doctor,
+        prescription_doctor
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while searching history of {}: {:?}", user_id, e);
+        AppError::InternalError
+    })
+}
+
+pub async fn search_own_history(
+    State(state): State<AppState>,
+    principal: Principal,
+    Query(SearchParams { q }): Query<SearchParams>,
+) -> APIResult<Json<Vec<SearchHit>>> {
+    search_history(&principal, principal.user_id, &q, &state)
+        .await
+        .map(Json)
+}
+
+pub async fn search_user_history(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(user_id): Path<Uuid>,
+    Query(SearchParams { q }): Query<SearchParams>,
+) -> APIResult<Json<Vec<SearchHit>>> {
+    search_history(&principal, user_id, &q, &state)
+        .await
+        .map(Json)
+}
@@ -0,0 +1,271 @@
+use axum::{
+    extract::{Path, State},
+    http::{StatusCode, header},
+    response::{Html, IntoResponse},
+};
+use chrono::{DateTime, Utc};
+use data_encoding::HEXLOWER;
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::{query, query_as};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    AppState, audit,
+    error::{APIResult, AppError, DatabaseError, internal_error},
+    pagination::{Page, Paginated, fetch_page},
+    policy::{Action, Principal, Resource, Scope},
+    route::{allergy::fetch_allergies, medical_condition::fetch_conditions},
+    schema::{
+        Consultation, Diagnosis, DiagnosisSeverity, Prescription, UserDetail,
+        UserMeasurement,
+    },
+    summary::{
+        PatientSummary, RECENT_MEASUREMENTS, SummaryAttestation,
+        SummaryConsultation, render_verification,
+    },
+};
+
+#[derive(Serialize)]
+pub struct IssuedSummary {
+    pub summary_id: Uuid,
+    /// The guardian when issued for a dependent
+    pub issued_by: Uuid,
+    pub sha256: String,
+    pub issued_at: DateTime<Utc>,
+}
+
+async fn fetch_summary(
+    user_id: Uuid,
+    state: &AppState,
+) -> APIResult<PatientSummary> {
+    let db_pool = &state.db_pool;
+
+    let detail = query!(
+        "SELECT user_id, nik, name, dob, gender FROM user_details WHERE \
+         user_id = $1",
+        user_id
+    )
+    .fetch_optional(db_pool)
+    .await
+    .map_err(internal_error("details", user_id))?
+    .map(|row| UserDetail {
+        user_id: row.user_id,
+        nik: row.nik,
+        name: row.name,
+        dob: row.dob,
+        gender: row.gender.chars().next().unwrap_or('U'),
+    });
+
+    let allergies = fetch_allergies(user_id, db_pool).await?;
+
+    let conditions = fetch_conditions(user_id, db_pool).await?;
+
+    let measurements = query_as!(
+        UserMeasurement,
+        "SELECT * FROM user_measurements WHERE user_id = $1 ORDER BY \
+         measured_at DESC LIMIT $2",
+        user_id,
+        RECENT_MEASUREMENTS
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(internal_error("measurements", user_id))?;
+
+    let consultations = query_as!(
+        Consultation,
+        "SELECT * FROM consultations WHERE user_id = $1 ORDER BY created_at \
+         DESC",
+        user_id
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(internal_error("consultations", user_id))?;
+
+    let mut diagnoses = query_as!(
+        Diagnosis,
+        "SELECT d.diagnosis_id, d.consultation_id, d.diagnosis,
+            d.severity AS \"severity: DiagnosisSeverity\", d.icd10_code
+         FROM diagnoses AS d
+         JOIN consultations AS c ON c.consultation_id = d.consultation_id
+         WHERE c.user_id = $1
+         ORDER BY d.diagnosis",
+        user_id
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(internal_error("diagnoses", user_id))?;
+
+    // what the patient is taking, as for reminders
+    let prescriptions = query_as!(
+        Prescription,
+        "SELECT p.* FROM prescriptions AS p
+         JOIN consultations AS c ON c.consultation_id = p.consultation_id
+         WHERE c.user_id = $1 AND c.reminded
+         ORDER BY c.created_at DESC, p.drug_name",
+        user_id
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(internal_error("prescriptions", user_id))?;
+
+    let consultations = consultations
+        .into_iter()
+        .map(|consultation| {
+            let (of_consultation, rest) =
+                diagnoses.drain(..).partition(|diagnosis: &Diagnosis| {
+                    diagnosis.consultation_id == consultation.consultation_id
+                });
+            diagnoses = rest;
+
+            SummaryConsultation {
+                created_at: consultation.created_at,
+                symptoms: consultation.symptoms,
+                diagnoses: of_consultation,
+            }
+        })
+        .collect();
+
+    Ok(PatientSummary {
+        detail,
+        allergies,
+        conditions,
+        measurements,
+        consultations,
+        prescriptions,
+    })
+}
+
+/// Issues a new summary of the caller's record, every one is kept so it can
+/// be verified later
+pub async fn issue_own_summary_pdf(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> APIResult<impl IntoResponse> {
+    principal.require(Action::Create, Resource::Summary, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    let summary = fetch_summary(user_id, &state).await?;
+
+    let summary_id = Uuid::new_v4();
+    let issued_at = Utc::now();
+    let pdf = summary.render_pdf(
+        summary_id,
+        issued_at,
+        &state.card_issuer.summary_url(summary_id),
+    )?;
+    let sha256 = HEXLOWER.encode(&Sha256::digest(&pdf));
+
+    let token = state.card_issuer.sign_claims(&SummaryAttestation {
+        sub: user_id,
+        jti: summary_id,
+        iat: issued_at.timestamp(),
+        name: summary.detail.map(|detail| detail.name),
+        sha256: sha256.clone(),
+    })?;
+
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    query!(
+        "INSERT INTO patient_summaries (summary_id, user_id, issued_by, \
+         sha256, token, issued_at) VALUES ($1, $2, $3, $4, $5, $6)",
+        summary_id,
+        user_id,
+        principal.actor_id(),
+        sha256,
+        token,
+        issued_at
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        error!("Error while issuing summary of {}: {:?}", user_id, e);
+        AppError::InternalError
+    })?;
+
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "summary.issue",
+        json!({ "summary_id": summary_id, "sha256": sha256 }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
+    info!("Issued summary {} of {}", summary_id, user_id);
+
+    Ok((
+        StatusCode::CREATED,
+        [
+            (header::LOCATION, format!("/summaries/{summary_id}")),
+            (header::CONTENT_TYPE, "application/pdf".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"summary-{}.pdf\"",
+                    issued_at.format("%Y-%m-%d")
+                ),
+            ),
+        ],
+        pdf,
+    ))
+}
+
+/// Summaries issued of the caller, latest first
+pub async fn get_own_summaries(
+    State(state): State<AppState>,
+    principal: Principal,
+    page: Page,
+) -> APIResult<Paginated<IssuedSummary>> {
+    principal.require(Action::Read, Resource::Summary, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    fetch_page!(
+        IssuedSummary,
+        &page,
+        &state.db_pool,
+        "SELECT summary_id, issued_by, sha256, issued_at
+         FROM patient_summaries
+         WHERE user_id = $6",
+        "issued_at",
+        "summary_id",
+        user_id
+    )
+    .map(|rows| {
+        page.paginate(rows, |summary| (summary.issued_at, summary.summary_id))
+    })
+    .map_err(|e| {
+        error!("Error while retrieving summaries of {}: {:?}", user_id, e);
+        AppError::InternalError
+    })
+}
+
+/// The verification page the QR code of a summary links to
+pub async fn verify_summary(
+    State(state): State<AppState>,
+    Path(summary_id): Path<Uuid>,
+) -> APIResult<Html<String>> {
+    let token = query!(
+        "SELECT token FROM patient_summaries WHERE summary_id = $1",
+        summary_id
+    )
+    .fetch_optional(&state.db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while looking up summary {}: {:?}", summary_id, e);
+        AppError::InternalError
+    })?
+    .ok_or(DatabaseError::RowNotFound)?
+    .token;
+
+    // only fails once the card key was replaced
+    let attestation = state
+        .card_issuer
+        .verify_claims::<SummaryAttestation>(&token)
+        .filter(|attestation| attestation.jti == summary_id);
+
+    Ok(Html(render_verification(attestation.as_ref())))
+}
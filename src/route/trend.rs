@@ -0,0 +1,234 @@
+//! How weight, BMI and blood pressure evolve, read from the
+//! `measurement_readings` view over measurements and observations.
+//!
+//! Readings are bucketed by week or month in SQL, each bucket carrying the
+//! average, extremes and a moving average over the buckets before it.
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, query_as};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    error::{APIResult, AppError},
+    policy::{Action, Principal, Resource, Scope},
+    route::access_log::authorize_read,
+};
+
+/// Bounds an adult's readings are expected within, a reading below the first
+/// or from the second on raises an alert: the WHO classification for BMI,
+/// hypotension and stage 2 hypertension for blood pressure. A child's BMI is
+/// read against the BMI-for-age curves of [`crate::route::growth`] instead,
+/// so BMI taken before 19 years of age, where those end, raises none.
+const HEALTHY_RANGES: &[(&str, f64, f64)] = &[
+    ("bmi", 18.5, 25.0),
+    ("systolic", 90.0, 140.0),
+    ("diastolic", 60.0, 90.0),
+];
+
+/// Alerts returned at most, the latest ones
+const MAX_ALERTS: i64 = 100;
+
+/// Buckets a moving average spans unless asked otherwise
+const DEFAULT_WINDOW: i64 = 3;
+const MAX_WINDOW: i64 = 12;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrendPeriod {
+    Week,
+    #[default]
+    Month,
+}
+
+impl TrendPeriod {
+    /// Field of `date_trunc` the period is
+    fn as_str(self) -> &'static str {
+        match self {
+            TrendPeriod::Week => "week",
+            TrendPeriod::Month => "month",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TrendQuery {
+    #[serde(default)]
+    pub period: TrendPeriod,
+    /// Buckets the moving average spans, this one included
+    pub window: Option<i64>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+/// Readings of one metric within one period
+#[derive(Debug, Serialize)]
+pub struct TrendBucket {
+    pub metric: String,
+    pub unit: String,
+    pub period_start: DateTime<Utc>,
+    pub readings: i64,
+    pub average: f64,
+    pub min: f64,
+    pub max: f64,
+    /// Of the averages of this bucket and the ones before it with readings
+    pub moving_average: f64,
+}
+
+/// A reading outside of [`HEALTHY_RANGES`]
+#[derive(Debug, Serialize)]
+pub struct TrendAlert {
+    pub metric: String,
+    pub value: f64,
+    pub measured_at: DateTime<Utc>,
+    pub low: f64,
+    pub high: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Trends {
+    pub period: TrendPeriod,
+    pub window: i64,
+    pub buckets: Vec<TrendBucket>,
+    /// Oldest first, the latest [`MAX_ALERTS`] of them
+    pub alerts: Vec<TrendAlert>,
+    /// Whether older alerts were left out, narrow `from` and `to` to see them
+    pub more_alerts: bool,
+}
+
+async fn fetch_trends(
+    user_id: Uuid,
+    TrendQuery {
+        period,
+        window,
+        from,
+        to,
+    }: TrendQuery,
+    db_pool: &Pool<Postgres>,
+) -> APIResult<Json<Trends>> {
+    let window = window.unwrap_or(DEFAULT_WINDOW);
+    if !(1..=MAX_WINDOW).contains(&window)
+        || from.zip(to).is_some_and(|(from, to)| from >= to)
+    {
+        return Err(AppError::InvalidTrendQuery);
+    }
+
+    let buckets = query_as!(
+        TrendBucket,
+        r#"SELECT metric AS "metric!", unit AS "unit!",
+            period_start AS "period_start!", readings AS "readings!",
+            average AS "average!", min AS "min!", max AS "max!",
+            AVG(average) OVER (
+                PARTITION BY metric ORDER BY period_start
+                ROWS BETWEEN $5 PRECEDING AND CURRENT ROW
+            ) AS "moving_average!"
+        FROM (
+            SELECT metric, unit,
+                date_trunc($2, measured_at, 'UTC') AS period_start,
+                COUNT(*) AS readings, AVG(value) AS average,
+                MIN(value) AS min, MAX(value) AS max
+            FROM measurement_readings
+            WHERE user_id = $1
+                AND ($3::TIMESTAMPTZ IS NULL OR measured_at >= $3)
+                AND ($4::TIMESTAMPTZ IS NULL OR measured_at < $4)
+            GROUP BY metric, unit, period_start
+        ) AS b
+        ORDER BY metric, period_start"#,
+        user_id,
+        period.as_str(),
+        from,
+        to,
+        window - 1
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while bucketing readings of {}: {:?}", user_id, e);
+        AppError::InternalError
+    })?;
+
+    let (metrics, (lows, highs)): (Vec<String>, (Vec<f64>, Vec<f64>)) =
+        HEALTHY_RANGES
+            .iter()
+            .map(|&(metric, low, high)| (metric.to_string(), (low, high)))
+            .unzip();
+    let mut alerts = query_as!(
+        TrendAlert,
+        r#"SELECT r.metric AS "metric!", r.value AS "value!",
+            r.measured_at AS "measured_at!", h.low AS "low!", h.high AS "high!"
+        FROM measurement_readings AS r
+        JOIN UNNEST($2::TEXT[], $3::DOUBLE PRECISION[],
+            $4::DOUBLE PRECISION[]) AS h (metric, low, high)
+            ON h.metric = r.metric
+        LEFT JOIN user_details AS ud ON ud.user_id = r.user_id
+        WHERE r.user_id = $1
+            AND (r.value < h.low OR r.value >= h.high)
+            AND (r.metric <> 'bmi' OR ud.dob IS NULL
+                OR age(r.measured_at::DATE, ud.dob) >= INTERVAL '19 years')
+            AND ($5::TIMESTAMPTZ IS NULL OR r.measured_at >= $5)
+            AND ($6::TIMESTAMPTZ IS NULL OR r.measured_at < $6)
+        ORDER BY r.measured_at DESC, r.metric DESC
+        LIMIT $7"#,
+        user_id,
+        &metrics,
+        &lows,
+        &highs,
+        from,
+        to,
+        MAX_ALERTS + 1
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Error while looking for alerts of {}: {:?}", user_id, e);
+        AppError::InternalError
+    })?;
+
+    let more_alerts = alerts.len() as i64 > MAX_ALERTS;
+    alerts.truncate(MAX_ALERTS as usize);
+    alerts.reverse();
+
+    Ok(Json(Trends {
+        period,
+        window,
+        buckets,
+        alerts,
+        more_alerts,
+    }))
+}
+
+pub async fn get_user_trends(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(user_id): Path<Uuid>,
+    Query(query): Query<TrendQuery>,
+) -> APIResult<Json<Trends>> {
+    authorize_read(
+        &principal,
+        Resource::Measurement,
+        user_id,
+        principal
+            .patient_scope(user_id, Resource::Measurement, &state.db_pool)
+            .await?,
+        &state.db_pool,
+    )
+    .await?;
+
+    fetch_trends(user_id, query, &state.db_pool).await
+}
+
+pub async fn get_own_trends(
+    State(state): State<AppState>,
+    principal: Principal,
+    Query(query): Query<TrendQuery>,
+) -> APIResult<Json<Trends>> {
+    principal.require(Action::Read, Resource::Measurement, Scope::Own)?;
+
+    fetch_trends(principal.user_id, query, &state.db_pool).await
+}
@@ -9,14 +9,16 @@ use uuid::Uuid;
 
 use crate::{
     AppState,
-    auth::{AuthUser, LicensedUser},
     error::{APIResult, AppError, DatabaseError},
+    policy::{Action, Principal, Resource, Scope},
+    route::access_log::authorize_read,
 };
 
 #[derive(Serialize)]
 pub struct UserOpaque {
     user_id: Uuid,
-    email: String,
+    /// Absent for dependents that were not handed over yet
+    email: Option<String>,
 }
 
 // TODO: is this meant for doctors to see the patient info?
@@ -25,13 +27,19 @@ pub struct UserOpaque {
 // perhaps also make sure that they're connected?
 pub async fn get_user_info(
     State(state): State<AppState>,
-    auth: AuthUser,
-    doctor: Option<LicensedUser>,
+    principal: Principal,
     Path(user_id): Path<Uuid>,
 ) -> APIResult<Json<UserOpaque>> {
-    if user_id != auth.user_id && doctor.is_none() {
-        return Err(AppError::NotTheSameUser);
-    }
+    authorize_read(
+        &principal,
+        Resource::Profile,
+        user_id,
+        principal
+            .patient_scope(user_id, Resource::Profile, &state.db_pool)
+            .await?,
+        &state.db_pool,
+    )
+    .await?;
 
     query_as!(
         UserOpaque,
@@ -52,8 +60,11 @@ pub async fn get_user_info(
 
 pub async fn get_own_info(
     State(state): State<AppState>,
-    AuthUser { user_id, .. }: AuthUser,
+    principal: Principal,
 ) -> APIResult<Json<UserOpaque>> {
+    principal.require(Action::Read, Resource::Profile, Scope::Own)?;
+    let user_id = principal.user_id;
+
     query_as!(
         UserOpaque,
         "SELECT user_id, email FROM users WHERE user_id = $1",
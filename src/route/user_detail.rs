@@ -11,10 +11,11 @@ use tracing::{error, info, trace};
 use uuid::Uuid;
 
 use crate::{
-    AppState,
-    auth::{AuthUser, LicensedUser},
+    AppState, audit,
     error::{APIResult, AppError, DatabaseError},
+    policy::{Action, Principal, Resource, Scope},
     protocol::{NIK_LOWERBOUND, NIK_UPPERBOUND, Nik},
+    route::access_log::authorize_read,
     schema::UserDetail,
 };
 
@@ -28,13 +29,19 @@ pub struct UserDetailPayload {
 
 pub async fn get_user_details(
     State(state): State<AppState>,
-    auth: AuthUser,
-    doctor: Option<LicensedUser>,
+    principal: Principal,
     Path(user_id): Path<Uuid>,
 ) -> APIResult<Json<UserDetail>> {
-    if user_id != auth.user_id && doctor.is_none() {
-        return Err(AppError::NotTheSameUser);
-    }
+    authorize_read(
+        &principal,
+        Resource::Profile,
+        user_id,
+        principal
+            .patient_scope(user_id, Resource::Profile, &state.db_pool)
+            .await?,
+        &state.db_pool,
+    )
+    .await?;
 
     let row = sqlx::query!(
         "SELECT user_id, nik, name, dob, gender FROM user_details WHERE \
@@ -65,8 +72,11 @@ pub async fn get_user_details(
 
 pub async fn get_own_details(
     State(state): State<AppState>,
-    AuthUser { user_id, .. }: AuthUser,
+    principal: Principal,
 ) -> APIResult<Json<UserDetail>> {
+    principal.require(Action::Read, Resource::Profile, Scope::Own)?;
+    let user_id = principal.user_id;
+
     let row = sqlx::query!(
         "SELECT user_id, nik, name, dob, gender FROM user_details WHERE \
          user_id = $1",
@@ -102,9 +112,12 @@ pub async fn get_own_details(
 // to insert for the same user twice
 pub async fn set_own_details(
     State(state): State<AppState>,
-    AuthUser { user_id, .. }: AuthUser,
+    principal: Principal,
     Json(payload): Json<UserDetailPayload>,
 ) -> APIResult<(StatusCode, Json<Value>)> {
+    principal.require(Action::Update, Resource::Profile, Scope::Own)?;
+    let user_id = principal.user_id;
+
     trace!(
         "set_user_details\nuser_id: {}\npayload: {:?}",
         user_id, payload
@@ -113,6 +126,8 @@ pub async fn set_own_details(
         return Err(AppError::InvalidNik);
     }
 
+    let mut tx = audit::begin(&state.db_pool).await?;
+
     query!(
         "INSERT INTO user_details (user_id, nik, name, dob, gender) VALUES \
          ($1, $2, $3, $4, $5)",
@@ -122,13 +137,28 @@ pub async fn set_own_details(
         payload.dob,
         payload.gender as i8,
     )
-    .execute(&state.db_pool)
+    .execute(&mut *tx)
     .await
     .map_err(|e| {
         error!("Error while setting user_detail for {}: {:?}", user_id, e);
         AppError::InternalError
     })?;
 
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "user_detail.set",
+        json!({
+            "nik": payload.nik,
+            "name": payload.name,
+            "dob": payload.dob,
+            "gender": payload.gender,
+        }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
     Ok((
         StatusCode::CREATED,
         Json(json!({"message": "Successfully set user detail"})),
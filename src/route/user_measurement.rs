@@ -6,34 +6,38 @@ use axum::{
 use chrono::{DateTime, Utc};
 use serde::Deserialize;
 use serde_json::{Value, json};
+use sqlx::{Pool, Postgres};
 use tracing::error;
 use uuid::Uuid;
 
 use crate::{
-    AppState,
-    auth::{AuthUser, LicensedUser},
+    AppState, audit,
     error::{APIResult, AppError},
+    pagination::{Page, Paginated, fetch_page},
+    policy::{Action, Principal, Resource, Scope},
+    route::access_log::authorize_read,
     schema::UserMeasurement,
 };
 
-pub async fn get_user_measurements(
-    State(state): State<AppState>,
-    auth: AuthUser,
-    doctor: Option<LicensedUser>,
-    Path(user_id): Path<Uuid>,
-) -> APIResult<Json<Vec<UserMeasurement>>> {
-    if user_id != auth.user_id && doctor.is_none() {
-        return Err(AppError::NotTheSameUser);
-    }
-
-    sqlx::query_as!(
+async fn fetch_measurements(
+    user_id: Uuid,
+    page: &Page,
+    db_pool: &Pool<Postgres>,
+) -> APIResult<Paginated<UserMeasurement>> {
+    fetch_page!(
         UserMeasurement,
-        "SELECT * FROM user_measurements WHERE user_id = $1",
+        page,
+        db_pool,
+        "SELECT * FROM user_measurements WHERE user_id = $6",
+        "measured_at",
+        "measurement_id",
         user_id
     )
-    .fetch_all(&state.db_pool)
-    .await
-    .map(Json)
+    .map(|rows| {
+        page.paginate(rows, |measurement| {
+            (measurement.measured_at, measurement.measurement_id)
+        })
+    })
     .map_err(|e| {
         error!(
             "Error while fetching user_measurements for {}: {:?}",
@@ -43,25 +47,34 @@ pub async fn get_user_measurements(
     })
 }
 
-pub async fn get_own_measurements(
+pub async fn get_user_measurements(
     State(state): State<AppState>,
-    AuthUser { user_id, .. }: AuthUser,
-) -> APIResult<Json<Vec<UserMeasurement>>> {
-    sqlx::query_as!(
-        UserMeasurement,
-        "SELECT * FROM user_measurements WHERE user_id = $1",
-        user_id
+    principal: Principal,
+    Path(user_id): Path<Uuid>,
+    page: Page,
+) -> APIResult<Paginated<UserMeasurement>> {
+    authorize_read(
+        &principal,
+        Resource::Measurement,
+        user_id,
+        principal
+            .patient_scope(user_id, Resource::Measurement, &state.db_pool)
+            .await?,
+        &state.db_pool,
     )
-    .fetch_all(&state.db_pool)
-    .await
-    .map(Json)
-    .map_err(|e| {
-        error!(
-            "Error while fetching user_measurements for {}: {:?}",
-            user_id, e
-        );
-        AppError::InternalError
-    })
+    .await?;
+
+    fetch_measurements(user_id, &page, &state.db_pool).await
+}
+
+pub async fn get_own_measurements(
+    State(state): State<AppState>,
+    principal: Principal,
+    page: Page,
+) -> APIResult<Paginated<UserMeasurement>> {
+    principal.require(Action::Read, Resource::Measurement, Scope::Own)?;
+
+    fetch_measurements(principal.user_id, &page, &state.db_pool).await
 }
 
 #[derive(Deserialize)]
@@ -73,22 +86,28 @@ pub struct UserMeasurementPayload {
 
 pub async fn add_own_measurement(
     State(state): State<AppState>,
-    AuthUser { user_id, .. }: AuthUser,
+    principal: Principal,
     Json(UserMeasurementPayload {
         height_in_cm,
         weight_in_kg,
         measured_at,
     }): Json<UserMeasurementPayload>,
 ) -> APIResult<(StatusCode, Json<Value>)> {
-    let _ = sqlx::query!(
+    principal.require(Action::Create, Resource::Measurement, Scope::Own)?;
+    let user_id = principal.user_id;
+
+    let measured_at = measured_at.unwrap_or(Utc::now());
+    let mut tx = audit::begin(&state.db_pool).await?;
+
+    let measurement_id = sqlx::query_scalar!(
         "INSERT INTO user_measurements (user_id, height_in_cm, weight_in_kg, \
-         measured_at) VALUES ($1, $2, $3, $4)",
+         measured_at) VALUES ($1, $2, $3, $4) RETURNING measurement_id",
         user_id,
         height_in_cm,
         weight_in_kg,
-        measured_at.unwrap_or(Utc::now())
+        measured_at
     )
-    .execute(&state.db_pool)
+    .fetch_one(&mut *tx)
     .await
     .map_err(|e| {
         error!(
@@ -98,6 +117,21 @@ pub async fn add_own_measurement(
         AppError::InternalError
     })?;
 
+    audit::record(
+        &mut tx,
+        principal.actor_id(),
+        Some(user_id),
+        "measurement.create",
+        json!({
+            "measurement_id": measurement_id,
+            "height_in_cm": height_in_cm,
+            "weight_in_kg": weight_in_kg,
+            "measured_at": measured_at,
+        }),
+    )
+    .await?;
+    audit::commit(tx).await?;
+
     Ok((
         StatusCode::CREATED,
         Json(json!({"message": "Successfully added user measurement"})),
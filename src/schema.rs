@@ -12,8 +12,9 @@ use uuid::Uuid;
 #[derive(Serialize)]
 pub struct User {
     pub user_id: Uuid,
-    pub email: String,
-    pub password_hash: String,
+    /// Absent for dependents that were not handed over yet
+    pub email: Option<String>,
+    pub password_hash: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -34,6 +35,61 @@ pub struct UserMeasurement {
     pub measured_at: DateTime<Utc>,
 }
 
+#[derive(Serialize)]
+pub struct ObservationType {
+    /// LOINC
+    pub code: String,
+    pub name: String,
+    /// UCUM
+    pub unit: String,
+    /// `vital-signs` or `laboratory`
+    pub category: String,
+    pub min_value: f64,
+    pub max_value: f64,
+}
+
+/// What a child's growth is compared by against WHO's curves
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type,
+)]
+#[sqlx(type_name = "growth_indicator", rename_all = "SCREAMING_SNAKE_CASE")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum GrowthIndicator {
+    /// Length lying down before 2 years
+    HeightForAge,
+    /// Up to 10 years, past that only BMI is compared
+    WeightForAge,
+    BmiForAge,
+}
+
+/// LMS parameters of an indicator for boys (`M`) or girls (`F`) of a month
+/// of age
+#[derive(Debug, Clone)]
+pub struct GrowthStandard {
+    pub indicator: GrowthIndicator,
+    pub sex: String,
+    pub age_in_months: i32,
+    pub l: f64,
+    pub m: f64,
+    pub s: f64,
+}
+
+/// A vital sign or lab value, with the name and unit of its type
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Observation {
+    pub observation_id: Uuid,
+    pub user_id: Uuid,
+    pub code: String,
+    pub name: String,
+    pub unit: String,
+    pub category: String,
+    pub value: f64,
+    pub observed_at: DateTime<Utc>,
+    pub recorded_by: Uuid,
+    /// The consultation a doctor took it in
+    pub consultation_id: Option<Uuid>,
+}
+
 #[derive(Serialize)]
 pub struct DoctorProfile {
     pub doctor_id: Uuid,
@@ -54,7 +110,9 @@ pub struct DoctorPracticeLocation {
     pub created_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Serialize, Deserialize, sqlx::Type)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type,
+)]
 #[sqlx(type_name = "allergy_severity", rename_all = "SCREAMING_SNAKE_CASE")]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum AllergySeverity {
@@ -64,12 +122,65 @@ pub enum AllergySeverity {
     AnaphylacticShock,
 }
 
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type,
+)]
+#[sqlx(type_name = "allergy_category", rename_all = "SCREAMING_SNAKE_CASE")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AllergyCategory {
+    Drug,
+    Food,
+    Environmental,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type,
+)]
+#[sqlx(type_name = "allergy_reaction", rename_all = "SCREAMING_SNAKE_CASE")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AllergyReaction {
+    Rash,
+    Hives,
+    Itching,
+    Angioedema,
+    Wheezing,
+    ShortnessOfBreath,
+    Nausea,
+    Vomiting,
+    Diarrhea,
+    Anaphylaxis,
+    Other,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type,
+)]
+#[sqlx(
+    type_name = "allergy_verification",
+    rename_all = "SCREAMING_SNAKE_CASE"
+)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AllergyVerification {
+    SelfReported,
+    ClinicianConfirmed,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Allergy {
     pub allergy_id: Uuid,
     pub user_id: Uuid,
     pub allergen: String,
     pub severity: AllergySeverity,
+    /// Absent for allergies from before they were categorized
+    pub category: Option<AllergyCategory>,
+    /// The ingredient of the medicine catalog a drug allergy is to
+    pub medicine_ingredient_id: Option<Uuid>,
+    pub reactions: Vec<AllergyReaction>,
+    pub onset: Option<NaiveDate>,
+    pub verification: AllergyVerification,
+    /// The doctor who confirmed the allergy
+    pub verified_by: Option<Uuid>,
+    pub verified_at: Option<DateTime<Utc>>,
 }
 
 // TODO map device_id to public_key in an lru cache
@@ -80,6 +191,13 @@ pub struct DeviceKey {
     pub user_id: Uuid,
     pub public_key_pem: String,
     pub revoked_at: Option<DateTime<Utc>>,
+    pub device_name: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    /// Incremented on every key rotation, starts at 1
+    pub generation: i32,
+    /// When the current key of the device was put in place
+    pub key_created_at: DateTime<Utc>,
 }
 
 #[derive(Serialize)]
@@ -90,6 +208,7 @@ pub struct Medicine {
     pub composition_notes: String,
 }
 
+#[allow(dead_code)]
 pub struct MedicineIngredient {
     medicine_ingredient_id: Uuid,
     medicine_id: Uuid,
@@ -103,6 +222,7 @@ pub struct Purchase {
     pub user_id: Uuid,
     pub medicine_id: Uuid,
     pub quantity: i32,
+    pub purchased_at: DateTime<Utc>,
 }
 
 #[derive(Serialize)]
@@ -114,17 +234,39 @@ pub struct Consultation {
     pub symptoms: String,
     pub created_at: DateTime<Utc>,
     pub reminded: bool,
+    /// The consultation this one corrects
+    pub amends: Option<Uuid>,
 }
 
-#[derive(Serialize)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type,
+)]
+#[sqlx(type_name = "diagnosis_severity", rename_all = "SCREAMING_SNAKE_CASE")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum DiagnosisSeverity {
+    Mild,
+    Moderate,
+    Severe,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Diagnosis {
     pub diagnosis_id: Uuid,
     pub consultation_id: Uuid,
     pub diagnosis: String,
-    pub severity: String,
+    /// Absent for diagnoses from before severities were typed whose free text
+    /// couldn't be read as one
+    pub severity: Option<DiagnosisSeverity>,
+    pub icd10_code: Option<String>,
 }
 
 #[derive(Serialize)]
+pub struct Icd10Code {
+    pub code: String,
+    pub title: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Prescription {
     pub prescription_id: Uuid,
     pub consultation_id: Uuid,
@@ -136,9 +278,50 @@ pub struct Prescription {
     pub purchased_at: Option<DateTime<Utc>>,
 }
 
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type,
+)]
+#[sqlx(type_name = "condition_status", rename_all = "SCREAMING_SNAKE_CASE")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ConditionStatus {
+    Active,
+    Resolved,
+    InRemission,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type,
+)]
+#[sqlx(type_name = "condition_source", rename_all = "SCREAMING_SNAKE_CASE")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ConditionSource {
+    SelfReported,
+    /// Diagnosed in `consultation_id`
+    Diagnosed,
+}
+
 #[derive(Serialize)]
 pub struct MedicalCondition {
     pub condition_id: Uuid,
     pub user_id: Uuid,
     pub condition: String,
+    pub onset: Option<NaiveDate>,
+    pub status: ConditionStatus,
+    pub icd10_code: Option<String>,
+    pub source: ConditionSource,
+    pub consultation_id: Option<Uuid>,
+    pub confirmed_by: Option<Uuid>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct ConditionTransition {
+    pub transition_id: Uuid,
+    pub condition_id: Uuid,
+    /// Absent for the status the condition was recorded with
+    pub from_status: Option<ConditionStatus>,
+    pub to_status: ConditionStatus,
+    pub changed_by: Uuid,
+    pub changed_at: DateTime<Utc>,
 }
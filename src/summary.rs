@@ -0,0 +1,588 @@
+//! Printable summary of a patient's record.
+//!
+//! Patients bring it along to clinics that don't use Medigram: their details,
+//! allergies, conditions, the latest measurements, consultations with their
+//! diagnoses and the prescriptions they are taking, on plain A4 pages in the
+//! standard Helvetica so no font has to be embedded.
+//!
+//! A QR code on the first page links to `GET /summaries/{summary_id}`, where
+//! the server vouches for the SHA-256 of the file it handed out through a
+//! [`SummaryAttestation`] signed with the [`CardIssuer`] key. A digital copy is
+//! checked against the hash, a paper one against the name and date shown.
+//!
+//! [`CardIssuer`]: crate::emergency_card::CardIssuer
+
+use chrono::{DateTime, Utc};
+use pdf_writer::{Content, Finish, Name, Pdf, Rect, Ref, Str, TextStr};
+use qrcode::{Color, QrCode};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{
+    emergency_card::escape_html,
+    error::{APIResult, AppError},
+    schema::{
+        Allergy, AllergyReaction, AllergySeverity, ConditionSource,
+        ConditionStatus, Diagnosis, DiagnosisSeverity, MedicalCondition,
+        Prescription, UserDetail, UserMeasurement,
+    },
+};
+
+/// How many of the latest measurements are shown
+pub const RECENT_MEASUREMENTS: i64 = 5;
+
+// A4 in points
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 50.0;
+const QR_SIZE: f32 = 100.0;
+
+const REGULAR: Name = Name(b"F1");
+const BOLD: Name = Name(b"F2");
+
+pub struct SummaryConsultation {
+    pub created_at: DateTime<Utc>,
+    pub symptoms: String,
+    pub diagnoses: Vec<Diagnosis>,
+}
+
+/// Everything the summary shows
+pub struct PatientSummary {
+    pub detail: Option<UserDetail>,
+    pub allergies: Vec<Allergy>,
+    pub conditions: Vec<MedicalCondition>,
+    /// Latest first, at most [`RECENT_MEASUREMENTS`]
+    pub measurements: Vec<UserMeasurement>,
+    /// Latest first
+    pub consultations: Vec<SummaryConsultation>,
+    /// Those of consultations the patient keeps a reminder for
+    pub prescriptions: Vec<Prescription>,
+}
+
+/// Claims the server signs for every summary it hands out
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SummaryAttestation {
+    /// The patient
+    pub sub: Uuid,
+    /// The summary
+    pub jti: Uuid,
+    pub iat: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Hex SHA-256 of the PDF
+    pub sha256: String,
+}
+
+/// Text as WinAnsiEncoding, what the standard fonts can't show becomes `?`
+fn win_ansi(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match c {
+            ' '..='~' => c as u8,
+            '\u{a0}'..='\u{ff}' => c as u32 as u8,
+            '\t' | '\n' | '\r' => b' ',
+            '\u{20ac}' => 0x80,
+            '\u{2018}' => 0x91,
+            '\u{2019}' => 0x92,
+            '\u{201c}' => 0x93,
+            '\u{201d}' => 0x94,
+            '\u{2022}' => 0x95,
+            '\u{2013}' => 0x96,
+            '\u{2014}' => 0x97,
+            _ => b'?',
+        })
+        .collect()
+}
+
+/// Breaks `text` into lines of at most `max_chars`, between words where
+/// possible
+fn wrap(text: &str, max_chars: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+
+    for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+
+        if !line.is_empty() && line.chars().count() + 1 + word.len() > max_chars
+        {
+            lines.push(std::mem::take(&mut line));
+        }
+        // longer than a line on its own, e.g. a URL
+        while word.len() > max_chars {
+            let rest = word.split_off(max_chars);
+            lines.push(word.into_iter().collect());
+            word = rest;
+        }
+
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.extend(word);
+    }
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Lays out lines of text top to bottom, starting a new page when full
+struct Pages {
+    finished: Vec<Content>,
+    current: Content,
+    y: f32,
+}
+
+impl Pages {
+    fn new() -> Self {
+        Pages {
+            finished: Vec::new(),
+            current: Content::new(),
+            y: PAGE_HEIGHT - MARGIN,
+        }
+    }
+
+    /// Room for text on the current line, less beside the QR code
+    fn width(&self) -> f32 {
+        let beside_qr = self.finished.is_empty()
+            && self.y > PAGE_HEIGHT - MARGIN - QR_SIZE - 10.0;
+
+        match beside_qr {
+            true => PAGE_WIDTH - 2.0 * MARGIN - QR_SIZE - 10.0,
+            false => PAGE_WIDTH - 2.0 * MARGIN,
+        }
+    }
+
+    fn text(&mut self, font: Name, size: f32, indent: f32, text: &str) {
+        let leading = size * 1.4;
+        // Helvetica averages a little over half an em per character
+        let max_chars = ((self.width() - indent) / (size * 0.55)) as usize;
+
+        for line in wrap(text, max_chars) {
+            if self.y - leading < MARGIN {
+                let full = std::mem::replace(&mut self.current, Content::new());
+                self.finished.push(full);
+                self.y = PAGE_HEIGHT - MARGIN;
+            }
+            self.y -= leading;
+
+            self.current
+                .begin_text()
+                .set_font(font, size)
+                .next_line(MARGIN + indent, self.y)
+                .show(Str(&win_ansi(&line)))
+                .end_text();
+        }
+    }
+
+    fn heading(&mut self, text: &str) {
+        self.y -= 8.0;
+        self.text(BOLD, 12.0, 0.0, text);
+    }
+
+    fn item(&mut self, text: &str) {
+        self.text(REGULAR, 10.0, 10.0, &format!("\u{2022} {text}"));
+    }
+
+    fn items(&mut self, items: impl IntoIterator<Item = String>) {
+        let mut empty = true;
+        for item in items {
+            empty = false;
+            self.item(&item);
+        }
+        if empty {
+            self.item("None recorded");
+        }
+    }
+
+    fn finish(mut self) -> Vec<Content> {
+        self.finished.push(self.current);
+        self.finished
+    }
+}
+
+/// Draws `code` with its top right corner at the top right margin
+fn draw_qr(content: &mut Content, code: &QrCode) {
+    // four modules of quiet zone on every side
+    let width = code.width();
+    let module = QR_SIZE / (width + 8) as f32;
+    let left = PAGE_WIDTH - MARGIN - QR_SIZE + 4.0 * module;
+    let top = PAGE_HEIGHT - MARGIN - 4.0 * module;
+
+    content.set_fill_gray(0.0);
+    for (index, color) in code.to_colors().into_iter().enumerate() {
+        if color == Color::Dark {
+            let (x, y) = (index % width, index / width);
+            content.rect(
+                left + x as f32 * module,
+                top - (y + 1) as f32 * module,
+                module,
+                module,
+            );
+        }
+    }
+    content.fill_nonzero();
+}
+
+/// e.g. `Diagnosis: Common cold [J00] (mild)`
+fn diagnosis_line(diagnosis: &Diagnosis) -> String {
+    let mut line = format!("Diagnosis: {}", diagnosis.diagnosis);
+    if let Some(code) = &diagnosis.icd10_code {
+        line.push_str(&format!(" [{code}]"));
+    }
+    if let Some(severity) = diagnosis.severity {
+        line.push_str(match severity {
+            DiagnosisSeverity::Mild => " (mild)",
+            DiagnosisSeverity::Moderate => " (moderate)",
+            DiagnosisSeverity::Severe => " (severe)",
+        });
+    }
+    line
+}
+
+/// e.g. `penicillin (severe): hives, angioedema`
+fn allergy_line(allergy: &Allergy) -> String {
+    let mut line =
+        format!("{} ({})", allergy.allergen, severity(allergy.severity));
+    if !allergy.reactions.is_empty() {
+        let reactions: Vec<&str> =
+            allergy.reactions.iter().copied().map(reaction).collect();
+        line.push_str(&format!(": {}", reactions.join(", ")));
+    }
+    line
+}
+
+fn reaction(reaction: AllergyReaction) -> &'static str {
+    match reaction {
+        AllergyReaction::Rash => "rash",
+        AllergyReaction::Hives => "hives",
+        AllergyReaction::Itching => "itching",
+        AllergyReaction::Angioedema => "angioedema",
+        AllergyReaction::Wheezing => "wheezing",
+        AllergyReaction::ShortnessOfBreath => "shortness of breath",
+        AllergyReaction::Nausea => "nausea",
+        AllergyReaction::Vomiting => "vomiting",
+        AllergyReaction::Diarrhea => "diarrhea",
+        AllergyReaction::Anaphylaxis => "anaphylaxis",
+        AllergyReaction::Other => "other",
+    }
+}
+
+/// e.g. `Asthma [J45] (active since 2010-05-01, self-reported)`
+fn condition_line(condition: &MedicalCondition) -> String {
+    let mut line = condition.condition.clone();
+    if let Some(code) = &condition.icd10_code {
+        line.push_str(&format!(" [{code}]"));
+    }
+    line.push_str(match condition.status {
+        ConditionStatus::Active => " (active",
+        ConditionStatus::Resolved => " (resolved",
+        ConditionStatus::InRemission => " (in remission",
+    });
+    if let Some(onset) = condition.onset {
+        line.push_str(&format!(" since {onset}"));
+    }
+    if condition.source == ConditionSource::SelfReported
+        && condition.confirmed_at.is_none()
+    {
+        line.push_str(", self-reported");
+    }
+    line.push(')');
+    line
+}
+
+fn severity(severity: AllergySeverity) -> &'static str {
+    match severity {
+        AllergySeverity::Mild => "mild",
+        AllergySeverity::Moderate => "moderate",
+        AllergySeverity::Severe => "severe",
+        AllergySeverity::AnaphylacticShock => "anaphylactic shock",
+    }
+}
+
+impl PatientSummary {
+    /// The summary as a PDF, with a QR code of `verification_url`
+    pub fn render_pdf(
+        &self,
+        summary_id: Uuid,
+        issued_at: DateTime<Utc>,
+        verification_url: &str,
+    ) -> APIResult<Vec<u8>> {
+        let code = QrCode::new(verification_url.as_bytes()).map_err(|e| {
+            error!("Error while encoding summary QR: {:?}", e);
+            AppError::InternalError
+        })?;
+
+        let mut pages = Pages::new();
+
+        pages.text(BOLD, 18.0, 0.0, "Patient summary");
+        match &self.detail {
+            Some(detail) => {
+                pages.text(BOLD, 12.0, 0.0, &detail.name);
+                pages.text(REGULAR, 10.0, 0.0, &format!("NIK {}", detail.nik));
+                let gender = match detail.gender {
+                    'M' => "male",
+                    'F' => "female",
+                    _ => "unknown",
+                };
+                pages.text(
+                    REGULAR,
+                    10.0,
+                    0.0,
+                    &format!("Born {}, {gender}", detail.dob),
+                );
+            }
+            None => pages.text(REGULAR, 10.0, 0.0, "No personal details"),
+        }
+        pages.text(
+            REGULAR,
+            8.0,
+            0.0,
+            &format!(
+                "Issued by Medigram on {} UTC as document {summary_id}. \
+                 Verify at {verification_url}",
+                issued_at.format("%Y-%m-%d %H:%M")
+            ),
+        );
+
+        pages.heading("Allergies");
+        pages.items(self.allergies.iter().map(allergy_line));
+
+        pages.heading("Medical conditions");
+        pages.items(self.conditions.iter().map(condition_line));
+
+        pages.heading("Recent measurements");
+        pages.items(self.measurements.iter().map(|measurement| {
+            format!(
+                "{}: {:.1} cm, {:.1} kg",
+                measurement.measured_at.format("%Y-%m-%d"),
+                measurement.height_in_cm,
+                measurement.weight_in_kg
+            )
+        }));
+
+        pages.heading("Active prescriptions");
+        pages.items(self.prescriptions.iter().map(|prescription| {
+            format!(
+                "{} {} mg, {} x {} a day: {}",
+                prescription.drug_name,
+                prescription.doses_in_mg,
+                prescription.quantity_per_dose,
+                prescription.regimen_per_day,
+                prescription.instruction
+            )
+        }));
+
+        pages.heading("Consultations");
+        if self.consultations.is_empty() {
+            pages.item("None recorded");
+        }
+        for consultation in &self.consultations {
+            pages.item(&format!(
+                "{}: {}",
+                consultation.created_at.format("%Y-%m-%d"),
+                consultation.symptoms
+            ));
+            for diagnosis in &consultation.diagnoses {
+                pages.text(REGULAR, 10.0, 24.0, &diagnosis_line(diagnosis));
+            }
+        }
+
+        let mut contents = pages.finish();
+        if let Some(first) = contents.first_mut() {
+            draw_qr(first, &code);
+        }
+
+        let mut next_id = Ref::new(1);
+        let catalog_id = next_id.bump();
+        let page_tree_id = next_id.bump();
+        let regular_id = next_id.bump();
+        let bold_id = next_id.bump();
+        let info_id = next_id.bump();
+
+        let mut pdf = Pdf::new();
+        pdf.catalog(catalog_id).pages(page_tree_id);
+        pdf.document_info(info_id)
+            .title(TextStr("Patient summary"))
+            .producer(TextStr("Medigram"));
+        pdf.type1_font(regular_id)
+            .base_font(Name(b"Helvetica"))
+            .encoding_predefined(Name(b"WinAnsiEncoding"));
+        pdf.type1_font(bold_id)
+            .base_font(Name(b"Helvetica-Bold"))
+            .encoding_predefined(Name(b"WinAnsiEncoding"));
+
+        let mut page_ids = Vec::new();
+        for content in contents {
+            let page_id = next_id.bump();
+            let content_id = next_id.bump();
+            page_ids.push(page_id);
+
+            let mut page = pdf.page(page_id);
+            page.media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT))
+                .parent(page_tree_id)
+                .contents(content_id);
+            page.resources()
+                .fonts()
+                .pair(REGULAR, regular_id)
+                .pair(BOLD, bold_id);
+            page.finish();
+
+            pdf.stream(content_id, &content.finish());
+        }
+        pdf.pages(page_tree_id)
+            .count(page_ids.len() as i32)
+            .kids(page_ids);
+
+        Ok(pdf.finish())
+    }
+}
+
+/// The page the QR code links to, `None` when the attestation doesn't verify
+pub fn render_verification(attestation: Option<&SummaryAttestation>) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><meta \
+             name=\"viewport\" content=\"width=device-width\"><title>Patient \
+             summary verification</title></head><body><h1>Patient summary \
+             verification</h1>",
+    );
+
+    let Some(attestation) = attestation else {
+        html.push_str(
+            "<p><b>This summary can no longer be verified.</b></p>\
+                 </body></html>",
+        );
+        return html;
+    };
+
+    html.push_str("<p><b>Issued by Medigram.</b></p>");
+    if let Some(name) = &attestation.name {
+        html.push_str(&format!("<p>Patient: {}</p>", escape_html(name)));
+    }
+    let issued_at = DateTime::from_timestamp(attestation.iat, 0)
+        .map(|issued_at| issued_at.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_default();
+    html.push_str(&format!(
+        "<p>Document {} issued on {issued_at}</p><p>SHA-256 of the PDF: \
+             <code>{}</code></p>",
+        attestation.jti,
+        escape_html(&attestation.sha256)
+    ));
+
+    html.push_str("</body></html>");
+    html
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDate;
+    use data_encoding::HEXUPPER;
+
+    use super::*;
+    use crate::schema::AllergyVerification;
+
+    fn summary() -> PatientSummary {
+        PatientSummary {
+            detail: None,
+            allergies: vec![Allergy {
+                allergy_id: Uuid::nil(),
+                user_id: Uuid::nil(),
+                allergen: "penicillin".to_string(),
+                severity: AllergySeverity::AnaphylacticShock,
+                category: None,
+                medicine_ingredient_id: None,
+                reactions: vec![],
+                onset: None,
+                verification: AllergyVerification::SelfReported,
+                verified_by: None,
+                verified_at: None,
+            }],
+            conditions: vec![],
+            measurements: vec![],
+            consultations: vec![],
+            prescriptions: vec![],
+        }
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap("a bb ccc", 4), ["a bb", "ccc"]);
+        assert_eq!(wrap("abcdefghij", 4), ["abcd", "efgh", "ij"]);
+        assert_eq!(wrap("", 4), [""]);
+    }
+
+    #[test]
+    fn test_win_ansi() {
+        assert_eq!(win_ansi("Café – ok"), b"Caf\xe9 \x96 ok");
+        assert_eq!(win_ansi("日本"), b"??");
+    }
+
+    #[test]
+    fn test_allergy_line() {
+        let mut allergy = summary().allergies.remove(0);
+        assert_eq!(allergy_line(&allergy), "penicillin (anaphylactic shock)");
+
+        allergy.reactions =
+            vec![AllergyReaction::Hives, AllergyReaction::ShortnessOfBreath];
+        assert_eq!(
+            allergy_line(&allergy),
+            "penicillin (anaphylactic shock): hives, shortness of breath"
+        );
+    }
+
+    #[test]
+    fn test_condition_line() {
+        let mut condition = MedicalCondition {
+            condition_id: Uuid::nil(),
+            user_id: Uuid::nil(),
+            condition: "Asthma".to_string(),
+            onset: NaiveDate::from_ymd_opt(2010, 5, 1),
+            status: ConditionStatus::Active,
+            icd10_code: Some("J45".to_string()),
+            source: ConditionSource::SelfReported,
+            consultation_id: None,
+            confirmed_by: None,
+            confirmed_at: None,
+            created_at: Utc::now(),
+        };
+        assert_eq!(
+            condition_line(&condition),
+            "Asthma [J45] (active since 2010-05-01, self-reported)"
+        );
+
+        condition.status = ConditionStatus::InRemission;
+        condition.onset = None;
+        condition.confirmed_by = Some(Uuid::nil());
+        condition.confirmed_at = Some(Utc::now());
+        assert_eq!(condition_line(&condition), "Asthma [J45] (in remission)");
+    }
+
+    #[test]
+    fn test_render_pdf() {
+        let pdf = summary()
+            .render_pdf(Uuid::nil(), Utc::now(), "http://localhost/summaries")
+            .ok()
+            .unwrap();
+        let text = String::from_utf8_lossy(&pdf);
+
+        assert!(pdf.starts_with(b"%PDF-"));
+        // strings beyond ASCII are written in hex
+        let bullet = HEXUPPER.encode(b"\x95 penicillin (anaphylactic shock)");
+        assert!(text.contains(&format!("<{bullet}> Tj")));
+        assert!(text.contains("/Count 1"));
+
+        // a long record goes on over several pages
+        let mut long = summary();
+        long.allergies = (0..100)
+            .map(|index| Allergy {
+                allergen: format!("allergen {index}"),
+                ..summary().allergies.remove(0)
+            })
+            .collect();
+        let pdf = long
+            .render_pdf(Uuid::nil(), Utc::now(), "http://localhost/summaries")
+            .ok()
+            .unwrap();
+        assert!(String::from_utf8_lossy(&pdf).contains("/Count 3"));
+    }
+}
@@ -0,0 +1,125 @@
+mod common;
+
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use http_body_util::BodyExt;
+use serde_json::{Value, json};
+use sqlx::Pool;
+use sqlx::postgres::Postgres;
+use tower::{Service, ServiceExt};
+use uuid::Uuid;
+
+use common::*;
+
+static ALICE_ID: &str = "d3969164-86ea-442d-a589-79de89116f9c";
+static BOB_ID: &str = "41490144-e4e1-4d1f-9eb7-f90af81c12ce";
+static ALICE_DOCTOR_ID: &str = "a5ca9dee-89b4-4228-aff5-506b995f3b42";
+
+// .route("/me/access-log", get(get_own_access_log))
+// .route(
+//     "/doctors/{doctor_id}/users/{user_id}/consultations",
+//     get(get_doctor_consultations_with_user),
+// )
+
+async fn get(app: &mut Router, session_id: &str, uri: &str) -> Value {
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}{uri}"))
+        .method("GET")
+        .header("Authorization", format!("Bearer {session_id}"))
+        .body(Body::empty())
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[sqlx::test(fixtures("users", "pharmacist", "dispensing"))]
+async fn read_by_pharmacist_is_logged(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (alice_session, _user_id) = login_as_alice(&mut app).await;
+    let (bob_session, _user_id) = login_as_bob(&mut app).await;
+
+    get(
+        &mut app,
+        &alice_session,
+        &format!("/users/{BOB_ID}/allergies"),
+    )
+    .await;
+
+    let log = get(&mut app, &bob_session, "/me/access-log").await;
+    assert_eq!(log.as_array().unwrap().len(), 1);
+    assert_eq!(log[0]["actor_id"], json!(ALICE_ID));
+    assert_eq!(log[0]["role"], json!("pharmacist"));
+    assert_eq!(log[0]["scope"], json!("dispensing"));
+    assert_eq!(log[0]["resource"], json!("allergy"));
+}
+
+#[sqlx::test(fixtures("users", "allergies"))]
+async fn own_reads_are_not_logged(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _user_id) = login_as_alice(&mut app).await;
+
+    get(
+        &mut app,
+        &session_id,
+        &format!("/users/{ALICE_ID}/allergies"),
+    )
+    .await;
+
+    let log = get(&mut app, &session_id, "/me/access-log").await;
+    assert_eq!(log, json!([]));
+}
+
+#[sqlx::test(fixtures("users", "doctor_info", "prescriptions"))]
+async fn empty_reads_are_not_logged(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (alice_session, _user_id) = login_as_alice(&mut app).await;
+    let (bob_session, _user_id) = login_as_bob(&mut app).await;
+
+    // bob never saw this doctor
+    let consultations = get(
+        &mut app,
+        &alice_session,
+        &format!("/doctors/{}/users/{BOB_ID}/consultations", Uuid::new_v4()),
+    )
+    .await;
+    assert_eq!(consultations, json!([]));
+    let log = get(&mut app, &bob_session, "/me/access-log").await;
+    assert_eq!(log, json!([]));
+
+    let consultations = get(
+        &mut app,
+        &alice_session,
+        &format!("/doctors/{ALICE_DOCTOR_ID}/users/{BOB_ID}/consultations"),
+    )
+    .await;
+    assert_eq!(consultations.as_array().unwrap().len(), 2);
+    let log = get(&mut app, &bob_session, "/me/access-log").await;
+    assert_eq!(log.as_array().unwrap().len(), 1);
+    assert_eq!(log[0]["scope"], json!("attending"));
+}
+
+#[sqlx::test(fixtures("users", "doctor_info"))]
+async fn doctor_of_the_path_is_not_attending(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (alice_session, _user_id) = login_as_alice(&mut app).await;
+
+    let (status, _) = send(
+        &mut app,
+        Some(&alice_session),
+        "GET",
+        &format!("/doctors/{ALICE_DOCTOR_ID}/users/{BOB_ID}/consultations"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
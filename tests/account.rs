@@ -0,0 +1,319 @@
+mod common;
+
+use std::io::{Cursor, Read};
+
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use http_body_util::BodyExt;
+use medigram::audit::verify_chain;
+use medigram::data_export::fail_stale_exports;
+use medigram::route::account::purge_due_accounts;
+use serde_json::{Value, json};
+use sqlx::Pool;
+use sqlx::postgres::Postgres;
+use tower::{Service, ServiceExt};
+use uuid::Uuid;
+use zip::ZipArchive;
+
+use common::*;
+
+// .route("/me/data-exports", post(request_own_data_export))
+// .route("/me/data-exports", get(get_own_data_exports))
+// .route("/me/data-exports/{export_id}/archive", get(download_own_data_export))
+// .route("/me/account/deletion", post(request_own_account_deletion))
+// .route("/me/account/deletion", get(get_own_account_deletion))
+// .route("/me/account/deletion", delete(cancel_own_account_deletion))
+
+const BOB_ID: &str = "41490144-e4e1-4d1f-9eb7-f90af81c12ce";
+
+async fn login_as_bob(app: &mut Router) -> Option<String> {
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/login"))
+        .method("POST")
+        .header("Content-Type", "application/json")
+        .body(Body::from(
+            json!({ "email": "bob@example.com", "password": "test" })
+                .to_string(),
+        ))
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    if response.status() != StatusCode::OK {
+        return None;
+    }
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    Some(extract_session_id(&body).await)
+}
+
+#[sqlx::test(fixtures("users", "details", "allergies", "measurements"))]
+async fn data_export_has_every_table(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_alice(&mut app).await;
+
+    let (status, body) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        "/me/data-exports",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let export_id = body["export_id"].as_str().unwrap().to_string();
+
+    // built in the background
+    let mut ready = false;
+    for _ in 0..50 {
+        let (status, exports) =
+            send(&mut app, Some(&session_id), "GET", "/me/data-exports", None)
+                .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(exports[0]["export_id"], export_id);
+        if exports[0]["status"] == "ready" {
+            ready = true;
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert!(ready, "the export was not built");
+
+    let request = build_request(
+        Some(&session_id),
+        "GET",
+        &format!("/me/data-exports/{export_id}/archive"),
+        None,
+    );
+    let (status, _, archive) = send_request(&mut app, request).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let mut zip = ZipArchive::new(Cursor::new(archive.to_vec())).unwrap();
+    for name in ["README.txt", "user_details.csv", "audit_events.json"] {
+        assert!(zip.by_name(name).is_ok(), "{name} missing");
+    }
+
+    let mut allergies = String::new();
+    zip.by_name("allergies.json")
+        .unwrap()
+        .read_to_string(&mut allergies)
+        .unwrap();
+    let allergies: Value = serde_json::from_str(&allergies).unwrap();
+    assert_eq!(allergies[0]["allergen"], "pollen");
+
+    let mut account = String::new();
+    zip.by_name("account.json")
+        .unwrap()
+        .read_to_string(&mut account)
+        .unwrap();
+    assert!(!account.contains("password_hash"));
+}
+
+#[sqlx::test(fixtures("users", "doctor_info", "prescriptions", "diagnoses"))]
+async fn account_is_purged_after_grace_period(db_pool: Pool<Postgres>) {
+    let state = get_state(db_pool.clone());
+    let mut app = medigram::app(state.clone());
+    let bob_id = Uuid::parse_str(BOB_ID).unwrap();
+
+    // listed by his doctor during the first consultation, and by himself
+    sqlx::query(
+        "INSERT INTO medical_conditions (user_id, condition, source, \
+         consultation_id) VALUES ($1, 'Asthma', 'DIAGNOSED', \
+         '5b1c7a0e-2f4d-4a8e-9c3b-7d6e5f4a3b2c'), ($1, 'Hay fever', \
+         'SELF_REPORTED', NULL)",
+    )
+    .bind(bob_id)
+    .execute(&db_pool)
+    .await
+    .unwrap();
+
+    let session_id = login_as_bob(&mut app).await.unwrap();
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "PUT",
+        "/me/details",
+        Some(json!({
+            "nik": 1000000000000001i64,
+            "name": "bob",
+            "dob": "1990-01-15",
+            "gender": "M",
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        "/me/account/deletion",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    // signed out everywhere, but can still come back and cancel
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        "/me/account/deletion",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let session_id = login_as_bob(&mut app).await.unwrap();
+    let (status, deletion) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        "/me/account/deletion",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(deletion["requested_by"], BOB_ID);
+
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "DELETE",
+        "/me/account/deletion",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(purge_due_accounts(&state).await.ok().unwrap(), 0);
+
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        "/me/account/deletion",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    // nothing happens until the grace period is over
+    assert_eq!(purge_due_accounts(&state).await.ok().unwrap(), 0);
+    sqlx::query(
+        "UPDATE account_deletions SET purge_after = NOW() WHERE user_id = $1",
+    )
+    .bind(bob_id)
+    .execute(&db_pool)
+    .await
+    .unwrap();
+    assert_eq!(purge_due_accounts(&state).await.ok().unwrap(), 1);
+
+    assert!(login_as_bob(&mut app).await.is_none());
+
+    let email: Option<String> =
+        sqlx::query_scalar("SELECT email FROM users WHERE user_id = $1")
+            .bind(bob_id)
+            .fetch_one(&db_pool)
+            .await
+            .unwrap();
+    assert_eq!(email, None);
+
+    // written by his doctor, so kept
+    let consultations: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM consultations WHERE user_id = $1",
+    )
+    .bind(bob_id)
+    .fetch_one(&db_pool)
+    .await
+    .unwrap();
+    assert_eq!(consultations, 2);
+    let conditions: Vec<String> = sqlx::query_scalar(
+        "SELECT condition FROM medical_conditions WHERE user_id = $1",
+    )
+    .bind(bob_id)
+    .fetch_all(&db_pool)
+    .await
+    .unwrap();
+    assert_eq!(conditions, ["Asthma"]);
+
+    // the audit trail keeps what happened, but not his name
+    let payloads: Vec<Value> = sqlx::query_scalar(
+        "SELECT payload FROM audit_events WHERE subject_id = $1 AND action \
+         <> 'account.purge'",
+    )
+    .bind(bob_id)
+    .fetch_all(&db_pool)
+    .await
+    .unwrap();
+    assert!(!payloads.is_empty());
+    assert!(payloads.iter().all(Value::is_null));
+    assert!(verify_chain(&db_pool).await.ok().unwrap().valid);
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn stale_export_is_marked_failed(db_pool: Pool<Postgres>) {
+    // one lost to a restart two hours ago, then one still being built
+    for (requested_at, failed) in
+        [("NOW() - INTERVAL '2 hours'", 1), ("NOW()", 0)]
+    {
+        sqlx::query(&format!(
+            "INSERT INTO data_exports (user_id, requested_by, requested_at) \
+             VALUES ($1, $1, {requested_at})"
+        ))
+        .bind(Uuid::parse_str(BOB_ID).unwrap())
+        .execute(&db_pool)
+        .await
+        .unwrap();
+
+        assert_eq!(fail_stale_exports(&db_pool).await.unwrap(), failed);
+    }
+
+    let mut app = get_app(db_pool);
+    let session_id = login_as_bob(&mut app).await.unwrap();
+    let (_, exports) =
+        send(&mut app, Some(&session_id), "GET", "/me/data-exports", None)
+            .await;
+    assert_eq!(exports[0]["status"], "pending");
+    assert_eq!(exports[1]["status"], "failed");
+
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        "/me/data-exports",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+}
+
+#[sqlx::test(fixtures("users", "admin_alice"))]
+async fn admin_cannot_delete_account(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_alice(&mut app).await;
+
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        "/me/account/deletion",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        "/me/account/deletion",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
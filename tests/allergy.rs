@@ -1,5 +1,6 @@
 mod common;
 
+use axum::Router;
 use axum::body::Body;
 use axum::http::{Request, StatusCode};
 use http_body_util::BodyExt;
@@ -13,10 +14,15 @@ use common::*;
 use medigram::schema::Allergy;
 
 static ALLERGY_ID: &str = "f7769edf-b06b-4749-b6ff-d91efcca8403";
+static ALICE_ID: &str = "d3969164-86ea-442d-a589-79de89116f9c";
+/// amoxicillin in the `medicines` fixture
+static AMOXICILLIN_ID: &str = "3e9a7b1c-5d2f-4a8e-8b6c-1f2e3d4c5b6a";
 
 // .route("/allergy", get(get_allergies))
 // .route("/allergy", post(add_allergy))
 // .route("/allergy", delete(remove_allergy))
+// .route("/me/allergies/{allergy_id}", put(update_own_allergy))
+// .route("/users/{user_id}/allergies/{allergy_id}/confirm", post(confirm_user_allergy))
 
 #[sqlx::test(fixtures("users"))]
 async fn add_allergy(db_pool: Pool<Postgres>) {
@@ -104,6 +110,13 @@ async fn get_allergies_nonempty(db_pool: Pool<Postgres>) {
                 "user_id": "d3969164-86ea-442d-a589-79de89116f9c",
                 "allergen": "pollen",
                 "severity": "MILD",
+                "category": null,
+                "medicine_ingredient_id": null,
+                "reactions": [],
+                "onset": null,
+                "verification": "SELF_REPORTED",
+                "verified_by": null,
+                "verified_at": null,
             }
         ])
     );
@@ -174,3 +187,184 @@ async fn delete_allergy_different_user(db_pool: Pool<Postgres>) {
 
     assert_eq!(response.status(), StatusCode::NOT_FOUND);
 }
+
+#[sqlx::test(fixtures("users", "medicines"))]
+async fn add_drug_allergy_to_ingredient(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _user_id) = login_as_bob(&mut app).await;
+
+    // food can't be an ingredient of a medicine
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        "/me/allergies",
+        Some(json!({
+            "allergen": "amoxicillin",
+            "severity": "SEVERE",
+            "category": "FOOD",
+            "medicine_ingredient_id": AMOXICILLIN_ID,
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        "/me/allergies",
+        Some(json!({
+            "allergen": "amoxicillin",
+            "severity": "SEVERE",
+            "medicine_ingredient_id": AMOXICILLIN_ID,
+            "reactions": ["HIVES", "ANGIOEDEMA", "HIVES"],
+            "onset": "2018-11-02",
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (_, allergies) =
+        send(&mut app, Some(&session_id), "GET", "/me/allergies", None).await;
+    assert_eq!(allergies[0]["category"], "DRUG");
+    assert_eq!(allergies[0]["medicine_ingredient_id"], AMOXICILLIN_ID);
+    assert_eq!(allergies[0]["reactions"], json!(["HIVES", "ANGIOEDEMA"]));
+    assert_eq!(allergies[0]["onset"], "2018-11-02");
+    assert_eq!(allergies[0]["verification"], "SELF_REPORTED");
+}
+
+/// Replaces an allergy of the caller's, returning its verification after
+async fn update_allergy(
+    app: &mut Router,
+    session_id: &str,
+    uri: &str,
+    allergy: &Value,
+) -> Value {
+    let (status, _) =
+        send(app, Some(session_id), "PUT", uri, Some(allergy.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, allergies) =
+        send(app, Some(session_id), "GET", "/me/allergies", None).await;
+    allergies[0]["verification"].clone()
+}
+
+#[sqlx::test(fixtures(
+    "users",
+    "allergies",
+    "doctor_info",
+    "prescriptions",
+    "diagnoses"
+))]
+async fn update_and_confirm_allergy(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (bob_session, _) = login_as_bob(&mut app).await;
+    let (alice_session, _) = login_as_alice(&mut app).await;
+    let uri = format!("/me/allergies/{ALLERGY_ID}");
+
+    // only the owner can edit an allergy
+    let (status, _) = send(
+        &mut app,
+        Some(&bob_session),
+        "PUT",
+        &uri,
+        Some(json!({ "allergen": "pollen", "severity": "MILD" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = send(
+        &mut app,
+        Some(&alice_session),
+        "PUT",
+        &uri,
+        Some(json!({
+            "allergen": "pollen",
+            "severity": "MODERATE",
+            "category": "ENVIRONMENTAL",
+            "reactions": ["WHEEZING"],
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, allergies) =
+        send(&mut app, Some(&alice_session), "GET", "/me/allergies", None)
+            .await;
+    assert_eq!(allergies[0]["severity"], "MODERATE");
+    assert_eq!(allergies[0]["category"], "ENVIRONMENTAL");
+    assert_eq!(allergies[0]["reactions"], json!(["WHEEZING"]));
+
+    let (status, _) = send(
+        &mut app,
+        Some(&bob_session),
+        "POST",
+        "/me/allergies",
+        Some(json!({ "allergen": "latex", "severity": "SEVERE" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (_, allergies) =
+        send(&mut app, Some(&bob_session), "GET", "/me/allergies", None).await;
+    let latex_id = allergies[0]["allergy_id"].as_str().unwrap().to_string();
+    let bob_id = allergies[0]["user_id"].as_str().unwrap().to_string();
+
+    let confirm_uri = format!("/users/{bob_id}/allergies/{latex_id}/confirm");
+    let (status, _) =
+        send(&mut app, Some(&alice_session), "POST", &confirm_uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) =
+        send(&mut app, Some(&alice_session), "POST", &confirm_uri, None).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"], "The allergy is confirmed already");
+
+    // correcting the onset keeps the confirmation
+    let latex_uri = format!("/me/allergies/{latex_id}");
+    let mut latex = json!({
+        "allergen": "latex",
+        "severity": "SEVERE",
+        "onset": "2020-05-01",
+    });
+    let verification =
+        update_allergy(&mut app, &bob_session, &latex_uri, &latex).await;
+    assert_eq!(verification, "CLINICIAN_CONFIRMED");
+
+    // what the doctor confirmed can't change under them
+    latex["severity"] = json!("MODERATE");
+    let verification =
+        update_allergy(&mut app, &bob_session, &latex_uri, &latex).await;
+    assert_eq!(verification, "SELF_REPORTED");
+
+    latex["reactions"] = json!(["HIVES", "RASH"]);
+    update_allergy(&mut app, &bob_session, &latex_uri, &latex).await;
+    send(&mut app, Some(&alice_session), "POST", &confirm_uri, None).await;
+    latex["reactions"] = json!(["RASH", "HIVES"]);
+    let verification =
+        update_allergy(&mut app, &bob_session, &latex_uri, &latex).await;
+    assert_eq!(verification, "CLINICIAN_CONFIRMED");
+    latex["reactions"] = json!(["RASH"]);
+    let verification =
+        update_allergy(&mut app, &bob_session, &latex_uri, &latex).await;
+    assert_eq!(verification, "SELF_REPORTED");
+
+    send(&mut app, Some(&alice_session), "POST", &confirm_uri, None).await;
+    latex["allergen"] = json!("natural rubber");
+    let verification =
+        update_allergy(&mut app, &bob_session, &latex_uri, &latex).await;
+    assert_eq!(verification, "SELF_REPORTED");
+    let (_, allergies) =
+        send(&mut app, Some(&bob_session), "GET", "/me/allergies", None).await;
+    assert_eq!(allergies[0]["verified_by"], Value::Null);
+
+    // confirming an allergy is for doctors attending the patient
+    let (status, _) = send(
+        &mut app,
+        Some(&bob_session),
+        "POST",
+        &format!("/users/{ALICE_ID}/allergies/{ALLERGY_ID}/confirm"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
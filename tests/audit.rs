@@ -0,0 +1,147 @@
+mod common;
+
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use http_body_util::BodyExt;
+use serde_json::{Value, json};
+use sqlx::Pool;
+use sqlx::postgres::Postgres;
+use tower::{Service, ServiceExt};
+
+use common::*;
+use medigram::audit::verify_chain;
+
+// .route("/audit-events", get(get_audit_events))
+// .route("/audit-events/verify", get(verify_audit_events))
+
+async fn add_allergy(app: &mut Router, session_id: &str, allergen: &str) {
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/me/allergies"))
+        .method("POST")
+        .header("Content-Type", "application/json")
+        .header("Authorization", format!("Bearer {session_id}"))
+        .body(Body::from(
+            json!({ "allergen": allergen, "severity": "MILD" }).to_string(),
+        ))
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+async fn get(
+    app: &mut Router,
+    session_id: &str,
+    uri: &str,
+) -> (StatusCode, Value) {
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}{uri}"))
+        .method("GET")
+        .header("Authorization", format!("Bearer {session_id}"))
+        .body(Body::empty())
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+#[sqlx::test(fixtures("users", "auditor"))]
+async fn writes_are_chained(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, user_id) = login_as_alice(&mut app).await;
+
+    add_allergy(&mut app, &session_id, "pollen").await;
+    add_allergy(&mut app, &session_id, "beans").await;
+
+    let (status, events) =
+        get(&mut app, &session_id, "/audit-events?order=asc").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(events.as_array().unwrap().len(), 2);
+    assert_eq!(events[0]["action"], json!("allergy.create"));
+    assert_eq!(events[0]["actor_id"], json!(user_id));
+    assert_eq!(events[1]["prev_hash"], events[0]["hash"]);
+
+    let (status, report) =
+        get(&mut app, &session_id, "/audit-events/verify").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(report, json!({ "valid": true, "events": 2 }));
+}
+
+#[sqlx::test(fixtures("users", "auditor"))]
+async fn rewritten_history_is_detected(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool.clone());
+    let (session_id, _user_id) = login_as_alice(&mut app).await;
+
+    add_allergy(&mut app, &session_id, "pollen").await;
+    add_allergy(&mut app, &session_id, "beans").await;
+    add_allergy(&mut app, &session_id, "dust").await;
+
+    sqlx::query(
+        "UPDATE audit_events SET payload = jsonb_set(payload, '{allergen}', \
+         '\"nothing\"') WHERE seq = 2",
+    )
+    .execute(&db_pool)
+    .await
+    .unwrap();
+
+    let (status, report) =
+        get(&mut app, &session_id, "/audit-events/verify").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        report,
+        json!({ "valid": false, "events": 3, "first_invalid_seq": 2 })
+    );
+
+    // dropping an entry breaks the link of the one after it
+    sqlx::query("DELETE FROM audit_events WHERE seq = 1")
+        .execute(&db_pool)
+        .await
+        .unwrap();
+
+    let report = verify_chain(&db_pool).await.ok().unwrap();
+    assert_eq!(report.first_invalid_seq, Some(2));
+}
+
+#[sqlx::test(fixtures("users", "auditor"))]
+async fn cut_off_end_is_detected(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool.clone());
+    let (session_id, _user_id) = login_as_alice(&mut app).await;
+
+    add_allergy(&mut app, &session_id, "pollen").await;
+    add_allergy(&mut app, &session_id, "beans").await;
+
+    // the rest of the chain still links up, only the head remembers
+    sqlx::query("DELETE FROM audit_events WHERE seq = 2")
+        .execute(&db_pool)
+        .await
+        .unwrap();
+
+    let report = verify_chain(&db_pool).await.ok().unwrap();
+    assert!(!report.valid);
+    assert_eq!(report.events, 1);
+    assert_eq!(report.first_invalid_seq, Some(2));
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn verify_requires_auditor(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _user_id) = login_as_bob(&mut app).await;
+
+    let (status, _) = get(&mut app, &session_id, "/audit-events/verify").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
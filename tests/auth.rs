@@ -1,6 +1,11 @@
+use std::net::SocketAddr;
+
 use axum::body::Body;
+use axum::extract::ConnectInfo;
 use axum::http::{Request, StatusCode};
 use medigram::AppState;
+use medigram::auth::throttle::{BASE_BACKOFF, IP_POLICY, TrustedProxies};
+use medigram::emergency_card::CardIssuer;
 use moka::sync::Cache;
 use serde_json::json;
 use sqlx::Pool;
@@ -20,6 +25,18 @@ async fn register(db_pool: Pool<Postgres>) {
         recognized_session_id: Cache::builder()
             .time_to_live(Duration::from_secs(30 * 24 * 60 * 60))
             .build(),
+        two_factor_challenges: Cache::builder()
+            .time_to_live(Duration::from_secs(5 * 60))
+            .build(),
+        login_throttle: Cache::builder()
+            .time_to_idle(Duration::from_secs(24 * 60 * 60))
+            .build(),
+        jwt: None,
+        card_issuer: CardIssuer::generate("http://127.0.0.1:3001"),
+        trusted_proxies: TrustedProxies::default(),
+        touched_devices: Cache::builder()
+            .time_to_live(Duration::from_secs(5 * 60))
+            .build(),
     };
 
     let mut app = medigram::app(state);
@@ -57,6 +74,18 @@ async fn register_email_used(db_pool: Pool<Postgres>) {
         recognized_session_id: Cache::builder()
             .time_to_live(Duration::from_secs(30 * 24 * 60 * 60))
             .build(),
+        two_factor_challenges: Cache::builder()
+            .time_to_live(Duration::from_secs(5 * 60))
+            .build(),
+        login_throttle: Cache::builder()
+            .time_to_idle(Duration::from_secs(24 * 60 * 60))
+            .build(),
+        jwt: None,
+        card_issuer: CardIssuer::generate("http://127.0.0.1:3001"),
+        trusted_proxies: TrustedProxies::default(),
+        touched_devices: Cache::builder()
+            .time_to_live(Duration::from_secs(5 * 60))
+            .build(),
     };
 
     let mut app = medigram::app(state);
@@ -94,6 +123,18 @@ async fn login(db_pool: Pool<Postgres>) {
         recognized_session_id: Cache::builder()
             .time_to_live(Duration::from_secs(30 * 24 * 60 * 60))
             .build(),
+        two_factor_challenges: Cache::builder()
+            .time_to_live(Duration::from_secs(5 * 60))
+            .build(),
+        login_throttle: Cache::builder()
+            .time_to_idle(Duration::from_secs(24 * 60 * 60))
+            .build(),
+        jwt: None,
+        card_issuer: CardIssuer::generate("http://127.0.0.1:3001"),
+        trusted_proxies: TrustedProxies::default(),
+        touched_devices: Cache::builder()
+            .time_to_live(Duration::from_secs(5 * 60))
+            .build(),
     };
 
     let mut app = medigram::app(state);
@@ -131,6 +172,18 @@ async fn login_not_found(db_pool: Pool<Postgres>) {
         recognized_session_id: Cache::builder()
             .time_to_live(Duration::from_secs(30 * 24 * 60 * 60))
             .build(),
+        two_factor_challenges: Cache::builder()
+            .time_to_live(Duration::from_secs(5 * 60))
+            .build(),
+        login_throttle: Cache::builder()
+            .time_to_idle(Duration::from_secs(24 * 60 * 60))
+            .build(),
+        jwt: None,
+        card_issuer: CardIssuer::generate("http://127.0.0.1:3001"),
+        trusted_proxies: TrustedProxies::default(),
+        touched_devices: Cache::builder()
+            .time_to_live(Duration::from_secs(5 * 60))
+            .build(),
     };
 
     let mut app = medigram::app(state);
@@ -157,3 +210,220 @@ async fn login_not_found(db_pool: Pool<Postgres>) {
 
     assert_eq!(response.status(), StatusCode::NOT_FOUND);
 }
+
+#[sqlx::test(fixtures("users"))]
+async fn login_throttled(db_pool: Pool<Postgres>) {
+    let state = AppState {
+        nonce_cache: Cache::builder()
+            .time_to_live(Duration::from_secs(7 * 24 * 60 * 60))
+            .build(),
+        db_pool: db_pool.clone(),
+        recognized_session_id: Cache::builder()
+            .time_to_live(Duration::from_secs(30 * 24 * 60 * 60))
+            .build(),
+        two_factor_challenges: Cache::builder()
+            .time_to_live(Duration::from_secs(5 * 60))
+            .build(),
+        login_throttle: Cache::builder()
+            .time_to_idle(Duration::from_secs(24 * 60 * 60))
+            .build(),
+        jwt: None,
+        card_issuer: CardIssuer::generate("http://127.0.0.1:3001"),
+        trusted_proxies: TrustedProxies::default(),
+        touched_devices: Cache::builder()
+            .time_to_live(Duration::from_secs(5 * 60))
+            .build(),
+    };
+
+    let mut app = medigram::app(state);
+
+    for (password, status) in [
+        ("wrong", StatusCode::UNAUTHORIZED),
+        ("wrong", StatusCode::UNAUTHORIZED),
+        ("wrong", StatusCode::UNAUTHORIZED),
+        // even the right password is refused while backing off
+        ("test", StatusCode::TOO_MANY_REQUESTS),
+    ] {
+        let request = Request::builder()
+            .uri(format!("http://{API_ROOT_URL}/login"))
+            .method("POST")
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                json!({
+                    "email": "alice@example.com",
+                    "password": password,
+                })
+                .to_string(),
+            ))
+            .unwrap();
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut app)
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), status);
+
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after: u64 = response.headers()["retry-after"]
+                .to_str()
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert!((1..=BASE_BACKOFF.as_secs()).contains(&retry_after));
+        }
+    }
+
+    let audited: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM failed_login_attempts WHERE email = $1",
+    )
+    .bind("alice@example.com")
+    .fetch_one(&db_pool)
+    .await
+    .unwrap();
+
+    // the throttled attempt never got to the password
+    assert_eq!(audited, 3);
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn login_throttled_by_peer_address(db_pool: Pool<Postgres>) {
+    let state = AppState {
+        nonce_cache: Cache::builder()
+            .time_to_live(Duration::from_secs(7 * 24 * 60 * 60))
+            .build(),
+        db_pool,
+        recognized_session_id: Cache::builder()
+            .time_to_live(Duration::from_secs(30 * 24 * 60 * 60))
+            .build(),
+        two_factor_challenges: Cache::builder()
+            .time_to_live(Duration::from_secs(5 * 60))
+            .build(),
+        login_throttle: Cache::builder()
+            .time_to_idle(Duration::from_secs(24 * 60 * 60))
+            .build(),
+        jwt: None,
+        card_issuer: CardIssuer::generate("http://127.0.0.1:3001"),
+        trusted_proxies: TrustedProxies::default(),
+        touched_devices: Cache::builder()
+            .time_to_live(Duration::from_secs(5 * 60))
+            .build(),
+    };
+
+    let mut app = medigram::app(state);
+
+    // a new account every time, so only the address is left to throttle by
+    let attempts = (0..IP_POLICY.free_attempts)
+        .map(|_| ("203.0.113.7:50000", StatusCode::NOT_FOUND))
+        .chain([
+            ("203.0.113.7:50000", StatusCode::TOO_MANY_REQUESTS),
+            ("198.51.100.1:50000", StatusCode::NOT_FOUND),
+        ]);
+    for (attempt, (peer, status)) in attempts.enumerate() {
+        let mut request = Request::builder()
+            .uri(format!("http://{API_ROOT_URL}/login"))
+            .method("POST")
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                json!({
+                    "email": format!("random{attempt}@example.com"),
+                    "password": "test",
+                })
+                .to_string(),
+            ))
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut app)
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), status, "{peer} #{attempt}");
+    }
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn login_throttle_ignores_spoofed_forwarded_for(db_pool: Pool<Postgres>) {
+    let state = AppState {
+        nonce_cache: Cache::builder()
+            .time_to_live(Duration::from_secs(7 * 24 * 60 * 60))
+            .build(),
+        db_pool,
+        recognized_session_id: Cache::builder()
+            .time_to_live(Duration::from_secs(30 * 24 * 60 * 60))
+            .build(),
+        two_factor_challenges: Cache::builder()
+            .time_to_live(Duration::from_secs(5 * 60))
+            .build(),
+        login_throttle: Cache::builder()
+            .time_to_idle(Duration::from_secs(24 * 60 * 60))
+            .build(),
+        jwt: None,
+        card_issuer: CardIssuer::generate("http://127.0.0.1:3001"),
+        trusted_proxies: TrustedProxies::new(["10.0.0.1".parse().unwrap()]),
+        touched_devices: Cache::builder()
+            .time_to_live(Duration::from_secs(5 * 60))
+            .build(),
+    };
+
+    let mut app = medigram::app(state);
+
+    let attempts = (0..IP_POLICY.free_attempts)
+        .map(|_| ("203.0.113.7:50000", None, StatusCode::NOT_FOUND))
+        .chain([
+            // made up by the client itself, which is no proxy
+            (
+                "203.0.113.7:50000",
+                Some("198.51.100.1"),
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            // appended by the proxy, whoever the client claims to be
+            (
+                "10.0.0.1:50000",
+                Some("198.51.100.1, 203.0.113.7"),
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            (
+                "10.0.0.1:50000",
+                Some("198.51.100.1"),
+                StatusCode::NOT_FOUND,
+            ),
+        ]);
+    for (attempt, (peer, forwarded_for, status)) in attempts.enumerate() {
+        let mut request = Request::builder()
+            .uri(format!("http://{API_ROOT_URL}/login"))
+            .method("POST")
+            .header("Content-Type", "application/json");
+        if let Some(forwarded_for) = forwarded_for {
+            request = request.header("X-Forwarded-For", forwarded_for);
+        }
+        let mut request = request
+            .body(Body::from(
+                json!({
+                    "email": format!("random{attempt}@example.com"),
+                    "password": "test",
+                })
+                .to_string(),
+            ))
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut app)
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), status, "{peer} #{attempt}");
+    }
+}
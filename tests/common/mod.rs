@@ -1,12 +1,17 @@
+// not every test binary uses every helper
+#![allow(dead_code)]
+
 use std::time::Duration;
 
 use axum::{
     Router,
     body::{Body, Bytes},
-    http::{Request, Response, StatusCode},
+    http::{HeaderMap, Request, StatusCode},
 };
 use http_body_util::BodyExt;
 use medigram::AppState;
+use medigram::auth::{jwt::JwtKeys, throttle::TrustedProxies, totp};
+use medigram::emergency_card::CardIssuer;
 use moka::sync::Cache;
 use serde_json::{Value, json};
 use sqlx::{Pool, Postgres};
@@ -14,9 +19,11 @@ use tower::{Service, ServiceExt};
 use uuid::Uuid;
 
 pub static API_ROOT_URL: &str = "127.0.0.1:3001";
+/// TOTP secret enrolled for alice in the `doctor_info` fixture
+pub static ALICE_TOTP_SECRET: &str = "JBSWY3DPEHPK3PXP";
 
-pub fn get_app(db_pool: Pool<Postgres>) -> Router {
-    let state = AppState {
+pub fn get_state(db_pool: Pool<Postgres>) -> AppState {
+    AppState {
         nonce_cache: Cache::builder()
             .time_to_live(Duration::from_secs(7 * 24 * 60 * 60))
             .build(),
@@ -24,9 +31,84 @@ pub fn get_app(db_pool: Pool<Postgres>) -> Router {
         recognized_session_id: Cache::builder()
             .time_to_live(Duration::from_secs(30 * 24 * 60 * 60))
             .build(),
-    };
+        two_factor_challenges: Cache::builder()
+            .time_to_live(Duration::from_secs(5 * 60))
+            .build(),
+        login_throttle: Cache::builder()
+            .time_to_idle(Duration::from_secs(24 * 60 * 60))
+            .build(),
+        jwt: None,
+        card_issuer: CardIssuer::generate(&format!("http://{API_ROOT_URL}")),
+        trusted_proxies: TrustedProxies::default(),
+        touched_devices: Cache::builder()
+            .time_to_live(Duration::from_secs(5 * 60))
+            .build(),
+    }
+}
+
+pub fn get_app(db_pool: Pool<Postgres>) -> Router {
+    medigram::app(get_state(db_pool))
+}
+
+/// App that hands out access tokens instead of sessions
+pub fn get_app_with_jwt(db_pool: Pool<Postgres>) -> Router {
+    medigram::app(AppState {
+        jwt: Some(JwtKeys::generate()),
+        ..get_state(db_pool)
+    })
+}
+
+/// Request to `uri` with `body` as JSON, signed in when `session_id` is given
+pub fn build_request(
+    session_id: Option<&str>,
+    method: &str,
+    uri: &str,
+    body: Option<Value>,
+) -> Request<Body> {
+    let mut request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}{uri}"))
+        .method(method)
+        .header("Content-Type", "application/json");
+    if let Some(session_id) = session_id {
+        request =
+            request.header("Authorization", format!("Bearer {session_id}"));
+    }
+    request
+        .body(match body {
+            Some(body) => Body::from(body.to_string()),
+            None => Body::empty(),
+        })
+        .unwrap()
+}
 
-    medigram::app(state)
+pub async fn send_request(
+    app: &mut Router,
+    request: Request<Body>,
+) -> (StatusCode, HeaderMap, Bytes) {
+    let response = ServiceExt::<Request<Body>>::ready(app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, headers, body)
+}
+
+/// Sends a JSON request, answering `Value::Null` for a body that isn't JSON
+pub async fn send(
+    app: &mut Router,
+    session_id: Option<&str>,
+    method: &str,
+    uri: &str,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let request = build_request(session_id, method, uri, body);
+    let (status, _, body) = send_request(app, request).await;
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
 }
 
 pub async fn extract_session_id(body_bytes: &Bytes) -> String {
@@ -44,6 +126,12 @@ pub async fn extract_user_id(body_bytes: &Bytes) -> Uuid {
         .expect("user_id not a parsable Uuid")
 }
 
+pub fn current_totp_code(encoded_secret: &str) -> String {
+    let secret = totp::decode_secret(encoded_secret).unwrap();
+    let now = chrono::Utc::now().timestamp() as u64;
+    totp::totp_at(&secret, now)
+}
+
 pub async fn login_as_alice(app: &mut Router) -> (String, Uuid) {
     let request = Request::builder()
         .uri(format!("http://{API_ROOT_URL}/login"))
@@ -58,12 +146,43 @@ pub async fn login_as_alice(app: &mut Router) -> (String, Uuid) {
         ))
         .unwrap();
 
-    let login_response = ServiceExt::<Request<Body>>::ready(app)
+    let mut login_response = ServiceExt::<Request<Body>>::ready(&mut *app)
         .await
         .unwrap()
         .call(request)
         .await
         .unwrap();
+
+    // alice has 2FA enrolled whenever she is loaded as a doctor
+    if login_response.status() == StatusCode::ACCEPTED {
+        let body = login_response
+            .into_body()
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+
+        let request = Request::builder()
+            .uri(format!("http://{API_ROOT_URL}/login/two-factor"))
+            .method("POST")
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                json!({
+                    "challenge_id": body["challenge_id"],
+                    "code": current_totp_code(ALICE_TOTP_SECRET),
+                })
+                .to_string(),
+            ))
+            .unwrap();
+
+        login_response = ServiceExt::<Request<Body>>::ready(app)
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap();
+    }
     assert_eq!(login_response.status(), StatusCode::OK);
 
     let login_body = login_response
@@ -1,26 +1,60 @@
 mod common;
 
+use axum::Router;
 use axum::body::Body;
 use axum::http::{Request, StatusCode};
+use base64::{Engine, engine::general_purpose};
+use ed25519_compact::SecretKey;
 use http_body_util::BodyExt;
-use medigram::protocol::Nonce;
-use medigram::route::request_nonce;
 use serde_json::Value;
 use serde_json::json;
+use serde_json_canonicalizer::to_string;
 use sqlx::Pool;
 use sqlx::postgres::Postgres;
 use tower::{Service, ServiceExt};
+use uuid::Uuid;
 
 use common::*;
-use medigram::schema::Allergy;
-
-static ALLERGY_ID: &str = "f7769edf-b06b-4749-b6ff-d91efcca8403";
 
 // .route("/consultation", get(get_consultations))
 // .route("/consultation", post(add_consultation))
 // .route("/diagnosis/{consultation_id}", get(get_diagnoses))
 // .route("/symptom/{consultation_id}", get(get_symptoms))
 // .route("/prescription/{consultation_id}", get(get_prescriptions))
+// .route("/consultations/{consultation_id}", get(get_consultation_detail))
+
+const BOB_ID: &str = "41490144-e4e1-4d1f-9eb7-f90af81c12ce";
+/// bob's first consultation in the `prescriptions` fixture
+const COLD_CONSULTATION_ID: &str = "5b1c7a0e-2f4d-4a8e-9c3b-7d6e5f4a3b2c";
+
+/// bob's consent to a consultation, from the device he logs in with
+async fn bob_consent(app: &mut Router) -> Value {
+    let (_, login) = send(
+        app,
+        None,
+        "POST",
+        "/login",
+        Some(json!({ "email": "bob@example.com", "password": "test" })),
+    )
+    .await;
+    let device_id: Uuid = login["device_id"].as_str().unwrap().parse().unwrap();
+    let key = SecretKey::from_slice(
+        &general_purpose::STANDARD
+            .decode(login["private_key"].as_str().unwrap())
+            .unwrap(),
+    )
+    .unwrap();
+
+    let (_, nonce) = send(app, None, "GET", "/request-nonce", None).await;
+    let nonce = nonce["nonce"].as_str().unwrap();
+    let message = to_string(&(device_id, nonce)).unwrap();
+
+    json!({
+        "signer_device_id": device_id,
+        "nonce": nonce,
+        "signature": general_purpose::STANDARD.encode(*key.sign(message, None)),
+    })
+}
 
 #[sqlx::test(fixtures("users"))]
 async fn add_consultations_non_doctor(db_pool: Pool<Postgres>) {
@@ -207,3 +241,329 @@ async fn add_consultations_no_device(db_pool: Pool<Postgres>) {
 
     assert_eq!(response.status(), StatusCode::NOT_FOUND);
 }
+
+#[sqlx::test(fixtures(
+    "users",
+    "details",
+    "doctor_info",
+    "prescriptions",
+    "diagnoses"
+))]
+async fn consultation_detail_joins_everything(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_alice(&mut app).await;
+
+    let (status, detail) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        &format!("/consultations/{COLD_CONSULTATION_ID}"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(detail["user_id"], BOB_ID);
+    assert_eq!(detail["doctor"]["name"], "alice");
+    assert!(
+        detail["location"]["practice_address"]
+            .as_str()
+            .unwrap()
+            .starts_with("Jl. Raya Kb. Jeruk")
+    );
+    assert_eq!(detail["diagnoses"][0]["diagnosis"], "Common cold");
+    assert_eq!(detail["prescriptions"][0]["drug_name"], "Paracetamol");
+    assert_eq!(detail["dispensed"], false);
+    assert_eq!(detail["amends"], Value::Null);
+    assert_eq!(detail["amended_by"], Value::Null);
+
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        &format!("/consultations/{}", Uuid::new_v4()),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[sqlx::test(fixtures("users", "doctor_info", "prescriptions", "diagnoses"))]
+async fn consultation_amended_once(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_alice(&mut app).await;
+
+    let amendment = |consent: Value| {
+        json!({
+            "consent": consent,
+            "user_id": BOB_ID,
+            "location_id": "fbc0a545-f266-495d-91a1-667479a13ace",
+            "diagnoses": [{ "diagnosis": "Influenza", "severity": "MODERATE" }],
+            "symptoms": "fever and cough",
+            "prescriptions": [],
+            "amends": COLD_CONSULTATION_ID,
+        })
+    };
+
+    let consent = bob_consent(&mut app).await;
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        &format!("/users/{BOB_ID}/consultations"),
+        Some(amendment(consent)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (_, detail) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        &format!("/consultations/{COLD_CONSULTATION_ID}"),
+        None,
+    )
+    .await;
+    let amended_by = detail["amended_by"].as_str().unwrap().to_string();
+
+    let (_, detail) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        &format!("/consultations/{amended_by}"),
+        None,
+    )
+    .await;
+    assert_eq!(detail["amends"], COLD_CONSULTATION_ID);
+    assert_eq!(detail["diagnoses"][0]["diagnosis"], "Influenza");
+    // nothing prescribed, so nothing dispensed
+    assert_eq!(detail["dispensed"], false);
+
+    let consent = bob_consent(&mut app).await;
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        &format!("/users/{BOB_ID}/consultations"),
+        Some(amendment(consent)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+}
+
+#[sqlx::test(fixtures("users", "doctor_info"))]
+async fn diagnoses_coded_with_icd10(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_alice(&mut app).await;
+
+    let consultation = |consent: Value, code: &str| {
+        json!({
+            "consent": consent,
+            "user_id": BOB_ID,
+            "location_id": "fbc0a545-f266-495d-91a1-667479a13ace",
+            "diagnoses": [{
+                "diagnosis": "Common cold",
+                "severity": "MILD",
+                "icd10_code": code,
+            }],
+            "symptoms": "runny nose",
+            "prescriptions": [],
+        })
+    };
+
+    let consent = bob_consent(&mut app).await;
+    let (status, body) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        &format!("/users/{BOB_ID}/consultations"),
+        Some(consultation(consent, "X99.9")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "Unknown ICD-10 code");
+
+    let consent = bob_consent(&mut app).await;
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        &format!("/users/{BOB_ID}/consultations"),
+        Some(consultation(consent, " j00 ")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (_, consultations) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        &format!("/users/{BOB_ID}/consultations"),
+        None,
+    )
+    .await;
+    let consultation_id = consultations[0]["consultation_id"].as_str().unwrap();
+    let (_, detail) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        &format!("/consultations/{consultation_id}"),
+        None,
+    )
+    .await;
+    assert_eq!(detail["diagnoses"][0]["icd10_code"], "J00");
+    assert_eq!(detail["diagnoses"][0]["severity"], "MILD");
+
+    // severities are no longer free text
+    let consent = bob_consent(&mut app).await;
+    let mut payload = consultation(consent, "J00");
+    payload["diagnoses"][0]["severity"] = json!("somewhat");
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        &format!("/users/{BOB_ID}/consultations"),
+        Some(payload),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[sqlx::test(fixtures("users", "doctor_info"))]
+async fn ongoing_diagnoses_listed_as_conditions(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_alice(&mut app).await;
+
+    let consent = bob_consent(&mut app).await;
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        &format!("/users/{BOB_ID}/consultations"),
+        Some(json!({
+            "consent": consent,
+            "user_id": BOB_ID,
+            "location_id": "fbc0a545-f266-495d-91a1-667479a13ace",
+            "diagnoses": [
+                {
+                    "diagnosis": "Hypertension",
+                    "severity": "MODERATE",
+                    "icd10_code": "I10",
+                    "ongoing": true,
+                },
+                { "diagnosis": "Common cold", "severity": "MILD" },
+            ],
+            "symptoms": "headache",
+            "prescriptions": [],
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (_, consultations) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        &format!("/users/{BOB_ID}/consultations"),
+        None,
+    )
+    .await;
+    let (_, conditions) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        &format!("/users/{BOB_ID}/medical-conditions"),
+        None,
+    )
+    .await;
+    assert_eq!(conditions.as_array().unwrap().len(), 1);
+    assert_eq!(conditions[0]["condition"], "Hypertension");
+    assert_eq!(conditions[0]["icd10_code"], "I10");
+    assert_eq!(conditions[0]["status"], "ACTIVE");
+    assert_eq!(conditions[0]["source"], "DIAGNOSED");
+    assert_eq!(
+        conditions[0]["consultation_id"],
+        consultations[0]["consultation_id"]
+    );
+}
+
+#[sqlx::test(fixtures("users", "doctor_info"))]
+async fn observations_taken_during_consultation(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, alice_id) = login_as_alice(&mut app).await;
+    let consultation = |consent: Value, temperature: f64| {
+        json!({
+            "consent": consent,
+            "user_id": BOB_ID,
+            "location_id": "fbc0a545-f266-495d-91a1-667479a13ace",
+            "diagnoses": [],
+            "symptoms": "fever",
+            "prescriptions": [],
+            "observations": [
+                { "code": "8310-5", "value": temperature },
+                { "code": "8867-4", "value": 96 },
+            ],
+        })
+    };
+
+    // a temperature in Fahrenheit is refused before the consent is used
+    let consent = bob_consent(&mut app).await;
+    let (status, body) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        &format!("/users/{BOB_ID}/consultations"),
+        Some(consultation(consent.clone(), 100.8)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body["error"],
+        "The observed value is out of range for its type"
+    );
+
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        &format!("/users/{BOB_ID}/consultations"),
+        Some(consultation(consent, 38.2)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (_, consultations) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        &format!("/users/{BOB_ID}/consultations"),
+        None,
+    )
+    .await;
+    let consultation_id = consultations[0]["consultation_id"].as_str().unwrap();
+    let (_, detail) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        &format!("/consultations/{consultation_id}"),
+        None,
+    )
+    .await;
+    let observations = detail["observations"].as_array().unwrap();
+    assert_eq!(observations.len(), 2);
+    assert_eq!(observations[0]["name"], "Body temperature");
+    assert_eq!(observations[0]["unit"], "Cel");
+    assert_eq!(observations[0]["value"], 38.2);
+    assert_eq!(observations[0]["recorded_by"], alice_id.to_string());
+    assert_eq!(observations[0]["observed_at"], detail["created_at"]);
+
+    let (_, observations) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        &format!("/users/{BOB_ID}/observations?code=8867-4"),
+        None,
+    )
+    .await;
+    assert_eq!(observations.as_array().unwrap().len(), 1);
+    assert_eq!(observations[0]["consultation_id"], consultation_id);
+}
@@ -0,0 +1,208 @@
+mod common;
+
+use axum::Router;
+use axum::http::StatusCode;
+use chrono::{Duration, Utc};
+use serde_json::{Value, json};
+use sqlx::Pool;
+use sqlx::postgres::Postgres;
+use uuid::Uuid;
+
+use common::*;
+
+static BOB_ID: &str = "41490144-e4e1-4d1f-9eb7-f90af81c12ce";
+
+// .route("/me/delegations", get(get_own_delegations))
+// .route("/me/delegations", post(add_own_delegation))
+// .route("/me/delegations/received", get(get_received_delegations))
+// .route("/me/delegations/{delegation_id}", delete(revoke_own_delegation))
+// .route("/users/{user_id}/reminders", get(get_user_reminders))
+// .route("/users/{user_id}/prescriptions", get(get_user_prescriptions))
+
+/// Registers Carol, whom Bob trusts, and logs her in
+async fn login_as_carol(app: &mut Router) -> (String, Uuid) {
+    let credentials = json!({
+        "email": "carol@example.com",
+        "password": "test",
+    });
+
+    let (status, _) =
+        send(app, None, "POST", "/register", Some(credentials.clone())).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, body) =
+        send(app, None, "POST", "/login", Some(credentials)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    (
+        body["session_id"].as_str().unwrap().to_string(),
+        body["user_id"].as_str().unwrap().parse().unwrap(),
+    )
+}
+
+async fn delegate(
+    app: &mut Router,
+    session_id: &str,
+    delegate_id: Uuid,
+    resources: Value,
+) -> (StatusCode, Value) {
+    send(
+        app,
+        Some(session_id),
+        "POST",
+        "/me/delegations",
+        Some(json!({
+            "delegate_id": delegate_id,
+            "resources": resources,
+            "expires_at": Utc::now() + Duration::days(30),
+        })),
+    )
+    .await
+}
+
+#[sqlx::test(fixtures("users", "doctor_info", "prescriptions"))]
+async fn delegate_reads_only_what_was_delegated(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (bob_session, _user_id) = login_as_bob(&mut app).await;
+    let (carol_session, carol_id) = login_as_carol(&mut app).await;
+    let reminders = format!("/users/{BOB_ID}/reminders");
+
+    let (status, _) =
+        send(&mut app, Some(&carol_session), "GET", &reminders, None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, body) = delegate(
+        &mut app,
+        &bob_session,
+        carol_id,
+        json!(["reminder", "prescription"]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let delegation_id = body["delegation_id"].as_str().unwrap().to_string();
+
+    let (status, body) =
+        send(&mut app, Some(&carol_session), "GET", &reminders, None).await;
+    assert_eq!(status, StatusCode::OK);
+    // only the consultation bob set a reminder for
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    assert_eq!(body[0]["drug_name"], "Paracetamol");
+
+    let (status, body) = send(
+        &mut app,
+        Some(&carol_session),
+        "GET",
+        &format!("/users/{BOB_ID}/prescriptions"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.as_array().unwrap().len(), 2);
+
+    for uri in [
+        format!("/users/{BOB_ID}/measurements"),
+        format!("/users/{BOB_ID}/allergies"),
+        format!("/users/{BOB_ID}/consultations"),
+    ] {
+        let (status, _) =
+            send(&mut app, Some(&carol_session), "GET", &uri, None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{uri}");
+    }
+
+    let (_, received) = send(
+        &mut app,
+        Some(&carol_session),
+        "GET",
+        "/me/delegations/received",
+        None,
+    )
+    .await;
+    assert_eq!(received[0]["user_id"], BOB_ID);
+
+    let (_, access_log) =
+        send(&mut app, Some(&bob_session), "GET", "/me/access-log", None).await;
+    assert!(
+        access_log
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|entry| entry["scope"] == "delegated")
+    );
+    assert_eq!(access_log.as_array().unwrap().len(), 2);
+
+    let (status, _) = send(
+        &mut app,
+        Some(&bob_session),
+        "DELETE",
+        &format!("/me/delegations/{delegation_id}"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) =
+        send(&mut app, Some(&carol_session), "GET", &reminders, None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[sqlx::test(fixtures("users", "doctor_info", "prescriptions"))]
+async fn expired_delegation_grants_nothing(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool.clone());
+    let (bob_session, _user_id) = login_as_bob(&mut app).await;
+    let (carol_session, carol_id) = login_as_carol(&mut app).await;
+
+    let (status, _) =
+        delegate(&mut app, &bob_session, carol_id, json!(["reminder"])).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    sqlx::query("UPDATE delegations SET expires_at = NOW()")
+        .execute(&db_pool)
+        .await
+        .unwrap();
+
+    let (status, _) = send(
+        &mut app,
+        Some(&carol_session),
+        "GET",
+        &format!("/users/{BOB_ID}/reminders"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (_, delegations) =
+        send(&mut app, Some(&bob_session), "GET", "/me/delegations", None)
+            .await;
+    assert_eq!(delegations.as_array().unwrap().len(), 1);
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn only_some_resources_can_be_delegated(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (bob_session, bob_id) = login_as_bob(&mut app).await;
+    let (_carol_session, carol_id) = login_as_carol(&mut app).await;
+
+    for resources in [json!([]), json!(["allergy"]), json!(["device"])] {
+        let (status, _) =
+            delegate(&mut app, &bob_session, carol_id, resources).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    let (status, _) =
+        delegate(&mut app, &bob_session, bob_id, json!(["reminder"])).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = send(
+        &mut app,
+        Some(&bob_session),
+        "POST",
+        "/me/delegations",
+        Some(json!({
+            "delegate_id": carol_id,
+            "resources": ["reminder"],
+            "expires_at": Utc::now() - Duration::days(1),
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
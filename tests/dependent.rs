@@ -0,0 +1,318 @@
+mod common;
+
+use axum::Router;
+use axum::body::Body;
+use axum::http::{HeaderValue, Request, StatusCode};
+use base64::{Engine, engine::general_purpose};
+use chrono::{Datelike, Utc};
+use ed25519_compact::SecretKey;
+use http_body_util::BodyExt;
+use medigram::protocol::Consent;
+use medigram::route::verify_consent;
+use moka::sync::Cache;
+use serde_json::{Value, json};
+use serde_json_canonicalizer::to_string;
+use sqlx::Pool;
+use sqlx::postgres::Postgres;
+use tower::{Service, ServiceExt};
+use uuid::Uuid;
+
+use common::*;
+
+// .route("/me/dependents", get(get_own_dependents))
+// .route("/me/dependents", post(add_own_dependent))
+// .route("/me/dependents/{dependent_id}/guardians", post(add_dependent_guardian))
+// .route("/me/dependents/{dependent_id}/guardians/{guardian_id}", delete(remove_dependent_guardian))
+// .route("/me/dependents/{dependent_id}/handover", post(create_dependent_handover))
+// .route("/register/dependent", post(auth::email::register_dependent))
+
+/// Sends a JSON request on behalf of the dependent `acting_as`
+async fn send_as(
+    app: &mut Router,
+    session_id: &str,
+    acting_as: Uuid,
+    method: &str,
+    uri: &str,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let mut request = build_request(Some(session_id), method, uri, body);
+    request.headers_mut().insert(
+        "X-Acting-As",
+        HeaderValue::from_str(&acting_as.to_string()).unwrap(),
+    );
+    let (status, _, body) = send_request(app, request).await;
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+async fn add_dependent(app: &mut Router, session_id: &str, dob: &str) -> Uuid {
+    let (status, body) = send(
+        app,
+        Some(session_id),
+        "POST",
+        "/me/dependents",
+        Some(json!({
+            "nik": 3171234567890123_i64,
+            "name": "Charlie",
+            "dob": dob,
+            "gender": "M",
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    body["user_id"].as_str().unwrap().parse().unwrap()
+}
+
+/// Today minus `years`, shifted so the birthday is never today
+fn born_years_ago(years: i32) -> String {
+    let today = Utc::now().date_naive();
+    today
+        .with_year(today.year() - years)
+        .unwrap_or(today)
+        .pred_opt()
+        .unwrap()
+        .to_string()
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn guardian_manages_dependent_records(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (alice_session, _user_id) = login_as_alice(&mut app).await;
+    let (bob_session, _user_id) = login_as_bob(&mut app).await;
+    let dependent_id =
+        add_dependent(&mut app, &alice_session, &born_years_ago(8)).await;
+
+    let (status, _) = send_as(
+        &mut app,
+        &alice_session,
+        dependent_id,
+        "POST",
+        "/me/allergies",
+        Some(json!({ "allergen": "peanuts", "severity": "SEVERE" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (_, allergies) = send_as(
+        &mut app,
+        &alice_session,
+        dependent_id,
+        "GET",
+        "/me/allergies",
+        None,
+    )
+    .await;
+    assert_eq!(allergies[0]["user_id"], json!(dependent_id));
+
+    // the guardian's own records are untouched
+    let (_, allergies) =
+        send(&mut app, Some(&alice_session), "GET", "/me/allergies", None)
+            .await;
+    assert_eq!(allergies, json!([]));
+
+    let (status, _) = send_as(
+        &mut app,
+        &bob_session,
+        dependent_id,
+        "GET",
+        "/me/allergies",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let bob_id = "41490144-e4e1-4d1f-9eb7-f90af81c12ce";
+    let (status, _) = send(
+        &mut app,
+        Some(&alice_session),
+        "POST",
+        &format!("/me/dependents/{dependent_id}/guardians"),
+        Some(json!({ "guardian_id": bob_id })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, _) = send_as(
+        &mut app,
+        &bob_session,
+        dependent_id,
+        "GET",
+        "/me/allergies",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, dependents) =
+        send(&mut app, Some(&bob_session), "GET", "/me/dependents", None).await;
+    assert_eq!(dependents[0]["of_age"], json!(false));
+    assert_eq!(dependents[0]["guardians"].as_array().unwrap().len(), 2);
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn guardian_reads_are_logged(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (alice_session, alice_id) = login_as_alice(&mut app).await;
+    let dependent_id =
+        add_dependent(&mut app, &alice_session, &born_years_ago(8)).await;
+
+    let (status, _) = send_as(
+        &mut app,
+        &alice_session,
+        dependent_id,
+        "GET",
+        &format!("/users/{dependent_id}/allergies"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, log) = send_as(
+        &mut app,
+        &alice_session,
+        dependent_id,
+        "GET",
+        "/me/access-log",
+        None,
+    )
+    .await;
+    assert_eq!(log.as_array().unwrap().len(), 1);
+    assert_eq!(log[0]["actor_id"], json!(alice_id));
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn guardian_consents_for_dependent(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool.clone());
+
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/login"))
+        .method("POST")
+        .header("Content-Type", "application/json")
+        .body(Body::from(
+            json!({ "email": "bob@example.com", "password": "test" })
+                .to_string(),
+        ))
+        .unwrap();
+    let response = ServiceExt::<Request<Body>>::ready(&mut app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    let bob_session = body["session_id"].as_str().unwrap().to_string();
+    let device_id: Uuid = body["device_id"].as_str().unwrap().parse().unwrap();
+    let key = SecretKey::from_slice(
+        &general_purpose::STANDARD
+            .decode(body["private_key"].as_str().unwrap())
+            .unwrap(),
+    )
+    .unwrap();
+
+    let dependent_id =
+        add_dependent(&mut app, &bob_session, &born_years_ago(8)).await;
+    let (alice_session, alice_id) = login_as_alice(&mut app).await;
+    let other_dependent_id =
+        add_dependent(&mut app, &alice_session, &born_years_ago(8)).await;
+
+    let nonce_cache = Cache::builder().build();
+    let sign = |nonce: &str| {
+        nonce_cache.insert(nonce.to_string(), Utc::now());
+        let message = to_string(&(device_id, nonce)).unwrap();
+        Consent {
+            signer_device_id: device_id,
+            nonce: nonce.to_string(),
+            signature: key.sign(message, None),
+        }
+    };
+
+    let consent = sign("forthedependent0");
+    assert!(
+        verify_consent(consent, dependent_id, &db_pool, &nonce_cache)
+            .await
+            .is_ok()
+    );
+
+    let consent = sign("forsomebodyelse0");
+    assert!(
+        verify_consent(consent, other_dependent_id, &db_pool, &nonce_cache)
+            .await
+            .is_err()
+    );
+
+    let consent = sign("foranotheradult0");
+    assert!(
+        verify_consent(consent, alice_id, &db_pool, &nonce_cache)
+            .await
+            .is_err()
+    );
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn control_passes_to_dependent_of_age(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (alice_session, _user_id) = login_as_alice(&mut app).await;
+    let dependent_id =
+        add_dependent(&mut app, &alice_session, &born_years_ago(18)).await;
+
+    let (status, _) = send_as(
+        &mut app,
+        &alice_session,
+        dependent_id,
+        "GET",
+        "/me/allergies",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, handover) = send(
+        &mut app,
+        Some(&alice_session),
+        "POST",
+        &format!("/me/dependents/{dependent_id}/handover"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/register/dependent"))
+        .method("POST")
+        .header("Content-Type", "application/json")
+        .body(Body::from(
+            json!({
+                "code": handover["code"],
+                "email": "charlie@example.com",
+                "password": "test",
+            })
+            .to_string(),
+        ))
+        .unwrap();
+    let response = ServiceExt::<Request<Body>>::ready(&mut app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/login"))
+        .method("POST")
+        .header("Content-Type", "application/json")
+        .body(Body::from(
+            json!({ "email": "charlie@example.com", "password": "test" })
+                .to_string(),
+        ))
+        .unwrap();
+    let response = ServiceExt::<Request<Body>>::ready(&mut app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(extract_user_id(&body).await, dependent_id);
+}
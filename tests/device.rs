@@ -0,0 +1,393 @@
+mod common;
+
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Request, Response, StatusCode};
+use base64::{Engine, engine::general_purpose};
+use chrono::{Duration, Utc};
+use ed25519_compact::{KeyPair, PublicKey, SecretKey};
+use http_body_util::BodyExt;
+use medigram::protocol::Consent;
+use medigram::route::verify_consent;
+use moka::sync::Cache;
+use serde_json::{Value, json};
+use serde_json_canonicalizer::to_string;
+use sqlx::Pool;
+use sqlx::postgres::Postgres;
+use tower::{Service, ServiceExt};
+use uuid::Uuid;
+
+use common::*;
+
+// .route("/me/devices", get(get_own_devices))
+// .route("/me/devices/{device_id}", delete(revoke_own_device))
+// .route("/me/devices/logout-all", post(logout_everywhere))
+// .route("/me/devices/{device_id}/rotate", post(rotate_device_key))
+
+async fn get_devices(app: &mut Router, session_id: &str) -> Vec<Value> {
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/me/devices"))
+        .method("GET")
+        .header("Authorization", format!("Bearer {session_id}"))
+        .body(Body::empty())
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    serde_json::from_slice(&body).unwrap()
+}
+
+async fn get_me(app: &mut Router, session_id: &str) -> StatusCode {
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/me"))
+        .method("GET")
+        .header("Authorization", format!("Bearer {session_id}"))
+        .body(Body::empty())
+        .unwrap();
+
+    ServiceExt::<Request<Body>>::ready(app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap()
+        .status()
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn list_devices(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (_first_session_id, _user_id) = login_as_alice(&mut app).await;
+    let (session_id, _user_id) = login_as_alice(&mut app).await;
+
+    let devices = get_devices(&mut app, &session_id).await;
+
+    assert_eq!(devices.len(), 2);
+    assert_eq!(
+        devices
+            .iter()
+            .filter(|device| device["current"] == Value::Bool(true))
+            .count(),
+        1
+    );
+    assert!(devices.iter().all(|device| device["revoked_at"].is_null()));
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn requests_touch_their_device(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool.clone());
+    let (session_id, _user_id) = login_as_alice(&mut app).await;
+
+    let age_device = || {
+        sqlx::query("UPDATE device_keys SET last_used_at = '1970-01-01'")
+            .execute(&db_pool)
+    };
+
+    age_device().await.unwrap();
+    let devices = get_devices(&mut app, &session_id).await;
+    let last_used_at = devices[0]["last_used_at"].as_str().unwrap();
+    assert!(!last_used_at.starts_with("1970"));
+
+    // written once per interval, not on every request
+    age_device().await.unwrap();
+    let devices = get_devices(&mut app, &session_id).await;
+    assert_eq!(devices[0]["last_used_at"], json!("1970-01-01T00:00:00Z"));
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn revoke_device_ends_its_sessions(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _user_id) = login_as_alice(&mut app).await;
+    let (other_session_id, _user_id) = login_as_alice(&mut app).await;
+
+    let devices = get_devices(&mut app, &other_session_id).await;
+    let other_device_id = devices
+        .iter()
+        .find(|device| device["current"] == Value::Bool(true))
+        .and_then(|device| device["device_id"].as_str())
+        .unwrap()
+        .to_string();
+
+    let request = Request::builder()
+        .uri(format!(
+            "http://{API_ROOT_URL}/me/devices/{other_device_id}"
+        ))
+        .method("DELETE")
+        .header("Authorization", format!("Bearer {session_id}"))
+        .body(Body::empty())
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(&mut app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        get_me(&mut app, &other_session_id).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(get_me(&mut app, &session_id).await, StatusCode::OK);
+}
+
+#[sqlx::test(fixtures("users", "device_keys"))]
+async fn revoke_device_different_user(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _user_id) = login_as_alice(&mut app).await;
+
+    // belongs to bob
+    let request = Request::builder()
+        .uri(format!(
+            "http://{API_ROOT_URL}/me/devices/b896cff8-de47-451c-96c1-74086c86b9e7"
+        ))
+        .method("DELETE")
+        .header("Authorization", format!("Bearer {session_id}"))
+        .body(Body::empty())
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(&mut app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn logout_everywhere(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _user_id) = login_as_alice(&mut app).await;
+    let (other_session_id, _user_id) = login_as_alice(&mut app).await;
+
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/me/devices/logout-all"))
+        .method("POST")
+        .header("Authorization", format!("Bearer {session_id}"))
+        .body(Body::empty())
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(&mut app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        get_me(&mut app, &session_id).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        get_me(&mut app, &other_session_id).await,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+/// Logs in as alice and returns her session, device and the device's key
+async fn login_with_device_key(
+    app: &mut Router,
+) -> (String, Uuid, Uuid, SecretKey) {
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/login"))
+        .method("POST")
+        .header("Content-Type", "application/json")
+        .body(Body::from(
+            json!({
+                "email": "alice@example.com",
+                "password": "test",
+            })
+            .to_string(),
+        ))
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    let private_key = general_purpose::STANDARD
+        .decode(body["private_key"].as_str().unwrap())
+        .unwrap();
+
+    (
+        body["session_id"].as_str().unwrap().to_string(),
+        body["user_id"].as_str().unwrap().parse().unwrap(),
+        body["device_id"].as_str().unwrap().parse().unwrap(),
+        SecretKey::from_slice(&private_key).unwrap(),
+    )
+}
+
+async fn request_nonce(app: &mut Router) -> String {
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/request-nonce"))
+        .method("GET")
+        .body(Body::empty())
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    body["nonce"].as_str().unwrap().to_string()
+}
+
+async fn rotate(
+    app: &mut Router,
+    session_id: &str,
+    device_id: Uuid,
+    signing_key: &SecretKey,
+    new_key: &PublicKey,
+) -> Response<Body> {
+    let nonce = request_nonce(app).await;
+    let public_key_pem = new_key.to_pem();
+    let message = to_string(&(device_id, &public_key_pem, &nonce)).unwrap();
+    let signature = signing_key.sign(message, None);
+
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/me/devices/{device_id}/rotate"))
+        .method("POST")
+        .header("Content-Type", "application/json")
+        .header("Authorization", format!("Bearer {session_id}"))
+        .body(Body::from(
+            json!({
+                "public_key_pem": public_key_pem,
+                "nonce": nonce,
+                "signature": general_purpose::STANDARD.encode(signature.as_ref()),
+            })
+            .to_string(),
+        ))
+        .unwrap();
+
+    ServiceExt::<Request<Body>>::ready(app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap()
+}
+
+fn sign_consent(device_id: Uuid, nonce: &str, key: &SecretKey) -> Consent {
+    let message = to_string(&(device_id, nonce)).unwrap();
+
+    Consent {
+        signer_device_id: device_id,
+        nonce: nonce.to_string(),
+        signature: key.sign(message, None),
+    }
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn rotate_device_key(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _user_id, device_id, old_key) =
+        login_with_device_key(&mut app).await;
+    let new_key = KeyPair::generate();
+
+    let response =
+        rotate(&mut app, &session_id, device_id, &old_key, &new_key.pk).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["generation"], json!(2));
+
+    let devices = get_devices(&mut app, &session_id).await;
+    assert_eq!(devices[0]["generation"], json!(2));
+
+    // the retired key can no longer hand the device over
+    let response = rotate(
+        &mut app,
+        &session_id,
+        device_id,
+        &old_key,
+        &KeyPair::generate().pk,
+    )
+    .await;
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[sqlx::test(fixtures("users", "device_keys"))]
+async fn rotate_device_key_different_user(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _user_id, _device_id, key) =
+        login_with_device_key(&mut app).await;
+
+    // belongs to bob
+    let device_id: Uuid =
+        "b896cff8-de47-451c-96c1-74086c86b9e7".parse().unwrap();
+    let response = rotate(
+        &mut app,
+        &session_id,
+        device_id,
+        &key,
+        &KeyPair::generate().pk,
+    )
+    .await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn consent_checked_against_key_at_nonce_issue(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool.clone());
+    let (session_id, user_id, device_id, old_key) =
+        login_with_device_key(&mut app).await;
+    let new_key = KeyPair::generate();
+    let nonce_cache = Cache::builder().build();
+
+    nonce_cache.insert(
+        "issuedbeforerota".to_string(),
+        Utc::now() - Duration::minutes(1),
+    );
+
+    let response =
+        rotate(&mut app, &session_id, device_id, &old_key, &new_key.pk).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    nonce_cache.insert("issuedafterrotat".to_string(), Utc::now());
+    nonce_cache.insert("issuedafterrota2".to_string(), Utc::now());
+
+    let consent = sign_consent(device_id, "issuedbeforerota", &old_key);
+    assert!(
+        verify_consent(consent, user_id, &db_pool, &nonce_cache)
+            .await
+            .is_ok()
+    );
+
+    let consent = sign_consent(device_id, "issuedafterrotat", &old_key);
+    assert!(
+        verify_consent(consent, user_id, &db_pool, &nonce_cache)
+            .await
+            .is_err()
+    );
+
+    let consent = sign_consent(device_id, "issuedafterrota2", &new_key.sk);
+    assert!(
+        verify_consent(consent, user_id, &db_pool, &nonce_cache)
+            .await
+            .is_ok()
+    );
+}
@@ -0,0 +1,202 @@
+mod common;
+
+use axum::http::StatusCode;
+use serde_json::{Value, json};
+use sqlx::Pool;
+use sqlx::postgres::Postgres;
+
+use common::*;
+
+static ALICE_ID: &str = "d3969164-86ea-442d-a589-79de89116f9c";
+static BOB_ID: &str = "41490144-e4e1-4d1f-9eb7-f90af81c12ce";
+
+// .route("/users/{user_id}/emergency-access", post(open_emergency_access))
+// .route("/me/notifications", get(get_own_notifications))
+// .route("/me/notifications/{notification_id}/read", post(mark_notification_read))
+// .route("/admin/emergency-access", get(get_emergency_access_grants))
+// .route("/admin/emergency-access/{grant_id}/review", post(review_emergency_access))
+
+#[sqlx::test(fixtures("users", "doctor_info"))]
+async fn doctor_reads_patient_only_after_breaking_the_glass(
+    db_pool: Pool<Postgres>,
+) {
+    let mut app = get_app(db_pool);
+    let (alice_session, _user_id) = login_as_alice(&mut app).await;
+    let allergies = format!("/users/{BOB_ID}/allergies");
+    let emergency_access = format!("/users/{BOB_ID}/emergency-access");
+
+    let (status, _) =
+        send(&mut app, Some(&alice_session), "GET", &allergies, None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = send(
+        &mut app,
+        Some(&alice_session),
+        "POST",
+        &emergency_access,
+        Some(json!({ "justification": "urgent" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, body) = send(
+        &mut app,
+        Some(&alice_session),
+        "POST",
+        &emergency_access,
+        Some(json!({
+            "justification": "Unconscious in the ER, no relatives reachable"
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(body["resources"], json!(["allergy", "medical_condition"]));
+
+    let (status, _) =
+        send(&mut app, Some(&alice_session), "GET", &allergies, None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // limited to what is needed in an emergency
+    let (status, _) = send(
+        &mut app,
+        Some(&alice_session),
+        "GET",
+        &format!("/users/{BOB_ID}/consultations"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (bob_session, _user_id) = login_as_bob(&mut app).await;
+
+    let (_, notifications) = send(
+        &mut app,
+        Some(&bob_session),
+        "GET",
+        "/me/notifications",
+        None,
+    )
+    .await;
+    assert_eq!(notifications.as_array().unwrap().len(), 1);
+    assert_eq!(notifications[0]["kind"], json!("emergency_access"));
+    assert_eq!(notifications[0]["payload"]["grant_id"], body["grant_id"]);
+
+    let notification_id = notifications[0]["notification_id"].as_str().unwrap();
+    let (status, _) = send(
+        &mut app,
+        Some(&bob_session),
+        "POST",
+        &format!("/me/notifications/{notification_id}/read"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, log) =
+        send(&mut app, Some(&bob_session), "GET", "/me/access-log", None).await;
+    assert_eq!(log[0]["actor_id"], json!(ALICE_ID));
+    assert_eq!(log[0]["scope"], json!("emergency"));
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn patient_cannot_break_the_glass(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (bob_session, _user_id) = login_as_bob(&mut app).await;
+
+    let (status, _) = send(
+        &mut app,
+        Some(&bob_session),
+        "POST",
+        &format!("/users/{ALICE_ID}/emergency-access"),
+        Some(json!({
+            "justification": "Unconscious in the ER, no relatives reachable"
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[sqlx::test(fixtures("users", "doctor_info", "admin_doctor"))]
+async fn admin_reviews_flagged_access(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (alice_session, _user_id) = login_as_alice(&mut app).await;
+
+    send(
+        &mut app,
+        Some(&alice_session),
+        "POST",
+        &format!("/users/{BOB_ID}/emergency-access"),
+        Some(json!({
+            "justification": "Unconscious in the ER, no relatives reachable"
+        })),
+    )
+    .await;
+
+    let (_, flagged) = send(
+        &mut app,
+        Some(&alice_session),
+        "GET",
+        "/audit-events?flagged=true",
+        None,
+    )
+    .await;
+    assert_eq!(flagged.as_array().unwrap().len(), 1);
+    assert_eq!(flagged[0]["action"], json!("emergency_access.open"));
+
+    let (status, grants) = send(
+        &mut app,
+        Some(&alice_session),
+        "GET",
+        "/admin/emergency-access",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(grants[0]["user_id"], json!(BOB_ID));
+    assert_eq!(grants[0]["reviewed_at"], Value::Null);
+
+    let review = format!(
+        "/admin/emergency-access/{}/review",
+        grants[0]["grant_id"].as_str().unwrap()
+    );
+    let (status, _) = send(
+        &mut app,
+        Some(&alice_session),
+        "POST",
+        &review,
+        Some(json!({ "note": "confirmed with the ER" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // only reviewed once
+    let (status, _) = send(
+        &mut app,
+        Some(&alice_session),
+        "POST",
+        &review,
+        Some(json!({ "note": null })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (_, pending) = send(
+        &mut app,
+        Some(&alice_session),
+        "GET",
+        "/admin/emergency-access?reviewed=false",
+        None,
+    )
+    .await;
+    assert_eq!(pending, json!([]));
+
+    let (_, report) = send(
+        &mut app,
+        Some(&alice_session),
+        "GET",
+        "/audit-events/verify",
+        None,
+    )
+    .await;
+    assert_eq!(report["valid"], json!(true));
+}
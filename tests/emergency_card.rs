@@ -0,0 +1,170 @@
+mod common;
+
+use axum::Router;
+use axum::http::StatusCode;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, jwk::JwkSet};
+use serde_json::{Value, json};
+use sqlx::Pool;
+use sqlx::postgres::Postgres;
+
+use common::*;
+
+static ALICE_ID: &str = "d3969164-86ea-442d-a589-79de89116f9c";
+
+// .route("/me/emergency-card", get(get_own_emergency_card))
+// .route("/me/emergency-card", put(publish_own_emergency_card))
+// .route("/me/emergency-card", delete(withdraw_own_emergency_card))
+// .route("/me/emergency-card/qr", get(get_own_emergency_card_qr))
+// .route("/emergency-card/{token}", get(render_emergency_card))
+// .route("/.well-known/emergency-card-key", get(get_emergency_card_key))
+
+async fn publish(app: &mut Router, session_id: &str) -> Value {
+    let (status, body) = send(
+        app,
+        Some(session_id),
+        "PUT",
+        "/me/emergency-card",
+        Some(json!({
+            "blood_type": "O-",
+            "emergency_contact": { "name": "Bob", "phone": "+628123456789" }
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    body
+}
+
+#[sqlx::test(fixtures("users", "allergies"))]
+async fn card_renders_without_an_account(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _user_id) = login_as_alice(&mut app).await;
+
+    send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        "/me/allergies",
+        Some(
+            json!({ "allergen": "peanuts", "severity": "ANAPHYLACTIC_SHOCK" }),
+        ),
+    )
+    .await;
+
+    let published = publish(&mut app, &session_id).await;
+    // mild allergies stay off the card
+    assert_eq!(
+        published["card"]["allergies"],
+        json!([{ "allergen": "peanuts", "severity": "ANAPHYLACTIC_SHOCK" }])
+    );
+
+    let url = published["url"].as_str().unwrap();
+    let path = url.strip_prefix(&format!("http://{API_ROOT_URL}")).unwrap();
+    let request = build_request(None, "GET", path, None);
+    let (status, _, html) = send_request(&mut app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    let html = String::from_utf8(html.to_vec()).unwrap();
+    assert!(html.contains("peanuts (anaphylactic shock)"));
+    assert!(html.contains("O-"));
+    assert!(!html.contains("pollen"));
+
+    let request =
+        build_request(Some(&session_id), "GET", "/me/emergency-card/qr", None);
+    let (status, _, svg) = send_request(&mut app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(String::from_utf8(svg.to_vec()).unwrap().contains("<svg"));
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn card_verifies_offline(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _user_id) = login_as_alice(&mut app).await;
+
+    let published = publish(&mut app, &session_id).await;
+    let token = published["token"].as_str().unwrap();
+
+    let (_, jwks) = send(
+        &mut app,
+        None,
+        "GET",
+        "/.well-known/emergency-card-key",
+        None,
+    )
+    .await;
+    let jwks: JwkSet = serde_json::from_value(jwks).unwrap();
+
+    let mut validation = Validation::new(Algorithm::EdDSA);
+    validation.required_spec_claims.clear();
+    validation.validate_exp = false;
+    let card = decode::<Value>(
+        token,
+        &DecodingKey::from_jwk(&jwks.keys[0]).unwrap(),
+        &validation,
+    )
+    .unwrap()
+    .claims;
+
+    assert_eq!(card["sub"], json!(ALICE_ID));
+    assert_eq!(card["blood_type"], json!("O-"));
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn replaced_or_tampered_cards_do_not_render(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _user_id) = login_as_alice(&mut app).await;
+
+    let first = publish(&mut app, &session_id).await;
+    let first_token = first["token"].as_str().unwrap();
+    publish(&mut app, &session_id).await;
+
+    let (status, _) = send(
+        &mut app,
+        None,
+        "GET",
+        &format!("/emergency-card/{first_token}"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // signature no longer matches the claims
+    let mut parts: Vec<&str> = first_token.split('.').collect();
+    let (_, current) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        "/me/emergency-card",
+        None,
+    )
+    .await;
+    let current_token = current["token"].as_str().unwrap();
+    parts[1] = current_token.split('.').nth(1).unwrap();
+    let (status, _) = send(
+        &mut app,
+        None,
+        "GET",
+        &format!("/emergency-card/{}", parts.join(".")),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "DELETE",
+        "/me/emergency-card",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = send(
+        &mut app,
+        None,
+        "GET",
+        &format!("/emergency-card/{current_token}"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
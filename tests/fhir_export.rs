@@ -0,0 +1,153 @@
+mod common;
+
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Request, StatusCode, header};
+use http_body_util::BodyExt;
+use serde_json::Value;
+use sqlx::Pool;
+use sqlx::postgres::Postgres;
+use tower::{Service, ServiceExt};
+
+use common::*;
+
+static BOB_ID: &str = "41490144-e4e1-4d1f-9eb7-f90af81c12ce";
+
+// .route("/me/export/fhir", get(get_own_fhir_export))
+// .route("/users/{user_id}/export/fhir", get(get_user_fhir_export))
+
+async fn get(
+    app: &mut Router,
+    session_id: &str,
+    uri: &str,
+) -> (StatusCode, Option<String>, Value) {
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}{uri}"))
+        .method("GET")
+        .header("Authorization", format!("Bearer {session_id}"))
+        .body(Body::empty())
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    let status = response.status();
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (
+        status,
+        content_type,
+        serde_json::from_slice(&body).unwrap_or(Value::Null),
+    )
+}
+
+/// Resources of `bundle` of the given type
+fn of_type<'a>(bundle: &'a Value, resource_type: &str) -> Vec<&'a Value> {
+    bundle["entry"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| &entry["resource"])
+        .filter(|resource| resource["resourceType"] == resource_type)
+        .collect()
+}
+
+#[sqlx::test(fixtures("users", "details", "allergies", "measurements"))]
+async fn patient_exports_own_record(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, user_id) = login_as_alice(&mut app).await;
+
+    let (status, content_type, bundle) =
+        get(&mut app, &session_id, "/me/export/fhir").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_type.as_deref(), Some("application/fhir+json"));
+    assert_eq!(bundle["resourceType"], "Bundle");
+    assert_eq!(bundle["type"], "collection");
+
+    let patient = of_type(&bundle, "Patient");
+    assert_eq!(patient[0]["id"], user_id.to_string());
+    assert_eq!(patient[0]["gender"], "female");
+    assert_eq!(patient[0]["identifier"][0]["value"], "1000000000000000");
+
+    let allergies = of_type(&bundle, "AllergyIntolerance");
+    assert_eq!(allergies[0]["code"]["text"], "pollen");
+    assert_eq!(allergies[0]["reaction"][0]["severity"], "mild");
+    assert_eq!(
+        allergies[0]["patient"]["reference"],
+        format!("urn:uuid:{user_id}")
+    );
+
+    let observations = of_type(&bundle, "Observation");
+    assert_eq!(observations.len(), 2);
+    assert!(of_type(&bundle, "Encounter").is_empty());
+}
+
+#[sqlx::test(fixtures("users", "doctor_info", "prescriptions", "diagnoses"))]
+async fn attending_doctor_exports_patient_record(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (alice_session, _user_id) = login_as_alice(&mut app).await;
+    let (bob_session, _user_id) = login_as_bob(&mut app).await;
+
+    let (status, _, bundle) = get(
+        &mut app,
+        &alice_session,
+        &format!("/users/{BOB_ID}/export/fhir"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    assert_eq!(of_type(&bundle, "Encounter").len(), 2);
+    let conditions = of_type(&bundle, "Condition");
+    assert_eq!(conditions[0]["code"]["text"], "Common cold");
+    assert_eq!(
+        conditions[0]["encounter"]["reference"],
+        "urn:uuid:5b1c7a0e-2f4d-4a8e-9c3b-7d6e5f4a3b2c"
+    );
+    let requests = of_type(&bundle, "MedicationRequest");
+    assert_eq!(requests.len(), 2);
+    assert_eq!(
+        requests[0]["medicationCodeableConcept"]["text"],
+        "Paracetamol"
+    );
+    assert_eq!(
+        requests[0]["dosageInstruction"][0]["timing"]["repeat"]["frequency"],
+        3
+    );
+    assert_eq!(
+        requests[0]["dosageInstruction"][0]["doseAndRate"][0]["doseQuantity"]["value"],
+        1.0
+    );
+
+    // every part read shows up for the patient
+    let (_, _, access_log) =
+        get(&mut app, &bob_session, "/me/access-log").await;
+    assert!(
+        access_log
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|entry| entry["resource"] == "prescription")
+    );
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn stranger_cannot_export(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (alice_session, _user_id) = login_as_alice(&mut app).await;
+
+    let (status, _, _) = get(
+        &mut app,
+        &alice_session,
+        &format!("/users/{BOB_ID}/export/fhir"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
@@ -0,0 +1,266 @@
+mod common;
+
+use axum::http::StatusCode;
+use serde_json::{Value, json};
+use sqlx::Pool;
+use sqlx::postgres::Postgres;
+
+use common::*;
+
+// .route("/me/import/fhir", post(import_own_fhir))
+// .route("/me/imports", get(get_own_imports))
+// .route("/me/external-encounters", get(get_own_external_encounters))
+
+fn bundle() -> Value {
+    json!({
+        "resourceType": "Bundle",
+        "type": "collection",
+        "meta": { "source": "https://rs-sehat.example/fhir" },
+        "entry": [
+            {
+                "fullUrl": "urn:uuid:patient",
+                "resource": { "resourceType": "Patient", "id": "patient" }
+            },
+            {
+                "fullUrl": "urn:uuid:peanut",
+                "resource": {
+                    "resourceType": "AllergyIntolerance",
+                    "code": { "text": "Peanut" },
+                    "reaction": [{
+                        "manifestation": [{ "text": "Anaphylaxis" }]
+                    }]
+                }
+            },
+            {
+                "fullUrl": "urn:uuid:pollen",
+                "resource": {
+                    "resourceType": "AllergyIntolerance",
+                    "code": { "text": "Pollen" },
+                    "criticality": "low"
+                }
+            },
+            {
+                "fullUrl": "urn:uuid:asthma",
+                "resource": {
+                    "resourceType": "Condition",
+                    "clinicalStatus": { "coding": [{ "code": "active" }] },
+                    "code": { "text": "Asthma" }
+                }
+            },
+            {
+                "fullUrl": "urn:uuid:height",
+                "resource": {
+                    "resourceType": "Observation",
+                    "status": "final",
+                    "code": { "coding": [{
+                        "system": "http://loinc.org", "code": "8302-2"
+                    }] },
+                    "effectiveDateTime": "2025-01-02T03:04:05Z",
+                    "valueQuantity": { "value": 1.62, "unit": "m" }
+                }
+            },
+            {
+                "fullUrl": "urn:uuid:weight",
+                "resource": {
+                    "resourceType": "Observation",
+                    "status": "final",
+                    "code": { "coding": [{
+                        "system": "http://loinc.org", "code": "29463-7"
+                    }] },
+                    "effectiveDateTime": "2025-01-02T03:04:05Z",
+                    "valueQuantity": { "value": 55, "unit": "kg" }
+                }
+            },
+            {
+                "fullUrl": "urn:uuid:visit",
+                "resource": {
+                    "resourceType": "Encounter",
+                    "status": "finished",
+                    "period": { "start": "2025-01-02T03:00:00Z" },
+                    "serviceProvider": { "display": "RS Sehat" },
+                    "reasonCode": [{ "text": "Shortness of breath" }]
+                }
+            },
+            {
+                "fullUrl": "urn:uuid:immunization",
+                "resource": { "resourceType": "Immunization" }
+            }
+        ]
+    })
+}
+
+/// Source ids of the items of one part of the report
+fn source_ids(report: &Value, part: &str) -> Vec<String> {
+    report[part]
+        .as_array()
+        .unwrap()
+        .iter()
+        .flat_map(|item| item["source_ids"].as_array().unwrap())
+        .map(|id| id.as_str().unwrap().to_string())
+        .collect()
+}
+
+#[sqlx::test(fixtures("users", "allergies"))]
+async fn dry_run_creates_nothing(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_alice(&mut app).await;
+
+    let (status, report) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        "/me/import/fhir?dry_run=true",
+        Some(bundle()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(report["dry_run"], true);
+    assert!(report["import_id"].is_null());
+    assert_eq!(
+        source_ids(&report, "created"),
+        [
+            "urn:uuid:peanut",
+            "urn:uuid:asthma",
+            "urn:uuid:visit",
+            "urn:uuid:height",
+            "urn:uuid:weight"
+        ]
+    );
+    // alice has a pollen allergy already
+    assert_eq!(
+        source_ids(&report, "skipped"),
+        ["urn:uuid:patient", "urn:uuid:pollen"]
+    );
+    assert_eq!(source_ids(&report, "unmappable"), ["urn:uuid:immunization"]);
+    assert!(report["created"][0].get("row_id").is_none());
+
+    let (_, imports) =
+        send(&mut app, Some(&session_id), "GET", "/me/imports", None).await;
+    assert_eq!(imports, json!([]));
+    let (_, allergies) =
+        send(&mut app, Some(&session_id), "GET", "/me/allergies", None).await;
+    assert_eq!(allergies.as_array().unwrap().len(), 1);
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn import_keeps_provenance(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_alice(&mut app).await;
+
+    let (status, report) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        "/me/import/fhir",
+        Some(bundle()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(report["created"].as_array().unwrap().len(), 5);
+    let import_id = report["import_id"].as_str().unwrap();
+
+    let (_, allergies) =
+        send(&mut app, Some(&session_id), "GET", "/me/allergies", None).await;
+    let peanut = allergies
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|allergy| allergy["allergen"] == "Peanut")
+        .unwrap();
+    assert_eq!(peanut["severity"], "ANAPHYLACTIC_SHOCK");
+
+    let (_, measurements) =
+        send(&mut app, Some(&session_id), "GET", "/me/measurements", None)
+            .await;
+    assert_eq!(measurements[0]["height_in_cm"], 162.0);
+    assert_eq!(measurements[0]["weight_in_kg"], 55.0);
+
+    let (status, encounters) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        "/me/external-encounters",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(encounters[0]["facility"], "RS Sehat");
+    assert_eq!(encounters[0]["reason"], "Shortness of breath");
+    assert_eq!(encounters[0]["import_id"], import_id);
+
+    let (_, imports) =
+        send(&mut app, Some(&session_id), "GET", "/me/imports", None).await;
+    assert_eq!(imports[0]["import_id"], import_id);
+    assert_eq!(imports[0]["source"], "https://rs-sehat.example/fhir");
+    let records = imports[0]["records"].as_array().unwrap();
+    assert_eq!(records.len(), 6);
+    let record = records
+        .iter()
+        .find(|record| record["source_id"] == "urn:uuid:peanut")
+        .unwrap();
+    assert_eq!(record["target"], "allergy");
+    assert_eq!(record["row_id"], peanut["allergy_id"]);
+    // both observations point at the one measurement
+    let measurement_rows: Vec<&Value> = records
+        .iter()
+        .filter(|record| record["target"] == "measurement")
+        .map(|record| &record["row_id"])
+        .collect();
+    assert_eq!(measurement_rows.len(), 2);
+    assert_eq!(measurement_rows[0], measurement_rows[1]);
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn reimport_skips_recorded(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_alice(&mut app).await;
+
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        "/me/import/fhir",
+        Some(bundle()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, report) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        "/me/import/fhir",
+        Some(bundle()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(report["created"], json!([]));
+    let skipped = report["skipped"].as_array().unwrap();
+    assert_eq!(skipped.len(), 6);
+    assert!(
+        skipped[1..]
+            .iter()
+            .all(|item| item["reason"] == "already recorded")
+    );
+
+    let (_, allergies) =
+        send(&mut app, Some(&session_id), "GET", "/me/allergies", None).await;
+    assert_eq!(allergies.as_array().unwrap().len(), 2);
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn import_rejects_non_bundle(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_alice(&mut app).await;
+
+    let (status, body) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        "/me/import/fhir",
+        Some(json!({ "resourceType": "Patient", "id": "patient" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body, json!({ "error": "Not a FHIR R4 Bundle" }));
+}
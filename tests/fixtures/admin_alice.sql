@@ -0,0 +1,8 @@
+INSERT INTO admins (user_id, promoted_by, promoted_at)
+VALUES
+    ('d3969164-86ea-442d-a589-79de89116f9c', '080d497e-696b-423d-80f1-331014fb4bf4', '1970-03-01 00:00:00+00');
+
+-- admins have to enroll a second factor, the secret is `JBSWY3DPEHPK3PXP`
+INSERT INTO user_totp (user_id, secret, created_at, enabled_at)
+VALUES
+    ('d3969164-86ea-442d-a589-79de89116f9c', 'JBSWY3DPEHPK3PXP', '1970-03-01 00:00:00+00', '1970-03-01 00:00:00+00');
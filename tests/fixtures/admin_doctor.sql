@@ -0,0 +1,4 @@
+-- on top of `doctor_info`, which already enrolls alice's second factor
+INSERT INTO admins (user_id, promoted_by, promoted_at)
+VALUES
+    ('d3969164-86ea-442d-a589-79de89116f9c', '080d497e-696b-423d-80f1-331014fb4bf4', '1970-03-01 00:00:00+00');
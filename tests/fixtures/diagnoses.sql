@@ -0,0 +1,4 @@
+-- bob was diagnosed with a cold at his first consultation
+INSERT INTO diagnoses (diagnosis_id, consultation_id, diagnosis, severity)
+VALUES
+    ('e5c1a3b2-8f4d-4a9c-b7e0-2b3c4d5e6f70', '5b1c7a0e-2f4d-4a8e-9c3b-7d6e5f4a3b2c', 'Common cold', 'MILD');
@@ -0,0 +1,17 @@
+-- on top of `pharmacist`: admin practises too and prescribed bob amoxicillin,
+-- which hasn't been dispensed yet
+INSERT INTO doctor_profiles (doctor_id, user_id, created_at, approved_at, approved_by)
+VALUES
+    ('5e6f7a8b-9c0d-4e1f-8a2b-3c4d5e6f7a8b', '080d497e-696b-423d-80f1-331014fb4bf4', '1970-03-01 00:00:00+00', '1970-03-01 00:00:00+00', '080d497e-696b-423d-80f1-331014fb4bf4');
+
+INSERT INTO doctor_practice_locations (location_id, doctor_id, practice_permit, practice_address, approved_at, approved_by, created_at)
+VALUES
+    ('6f7a8b9c-0d1e-4f2a-9b3c-4d5e6f7a8b9c', '5e6f7a8b-9c0d-4e1f-8a2b-3c4d5e6f7a8b', '420/SIP-003/Dinkes/I/2025', 'Jl. Palmerah Barat No.8, Jakarta Barat 11480', '1970-03-04 00:00:00+00', '080d497e-696b-423d-80f1-331014fb4bf4', '1970-03-01 00:00:00+00');
+
+INSERT INTO consultations (consultation_id, user_id, doctor_id, location_id, symptoms, created_at, reminded)
+VALUES
+    ('7a8b9c0d-1e2f-4a3b-8c4d-6e7f8a9b0c1d', '41490144-e4e1-4d1f-9eb7-f90af81c12ce', '5e6f7a8b-9c0d-4e1f-8a2b-3c4d5e6f7a8b', '6f7a8b9c-0d1e-4f2a-9b3c-4d5e6f7a8b9c', 'sore throat', '1970-03-10 00:00:00+00', FALSE);
+
+INSERT INTO prescriptions (prescription_id, consultation_id, drug_name, doses_in_mg, regimen_per_day, quantity_per_dose, instruction)
+VALUES
+    ('8b9c0d1e-2f3a-4b4c-9d5e-7f8a9b0c1d2e', '7a8b9c0d-1e2f-4a3b-8c4d-6e7f8a9b0c1d', 'Amoxicillin', 500, 3, 1, 'until finished');
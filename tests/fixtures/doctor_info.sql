@@ -6,3 +6,8 @@ INSERT INTO doctor_practice_locations (location_id, doctor_id, practice_permit,
 VALUES
     ('fbc0a545-f266-495d-91a1-667479a13ace', 'a5ca9dee-89b4-4228-aff5-506b995f3b42', '420/SIP-001/Dinkes/I/2025', 'Jl. Raya Kb. Jeruk No.27, RT.1/RW.9, Kemanggisan, Kec. Palmerah, Kota Jakarta Barat, Daerah Khusus Ibukota Jakarta 11530', '1970-03-04 00:00:00+00', '080d497e-696b-423d-80f1-331014fb4bf4', '1970-03-01 00:00:00+00');
 
+
+-- doctors have to enroll a second factor, the secret is `JBSWY3DPEHPK3PXP`
+INSERT INTO user_totp (user_id, secret, created_at, enabled_at)
+VALUES
+    ('d3969164-86ea-442d-a589-79de89116f9c', 'JBSWY3DPEHPK3PXP', '1970-03-01 00:00:00+00', '1970-03-01 00:00:00+00');
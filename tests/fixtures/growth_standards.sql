@@ -0,0 +1,9 @@
+-- Round made-up parameters for boys around their first birthday, not WHO's
+INSERT INTO growth_standards (indicator, sex, age_in_months, l, m, s)
+VALUES
+    ('HEIGHT_FOR_AGE', 'M', 12, 1, 75, 0.04),
+    ('HEIGHT_FOR_AGE', 'M', 13, 1, 75, 0.04),
+    ('WEIGHT_FOR_AGE', 'M', 12, 1, 10, 0.1),
+    ('WEIGHT_FOR_AGE', 'M', 13, 1, 10, 0.1),
+    ('BMI_FOR_AGE', 'M', 12, 1, 20, 0.1),
+    ('BMI_FOR_AGE', 'M', 13, 1, 20, 0.1);
@@ -0,0 +1,7 @@
+INSERT INTO medicines (medicine_id, name, dosage_form, composition_notes)
+VALUES
+    ('0d8f6c2e-4b1a-4f7e-9c3d-2a5b6e7f8a91', 'Amoxil', 'capsule', NULL);
+
+INSERT INTO medicine_ingredients (medicine_ingredient_id, medicine_id, ingredient, dosage_in_mg)
+VALUES
+    ('3e9a7b1c-5d2f-4a8e-8b6c-1f2e3d4c5b6a', '0d8f6c2e-4b1a-4f7e-9c3d-2a5b6e7f8a91', 'amoxicillin', 500);
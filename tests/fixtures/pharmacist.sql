@@ -0,0 +1,12 @@
+INSERT INTO user_roles (user_id, role, granted_by, granted_at)
+VALUES
+    ('d3969164-86ea-442d-a589-79de89116f9c', 'pharmacist', '080d497e-696b-423d-80f1-331014fb4bf4', '1970-03-01 00:00:00+00');
+
+INSERT INTO allergies (allergy_id, user_id, allergen, severity)
+VALUES
+    ('5b0d3a57-1c4e-4f6a-8e0e-3f2b9d1c7a21', '41490144-e4e1-4d1f-9eb7-f90af81c12ce', 'penicillin', 'SEVERE');
+
+-- pharmacists have to enroll a second factor, the secret is `JBSWY3DPEHPK3PXP`
+INSERT INTO user_totp (user_id, secret, created_at, enabled_at)
+VALUES
+    ('d3969164-86ea-442d-a589-79de89116f9c', 'JBSWY3DPEHPK3PXP', '1970-03-01 00:00:00+00', '1970-03-01 00:00:00+00');
@@ -0,0 +1,10 @@
+-- bob was seen by alice twice and set a reminder for the first consultation
+INSERT INTO consultations (consultation_id, user_id, doctor_id, location_id, symptoms, created_at, reminded)
+VALUES
+    ('5b1c7a0e-2f4d-4a8e-9c3b-7d6e5f4a3b2c', '41490144-e4e1-4d1f-9eb7-f90af81c12ce', 'a5ca9dee-89b4-4228-aff5-506b995f3b42', 'fbc0a545-f266-495d-91a1-667479a13ace', 'fever and cough', '1970-03-10 00:00:00+00', TRUE),
+    ('8e2d4f6a-1b3c-4d5e-8f7a-9b0c1d2e3f4a', '41490144-e4e1-4d1f-9eb7-f90af81c12ce', 'a5ca9dee-89b4-4228-aff5-506b995f3b42', 'fbc0a545-f266-495d-91a1-667479a13ace', 'back pain', '1970-03-20 00:00:00+00', FALSE);
+
+INSERT INTO prescriptions (prescription_id, consultation_id, drug_name, doses_in_mg, regimen_per_day, quantity_per_dose, instruction)
+VALUES
+    ('c3a9e1f0-6d2b-4e7a-b5c8-0f1e2d3c4b5a', '5b1c7a0e-2f4d-4a8e-9c3b-7d6e5f4a3b2c', 'Paracetamol', 500, 3, 1, 'after meals'),
+    ('d4b0f2a1-7e3c-4f8b-a6d9-1a2b3c4d5e6f', '8e2d4f6a-1b3c-4d5e-8f7a-9b0c1d2e3f4a', 'Ibuprofen', 400, 2, 1, 'after meals');
@@ -0,0 +1,31 @@
+-- on top of `doctor_info`: bob practises as well, with the same second factor
+-- as alice, and both of them saw carol (password `test`). alice diagnosed a
+-- cold and prescribed paracetamol, bob looked at a sprained ankle
+INSERT INTO users (user_id, email, password_hash)
+VALUES
+    ('6f1e2d3c-4b5a-4968-8776-5a4b3c2d1e0f', 'carol@example.com', '$argon2id$v=19$m=19456,t=2,p=1$IICbY2zraHSN1biU03ZTYA$YcdL6uN+9Tzj+b11aDyazK+R7yQE6ZF8HNC2xdzdYSQ');
+
+INSERT INTO doctor_profiles (doctor_id, user_id, created_at, approved_at, approved_by)
+VALUES
+    ('0c8c2a3e-4d1b-4f0e-9a57-2f1a6c1d9b11', '41490144-e4e1-4d1f-9eb7-f90af81c12ce', '1970-03-01 00:00:00+00', '1970-03-01 00:00:00+00', '080d497e-696b-423d-80f1-331014fb4bf4');
+
+INSERT INTO doctor_practice_locations (location_id, doctor_id, practice_permit, practice_address, approved_at, approved_by, created_at)
+VALUES
+    ('2a7b9c1d-3e4f-4a5b-8c6d-7e8f9a0b1c2d', '0c8c2a3e-4d1b-4f0e-9a57-2f1a6c1d9b11', '420/SIP-002/Dinkes/I/2025', 'Jl. Kemanggisan Raya No.19, Palmerah, Jakarta Barat 11480', '1970-03-04 00:00:00+00', '080d497e-696b-423d-80f1-331014fb4bf4', '1970-03-01 00:00:00+00');
+
+INSERT INTO user_totp (user_id, secret, created_at, enabled_at)
+VALUES
+    ('41490144-e4e1-4d1f-9eb7-f90af81c12ce', 'JBSWY3DPEHPK3PXP', '1970-03-01 00:00:00+00', '1970-03-01 00:00:00+00');
+
+INSERT INTO consultations (consultation_id, user_id, doctor_id, location_id, symptoms, created_at, reminded)
+VALUES
+    ('7a8b9c0d-1e2f-4a3b-9c4d-5e6f7a8b9c0d', '6f1e2d3c-4b5a-4968-8776-5a4b3c2d1e0f', 'a5ca9dee-89b4-4228-aff5-506b995f3b42', 'fbc0a545-f266-495d-91a1-667479a13ace', 'fever and cough', '1970-03-10 00:00:00+00', FALSE),
+    ('9c0d1e2f-3a4b-4c5d-8e6f-7a8b9c0d1e2f', '6f1e2d3c-4b5a-4968-8776-5a4b3c2d1e0f', '0c8c2a3e-4d1b-4f0e-9a57-2f1a6c1d9b11', '2a7b9c1d-3e4f-4a5b-8c6d-7e8f9a0b1c2d', 'sprained ankle', '1970-03-20 00:00:00+00', FALSE);
+
+INSERT INTO prescriptions (prescription_id, consultation_id, drug_name, doses_in_mg, regimen_per_day, quantity_per_dose, instruction)
+VALUES
+    ('1d2e3f4a-5b6c-4d7e-8f9a-0b1c2d3e4f5a', '7a8b9c0d-1e2f-4a3b-9c4d-5e6f7a8b9c0d', 'Paracetamol', 500, 3, 1, 'after meals');
+
+INSERT INTO diagnoses (diagnosis_id, consultation_id, diagnosis, severity)
+VALUES
+    ('3f4a5b6c-7d8e-4f9a-8b0c-1d2e3f4a5b6c', '7a8b9c0d-1e2f-4a3b-9c4d-5e6f7a8b9c0d', 'Common cold', 'MILD');
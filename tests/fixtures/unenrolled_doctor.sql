@@ -0,0 +1,3 @@
+INSERT INTO doctor_profiles (doctor_id, user_id, created_at, approved_at, approved_by)
+VALUES
+    ('0c8c2a3e-4d1b-4f0e-9a57-2f1a6c1d9b11', '41490144-e4e1-4d1f-9eb7-f90af81c12ce', '1970-03-01 00:00:00+00', '1970-03-01 00:00:00+00', '080d497e-696b-423d-80f1-331014fb4bf4');
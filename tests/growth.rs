@@ -0,0 +1,116 @@
+mod common;
+
+use axum::http::StatusCode;
+use serde_json::Value;
+use serde_json::json;
+use sqlx::Pool;
+use sqlx::postgres::Postgres;
+
+use common::*;
+
+// .route("/users/{user_id}/growth", get(get_user_growth))
+// .route("/me/growth", get(get_own_growth))
+
+const BOB_ID: &str = "41490144-e4e1-4d1f-9eb7-f90af81c12ce";
+
+#[sqlx::test(fixtures("users", "growth_standards"))]
+async fn growth_of_a_toddler(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_bob(&mut app).await;
+
+    // no date of birth to tell the age by
+    let (status, _) =
+        send(&mut app, Some(&session_id), "GET", "/me/growth", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "PUT",
+        "/me/details",
+        Some(json!({
+            "nik": 1000000000000001i64,
+            "name": "bob",
+            "dob": "2024-01-15",
+            "gender": "M",
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    for (height, weight, measured_at) in [
+        (50.0, 3.3, "2024-01-15T08:00:00Z"),
+        (72.0, 11.0, "2025-01-15T08:00:00Z"),
+    ] {
+        let (status, _) = send(
+            &mut app,
+            Some(&session_id),
+            "POST",
+            "/me/measurements",
+            Some(json!({
+                "height_in_cm": height,
+                "weight_in_kg": weight,
+                "measured_at": measured_at,
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    let (status, growth) =
+        send(&mut app, Some(&session_id), "GET", "/me/growth", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(growth["gender"], "M");
+    let points = growth["points"].as_array().unwrap();
+    assert_eq!(points.len(), 2);
+
+    // nothing to compare a newborn to without the tables of birth
+    assert_eq!(points[0]["age_in_months"], 0.0);
+    assert_eq!(points[0]["height_for_age"], Value::Null);
+    assert_eq!(points[0]["weight_for_age"], Value::Null);
+
+    assert_eq!(points[1]["age_in_months"], 12.02);
+    assert_eq!(
+        points[1]["height_for_age"],
+        json!({ "value": 72.0, "z_score": -1.0, "percentile": 15.9 })
+    );
+    assert_eq!(
+        points[1]["weight_for_age"],
+        json!({ "value": 11.0, "z_score": 1.0, "percentile": 84.1 })
+    );
+    assert_eq!(points[1]["bmi_for_age"]["z_score"], 0.61);
+    assert_eq!(points[1]["bmi_for_age"]["percentile"], 72.9);
+
+    let (alice_session, _) = login_as_alice(&mut app).await;
+    let (status, _) = send(
+        &mut app,
+        Some(&alice_session),
+        "GET",
+        &format!("/users/{BOB_ID}/growth"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[sqlx::test(fixtures("users", "details", "growth_standards"))]
+async fn no_growth_of_adults(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_alice(&mut app).await;
+
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        "/me/measurements",
+        Some(json!({ "height_in_cm": 165.0, "weight_in_kg": 60.0 })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, growth) =
+        send(&mut app, Some(&session_id), "GET", "/me/growth", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(growth["dob"], "1970-01-01");
+    assert_eq!(growth["points"], json!([]));
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use http_body_util::BodyExt;
+use serde_json::Value;
+use sqlx::Pool;
+use sqlx::postgres::Postgres;
+use tower::{Service, ServiceExt};
+
+use common::*;
+
+// .route("/icd10", get(search_icd10_codes))
+
+async fn search(
+    app: &mut axum::Router,
+    session_id: &str,
+    q: &str,
+) -> Vec<String> {
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/icd10?q={q}"))
+        .header("Authorization", format!("Bearer {session_id}"))
+        .body(Body::empty())
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let codes: Value = serde_json::from_slice(&body).unwrap();
+    codes
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|code| code["code"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn icd10_codes_autocomplete(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_alice(&mut app).await;
+
+    assert_eq!(
+        search(&mut app, &session_id, "j0").await,
+        ["J00", "J01", "J02", "J03", "J06.9"]
+    );
+    assert_eq!(
+        search(&mut app, &session_id, "common%20cold").await,
+        ["J00"]
+    );
+    // by code first, then by title
+    assert_eq!(
+        search(&mut app, &session_id, "E1").await.first().unwrap(),
+        "E10"
+    );
+    assert!(search(&mut app, &session_id, "%25").await.is_empty());
+}
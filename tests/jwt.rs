@@ -0,0 +1,252 @@
+mod common;
+
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use http_body_util::BodyExt;
+use serde_json::Value;
+use serde_json::json;
+use sqlx::Pool;
+use sqlx::postgres::Postgres;
+use tower::{Service, ServiceExt};
+
+use common::*;
+
+// .route("/token/refresh", post(jwt::refresh_tokens))
+// .route("/.well-known/jwks.json", get(jwt::get_jwks))
+
+async fn login(app: &mut Router, email: &str, code: Option<String>) -> Value {
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/login"))
+        .method("POST")
+        .header("Content-Type", "application/json")
+        .body(Body::from(
+            json!({
+                "email": email,
+                "password": "test",
+            })
+            .to_string(),
+        ))
+        .unwrap();
+
+    let mut response = ServiceExt::<Request<Body>>::ready(&mut *app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    if let Some(code) = code {
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+
+        let request = Request::builder()
+            .uri(format!("http://{API_ROOT_URL}/login/two-factor"))
+            .method("POST")
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                json!({
+                    "challenge_id": body["challenge_id"],
+                    "code": code,
+                })
+                .to_string(),
+            ))
+            .unwrap();
+
+        response = ServiceExt::<Request<Body>>::ready(&mut *app)
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    serde_json::from_slice(&body).unwrap()
+}
+
+async fn get(app: &mut Router, uri: &str, token: &str) -> StatusCode {
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}{uri}"))
+        .method("GET")
+        .header("Authorization", format!("Bearer {token}"))
+        .body(Body::empty())
+        .unwrap();
+
+    ServiceExt::<Request<Body>>::ready(app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap()
+        .status()
+}
+
+async fn refresh(app: &mut Router, body: &Value) -> (StatusCode, Value) {
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/token/refresh"))
+        .method("POST")
+        .header("Content-Type", "application/json")
+        .body(Body::from(
+            json!({
+                "refresh_token": body["refresh_token"],
+                "device_id": body["device_id"],
+            })
+            .to_string(),
+        ))
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn login_issues_tokens(db_pool: Pool<Postgres>) {
+    let mut app = get_app_with_jwt(db_pool);
+    let body = login(&mut app, "bob@example.com", None).await;
+
+    assert!(body.get("session_id").is_none());
+    assert_eq!(body["expires_in"], json!(600));
+
+    let access_token = body["access_token"].as_str().unwrap();
+    assert_eq!(get(&mut app, "/me", access_token).await, StatusCode::OK);
+    assert_eq!(
+        get(&mut app, "/me", "not.a.token").await,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[sqlx::test(fixtures("users", "doctor_info"))]
+async fn doctor_authorized_from_token(db_pool: Pool<Postgres>) {
+    let mut app = get_app_with_jwt(db_pool);
+    let body = login(
+        &mut app,
+        "alice@example.com",
+        Some(current_totp_code(ALICE_TOTP_SECRET)),
+    )
+    .await;
+
+    let access_token = body["access_token"].as_str().unwrap();
+    assert_eq!(
+        get(&mut app, "/doctor/consultations", access_token).await,
+        StatusCode::OK
+    );
+
+    let body = login(&mut app, "bob@example.com", None).await;
+    let access_token = body["access_token"].as_str().unwrap();
+    assert_eq!(
+        get(&mut app, "/doctor/consultations", access_token).await,
+        StatusCode::FORBIDDEN
+    );
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn refresh_token_rotates(db_pool: Pool<Postgres>) {
+    let mut app = get_app_with_jwt(db_pool);
+    let login_body = login(&mut app, "bob@example.com", None).await;
+
+    let (status, refreshed) = refresh(&mut app, &login_body).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(refreshed["refresh_token"], login_body["refresh_token"]);
+
+    let access_token = refreshed["access_token"].as_str().unwrap();
+    assert_eq!(get(&mut app, "/me", access_token).await, StatusCode::OK);
+
+    // reusing the first token also burns the one it was traded for
+    let (status, _) = refresh(&mut app, &login_body).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let refreshed = json!({
+        "refresh_token": refreshed["refresh_token"],
+        "device_id": login_body["device_id"],
+    });
+    let (status, _) = refresh(&mut app, &refreshed).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn refresh_token_bound_to_device(db_pool: Pool<Postgres>) {
+    let mut app = get_app_with_jwt(db_pool);
+    let login_body = login(&mut app, "bob@example.com", None).await;
+    let other_login_body = login(&mut app, "bob@example.com", None).await;
+
+    let mismatched = json!({
+        "refresh_token": login_body["refresh_token"],
+        "device_id": other_login_body["device_id"],
+    });
+    let (status, _) = refresh(&mut app, &mismatched).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/me/devices/logout-all"))
+        .method("POST")
+        .header(
+            "Authorization",
+            format!("Bearer {}", login_body["access_token"].as_str().unwrap()),
+        )
+        .body(Body::empty())
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(&mut app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let (status, _) = refresh(&mut app, &login_body).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn jwks(db_pool: Pool<Postgres>) {
+    let mut app = get_app_with_jwt(db_pool.clone());
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/.well-known/jwks.json"))
+        .method("GET")
+        .body(Body::empty())
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(&mut app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(body["keys"][0]["kty"], json!("OKP"));
+    assert_eq!(body["keys"][0]["crv"], json!("Ed25519"));
+    assert_eq!(body["keys"][0]["alg"], json!("EdDSA"));
+
+    // disabled outside of JWT mode
+    let mut app = get_app(db_pool);
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/.well-known/jwks.json"))
+        .method("GET")
+        .body(Body::empty())
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(&mut app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,227 @@
+mod common;
+
+use axum::Router;
+use axum::http::StatusCode;
+use serde_json::Value;
+use serde_json::json;
+use sqlx::Pool;
+use sqlx::postgres::Postgres;
+
+use common::*;
+
+// .route("/me/medical-conditions", get(get_own_conditions))
+// .route("/me/medical-conditions", post(post_own_conditions))
+// .route("/me/medical-conditions/{condition_id}/status", put(put_own_condition_status))
+// .route("/me/medical-conditions/{condition_id}/history", get(get_own_condition_history))
+// .route("/users/{user_id}/medical-conditions/{condition_id}/confirm", post(confirm_user_condition))
+// .route("/me/medical-conditions/{condition_id}", delete(delete_own_conditions))
+
+const BOB_ID: &str = "41490144-e4e1-4d1f-9eb7-f90af81c12ce";
+/// bob's first consultation in the `prescriptions` fixture
+const COLD_CONSULTATION_ID: &str = "5b1c7a0e-2f4d-4a8e-9c3b-7d6e5f4a3b2c";
+
+/// Lists `condition` for the caller and returns its id
+async fn add_condition(
+    app: &mut Router,
+    session_id: &str,
+    condition: Value,
+) -> String {
+    let (status, _) = send(
+        app,
+        Some(session_id),
+        "POST",
+        "/me/medical-conditions",
+        Some(condition),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (_, conditions) =
+        send(app, Some(session_id), "GET", "/me/medical-conditions", None)
+            .await;
+    conditions[0]["condition_id"].as_str().unwrap().to_string()
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn condition_status_history(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_bob(&mut app).await;
+
+    let (status, body) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        "/me/medical-conditions",
+        Some(json!({ "condition": "Asthma", "icd10_code": "X99.9" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "Unknown ICD-10 code");
+
+    let condition_id = add_condition(
+        &mut app,
+        &session_id,
+        json!({
+            "condition": "Asthma",
+            "onset": "2010-05-01",
+            "icd10_code": "j45",
+        }),
+    )
+    .await;
+    let (_, conditions) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        "/me/medical-conditions",
+        None,
+    )
+    .await;
+    assert_eq!(conditions[0]["onset"], "2010-05-01");
+    assert_eq!(conditions[0]["status"], "ACTIVE");
+    assert_eq!(conditions[0]["icd10_code"], "J45");
+    assert_eq!(conditions[0]["source"], "SELF_REPORTED");
+    assert_eq!(conditions[0]["confirmed_by"], Value::Null);
+
+    let uri = format!("/me/medical-conditions/{condition_id}/status");
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "PUT",
+        &uri,
+        Some(json!({ "status": "IN_REMISSION" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "PUT",
+        &uri,
+        Some(json!({ "status": "IN_REMISSION" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, history) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        &format!("/me/medical-conditions/{condition_id}/history"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(history.as_array().unwrap().len(), 2);
+    assert_eq!(history[0]["from_status"], Value::Null);
+    assert_eq!(history[0]["to_status"], "ACTIVE");
+    assert_eq!(history[1]["from_status"], "ACTIVE");
+    assert_eq!(history[1]["to_status"], "IN_REMISSION");
+}
+
+#[sqlx::test(fixtures("users", "doctor_info", "prescriptions", "diagnoses"))]
+async fn doctor_confirms_self_reported_condition(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (bob_session, _) = login_as_bob(&mut app).await;
+    let condition_id = add_condition(
+        &mut app,
+        &bob_session,
+        json!({ "condition": "High blood pressure" }),
+    )
+    .await;
+    let uri =
+        format!("/users/{BOB_ID}/medical-conditions/{condition_id}/confirm");
+
+    // bob can't vouch for himself
+    let (status, _) =
+        send(&mut app, Some(&bob_session), "POST", &uri, Some(json!({}))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (alice_session, _) = login_as_alice(&mut app).await;
+    let (status, _) = send(
+        &mut app,
+        Some(&alice_session),
+        "POST",
+        &uri,
+        Some(json!({ "icd10_code": "i10" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = send(
+        &mut app,
+        Some(&alice_session),
+        "POST",
+        &uri,
+        Some(json!({})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (_, conditions) = send(
+        &mut app,
+        Some(&alice_session),
+        "GET",
+        &format!("/users/{BOB_ID}/medical-conditions"),
+        None,
+    )
+    .await;
+    assert_eq!(conditions[0]["icd10_code"], "I10");
+    assert_eq!(conditions[0]["source"], "SELF_REPORTED");
+    assert!(conditions[0]["confirmed_at"].is_string());
+}
+
+#[sqlx::test(fixtures("users", "doctor_info", "prescriptions", "diagnoses"))]
+async fn diagnosed_condition_cannot_be_removed(db_pool: Pool<Postgres>) {
+    let diagnosed: String = sqlx::query_scalar(
+        "INSERT INTO medical_conditions (user_id, condition, source, \
+         consultation_id) VALUES ($1::UUID, 'Psoriasis', 'DIAGNOSED', $2::UUID) \
+         RETURNING condition_id::TEXT",
+    )
+    .bind(BOB_ID)
+    .bind(COLD_CONSULTATION_ID)
+    .fetch_one(&db_pool)
+    .await
+    .unwrap();
+
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_bob(&mut app).await;
+    // listed before the diagnosed one by name
+    let reported = add_condition(
+        &mut app,
+        &session_id,
+        json!({ "condition": "Hay fever" }),
+    )
+    .await;
+
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "DELETE",
+        &format!("/me/medical-conditions/{diagnosed}"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "DELETE",
+        &format!("/me/medical-conditions/{reported}"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, conditions) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        "/me/medical-conditions",
+        None,
+    )
+    .await;
+    assert_eq!(conditions.as_array().unwrap().len(), 1);
+    assert_eq!(conditions[0]["condition_id"], diagnosed);
+}
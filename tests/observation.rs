@@ -0,0 +1,132 @@
+mod common;
+
+use axum::http::StatusCode;
+use serde_json::Value;
+use serde_json::json;
+use sqlx::Pool;
+use sqlx::postgres::Postgres;
+
+use common::*;
+
+// .route("/observation-types", get(get_observation_types))
+// .route("/users/{user_id}/observations", get(get_user_observations))
+// .route("/me/observations", get(get_own_observations))
+// .route("/me/observations", post(add_own_observations))
+
+const BOB_ID: &str = "41490144-e4e1-4d1f-9eb7-f90af81c12ce";
+
+#[sqlx::test(fixtures("users"))]
+async fn record_blood_pressure(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_bob(&mut app).await;
+
+    let (status, types) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        "/observation-types",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let types = types.as_array().unwrap();
+    assert!(types.iter().any(|kind| {
+        kind["code"] == "59408-5"
+            && kind["unit"] == "%"
+            && kind["category"] == "vital-signs"
+    }));
+
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        "/me/observations",
+        Some(json!({
+            "observations": [
+                { "code": "8480-6", "value": 120 },
+                { "code": "8462-4", "value": 80 },
+            ],
+            "observed_at": "2025-07-07T07:30:00Z",
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, observations) =
+        send(&mut app, Some(&session_id), "GET", "/me/observations", None)
+            .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(observations.as_array().unwrap().len(), 2);
+    assert_eq!(observations[0]["user_id"], BOB_ID);
+    assert_eq!(observations[0]["recorded_by"], BOB_ID);
+    assert_eq!(observations[0]["unit"], "mm[Hg]");
+    assert_eq!(observations[0]["observed_at"], "2025-07-07T07:30:00Z");
+    assert_eq!(observations[0]["consultation_id"], Value::Null);
+
+    let (_, observations) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        "/me/observations?code=8462-4",
+        None,
+    )
+    .await;
+    assert_eq!(observations.as_array().unwrap().len(), 1);
+    assert_eq!(observations[0]["name"], "Diastolic blood pressure");
+    assert_eq!(observations[0]["value"], 80.0);
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn reject_invalid_observations(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_bob(&mut app).await;
+
+    // an oxygen saturation over 100% is a typo
+    let (status, body) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        "/me/observations",
+        Some(json!({
+            "observations": [
+                { "code": "8867-4", "value": 64 },
+                { "code": "59408-5", "value": 970 },
+            ],
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body["error"],
+        "The observed value is out of range for its type"
+    );
+
+    let (status, body) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        "/me/observations",
+        Some(json!({ "observations": [{ "code": "0000-0", "value": 1 }] })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "Unknown observation code");
+
+    // nothing of the batch was kept
+    let (_, observations) =
+        send(&mut app, Some(&session_id), "GET", "/me/observations", None)
+            .await;
+    assert_eq!(observations, json!([]));
+
+    // nor can anybody else read them
+    let (alice_session, _) = login_as_alice(&mut app).await;
+    let (status, _) = send(
+        &mut app,
+        Some(&alice_session),
+        "GET",
+        &format!("/users/{BOB_ID}/observations"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
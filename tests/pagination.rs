@@ -0,0 +1,154 @@
+mod common;
+
+use axum::Router;
+use axum::http::{HeaderMap, StatusCode, header};
+use serde_json::{Value, json};
+use sqlx::Pool;
+use sqlx::postgres::Postgres;
+
+use common::*;
+
+// .route("/me/measurements", get(get_own_measurements))
+// .route("/me/consultations", get(get_own_consultations))
+// .route("/audit-events", get(get_audit_events))
+
+/// Rows of a page along with the headers pointing to the next one
+async fn get_page(
+    app: &mut Router,
+    session_id: &str,
+    uri: &str,
+) -> (StatusCode, HeaderMap, Value) {
+    let request = build_request(Some(session_id), "GET", uri, None);
+    let (status, headers, body) = send_request(app, request).await;
+    (
+        status,
+        headers,
+        serde_json::from_slice(&body).unwrap_or(Value::Null),
+    )
+}
+
+fn measured_at(rows: &Value) -> Vec<&str> {
+    rows.as_array()
+        .unwrap()
+        .iter()
+        .map(|row| row["measured_at"].as_str().unwrap())
+        .collect()
+}
+
+#[sqlx::test(fixtures("users", "measurements"))]
+async fn measurements_are_paged_by_cursor(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_alice(&mut app).await;
+
+    for month in ["02", "03", "04"] {
+        let (status, _) = send(
+            &mut app,
+            Some(&session_id),
+            "POST",
+            "/me/measurements",
+            Some(json!({
+                "height_in_cm": 160.0,
+                "weight_in_kg": 55.0,
+                "measured_at": format!("1970-{month}-01T00:00:00Z"),
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    // latest first by default
+    let (status, headers, rows) =
+        get_page(&mut app, &session_id, "/me/measurements?limit=3").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        measured_at(&rows),
+        [
+            "1970-04-01T00:00:00Z",
+            "1970-03-01T00:00:00Z",
+            "1970-02-01T00:00:00Z"
+        ]
+    );
+    let cursor = headers["x-next-cursor"].to_str().unwrap();
+    assert_eq!(
+        headers[header::LINK],
+        format!("</me/measurements?limit=3&cursor={cursor}>; rel=\"next\"")
+    );
+
+    let (status, headers, rows) = get_page(
+        &mut app,
+        &session_id,
+        &format!("/me/measurements?limit=3&cursor={cursor}"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(measured_at(&rows), ["1970-01-01T00:00:00Z"]);
+    assert!(!headers.contains_key("x-next-cursor"));
+    assert!(!headers.contains_key(header::LINK));
+
+    let (_, _, rows) = get_page(
+        &mut app,
+        &session_id,
+        "/me/measurements?order=asc&from=1970-02-01T00:00:00Z\
+         &to=1970-04-01T00:00:00Z",
+    )
+    .await;
+    assert_eq!(
+        measured_at(&rows),
+        ["1970-02-01T00:00:00Z", "1970-03-01T00:00:00Z"]
+    );
+}
+
+#[sqlx::test(fixtures("users", "auditor"))]
+async fn audit_events_are_paged_by_sequence(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_alice(&mut app).await;
+
+    for allergen in ["pollen", "beans"] {
+        let (status, _) = send(
+            &mut app,
+            Some(&session_id),
+            "POST",
+            "/me/allergies",
+            Some(json!({ "allergen": allergen, "severity": "MILD" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    let (status, headers, first) =
+        get_page(&mut app, &session_id, "/audit-events?order=asc&limit=1")
+            .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(first.as_array().unwrap().len(), 1);
+    let cursor = headers["x-next-cursor"].to_str().unwrap();
+
+    let (status, headers, second) = get_page(
+        &mut app,
+        &session_id,
+        &format!("/audit-events?order=asc&limit=1&cursor={cursor}"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        second[0]["seq"],
+        json!(first[0]["seq"].as_i64().unwrap() + 1)
+    );
+    assert_eq!(second[0]["prev_hash"], first[0]["hash"]);
+    assert!(!headers.contains_key("x-next-cursor"));
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn invalid_page_is_rejected(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_alice(&mut app).await;
+
+    for query in ["limit=0", "limit=1000", "cursor=nope", "order=sideways"] {
+        let (status, _, _) = get_page(
+            &mut app,
+            &session_id,
+            &format!("/me/consultations?{query}"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{query}");
+    }
+}
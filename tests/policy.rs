@@ -0,0 +1,187 @@
+mod common;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use http_body_util::BodyExt;
+use serde_json::{Value, json};
+use sqlx::Pool;
+use sqlx::postgres::Postgres;
+use tower::{Service, ServiceExt};
+
+use common::*;
+
+static ALICE_ID: &str = "d3969164-86ea-442d-a589-79de89116f9c";
+static BOB_ID: &str = "41490144-e4e1-4d1f-9eb7-f90af81c12ce";
+
+// .route("/users/{user_id}/roles", post(grant_role))
+// .route("/users/{user_id}/roles/{role}", delete(revoke_role))
+
+#[sqlx::test(fixtures("users", "allergies"))]
+async fn patient_denied_other_users_data(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _user_id) = login_as_bob(&mut app).await;
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/users/{ALICE_ID}/allergies"))
+        .method("GET")
+        .header("Authorization", format!("Bearer {session_id}"))
+        .body(Body::empty())
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(&mut app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[sqlx::test(fixtures("users", "pharmacist", "dispensing"))]
+async fn pharmacist_reads_allergies(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _user_id) = login_as_alice(&mut app).await;
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/users/{BOB_ID}/allergies"))
+        .method("GET")
+        .header("Authorization", format!("Bearer {session_id}"))
+        .body(Body::empty())
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(&mut app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body[0]["allergen"], json!("penicillin"));
+}
+
+#[sqlx::test(fixtures("users", "pharmacist"))]
+async fn pharmacist_denied_allergies_without_prescription(
+    db_pool: Pool<Postgres>,
+) {
+    let mut app = get_app(db_pool);
+    let (session_id, _user_id) = login_as_alice(&mut app).await;
+
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        &format!("/users/{BOB_ID}/allergies"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[sqlx::test(fixtures("users", "pharmacist"))]
+async fn pharmacist_denied_consultations(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _user_id) = login_as_alice(&mut app).await;
+    let request = Request::builder()
+        .uri(format!(
+            "http://{API_ROOT_URL}/users/{BOB_ID}/consultations"
+        ))
+        .method("GET")
+        .header("Authorization", format!("Bearer {session_id}"))
+        .body(Body::empty())
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(&mut app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[sqlx::test(fixtures("users", "admin_alice"))]
+async fn grant_and_revoke_role(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _user_id) = login_as_alice(&mut app).await;
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/users/{BOB_ID}/roles"))
+        .method("POST")
+        .header("Content-Type", "application/json")
+        .header("Authorization", format!("Bearer {session_id}"))
+        .body(Body::from(json!({ "role": "pharmacist" }).to_string()))
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(&mut app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let request = Request::builder()
+        .uri(format!(
+            "http://{API_ROOT_URL}/users/{BOB_ID}/roles/pharmacist"
+        ))
+        .method("DELETE")
+        .header("Authorization", format!("Bearer {session_id}"))
+        .body(Body::empty())
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(&mut app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[sqlx::test(fixtures("users", "admin_alice"))]
+async fn grant_implied_role(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _user_id) = login_as_alice(&mut app).await;
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/users/{BOB_ID}/roles"))
+        .method("POST")
+        .header("Content-Type", "application/json")
+        .header("Authorization", format!("Bearer {session_id}"))
+        .body(Body::from(json!({ "role": "doctor" }).to_string()))
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(&mut app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn grant_role_not_admin(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _user_id) = login_as_bob(&mut app).await;
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/users/{BOB_ID}/roles"))
+        .method("POST")
+        .header("Content-Type", "application/json")
+        .header("Authorization", format!("Bearer {session_id}"))
+        .body(Body::from(json!({ "role": "auditor" }).to_string()))
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(&mut app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
@@ -0,0 +1,173 @@
+mod common;
+
+use axum::Router;
+use axum::http::StatusCode;
+use serde_json::{Value, json};
+use sqlx::Pool;
+use sqlx::postgres::Postgres;
+
+use common::*;
+
+// .route("/me/search", get(search_own_history))
+// .route("/users/{user_id}/search", get(search_user_history))
+
+const BOB_ID: &str = "41490144-e4e1-4d1f-9eb7-f90af81c12ce";
+/// seen by both alice and bob in the `second_doctor` fixture
+const CAROL_ID: &str = "6f1e2d3c-4b5a-4968-8776-5a4b3c2d1e0f";
+
+async fn login_as_bob(app: &mut Router) -> String {
+    let (_, body) = send(
+        app,
+        None,
+        "POST",
+        "/login",
+        Some(json!({ "email": "bob@example.com", "password": "test" })),
+    )
+    .await;
+    body["session_id"].as_str().unwrap().to_string()
+}
+
+fn kinds(hits: &Value) -> Vec<&str> {
+    hits.as_array()
+        .unwrap()
+        .iter()
+        .map(|hit| hit["kind"].as_str().unwrap())
+        .collect()
+}
+
+#[sqlx::test(fixtures("users", "doctor_info", "prescriptions", "diagnoses"))]
+async fn patient_searches_own_history(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let session_id = login_as_bob(&mut app).await;
+
+    // "when was I last given paracetamol?"
+    let (status, hits) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        "/me/search?q=Paracetamol",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(kinds(&hits), ["prescription"]);
+    assert_eq!(hits[0]["text"], "Paracetamol: after meals");
+    assert_eq!(hits[0]["recorded_at"], "1970-03-10T00:00:00Z");
+
+    let (_, hits) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        "/me/search?q=after%20meals%20-ibuprofen",
+        None,
+    )
+    .await;
+    assert_eq!(kinds(&hits), ["prescription"]);
+    assert_eq!(
+        hits[0]["consultation_id"],
+        "5b1c7a0e-2f4d-4a8e-9c3b-7d6e5f4a3b2c"
+    );
+
+    let (_, hits) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        "/me/search?q=amoxicillin",
+        None,
+    )
+    .await;
+    assert_eq!(hits, json!([]));
+}
+
+#[sqlx::test(fixtures("users", "doctor_info", "prescriptions", "diagnoses"))]
+async fn attending_doctor_searches_patient(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_alice(&mut app).await;
+
+    let (status, hits) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        &format!("/users/{BOB_ID}/search?q=cold"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(kinds(&hits), ["diagnosis"]);
+    assert_eq!(hits[0]["text"], "Common cold");
+
+    // bob sees who searched
+    let bob_session = login_as_bob(&mut app).await;
+    let (_, log) =
+        send(&mut app, Some(&bob_session), "GET", "/me/access-log", None).await;
+    assert!(!log.as_array().unwrap().is_empty());
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn stranger_cannot_search(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_alice(&mut app).await;
+
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        &format!("/users/{BOB_ID}/search?q=cold"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[sqlx::test(fixtures("users", "doctor_info", "second_doctor"))]
+async fn attending_doctor_finds_only_own_consultations(
+    db_pool: Pool<Postgres>,
+) {
+    let mut app = get_app(db_pool);
+    // bob practises in this fixture, so he signs in with his second factor
+    let (_, challenge) = send(
+        &mut app,
+        None,
+        "POST",
+        "/login",
+        Some(json!({ "email": "bob@example.com", "password": "test" })),
+    )
+    .await;
+    let (status, login) = send(
+        &mut app,
+        None,
+        "POST",
+        "/login/two-factor",
+        Some(json!({
+            "challenge_id": challenge["challenge_id"],
+            "code": current_totp_code(ALICE_TOTP_SECRET),
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let session_id = login["session_id"].as_str().unwrap();
+
+    // alice's diagnosis, prescription and consultation stay hidden
+    for q in ["cold", "paracetamol", "fever"] {
+        let (status, hits) = send(
+            &mut app,
+            Some(session_id),
+            "GET",
+            &format!("/users/{CAROL_ID}/search?q={q}"),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(hits, json!([]), "{q}");
+    }
+
+    let (_, hits) = send(
+        &mut app,
+        Some(session_id),
+        "GET",
+        &format!("/users/{CAROL_ID}/search?q=ankle"),
+        None,
+    )
+    .await;
+    assert_eq!(kinds(&hits), ["consultation"]);
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use axum::http::{StatusCode, header};
+use data_encoding::HEXLOWER;
+use sha2::{Digest, Sha256};
+use sqlx::Pool;
+use sqlx::postgres::Postgres;
+
+use common::*;
+
+// .route("/me/summary/pdf", post(issue_own_summary_pdf))
+// .route("/me/summaries", get(get_own_summaries))
+// .route("/summaries/{summary_id}", get(verify_summary))
+
+#[sqlx::test(fixtures("users", "details", "allergies", "measurements"))]
+async fn summary_verifies_against_its_hash(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_alice(&mut app).await;
+
+    let request =
+        build_request(Some(&session_id), "POST", "/me/summary/pdf", None);
+    let (status, headers, pdf) = send_request(&mut app, request).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(headers[header::CONTENT_TYPE], "application/pdf");
+    assert!(pdf.starts_with(b"%PDF-"));
+    assert!(
+        String::from_utf8_lossy(&pdf).contains("(Patient summary) Tj"),
+        "the PDF is not compressed, so its text shows as is"
+    );
+
+    let (status, summaries) =
+        send(&mut app, Some(&session_id), "GET", "/me/summaries", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let sha256 = HEXLOWER.encode(&Sha256::digest(&pdf));
+    assert_eq!(summaries[0]["sha256"], sha256);
+
+    // the QR code links here, no account needed
+    let summary_id = summaries[0]["summary_id"].as_str().unwrap();
+    let location = format!("/summaries/{summary_id}");
+    assert_eq!(headers[header::LOCATION], location.as_str());
+    let request = build_request(None, "GET", &location, None);
+    let (status, _, page) = send_request(&mut app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    let page = String::from_utf8(page.to_vec()).unwrap();
+    assert!(page.contains("Issued by Medigram"));
+    assert!(page.contains("Patient: alice"));
+    assert!(page.contains(&sha256));
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn unknown_summary_not_found(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+
+    let (status, _) = send(
+        &mut app,
+        None,
+        "GET",
+        "/summaries/00000000-0000-0000-0000-000000000000",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,226 @@
+mod common;
+
+use axum::Router;
+use axum::http::StatusCode;
+use serde_json::Value;
+use serde_json::json;
+use sqlx::Pool;
+use sqlx::postgres::Postgres;
+
+use common::*;
+
+// .route("/users/{user_id}/measurements/trends", get(get_user_trends))
+// .route("/me/measurements/trends", get(get_own_trends))
+
+const BOB_ID: &str = "41490144-e4e1-4d1f-9eb7-f90af81c12ce";
+
+/// Bob losing weight from January to March while his blood pressure peaks
+async fn record_readings(app: &mut Router, session_id: &str) {
+    for (weight, measured_at) in [
+        (80.0, "2025-01-05T08:00:00Z"),
+        (78.0, "2025-01-20T08:00:00Z"),
+        (72.0, "2025-02-10T08:00:00Z"),
+        (70.0, "2025-03-03T08:00:00Z"),
+    ] {
+        let (status, _) = send(
+            app,
+            Some(session_id),
+            "POST",
+            "/me/measurements",
+            Some(json!({
+                "height_in_cm": 170.0,
+                "weight_in_kg": weight,
+                "measured_at": measured_at,
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    for (systolic, diastolic, observed_at) in [
+        (150, 95, "2025-02-10T08:00:00Z"),
+        (118, 76, "2025-03-03T08:00:00Z"),
+    ] {
+        let (status, _) = send(
+            app,
+            Some(session_id),
+            "POST",
+            "/me/observations",
+            Some(json!({
+                "observations": [
+                    { "code": "8480-6", "value": systolic },
+                    { "code": "8462-4", "value": diastolic },
+                ],
+                "observed_at": observed_at,
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+}
+
+fn series<'a>(trends: &'a Value, metric: &str) -> Vec<&'a Value> {
+    trends["buckets"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|bucket| bucket["metric"] == metric)
+        .collect()
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn monthly_trends_and_alerts(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_bob(&mut app).await;
+    record_readings(&mut app, &session_id).await;
+
+    let (status, trends) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        "/me/measurements/trends?window=2",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(trends["period"], "month");
+
+    let weight = series(&trends, "weight");
+    assert_eq!(weight.len(), 3);
+    assert_eq!(weight[0]["period_start"], "2025-01-01T00:00:00Z");
+    assert_eq!(weight[0]["unit"], "kg");
+    assert_eq!(weight[0]["readings"], 2);
+    assert_eq!(weight[0]["average"], 79.0);
+    assert_eq!(weight[0]["min"], 78.0);
+    assert_eq!(weight[0]["max"], 80.0);
+    assert_eq!(weight[0]["moving_average"], 79.0);
+    assert_eq!(weight[1]["moving_average"], 75.5);
+    assert_eq!(weight[2]["moving_average"], 71.0);
+
+    let bmi = series(&trends, "bmi");
+    assert_eq!(bmi.len(), 3);
+    assert!((bmi[2]["average"].as_f64().unwrap() - 24.22).abs() < 0.01);
+    assert_eq!(series(&trends, "systolic").len(), 2);
+
+    // overweight in January, hypertensive in February
+    let alerts: Vec<(&str, &str)> = trends["alerts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|alert| {
+            (
+                alert["metric"].as_str().unwrap(),
+                alert["measured_at"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        alerts,
+        [
+            ("bmi", "2025-01-05T08:00:00Z"),
+            ("bmi", "2025-01-20T08:00:00Z"),
+            ("diastolic", "2025-02-10T08:00:00Z"),
+            ("systolic", "2025-02-10T08:00:00Z"),
+        ]
+    );
+    assert_eq!(trends["alerts"][3]["high"], 140.0);
+    assert_eq!(trends["more_alerts"], false);
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn weekly_trends_within_range(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_bob(&mut app).await;
+    record_readings(&mut app, &session_id).await;
+
+    let (status, trends) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        "/me/measurements/trends?period=week&from=2025-01-01T00:00:00Z&to=\
+         2025-02-01T00:00:00Z",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(trends["window"], 3);
+
+    // weeks start on Monday
+    let weight = series(&trends, "weight");
+    assert_eq!(weight.len(), 2);
+    assert_eq!(weight[0]["period_start"], "2024-12-30T00:00:00Z");
+    assert_eq!(weight[1]["period_start"], "2025-01-20T00:00:00Z");
+    assert_eq!(series(&trends, "systolic").len(), 0);
+    assert_eq!(trends["alerts"].as_array().unwrap().len(), 2);
+
+    for query in [
+        "window=0",
+        "window=13",
+        "from=2025-02-01T00:00:00Z&to=2025-01-01T00:00:00Z",
+    ] {
+        let (status, body) = send(
+            &mut app,
+            Some(&session_id),
+            "GET",
+            &format!("/me/measurements/trends?{query}"),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "Invalid trend parameters");
+    }
+
+    let (alice_session, _) = login_as_alice(&mut app).await;
+    let (status, _) = send(
+        &mut app,
+        Some(&alice_session),
+        "GET",
+        &format!("/users/{BOB_ID}/measurements/trends"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn child_bmi_raises_no_alert(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _) = login_as_bob(&mut app).await;
+
+    // 15 years old in 2025
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "PUT",
+        "/me/details",
+        Some(json!({
+            "nik": 1000000000000001i64,
+            "name": "bob",
+            "dob": "2010-01-15",
+            "gender": "M",
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    record_readings(&mut app, &session_id).await;
+
+    let (status, trends) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        "/me/measurements/trends",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(series(&trends, "bmi").len(), 3);
+
+    let metrics: Vec<&str> = trends["alerts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|alert| alert["metric"].as_str().unwrap())
+        .collect();
+    assert_eq!(metrics, ["diastolic", "systolic"]);
+    assert_eq!(trends["more_alerts"], false);
+}
@@ -0,0 +1,231 @@
+mod common;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use http_body_util::BodyExt;
+use serde_json::Value;
+use serde_json::json;
+use sqlx::Pool;
+use sqlx::postgres::Postgres;
+use tower::{Service, ServiceExt};
+
+use common::*;
+
+// .route("/login/two-factor", post(login_two_factor))
+// .route("/me/two-factor", get(get_two_factor_status))
+// .route("/me/two-factor/totp", post(begin_totp_enrollment))
+// .route("/me/two-factor/totp/confirm", post(confirm_totp_enrollment))
+
+#[sqlx::test(fixtures("users", "doctor_info"))]
+async fn login_requires_second_factor(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/login"))
+        .method("POST")
+        .header("Content-Type", "application/json")
+        .body(Body::from(
+            json!({
+                "email": "alice@example.com",
+                "password": "test",
+            })
+            .to_string(),
+        ))
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(&mut app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(body["two_factor_required"], json!(true));
+    assert!(body.get("session_id").is_none());
+
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/login/two-factor"))
+        .method("POST")
+        .header("Content-Type", "application/json")
+        .body(Body::from(
+            json!({
+                "challenge_id": body["challenge_id"],
+                "code": "000000",
+            })
+            .to_string(),
+        ))
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(&mut app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[sqlx::test(fixtures("users", "doctor_info"))]
+async fn login_with_second_factor(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _user_id) = login_as_alice(&mut app).await;
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/doctor/consultations"))
+        .method("GET")
+        .header("Authorization", format!("Bearer {session_id}"))
+        .body(Body::empty())
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(&mut app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[sqlx::test(fixtures("users", "unenrolled_doctor"))]
+async fn doctor_without_second_factor(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _user_id) = login_as_bob(&mut app).await;
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/doctor/consultations"))
+        .method("GET")
+        .header("Authorization", format!("Bearer {session_id}"))
+        .body(Body::empty())
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(&mut app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[sqlx::test(fixtures("users", "unenrolled_doctor"))]
+async fn enrolling_does_not_upgrade_the_session(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _user_id) = login_as_bob(&mut app).await;
+
+    let (status, body) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        "/me/two-factor/totp",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "POST",
+        "/me/two-factor/totp/confirm",
+        Some(json!({
+            "code": current_totp_code(body["secret"].as_str().unwrap())
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // the session was started with the password alone
+    let (status, _) = send(
+        &mut app,
+        Some(&session_id),
+        "GET",
+        "/doctor/consultations",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[sqlx::test(fixtures("users"))]
+async fn enroll_totp(db_pool: Pool<Postgres>) {
+    let mut app = get_app(db_pool);
+    let (session_id, _user_id) = login_as_alice(&mut app).await;
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/me/two-factor/totp"))
+        .method("POST")
+        .header("Authorization", format!("Bearer {session_id}"))
+        .body(Body::empty())
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(&mut app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    let secret = body["secret"].as_str().unwrap();
+
+    assert!(
+        body["otpauth_uri"]
+            .as_str()
+            .unwrap()
+            .starts_with("otpauth://totp/Medigram:alice@example.com?")
+    );
+
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/me/two-factor/totp/confirm"))
+        .method("POST")
+        .header("Content-Type", "application/json")
+        .header("Authorization", format!("Bearer {session_id}"))
+        .body(Body::from(
+            json!({ "code": current_totp_code(secret) }).to_string(),
+        ))
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(&mut app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(body["recovery_codes"].as_array().unwrap().len(), 10);
+
+    let request = Request::builder()
+        .uri(format!("http://{API_ROOT_URL}/me/two-factor"))
+        .method("GET")
+        .header("Authorization", format!("Bearer {session_id}"))
+        .body(Body::empty())
+        .unwrap();
+
+    let response = ServiceExt::<Request<Body>>::ready(&mut app)
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(
+        body,
+        json!({
+            "enabled": true,
+            "required": false,
+            "recovery_codes_remaining": 10,
+        })
+    );
+}